@@ -0,0 +1,110 @@
+//! Undoes netwaystev2's optional compression of large packets before they're decoded.
+//!
+//! A compressed packet carries nothing but the compressed bincode, so it's recognised by the
+//! compression format's own header: zstd's magic number, or a zlib header around deflate. A plain
+//! bincode packet can't start with either, since it opens with a small variant index.
+
+use std::fmt;
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+
+/// Anything that decompresses to more than this is treated as corrupt rather than allocated.
+const MAX_DECOMPRESSED: u64 = 1 << 20;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Deflate,
+}
+
+impl Compression {
+    fn detect(payload: &[u8]) -> Option<Self> {
+        if payload.starts_with(&ZSTD_MAGIC) {
+            return Some(Compression::Zstd);
+        }
+        match payload {
+            // Deflate with a window of at most 32 KiB, and a header checksum that adds up
+            [cmf, flg, ..] if *cmf & 0x0f == 8 && *cmf >> 4 <= 7 => {
+                (u16::from_be_bytes([*cmf, *flg]) % 31 == 0).then_some(Compression::Deflate)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Deflate => write!(f, "deflate"),
+        }
+    }
+}
+
+/// The bincode inside a compressed payload, and how it was compressed. `None` if the payload
+/// isn't compressed.
+pub fn decompress(payload: &[u8]) -> io::Result<Option<(Compression, Vec<u8>)>> {
+    let Some(compression) = Compression::detect(payload) else {
+        return Ok(None);
+    };
+    let reader: Box<dyn Read + '_> = match compression {
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(payload)?),
+        Compression::Deflate => Box::new(ZlibDecoder::new(payload)),
+    };
+    let mut data = vec![];
+    reader.take(MAX_DECOMPRESSED + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_DECOMPRESSED {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("decompresses to more than {} bytes", MAX_DECOMPRESSED),
+        ));
+    }
+    Ok(Some((compression, data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+
+    use super::*;
+
+    #[test]
+    fn plain_bincode_is_left_alone() {
+        assert!(decompress(&[0, 0, 0, 0, 7, 0, 0, 0]).unwrap().is_none());
+        assert!(decompress(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let data = b"netwayste".repeat(100);
+        let compressed = zstd::encode_all(&data[..], 0).unwrap();
+        assert_eq!(
+            decompress(&compressed).unwrap(),
+            Some((Compression::Zstd, data))
+        );
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let data = b"netwayste".repeat(100);
+        let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(
+            decompress(&compressed).unwrap(),
+            Some((Compression::Deflate, data))
+        );
+    }
+
+    #[test]
+    fn oversized_payloads_are_refused() {
+        let data = vec![0; MAX_DECOMPRESSED as usize + 1];
+        let compressed = zstd::encode_all(&data[..], 0).unwrap();
+        assert!(decompress(&compressed).is_err());
+    }
+}
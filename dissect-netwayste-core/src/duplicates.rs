@@ -0,0 +1,70 @@
+//! Spots payloads that arrive more than once, byte for byte.
+//!
+//! Our retry logic resends identical packets, and some Wi-Fi drivers deliver the same frame
+//! twice, so the copies are worth telling apart from new traffic.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+/// How many recent payloads to remember per flow.
+const WINDOW: usize = 64;
+
+#[derive(Default)]
+pub struct DuplicateDetector {
+    recent: HashMap<(SocketAddr, SocketAddr), VecDeque<u64>>,
+}
+
+impl DuplicateDetector {
+    /// Call with every datagram. Returns whether the same payload was among the last few seen
+    /// on this flow.
+    pub fn observe(&mut self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let recent = self.recent.entry((src, dst)).or_default();
+        if recent.contains(&hash) {
+            return true;
+        }
+        if recent.len() == WINDOW {
+            recent.pop_front();
+        }
+        recent.push_back(hash);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_on_the_same_flow_are_duplicates() {
+        let (a, b): (SocketAddr, SocketAddr) = (
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:2016".parse().unwrap(),
+        );
+        let mut detector = DuplicateDetector::default();
+        assert!(!detector.observe(a, b, b"hello"));
+        assert!(detector.observe(a, b, b"hello"));
+        // Same bytes the other way are a different flow
+        assert!(!detector.observe(b, a, b"hello"));
+        assert!(!detector.observe(a, b, b"world"));
+    }
+
+    #[test]
+    fn only_recent_payloads_are_remembered() {
+        let (a, b): (SocketAddr, SocketAddr) = (
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:2016".parse().unwrap(),
+        );
+        let mut detector = DuplicateDetector::default();
+        detector.observe(a, b, &[0]);
+        for i in 1..=WINDOW as u32 {
+            detector.observe(a, b, &i.to_le_bytes());
+        }
+        assert!(!detector.observe(a, b, &[0]));
+    }
+}
@@ -0,0 +1,236 @@
+//! Readable summaries of `GenStateDiff`s, whose RLE patterns would otherwise fill the screen.
+//!
+//! A diff is recognised by its `gen0` and `gen1` fields wherever it sits in a packet. Its
+//! pattern is RLE, in which every live cell marks a cell that changed between the generations.
+
+use std::fmt;
+
+use netwaystev2::protocol::Packet;
+use serde_json::{Map, Value};
+
+use crate::output::packet_value;
+
+/// One diff found in a packet.
+pub struct Diff<'a> {
+    /// The generation the diff applies to; 0 means it's the whole state rather than a change
+    pub gen0: u64,
+    /// The generation the diff brings the universe to
+    pub gen1: u64,
+    pub pattern: &'a str,
+}
+
+impl<'a> Diff<'a> {
+    fn new(diff: &'a Map<String, Value>) -> Option<Self> {
+        Some(Diff {
+            gen0: diff.get("gen0")?.as_u64()?,
+            gen1: diff.get("gen1")?.as_u64()?,
+            pattern: diff
+                .iter()
+                .find(|(name, _)| name.starts_with("pattern"))
+                .and_then(|(_, value)| pattern_string(value))?,
+        })
+    }
+
+    /// The live runs of the pattern; see `runs()`.
+    pub fn runs(&self) -> Option<Vec<(u64, u64, u64)>> {
+        runs(self.pattern)
+    }
+}
+
+/// The live runs of an RLE pattern, as the column and row they start at and their length.
+/// `None` if a count, a position or the number of live cells doesn't fit in a `u64`, so the
+/// runs can be added up and their ends worked out without overflowing.
+pub fn runs(pattern: &str) -> Option<Vec<(u64, u64, u64)>> {
+    let mut runs = vec![];
+    let (mut x, mut y) = (0u64, 0u64);
+    let mut count = 0u64;
+    let mut live = 0u64;
+    for c in pattern.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            count = count.checked_mul(10)?.checked_add(digit as u64)?;
+            continue;
+        }
+        let run = count.max(1);
+        count = 0;
+        match c {
+            'b' | '.' => x = x.checked_add(run)?,
+            '$' => {
+                x = 0;
+                y = y.checked_add(run)?;
+            }
+            '!' => break,
+            c if c.is_whitespace() => {}
+            _ => {
+                runs.push((x, y, run));
+                x = x.checked_add(run)?;
+                live = live.checked_add(run)?;
+            }
+        }
+    }
+    Some(runs)
+}
+
+/// Every diff anywhere in the packet.
+pub fn find_diffs(packet: &Value) -> Vec<Diff<'_>> {
+    let mut maps = vec![];
+    find_diff_maps(packet, &mut maps);
+    maps.into_iter().filter_map(Diff::new).collect()
+}
+
+/// What's in one diff's pattern.
+struct DiffSummary {
+    gen0: u64,
+    gen1: u64,
+    changed: u64,
+    /// Left, top, right and bottom of the changed cells, inclusive
+    bounds: Option<(u64, u64, u64, u64)>,
+    /// Length of the RLE pattern, in bytes
+    rle_len: usize,
+    /// Size of the changed area as a bitmap, for comparison with the RLE
+    bitmap_len: u64,
+}
+
+impl DiffSummary {
+    /// `None` if the pattern can't be parsed, or covers an area too big to measure.
+    fn new(diff: &Diff) -> Option<Self> {
+        let mut changed = 0;
+        let mut bounds: Option<(u64, u64, u64, u64)> = None;
+        for (x, y, run) in diff.runs()? {
+            changed += run;
+            let (left, top, right, bottom) = bounds.unwrap_or((x, y, x, y));
+            bounds = Some((
+                left.min(x),
+                top.min(y),
+                right.max(x + run - 1),
+                bottom.max(y),
+            ));
+        }
+        let bitmap_len = match bounds {
+            Some((left, top, right, bottom)) => (right - left + 1)
+                .checked_mul(bottom - top + 1)?
+                .div_ceil(8),
+            None => 0,
+        };
+        Some(DiffSummary {
+            gen0: diff.gen0,
+            gen1: diff.gen1,
+            changed,
+            bounds,
+            rle_len: diff.pattern.len(),
+            bitmap_len,
+        })
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<diff gen {} -> {}, {} cells changed",
+            self.gen0, self.gen1, self.changed
+        )?;
+        if let Some((left, top, right, bottom)) = self.bounds {
+            write!(
+                f,
+                " within {}x{} at ({}, {})",
+                right - left + 1,
+                bottom - top + 1,
+                left,
+                top
+            )?;
+        }
+        write!(
+            f,
+            ", {} bytes of RLE vs {} as a bitmap>",
+            self.rle_len, self.bitmap_len
+        )
+    }
+}
+
+/// The packet's `Debug` text, with the pattern of every diff in it replaced by a summary.
+/// Patterns that can't be summarized are left as they are.
+pub fn readable(packet: &Packet) -> String {
+    let mut text = format!("{:?}", packet);
+    let value = packet_value(packet);
+    for diff in find_diffs(&value) {
+        let Some(summary) = DiffSummary::new(&diff) else {
+            continue;
+        };
+        text = text.replacen(&format!("{:?}", diff.pattern), &summary.to_string(), 1);
+    }
+    text
+}
+
+fn find_diff_maps<'a>(value: &'a Value, found: &mut Vec<&'a Map<String, Value>>) {
+    match value {
+        Value::Object(map) if map.contains_key("gen0") && map.contains_key("gen1") => {
+            found.push(map)
+        }
+        Value::Object(map) => map.values().for_each(|v| find_diff_maps(v, found)),
+        Value::Array(values) => values.iter().for_each(|v| find_diff_maps(v, found)),
+        _ => {}
+    }
+}
+
+/// The pattern is either a plain string or a newtype around one.
+fn pattern_string(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(s) => Some(s),
+        Value::Object(map) if map.len() == 1 => map.values().next().and_then(pattern_string),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn rle_runs() {
+        assert_eq!(runs("2o$b3o!").unwrap(), [(0, 0, 2), (1, 1, 3)]);
+        assert_eq!(runs("o2$12bo!o").unwrap(), [(0, 0, 1), (12, 2, 1)]);
+        assert!(runs("3b.!").unwrap().is_empty());
+    }
+
+    #[test]
+    fn oversized_runs() {
+        // More digits than a u64 holds
+        assert!(runs("99999999999999999999o!").is_none());
+        // Each count fits, but the position after them doesn't
+        assert!(runs("18446744073709551615bo!").is_none());
+        assert!(runs("18446744073709551615$18446744073709551615$o!").is_none());
+        // Fits, but the area is too big to have a bitmap size
+        let map =
+            json!({"gen0": 0, "gen1": 1, "pattern": "9223372036854775807o$9223372036854775807$o!"});
+        let diff = Diff::new(map.as_object().unwrap()).unwrap();
+        assert!(diff.runs().is_some());
+        assert!(DiffSummary::new(&diff).is_none());
+    }
+
+    #[test]
+    fn diffs_are_found_anywhere() {
+        let packet = json!({"Update": {"game_updates": [
+            {"GenStateDiff": {"gen0": 4, "gen1": 5, "pattern": {"Pattern": "bo$2o!"}}}
+        ]}});
+        let diffs = find_diffs(&packet);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(
+            (diffs[0].gen0, diffs[0].gen1, diffs[0].pattern),
+            (4, 5, "bo$2o!")
+        );
+        assert_eq!(diffs[0].runs().unwrap(), [(1, 0, 1), (0, 1, 2)]);
+        assert!(find_diffs(&json!({"Update": {}})).is_empty());
+    }
+
+    #[test]
+    fn summaries() {
+        let map = json!({"gen0": 0, "gen1": 1, "pattern": "2o$b3o!"});
+        let diff = Diff::new(map.as_object().unwrap()).unwrap();
+        assert_eq!(
+            DiffSummary::new(&diff).unwrap().to_string(),
+            "<diff gen 0 -> 1, 5 cells changed within 4x2 at (0, 0), 7 bytes of RLE vs 1 as a bitmap>"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Round-trip times, from matching each response's `request_ack` to the request it acknowledges.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::output::{sequence_number, variant_name};
+
+/// Requests nobody answered within this long are forgotten, so lost ones don't pile up.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Only the most recent samples count towards a client's percentiles, unless it's `unbounded()`.
+const MAX_SAMPLES: usize = 1000;
+
+/// One matched request and response.
+pub struct Exchange {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub sequence: u64,
+    pub rtt: Duration,
+}
+
+/// Running round-trip statistics for one client.
+pub struct RttStats {
+    pub min: Duration,
+    pub max: Duration,
+    total: Duration,
+    count: u32,
+    recent: VecDeque<Duration>,
+    /// How many samples `recent` keeps
+    max_samples: usize,
+}
+
+impl Default for RttStats {
+    fn default() -> Self {
+        RttStats {
+            min: Duration::ZERO,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+            count: 0,
+            recent: VecDeque::new(),
+            max_samples: MAX_SAMPLES,
+        }
+    }
+}
+
+impl RttStats {
+    /// Keeps every sample, so the percentiles cover the whole run rather than the end of it.
+    pub fn unbounded() -> Self {
+        RttStats {
+            max_samples: usize::MAX,
+            ..RttStats::default()
+        }
+    }
+
+    pub fn add(&mut self, rtt: Duration) {
+        if self.count == 0 || rtt < self.min {
+            self.min = rtt;
+        }
+        self.max = self.max.max(rtt);
+        self.total += rtt;
+        self.count += 1;
+        if self.recent.len() == self.max_samples {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rtt);
+    }
+
+    pub fn avg(&self) -> Duration {
+        self.total / self.count.max(1)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn p95(&self) -> Duration {
+        self.percentile(95)
+    }
+
+    /// The round-trip time `percent`% of the samples kept are at or under.
+    pub fn percentile(&self, percent: usize) -> Duration {
+        let mut sorted: Vec<_> = self.recent.iter().copied().collect();
+        sorted.sort();
+        let index = (sorted.len() * percent).div_ceil(100).saturating_sub(1);
+        sorted.get(index).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct LatencyTracker {
+    /// Capture time of each unanswered request, by (client, server, sequence)
+    pending: HashMap<(SocketAddr, SocketAddr, u64), Duration>,
+    stats: HashMap<SocketAddr, RttStats>,
+}
+
+impl LatencyTracker {
+    /// Call with every decoded packet. Returns the exchange a response completes, if any.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+    ) -> Option<Exchange> {
+        match variant_name(packet).as_str() {
+            "Request" => {
+                let sequence = sequence_number(packet)?;
+                // A retransmission is timed from the first copy, since there's no telling which
+                // copy the response answers
+                self.pending.entry((src, dst, sequence)).or_insert(ts);
+                self.pending
+                    .retain(|_, sent| ts.saturating_sub(*sent) < PENDING_TIMEOUT);
+                None
+            }
+            "Response" => {
+                let sequence = packet
+                    .get("Response")
+                    .and_then(|body| body.get("request_ack"))
+                    .and_then(Value::as_u64)?;
+                let sent = self.pending.remove(&(dst, src, sequence))?;
+                let rtt = ts.saturating_sub(sent);
+                self.stats.entry(dst).or_default().add(rtt);
+                Some(Exchange {
+                    client: dst,
+                    server: src,
+                    sequence,
+                    rtt,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn stats(&self, client: SocketAddr) -> Option<&RttStats> {
+        self.stats.get(&client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn responses_are_matched_to_requests() {
+        let client: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let server: SocketAddr = "10.0.0.2:2016".parse().unwrap();
+        let mut tracker = LatencyTracker::default();
+
+        let request = json!({"Request": {"sequence": 3, "action": "KeepAlive"}});
+        let retransmit = request.clone();
+        let response = json!({"Response": {"sequence": 9, "request_ack": 3, "code": "OK"}});
+        assert!(tracker
+            .observe(Duration::from_millis(10), client, server, &request)
+            .is_none());
+        assert!(tracker
+            .observe(Duration::from_millis(40), client, server, &retransmit)
+            .is_none());
+        let exchange = tracker
+            .observe(Duration::from_millis(60), server, client, &response)
+            .unwrap();
+        assert_eq!(exchange.client, client);
+        assert_eq!(exchange.sequence, 3);
+        assert_eq!(exchange.rtt, Duration::from_millis(50));
+        assert_eq!(
+            tracker.stats(client).unwrap().min,
+            Duration::from_millis(50)
+        );
+
+        // Answered once only
+        assert!(tracker
+            .observe(Duration::from_millis(70), server, client, &response)
+            .is_none());
+    }
+
+    #[test]
+    fn rtt_stats() {
+        let mut stats = RttStats::default();
+        for ms in 1..=100 {
+            stats.add(Duration::from_millis(ms));
+        }
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(stats.count(), 100);
+        assert_eq!(stats.avg(), Duration::from_micros(50_500));
+        assert_eq!(stats.p95(), Duration::from_millis(95));
+        assert_eq!(stats.percentile(50), Duration::from_millis(50));
+        assert_eq!(stats.percentile(99), Duration::from_millis(99));
+        assert_eq!(stats.percentile(100), Duration::from_millis(100));
+        assert_eq!(RttStats::default().p95(), Duration::ZERO);
+    }
+
+    #[test]
+    fn unbounded_rtt_stats_keep_every_sample() {
+        let mut bounded = RttStats::default();
+        let mut unbounded = RttStats::unbounded();
+        // A slow start followed by more fast round trips than the bounded stats keep
+        for ms in [100; 100].into_iter().chain([1; MAX_SAMPLES]) {
+            bounded.add(Duration::from_millis(ms));
+            unbounded.add(Duration::from_millis(ms));
+        }
+        assert_eq!(bounded.percentile(100), Duration::from_millis(1));
+        assert_eq!(unbounded.percentile(95), Duration::from_millis(100));
+        assert_eq!(unbounded.percentile(50), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,40 @@
+//! The parts of dissect-netwayste that don't care where packets come from: decoding netwayste
+//! payloads, following flows and sessions through them, and formatting what's found. Capture,
+//! dissection of the layers below UDP and everything to do with the terminal stay in the tool.
+//!
+//! A payload goes through `compression::decompress()`, then a `protocol::Decoder`. The decoded
+//! `Packet` is easiest to pick apart as `output::packet_value()`, which is what the trackers in
+//! `latency`, `sequence`, `session` and `packet_filter` take:
+//!
+//! ```no_run
+//! use dissect_netwayste_core::{compression, output, protocol::Protocol, session::SessionTracker};
+//! # let (src, dst, payload): (std::net::SocketAddr, std::net::SocketAddr, Vec<u8>) = todo!();
+//!
+//! let decoder = Protocol::Auto.decoder();
+//! let mut sessions = SessionTracker::new(netwaystev2::DEFAULT_PORT);
+//!
+//! let decompressed = compression::decompress(&payload).expect("corrupt compressed payload");
+//! let bincode = decompressed.as_ref().map_or(&payload[..], |(_, data)| &data[..]);
+//! let packet = decoder.decode(bincode).expect("not a netwayste packet");
+//! if let Some(transition) = sessions.observe(src, dst, &output::packet_value(&packet)) {
+//!     println!("{} {}", transition.client, transition.event());
+//! }
+//! ```
+//!
+//! Trackers keep state per flow, one direction of one conversation, and expect to see every
+//! packet in capture order.
+//!
+//! Going the other way, `templates` builds packets to send from the layout of `Packet` itself.
+
+pub mod compression;
+pub mod duplicates;
+pub mod gen_diff;
+pub mod latency;
+pub mod output;
+pub mod packet_filter;
+pub mod ports;
+pub mod protocol;
+pub mod sequence;
+pub mod session;
+pub mod templates;
+pub mod timing;
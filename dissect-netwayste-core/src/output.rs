@@ -0,0 +1,186 @@
+//! Machine-readable renderings of decoded packets, for piping into other tools.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use clap::ValueEnum;
+use netwaystev2::protocol::Packet;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored, human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Comma-separated values with a header row; see `CSV_HEADER`
+    Csv,
+    /// Columnar Parquet file, written to 'output-file'
+    Parquet,
+    /// Elasticsearch/OpenSearch bulk API requests, one action and one JSON document per packet
+    ElasticBulk,
+}
+
+impl OutputFormat {
+    /// Whether packets are written to stdout by themselves, leaving logs to go elsewhere.
+    pub fn is_machine_readable(&self) -> bool {
+        *self != OutputFormat::Text
+    }
+}
+
+/// The CSV column set. Scripts rely on it, so only ever add columns at the end.
+pub const CSV_HEADER: &str = "time,src,dst,variant,sequence,size";
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: Option<String>,
+    src: SocketAddr,
+    dst: SocketAddr,
+    variant: String,
+    packet: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interface: Option<&'a str>,
+}
+
+/// Formats a capture time as RFC 3339 with microseconds, in UTC.
+pub fn format_time(ts: Duration) -> Option<String> {
+    Utc.timestamp_opt(ts.as_secs() as i64, ts.subsec_nanos())
+        .single()
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string())
+}
+
+/// Renders one decoded packet, as returned by `packet_value()`, as a single line of JSON.
+pub fn json_line(
+    ts: Duration,
+    interface: Option<&str>,
+    src: SocketAddr,
+    dst: SocketAddr,
+    packet: &Value,
+) -> String {
+    let record = JsonRecord {
+        timestamp: format_time(ts),
+        src,
+        dst,
+        variant: variant_name(packet),
+        packet,
+        interface,
+    };
+    serde_json::to_string(&record).expect("failed to serialize JSON record")
+}
+
+/// Renders one decoded packet, as returned by `packet_value()`, as a row under `CSV_HEADER`.
+/// `size` is the length of the serialized packet. Packets without a sequence number leave that
+/// column empty.
+pub fn csv_line(
+    ts: Duration,
+    src: SocketAddr,
+    dst: SocketAddr,
+    packet: &Value,
+    size: usize,
+) -> String {
+    let sequence = sequence_number(packet);
+    // None of the fields can contain a comma or quote, so there's nothing to escape
+    format!(
+        "{},{},{},{},{},{}",
+        format_time(ts).unwrap_or_default(),
+        src,
+        dst,
+        variant_name(packet),
+        sequence.map(|s| s.to_string()).unwrap_or_default(),
+        size
+    )
+}
+
+/// The packet as serde sees it, which is easier to pick fields out of than the packet itself.
+pub fn packet_value(packet: &Packet) -> Value {
+    serde_json::to_value(packet).expect("netwayste packet not serializable to JSON")
+}
+
+/// serde represents enums as `"Variant"` when they carry no data and `{"Variant": ...}` when
+/// they do, so the name can be recovered from either.
+pub fn variant_name(value: &Value) -> String {
+    match value {
+        Value::String(name) => name.clone(),
+        Value::Object(map) if map.len() == 1 => map.keys().next().unwrap().clone(),
+        _ => "Unknown".to_owned(),
+    }
+}
+
+/// The variant of the enum in `field` of the packet's body, e.g. the `RequestAction` in the
+/// `action` field of a `Request`.
+pub fn nested_variant(packet: &Value, field: &str) -> Option<String> {
+    match packet {
+        Value::Object(map) => map
+            .values()
+            .next()
+            .and_then(|body| body.get(field))
+            .map(variant_name),
+        _ => None,
+    }
+}
+
+/// The `sequence` field of whichever variant `value` holds, if it has one.
+pub fn sequence_number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Object(map) => map
+            .values()
+            .next()
+            .and_then(|body| body.get("sequence"))
+            .and_then(Value::as_u64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn variant_names() {
+        assert_eq!(variant_name(&json!("GetStatus")), "GetStatus");
+        assert_eq!(
+            variant_name(&json!({"Request": {"sequence": 1}})),
+            "Request"
+        );
+        assert_eq!(variant_name(&json!({"a": 1, "b": 2})), "Unknown");
+        assert_eq!(variant_name(&json!(3)), "Unknown");
+    }
+
+    #[test]
+    fn nested_variants() {
+        let packet =
+            json!({"Request": {"sequence": 7, "action": {"JoinRoom": {"room_name": "x"}}}});
+        assert_eq!(
+            nested_variant(&packet, "action").as_deref(),
+            Some("JoinRoom")
+        );
+        assert_eq!(nested_variant(&packet, "code"), None);
+        assert_eq!(nested_variant(&json!("GetStatus"), "action"), None);
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        assert_eq!(
+            sequence_number(&json!({"Request": {"sequence": 7}})),
+            Some(7)
+        );
+        assert_eq!(
+            sequence_number(&json!({"Status": {"player_count": 2}})),
+            None
+        );
+        assert_eq!(sequence_number(&json!("GetStatus")), None);
+    }
+
+    #[test]
+    fn times_are_utc_with_microseconds() {
+        let ts = Duration::new(1_680_352_496, 789_012_345);
+        assert_eq!(
+            format_time(ts).as_deref(),
+            Some("2023-04-01T12:34:56.789012Z")
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Picks which decoded packets get shown, by what's inside them rather than where they came from.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+use serde_json::Value;
+
+use crate::output::{nested_variant, variant_name};
+use crate::ports::Ports;
+
+/// Everything that decides whether a decoded packet is shown.
+#[derive(Debug, Default)]
+pub struct Selector {
+    pub content: PacketFilter,
+    pub player: Option<PlayerFilter>,
+    pub room: Option<RoomFilter>,
+    pub follow: Option<FollowFilter>,
+}
+
+impl Selector {
+    /// `packet` is the packet as returned by `output::packet_value()`.
+    pub fn shows(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) -> bool {
+        if self.content.is_empty()
+            && self.player.is_none()
+            && self.room.is_none()
+            && self.follow.is_none()
+        {
+            return true;
+        }
+        // The stateful filters all run, whatever the others say, since they have to see
+        // everything to keep up
+        let player = self
+            .player
+            .as_mut()
+            .map_or(true, |player| player.matches(src, dst, packet));
+        let room = self
+            .room
+            .as_mut()
+            .map_or(true, |room| room.matches(src, dst, packet));
+        let follow = self
+            .follow
+            .as_mut()
+            .map_or(true, |follow| follow.matches(src, dst, packet));
+        player && room && follow && (self.content.is_empty() || self.content.matches(packet))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PacketFilter {
+    /// Top-level `Packet` variants to show; all of them if empty
+    pub only: Vec<String>,
+    /// Top-level `Packet` variants to hide, even if also in `only`
+    pub exclude: Vec<String>,
+    /// `RequestAction` variants of requests to show
+    pub actions: Vec<String>,
+    /// `ResponseCode` variants of responses to show
+    pub response_codes: Vec<String>,
+}
+
+impl PacketFilter {
+    /// `packet` is the packet as returned by `output::packet_value()`. Variant names are
+    /// compared without regard to case.
+    ///
+    /// Giving `actions` or `response_codes` limits the packets shown to requests and responses
+    /// that match them; giving both shows the matches of either.
+    pub fn matches(&self, packet: &Value) -> bool {
+        let variant = variant_name(packet);
+        if !(self.only.is_empty() || listed(&self.only, &variant))
+            || listed(&self.exclude, &variant)
+        {
+            return false;
+        }
+
+        if self.actions.is_empty() && self.response_codes.is_empty() {
+            return true;
+        }
+        let nested_matches = |field: &str, names: &[String]| {
+            nested_variant(packet, field).map_or(false, |nested| listed(names, &nested))
+        };
+        nested_matches("action", &self.actions) || nested_matches("code", &self.response_codes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty()
+            && self.exclude.is_empty()
+            && self.actions.is_empty()
+            && self.response_codes.is_empty()
+    }
+}
+
+fn listed(names: &[String], variant: &str) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(variant))
+}
+
+/// Follows one player's traffic across reconnects. A packet is theirs if it travels to or from
+/// an address they connected from, or carries a cookie they were given at login. Packets that
+/// merely mention them, such as chats sent to other players, don't count.
+#[derive(Debug)]
+pub struct PlayerFilter {
+    name: String,
+    /// The server's ports, which tell the client end of a packet from the server end
+    server_ports: Ports,
+    cookies: HashSet<String>,
+    clients: HashSet<SocketAddr>,
+}
+
+impl PlayerFilter {
+    pub fn new(name: String, server_ports: impl Into<Ports>) -> Self {
+        PlayerFilter {
+            name,
+            server_ports: server_ports.into(),
+            cookies: HashSet::new(),
+            clients: HashSet::new(),
+        }
+    }
+
+    /// Must see every decoded packet, shown or not, to keep track of the player.
+    pub fn matches(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) -> bool {
+        let client = client_end(src, dst, &self.server_ports);
+        let mut cookies = vec![];
+        find_strings(packet, "cookie", &mut cookies);
+
+        // Learn their new address whenever they connect as themselves
+        if client == src && nested_variant(packet, "action").as_deref() == Some("Connect") {
+            let mut names = vec![];
+            find_strings(packet, "name", &mut names);
+            if names.contains(&self.name.as_str()) {
+                self.clients.insert(client);
+            }
+        }
+        let related =
+            self.clients.contains(&client) || cookies.iter().any(|c| self.cookies.contains(*c));
+
+        // And the cookie the server hands out to that address
+        if client == dst
+            && self.clients.contains(&client)
+            && nested_variant(packet, "code").as_deref() == Some("LoggedIn")
+        {
+            self.cookies
+                .extend(cookies.into_iter().map(|c| c.to_owned()));
+        }
+        related
+    }
+}
+
+/// An `ip[:port]` to `--follow`; IPv6 addresses with a port go in brackets.
+#[derive(Debug, Clone)]
+pub struct FollowTarget {
+    pub ip: IpAddr,
+    /// Any port if `None`
+    pub port: Option<u16>,
+}
+
+impl FollowTarget {
+    /// Meant to be used as a clap `value_parser`.
+    pub fn parse(s: &str) -> Result<FollowTarget, String> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(FollowTarget {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            });
+        }
+        s.trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| FollowTarget { ip, port: None })
+            .map_err(|_| format!("'{}' is not an IP address, optionally with a port", s))
+    }
+}
+
+/// Follows one client's conversation with the server, like Wireshark's "Follow UDP Stream". When
+/// the client comes back from a new port or address, the cookie in its requests gives it away.
+#[derive(Debug)]
+pub struct FollowFilter {
+    target: FollowTarget,
+    server_ports: Ports,
+    clients: HashSet<SocketAddr>,
+    cookies: HashSet<String>,
+}
+
+impl FollowFilter {
+    pub fn new(target: FollowTarget, server_ports: impl Into<Ports>) -> Self {
+        FollowFilter {
+            target,
+            server_ports: server_ports.into(),
+            clients: HashSet::new(),
+            cookies: HashSet::new(),
+        }
+    }
+
+    /// Must see every decoded packet, shown or not, to keep up with the client.
+    pub fn matches(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) -> bool {
+        let client = client_end(src, dst, &self.server_ports);
+        let mut cookies = vec![];
+        find_strings(packet, "cookie", &mut cookies);
+
+        let is_target = client.ip() == self.target.ip
+            && self.target.port.map_or(true, |port| port == client.port());
+        let followed = is_target
+            || self.clients.contains(&client)
+            || cookies.iter().any(|c| self.cookies.contains(*c));
+        if followed {
+            self.clients.insert(client);
+            self.cookies
+                .extend(cookies.into_iter().map(|c| c.to_owned()));
+        }
+        followed
+    }
+}
+
+/// Follows one game room. A packet belongs to it if it names the room, or is to or from a
+/// client between joining and leaving it, which covers the room's chats and game updates.
+#[derive(Debug)]
+pub struct RoomFilter {
+    name: String,
+    server_ports: Ports,
+    members: HashSet<SocketAddr>,
+}
+
+impl RoomFilter {
+    pub fn new(name: String, server_ports: impl Into<Ports>) -> Self {
+        RoomFilter {
+            name,
+            server_ports: server_ports.into(),
+            members: HashSet::new(),
+        }
+    }
+
+    /// Must see every decoded packet, shown or not, to keep track of who's in the room.
+    pub fn matches(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) -> bool {
+        let client = client_end(src, dst, &self.server_ports);
+        let names_room = contains_string(packet, &self.name);
+        let member = self.members.contains(&client);
+        match nested_variant(packet, "action").as_deref() {
+            Some("JoinRoom") if names_room => {
+                self.members.insert(client);
+            }
+            // Shown, as the last packet of theirs that is
+            Some("LeaveRoom") if member => {
+                self.members.remove(&client);
+            }
+            _ => {}
+        }
+        names_room || member
+    }
+}
+
+/// Whichever end of a packet isn't the server.
+pub fn client_end(src: SocketAddr, dst: SocketAddr, server_ports: &Ports) -> SocketAddr {
+    if server_ports.contains(src.port()) {
+        dst
+    } else {
+        src
+    }
+}
+
+/// Whether any string anywhere in `value` is exactly `s`.
+fn contains_string(value: &Value, s: &str) -> bool {
+    match value {
+        Value::String(string) => string == s,
+        Value::Array(values) => values.iter().any(|v| contains_string(v, s)),
+        Value::Object(map) => map.values().any(|v| contains_string(v, s)),
+        _ => false,
+    }
+}
+
+/// Collects the string values of every field named `key`, at any depth.
+pub fn find_strings<'a>(value: &'a Value, key: &str, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Array(values) => values.iter().for_each(|v| find_strings(v, key, found)),
+        Value::Object(map) => {
+            for (k, v) in map {
+                match v {
+                    Value::String(s) if k == key => found.push(s),
+                    _ => find_strings(v, key, found),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const SERVER: &str = "10.0.0.2:2016";
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn content_filter() {
+        let join = json!({"Request": {"action": {"JoinRoom": {"room_name": "lobby"}}}});
+        let ok = json!({"Response": {"code": "OK"}});
+
+        let filter = PacketFilter {
+            only: names(&["request"]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&join));
+        assert!(!filter.matches(&ok));
+
+        let filter = PacketFilter {
+            exclude: names(&["Request"]),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&join));
+
+        let filter = PacketFilter {
+            actions: names(&["joinroom"]),
+            response_codes: names(&["BadRequest"]),
+            ..Default::default()
+        };
+        assert!(filter.matches(&join));
+        assert!(!filter.matches(&ok));
+        assert!(PacketFilter::default().is_empty());
+    }
+
+    #[test]
+    fn player_is_followed_across_reconnects() {
+        let mut filter = PlayerFilter::new("alice".to_owned(), 2016);
+        let server = addr(SERVER);
+        let (alice, bob, alice_again) = (
+            addr("10.0.0.1:5000"),
+            addr("10.0.0.3:5000"),
+            addr("192.168.1.9:6000"),
+        );
+        let connect =
+            |name: &str| json!({"Request": {"sequence": 1, "action": {"Connect": {"name": name}}}});
+        let logged_in = |cookie: &str| json!({"Response": {"code": {"LoggedIn": {"cookie": cookie, "server_version": "1"}}}});
+        let keepalive =
+            |cookie: &str| json!({"Request": {"cookie": cookie, "action": "KeepAlive"}});
+        let broadcast = json!({"Update": {"chats": [{"player_name": "alice", "message": "hi"}]}});
+
+        assert!(filter.matches(alice, server, &connect("alice")));
+        assert!(filter.matches(server, alice, &logged_in("c1")));
+        assert!(!filter.matches(bob, server, &connect("bob")));
+        assert!(!filter.matches(server, bob, &logged_in("c2")));
+
+        // Bob hearing about alice doesn't make his traffic hers
+        assert!(!filter.matches(server, bob, &broadcast));
+        assert!(!filter.matches(bob, server, &keepalive("c2")));
+        assert!(filter.matches(server, alice, &broadcast));
+
+        // Reconnecting from elsewhere, and being recognised by the new cookie
+        assert!(filter.matches(alice_again, server, &connect("alice")));
+        assert!(filter.matches(server, alice_again, &logged_in("c3")));
+        assert!(filter.matches(addr("192.168.1.9:6001"), server, &keepalive("c3")));
+        assert!(!filter.matches(bob, server, &keepalive("c2")));
+    }
+
+    #[test]
+    fn follow_targets() {
+        let target = FollowTarget::parse("10.0.0.1:5000").unwrap();
+        assert_eq!(
+            (target.ip, target.port),
+            (addr("10.0.0.1:1").ip(), Some(5000))
+        );
+        let target = FollowTarget::parse("[::1]").unwrap();
+        assert_eq!(target.port, None);
+        assert!(FollowTarget::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn follow_survives_a_new_port() {
+        let mut filter = FollowFilter::new(FollowTarget::parse("10.0.0.1").unwrap(), 2016);
+        let login = json!({"Response": {"code": {"LoggedIn": {"cookie": "abc"}}}});
+        let request = json!({"Request": {"cookie": "abc", "action": "KeepAlive"}});
+        assert!(filter.matches(addr(SERVER), addr("10.0.0.1:5000"), &login));
+        // Back from another address, but with the cookie it was given
+        assert!(filter.matches(addr("192.168.1.9:6000"), addr(SERVER), &request));
+        assert!(!filter.matches(addr("192.168.1.10:6000"), addr(SERVER), &json!("GetStatus")));
+    }
+
+    #[test]
+    fn room_members_are_followed_until_they_leave() {
+        let mut filter = RoomFilter::new("lobby".to_owned(), 2016);
+        let client = addr("10.0.0.1:5000");
+        let join = json!({"Request": {"action": {"JoinRoom": {"room_name": "lobby"}}}});
+        let chat = json!({"Request": {"action": {"SendChatMessage": {"message": "hi"}}}});
+        let leave = json!({"Request": {"action": "LeaveRoom"}});
+        assert!(!filter.matches(client, addr(SERVER), &chat));
+        assert!(filter.matches(client, addr(SERVER), &join));
+        assert!(filter.matches(client, addr(SERVER), &chat));
+        assert!(filter.matches(client, addr(SERVER), &leave));
+        assert!(!filter.matches(client, addr(SERVER), &chat));
+    }
+
+    #[test]
+    fn strings_are_found_at_any_depth() {
+        let value = json!({"a": {"cookie": "x", "nested": [{"cookie": "y"}, {"cookie": 3}]}});
+        let mut found = vec![];
+        find_strings(&value, "cookie", &mut found);
+        assert_eq!(found, ["x", "y"]);
+    }
+
+    #[test]
+    fn client_end_is_not_the_server() {
+        let (client, server) = (addr("10.0.0.1:5000"), addr(SERVER));
+        let ports = Ports::from(2016);
+        assert_eq!(client_end(client, server, &ports), client);
+        assert_eq!(client_end(server, client, &ports), client);
+    }
+}
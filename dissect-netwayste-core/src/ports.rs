@@ -0,0 +1,94 @@
+//! The ports netwayste servers listen on, of which there may be several, e.g. a few instances of
+//! a dev server on adjacent ports.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ports(Vec<RangeInclusive<u16>>);
+
+impl Ports {
+    /// Parses a single port, e.g. `2016`, or a range, e.g. `2016-2020`. Meant to be used as a
+    /// clap `value_parser`; `collect()` combines the values of a repeated option.
+    pub fn parse(s: &str) -> Result<Ports, String> {
+        let port = |s: &str| {
+            s.trim()
+                .parse::<u16>()
+                .map_err(|e| format!("'{}' is not a port: {}", s, e))
+        };
+        let range = match s.split_once('-') {
+            Some((first, last)) => port(first)?..=port(last)?,
+            None => {
+                let port = port(s)?;
+                port..=port
+            }
+        };
+        if range.is_empty() {
+            return Err(format!("'{}' is an empty range", s));
+        }
+        Ok(Ports(vec![range]))
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.iter().any(|range| range.contains(&port))
+    }
+
+    pub fn ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.0
+    }
+}
+
+impl From<u16> for Ports {
+    fn from(port: u16) -> Self {
+        Ports(vec![port..=port])
+    }
+}
+
+impl FromIterator<Ports> for Ports {
+    fn from_iter<I: IntoIterator<Item = Ports>>(iter: I) -> Self {
+        Ports(iter.into_iter().flat_map(|ports| ports.0).collect())
+    }
+}
+
+/// As `parse()` takes them, separated by commas.
+impl fmt::Display for Ports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_ports_and_ranges() {
+        assert_eq!(Ports::parse("2016"), Ok(Ports::from(2016)));
+        assert_eq!(Ports::parse("2016-2020"), Ok(Ports(vec![2016..=2020])));
+        assert!(Ports::parse("2020-2016").is_err());
+        assert!(Ports::parse("http").is_err());
+        assert!(Ports::parse("2016-").is_err());
+    }
+
+    #[test]
+    fn repeated_ports_combine() {
+        let ports: Ports = ["2016", "3000-3002"]
+            .into_iter()
+            .map(|s| Ports::parse(s).unwrap())
+            .collect();
+        assert!(ports.contains(2016));
+        assert!(ports.contains(3001));
+        assert!(!ports.contains(2017));
+        assert_eq!(ports.to_string(), "2016,3000-3002");
+    }
+}
@@ -0,0 +1,81 @@
+//! The netwayste protocol versions a payload can be decoded as.
+//!
+//! Everything after decoding works with netwaystev2's `Packet`, so older versions are translated
+//! into it field by field through serde. The original protocol is close enough for that to work
+//! for everything but the few messages v2 dropped, which fail to decode with an explanation.
+
+use bincode::{deserialize, ErrorKind};
+use clap::ValueEnum;
+use netwaystev2::protocol::Packet;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The original netwayste crate, spoken by old servers
+    V1,
+    /// netwaystev2
+    V2,
+    /// Whichever decodes, trying the newest first
+    Auto,
+}
+
+impl Protocol {
+    pub fn decoder(self) -> Box<dyn Decoder> {
+        match self {
+            Protocol::V1 => Box::new(V1),
+            Protocol::V2 => Box::new(V2),
+            Protocol::Auto => Box::new(Auto(vec![Box::new(V2), Box::new(V1)])),
+        }
+    }
+}
+
+/// Decodes one protocol version. A new version needs only an implementation of this and a
+/// `Protocol` variant.
+pub trait Decoder: Send + Sync {
+    fn decode(&self, payload: &[u8]) -> bincode::Result<Packet>;
+}
+
+struct V2;
+
+impl Decoder for V2 {
+    fn decode(&self, payload: &[u8]) -> bincode::Result<Packet> {
+        deserialize(payload)
+    }
+}
+
+struct V1;
+
+impl Decoder for V1 {
+    fn decode(&self, payload: &[u8]) -> bincode::Result<Packet> {
+        let packet: netwayste::net::Packet = deserialize(payload)?;
+        let value = serde_json::to_value(&packet).map_err(|e| {
+            Box::new(ErrorKind::Custom(format!(
+                "v1 packet can't be converted: {}",
+                e
+            )))
+        })?;
+        serde_json::from_value(value).map_err(|e| {
+            Box::new(ErrorKind::Custom(format!(
+                "v1 packet has no v2 equivalent: {}",
+                e
+            )))
+        })
+    }
+}
+
+/// Tries each decoder in turn, reporting the first one's error if none of them work.
+struct Auto(Vec<Box<dyn Decoder>>);
+
+impl Decoder for Auto {
+    fn decode(&self, payload: &[u8]) -> bincode::Result<Packet> {
+        let mut first_error = None;
+        for decoder in &self.0 {
+            match decoder.decode(payload) {
+                Ok(packet) => return Ok(packet),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.expect("no decoders to try"))
+    }
+}
@@ -0,0 +1,227 @@
+//! Spots lost, retransmitted and reordered packets from the sequence numbers netwayste packets
+//! carry.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A run of sequence numbers that never showed up.
+#[derive(Debug)]
+pub struct Gap {
+    /// The first and last missing numbers
+    pub first: u64,
+    pub last: u64,
+}
+
+impl Gap {
+    pub fn missing(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+/// Flows with no packets for this long, in capture time, are forgotten, so a long capture of
+/// players coming and going doesn't keep every one of them. A flow that picks up again after
+/// this starts over, with no gap reported.
+const FLOW_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Remembers the highest sequence number seen on each flow, i.e. each direction of each
+/// conversation, since client and server number their packets independently.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    /// The highest number and when the flow was last seen
+    highest: HashMap<(SocketAddr, SocketAddr), (u64, Duration)>,
+}
+
+impl SequenceTracker {
+    /// Returns the gap between the highest number seen so far on the flow and `seq`, if any.
+    /// Repeated and older numbers are retransmissions or reordering, not loss, so they never
+    /// make a gap.
+    pub fn observe(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u64,
+        ts: Duration,
+    ) -> Option<Gap> {
+        self.highest
+            .retain(|_, (_, last_seen)| ts.saturating_sub(*last_seen) < FLOW_TIMEOUT);
+        let (highest, last_seen) = self.highest.entry((src, dst)).or_insert((seq, ts));
+        *last_seen = ts;
+        if seq <= *highest {
+            return None;
+        }
+        let gap = (seq > *highest + 1).then(|| Gap {
+            first: *highest + 1,
+            last: seq - 1,
+        });
+        *highest = seq;
+        gap
+    }
+}
+
+/// How many recent sequence numbers to remember per flow when looking for retransmissions.
+const RECENT_SEQUENCES: usize = 256;
+
+/// Remembers when each of the last few sequence numbers on each flow was first seen.
+#[derive(Debug, Default)]
+pub struct RetransmitTracker {
+    recent: HashMap<(SocketAddr, SocketAddr), VecDeque<(u64, Duration)>>,
+}
+
+impl RetransmitTracker {
+    /// Returns how long ago `seq` was first seen on the flow, if this is a retransmission of it.
+    pub fn observe(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u64,
+        ts: Duration,
+    ) -> Option<Duration> {
+        // Only new numbers are pushed, so a flow sending nothing but copies goes too
+        self.recent.retain(|_, recent| {
+            recent
+                .back()
+                .is_some_and(|(_, first_seen)| ts.saturating_sub(*first_seen) < FLOW_TIMEOUT)
+        });
+        let recent = self.recent.entry((src, dst)).or_default();
+        if let Some((_, first_seen)) = recent.iter().find(|(recent_seq, _)| *recent_seq == seq) {
+            return Some(ts.checked_sub(*first_seen).unwrap_or_default());
+        }
+        if recent.len() == RECENT_SEQUENCES {
+            recent.pop_front();
+        }
+        recent.push_back((seq, ts));
+        None
+    }
+}
+
+#[derive(Debug)]
+struct ReorderFlow {
+    highest: u64,
+    /// Recently seen numbers, so a retransmission isn't mistaken for a late arrival
+    recent: VecDeque<u64>,
+    last_seen: Duration,
+}
+
+/// Spots packets overtaken by later ones on the way.
+#[derive(Debug, Default)]
+pub struct ReorderTracker {
+    flows: HashMap<(SocketAddr, SocketAddr), ReorderFlow>,
+}
+
+impl ReorderTracker {
+    /// Returns how far behind the highest number seen on the flow `seq` is, if it's lower and
+    /// hasn't been seen before.
+    pub fn observe(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u64,
+        ts: Duration,
+    ) -> Option<u64> {
+        self.flows
+            .retain(|_, flow| ts.saturating_sub(flow.last_seen) < FLOW_TIMEOUT);
+        let flow = self.flows.entry((src, dst)).or_insert_with(|| ReorderFlow {
+            highest: seq,
+            recent: VecDeque::new(),
+            last_seen: ts,
+        });
+        flow.last_seen = ts;
+        if flow.recent.contains(&seq) {
+            return None;
+        }
+        if flow.recent.len() == RECENT_SEQUENCES {
+            flow.recent.pop_front();
+        }
+        flow.recent.push_back(seq);
+
+        if seq < flow.highest {
+            return Some(flow.highest - seq);
+        }
+        flow.highest = seq;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow() -> (SocketAddr, SocketAddr) {
+        (
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:2016".parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn skipped_numbers_make_a_gap() {
+        let (a, b) = flow();
+        let ts = Duration::ZERO;
+        let mut tracker = SequenceTracker::default();
+        assert!(tracker.observe(a, b, 1, ts).is_none());
+        assert!(tracker.observe(a, b, 2, ts).is_none());
+        let gap = tracker.observe(a, b, 6, ts).unwrap();
+        assert_eq!((gap.first, gap.last, gap.missing()), (3, 5, 3));
+        // Late arrivals and the other direction don't
+        assert!(tracker.observe(a, b, 4, ts).is_none());
+        assert!(tracker.observe(b, a, 100, ts).is_none());
+    }
+
+    #[test]
+    fn idle_flows_start_over() {
+        let (a, b) = flow();
+        let mut tracker = SequenceTracker::default();
+        assert!(tracker.observe(a, b, 1, Duration::ZERO).is_none());
+        assert!(tracker.observe(b, a, 1, FLOW_TIMEOUT).is_none());
+        assert_eq!(tracker.highest.len(), 1);
+        assert!(tracker.observe(a, b, 50, FLOW_TIMEOUT).is_none());
+    }
+
+    #[test]
+    fn retransmissions_are_timed_from_the_first_copy() {
+        let (a, b) = flow();
+        let mut tracker = RetransmitTracker::default();
+        assert_eq!(tracker.observe(a, b, 1, Duration::from_millis(100)), None);
+        assert_eq!(
+            tracker.observe(a, b, 1, Duration::from_millis(350)),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            tracker.observe(a, b, 1, Duration::from_millis(600)),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn idle_flows_forget_their_numbers() {
+        let (a, b) = flow();
+        let mut tracker = RetransmitTracker::default();
+        assert_eq!(tracker.observe(a, b, 1, Duration::ZERO), None);
+        assert_eq!(tracker.observe(b, a, 1, FLOW_TIMEOUT), None);
+        assert_eq!(tracker.recent.len(), 1);
+        assert_eq!(tracker.observe(a, b, 1, FLOW_TIMEOUT), None);
+    }
+
+    #[test]
+    fn late_arrivals_are_reordered_but_copies_are_not() {
+        let (a, b) = flow();
+        let ts = Duration::ZERO;
+        let mut tracker = ReorderTracker::default();
+        assert_eq!(tracker.observe(a, b, 1, ts), None);
+        assert_eq!(tracker.observe(a, b, 4, ts), None);
+        assert_eq!(tracker.observe(a, b, 2, ts), Some(2));
+        assert_eq!(tracker.observe(a, b, 2, ts), None);
+        assert_eq!(tracker.observe(a, b, 4, ts), None);
+    }
+
+    #[test]
+    fn idle_flows_are_not_reordered() {
+        let (a, b) = flow();
+        let mut tracker = ReorderTracker::default();
+        assert_eq!(tracker.observe(a, b, 10, Duration::ZERO), None);
+        assert_eq!(tracker.observe(b, a, 1, FLOW_TIMEOUT), None);
+        assert_eq!(tracker.flows.len(), 1);
+        assert_eq!(tracker.observe(a, b, 2, FLOW_TIMEOUT), None);
+    }
+}
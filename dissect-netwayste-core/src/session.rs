@@ -0,0 +1,213 @@
+//! Each client's session as a small state machine, so a capture reads as a story of who
+//! connected, joined what and left when, rather than a pile of packets.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+use serde_json::Value;
+
+use crate::output::{nested_variant, variant_name};
+use crate::packet_filter::{client_end, find_strings};
+use crate::ports::Ports;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionState {
+    /// Sent `Connect`, not yet logged in
+    Connecting,
+    Connected,
+    InRoom(String),
+    /// In a room that's exchanging game updates
+    InGame(String),
+    /// Sent `Disconnect`
+    Disconnecting,
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionState::Connecting => write!(f, "connecting"),
+            SessionState::Connected => write!(f, "connected"),
+            SessionState::InRoom(room) => write!(f, "in room '{}'", room),
+            SessionState::InGame(room) => write!(f, "in game in room '{}'", room),
+            SessionState::Disconnecting => write!(f, "disconnecting"),
+        }
+    }
+}
+
+/// A client moving from one state to another. `from` is `None` for a client seen for the
+/// first time.
+pub struct Transition {
+    pub client: SocketAddr,
+    pub from: Option<SessionState>,
+    pub to: SessionState,
+}
+
+impl Transition {
+    /// What happened, from the player's point of view.
+    pub fn event(&self) -> String {
+        match (&self.from, &self.to) {
+            (_, SessionState::Connecting) => "connected".to_owned(),
+            (Some(SessionState::InRoom(room)), SessionState::Connected)
+            | (Some(SessionState::InGame(room)), SessionState::Connected) => {
+                format!("left room '{}'", room)
+            }
+            (_, SessionState::Connected) => "authenticated".to_owned(),
+            (_, SessionState::InRoom(room)) => format!("joined room '{}'", room),
+            (_, SessionState::InGame(room)) => format!("game started in room '{}'", room),
+            (_, SessionState::Disconnecting) => "disconnected".to_owned(),
+        }
+    }
+}
+
+pub struct SessionTracker {
+    server_ports: Ports,
+    states: HashMap<SocketAddr, SessionState>,
+    /// Room each client last asked to join, until the server confirms it
+    joining: HashMap<SocketAddr, String>,
+    /// The names clients connected with
+    names: HashMap<SocketAddr, String>,
+}
+
+impl SessionTracker {
+    pub fn new(server_ports: impl Into<Ports>) -> Self {
+        SessionTracker {
+            server_ports: server_ports.into(),
+            states: HashMap::new(),
+            joining: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Where `client` is up to, if any of its session has been seen.
+    pub fn state(&self, client: SocketAddr) -> Option<&SessionState> {
+        self.states.get(&client)
+    }
+
+    /// The name `client` connected with, if its `Connect` was seen.
+    pub fn name(&self, client: SocketAddr) -> Option<&str> {
+        self.names.get(&client).map(String::as_str)
+    }
+
+    /// Call with every decoded packet, in capture order.
+    pub fn observe(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+    ) -> Option<Transition> {
+        let client = client_end(src, dst, &self.server_ports);
+        let current = self.states.get(&client).cloned();
+
+        let next = match (
+            variant_name(packet).as_str(),
+            nested_variant(packet, "action"),
+            nested_variant(packet, "code"),
+        ) {
+            ("Request", Some(action), _) => match action.as_str() {
+                "Connect" => {
+                    let mut names = vec![];
+                    find_strings(packet, "name", &mut names);
+                    if let Some(name) = names.first() {
+                        self.names.insert(client, name.to_string());
+                    }
+                    Some(SessionState::Connecting)
+                }
+                "JoinRoom" => {
+                    let mut names = vec![];
+                    find_strings(packet, "room_name", &mut names);
+                    if let Some(name) = names.first() {
+                        self.joining.insert(client, name.to_string());
+                    }
+                    None
+                }
+                "LeaveRoom" => Some(SessionState::Connected),
+                "Disconnect" => Some(SessionState::Disconnecting),
+                _ => None,
+            },
+            ("Response", _, Some(code)) => match code.as_str() {
+                "LoggedIn" => Some(SessionState::Connected),
+                "JoinedRoom" => {
+                    let mut names = vec![];
+                    find_strings(packet, "room_name", &mut names);
+                    let name = names
+                        .first()
+                        .map(|name| name.to_string())
+                        .or_else(|| self.joining.remove(&client))
+                        .unwrap_or_default();
+                    Some(SessionState::InRoom(name))
+                }
+                _ => None,
+            },
+            // Game updates only flow once a game is under way
+            ("Update", _, _) | ("UpdateReply", _, _) => match &current {
+                Some(SessionState::InRoom(room)) => Some(SessionState::InGame(room.clone())),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let next = next.filter(|next| current.as_ref() != Some(next))?;
+        if next == SessionState::Disconnecting {
+            self.joining.remove(&client);
+        }
+        self.states.insert(client, next.clone());
+        Some(Transition {
+            client,
+            from: current,
+            to: next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn a_session_from_connect_to_disconnect() {
+        let client: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let server: SocketAddr = "10.0.0.2:2016".parse().unwrap();
+        let mut sessions = SessionTracker::new(2016);
+        let mut events = vec![];
+        let mut see = |src, dst, packet: Value| {
+            if let Some(transition) = sessions.observe(src, dst, &packet) {
+                events.push(transition.event());
+            }
+        };
+
+        see(
+            client,
+            server,
+            json!({"Request": {"action": {"Connect": {"name": "alice"}}}}),
+        );
+        see(server, client, json!({"Response": {"code": "LoggedIn"}}));
+        see(
+            client,
+            server,
+            json!({"Request": {"action": {"JoinRoom": {"room_name": "lobby"}}}}),
+        );
+        // The room is remembered from the request when the response doesn't name it
+        see(server, client, json!({"Response": {"code": "JoinedRoom"}}));
+        see(server, client, json!({"Update": {}}));
+        see(server, client, json!({"Update": {}}));
+        see(client, server, json!({"Request": {"action": "LeaveRoom"}}));
+        see(client, server, json!({"Request": {"action": "Disconnect"}}));
+
+        assert_eq!(
+            events,
+            [
+                "connected",
+                "authenticated",
+                "joined room 'lobby'",
+                "game started in room 'lobby'",
+                "left room 'lobby'",
+                "disconnected",
+            ]
+        );
+        assert_eq!(sessions.name(client), Some("alice"));
+        assert_eq!(sessions.state(client), Some(&SessionState::Disconnecting));
+    }
+}
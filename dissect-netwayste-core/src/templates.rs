@@ -0,0 +1,375 @@
+//! Netwayste packets built from the layout serde-reflection records of `Packet`, for tools that
+//! send packets rather than read them: load generators, fuzzers, probes and mock servers.
+//!
+//! Building packets from the layout rather than writing them out by hand means any `Packet`
+//! variant, request action or response code can be asked for by name, and the tools keep up
+//! with the protocol. Every field starts out as its zero value: 0, an empty string, `None`, an
+//! empty list, or an enum's first variant. Callers then set the fields they care about by name
+//! with `set_field()`, and turn the result into a `Packet` with `serde_json::from_value()`.
+
+use netwaystev2::protocol::Packet;
+use serde_json::{Map, Value};
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Tracer, TracerConfig, VariantFormat,
+};
+
+/// Each pass explores more variants of enums nested inside `Packet`, so keep going until
+/// they've all been seen. Far more than the protocol needs today.
+const TRACE_PASSES: usize = 64;
+
+/// How deep `template_with()` looks for a place to put what it's given, in case a type
+/// contains itself.
+const MAX_DEPTH: usize = 16;
+
+/// The layout of `Packet` and every type inside it.
+pub fn trace_packet() -> Result<Registry, String> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    for _ in 0..TRACE_PASSES {
+        tracer
+            .trace_simple_type::<Packet>()
+            .map_err(|e| format!("failed to trace netwayste Packet: {}", e))?;
+    }
+    tracer
+        .registry()
+        .map_err(|e| format!("netwayste Packet couldn't be fully traced: {}", e))
+}
+
+/// A `Packet` that is the variant `name`, or has a field that is the variant `name`, such as a
+/// request with that action or a response with that code, with every field zero.
+pub fn template(registry: &Registry, name: &str) -> Result<Value, String> {
+    Zero {
+        registry,
+        nested: None,
+    }
+    .template(name)
+}
+
+/// Like `template()`, but with `inner` as the value of the first field of type `type_name`
+/// found at any depth, wrapped in `Some` or a one-item list on the way if need be. E.g. a
+/// server update carrying one chat message.
+pub fn template_with(
+    registry: &Registry,
+    name: &str,
+    type_name: &str,
+    inner: &Value,
+) -> Result<Value, String> {
+    let zero = Zero {
+        registry,
+        nested: Some((type_name, inner)),
+    };
+    let template = zero.template(name)?;
+    if !contains(&template, inner) {
+        return Err(format!("a {} has no {} in it", name, type_name));
+    }
+    Ok(template)
+}
+
+/// A zero value of the type `type_name`.
+pub fn zero_value(registry: &Registry, type_name: &str) -> Result<Value, String> {
+    if !registry.contains_key(type_name) {
+        return Err(format!("netwayste has no type called {}", type_name));
+    }
+    Ok(Zero {
+        registry,
+        nested: None,
+    }
+    .format(&Format::TypeName(type_name.to_owned()), 0))
+}
+
+/// Everything `template()` takes.
+pub fn names(registry: &Registry) -> Vec<String> {
+    let mut names = vec![];
+    for container in registry.values() {
+        if let ContainerFormat::Enum(variants) = container {
+            names.extend(variants.values().map(|variant| variant.name.clone()));
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The name of the first struct whose fields `matches` accepts, for finding a type by what's in
+/// it rather than by a name that may change.
+pub fn find_struct(
+    registry: &Registry,
+    matches: impl Fn(&[Named<Format>]) -> bool,
+) -> Option<&str> {
+    registry
+        .iter()
+        .find_map(|(name, container)| match container {
+            ContainerFormat::Struct(fields) if matches(fields) => Some(name.as_str()),
+            _ => None,
+        })
+}
+
+/// Sets every field called `key`, at any depth, to `to`. Returns whether there were any.
+pub fn set_field(value: &mut Value, key: &str, to: &Value) -> bool {
+    let mut found = false;
+    match value {
+        Value::Object(map) => {
+            for (name, field) in map.iter_mut() {
+                if name == key {
+                    *field = to.clone();
+                    found = true;
+                } else {
+                    found |= set_field(field, key, to);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                found |= set_field(value, key, to);
+            }
+        }
+        _ => {}
+    }
+    found
+}
+
+fn contains(value: &Value, inner: &Value) -> bool {
+    value == inner
+        || match value {
+            Value::Object(map) => map.values().any(|field| contains(field, inner)),
+            Value::Array(values) => values.iter().any(|value| contains(value, inner)),
+            _ => false,
+        }
+}
+
+struct Zero<'a> {
+    registry: &'a Registry,
+    /// A type to fill in with a given value rather than zero, and everything on the way to it
+    /// with something rather than nothing
+    nested: Option<(&'a str, &'a Value)>,
+}
+
+impl Zero<'_> {
+    fn template(&self, name: &str) -> Result<Value, String> {
+        let Some(ContainerFormat::Enum(packets)) = self.registry.get("Packet") else {
+            return Err("Packet isn't an enum".to_owned());
+        };
+        if let Some(packet) = packets.values().find(|variant| variant.name == name) {
+            return Ok(self.variant(packet, 0));
+        }
+        // Otherwise look for a packet with a field that can be the variant, e.g. a request's
+        // action
+        for packet in packets.values() {
+            let VariantFormat::Struct(fields) = &packet.value else {
+                continue;
+            };
+            for field in fields {
+                let Format::TypeName(type_name) = &field.value else {
+                    continue;
+                };
+                let Some(ContainerFormat::Enum(variants)) = self.registry.get(type_name) else {
+                    continue;
+                };
+                if let Some(inner) = variants.values().find(|variant| variant.name == name) {
+                    let mut value = self.variant(packet, 0);
+                    value[&packet.name][&field.name] = self.variant(inner, 0);
+                    return Ok(value);
+                }
+            }
+        }
+        Err(format!(
+            "'{}' is neither a Packet variant nor a variant of one of their fields; try one of {}",
+            name,
+            names(self.registry).join(", ")
+        ))
+    }
+
+    fn variant(&self, variant: &Named<VariantFormat>, depth: usize) -> Value {
+        let value = match &variant.value {
+            VariantFormat::Unit | VariantFormat::Variable(_) => {
+                return Value::String(variant.name.clone())
+            }
+            VariantFormat::NewType(format) => self.format(format, depth + 1),
+            VariantFormat::Tuple(formats) => {
+                formats.iter().map(|f| self.format(f, depth + 1)).collect()
+            }
+            VariantFormat::Struct(fields) => self.fields(fields, depth + 1),
+        };
+        let mut map = Map::new();
+        map.insert(variant.name.clone(), value);
+        Value::Object(map)
+    }
+
+    fn fields(&self, fields: &[Named<Format>], depth: usize) -> Value {
+        Value::Object(
+            fields
+                .iter()
+                .map(|field| (field.name.clone(), self.format(&field.value, depth)))
+                .collect(),
+        )
+    }
+
+    fn format(&self, format: &Format, depth: usize) -> Value {
+        match format {
+            Format::TypeName(name) => {
+                if let Some((type_name, inner)) = self.nested {
+                    if name == type_name {
+                        return inner.clone();
+                    }
+                }
+                match self.registry.get(name) {
+                    Some(ContainerFormat::UnitStruct) | None => Value::Null,
+                    Some(ContainerFormat::NewTypeStruct(format)) => self.format(format, depth + 1),
+                    Some(ContainerFormat::TupleStruct(formats)) => {
+                        formats.iter().map(|f| self.format(f, depth + 1)).collect()
+                    }
+                    Some(ContainerFormat::Struct(fields)) => self.fields(fields, depth + 1),
+                    Some(ContainerFormat::Enum(variants)) => {
+                        let variant = variants
+                            .values()
+                            .find(|variant| self.variant_leads_on(variant, depth + 1))
+                            .or_else(|| variants.values().next());
+                        match variant {
+                            Some(variant) => self.variant(variant, depth + 1),
+                            None => Value::Null,
+                        }
+                    }
+                }
+            }
+            // serde_json writes `Some(x)` as just `x`
+            Format::Option(inner) if self.leads_on(inner, depth + 1) => {
+                self.format(inner, depth + 1)
+            }
+            Format::Seq(inner) if self.leads_on(inner, depth + 1) => {
+                Value::Array(vec![self.format(inner, depth + 1)])
+            }
+            Format::Unit | Format::Option(_) | Format::Variable(_) => Value::Null,
+            Format::Bool => false.into(),
+            Format::F32 | Format::F64 => 0.0.into(),
+            Format::Char => "a".into(),
+            Format::Str => "".into(),
+            Format::Bytes | Format::Seq(_) => Value::Array(vec![]),
+            Format::Map { .. } => Value::Object(Map::new()),
+            Format::Tuple(formats) => formats.iter().map(|f| self.format(f, depth + 1)).collect(),
+            Format::TupleArray { content, size } => (0..*size)
+                .map(|_| self.format(content, depth + 1))
+                .collect(),
+            // Every integer type
+            _ => 0.into(),
+        }
+    }
+
+    /// Whether `format` is, or has somewhere inside it, the type being filled in.
+    fn leads_on(&self, format: &Format, depth: usize) -> bool {
+        let Some((type_name, _)) = self.nested else {
+            return false;
+        };
+        if depth > MAX_DEPTH {
+            return false;
+        }
+        match format {
+            Format::TypeName(name) if name == type_name => true,
+            Format::TypeName(name) => match self.registry.get(name) {
+                Some(ContainerFormat::NewTypeStruct(format)) => self.leads_on(format, depth + 1),
+                Some(ContainerFormat::TupleStruct(formats)) => {
+                    formats.iter().any(|f| self.leads_on(f, depth + 1))
+                }
+                Some(ContainerFormat::Struct(fields)) => fields
+                    .iter()
+                    .any(|field| self.leads_on(&field.value, depth + 1)),
+                Some(ContainerFormat::Enum(variants)) => variants
+                    .values()
+                    .any(|variant| self.variant_leads_on(variant, depth + 1)),
+                _ => false,
+            },
+            Format::Option(inner) | Format::Seq(inner) => self.leads_on(inner, depth + 1),
+            Format::Tuple(formats) => formats.iter().any(|f| self.leads_on(f, depth + 1)),
+            Format::TupleArray { content, .. } => self.leads_on(content, depth + 1),
+            _ => false,
+        }
+    }
+
+    fn variant_leads_on(&self, variant: &Named<VariantFormat>, depth: usize) -> bool {
+        match &variant.value {
+            VariantFormat::NewType(format) => self.leads_on(format, depth),
+            VariantFormat::Tuple(formats) => formats.iter().any(|f| self.leads_on(f, depth)),
+            VariantFormat::Struct(fields) => fields
+                .iter()
+                .any(|field| self.leads_on(&field.value, depth)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Chat {
+        name: String,
+        message: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum Action {
+        KeepAlive,
+        Chat { message: String },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum Packet {
+        GetStatus,
+        Request {
+            sequence: u64,
+            action: Action,
+        },
+        Update {
+            sequence: u64,
+            chats: Option<Vec<Chat>>,
+        },
+    }
+
+    fn registry() -> Registry {
+        let mut tracer = Tracer::new(TracerConfig::default());
+        for _ in 0..TRACE_PASSES {
+            tracer.trace_simple_type::<Packet>().unwrap();
+        }
+        tracer.registry().unwrap()
+    }
+
+    #[test]
+    fn templates_are_zero() {
+        let registry = registry();
+        assert_eq!(
+            template(&registry, "GetStatus").unwrap(),
+            json!("GetStatus")
+        );
+        assert_eq!(
+            template(&registry, "Chat").unwrap(),
+            json!({"Request": {"sequence": 0, "action": {"Chat": {"message": ""}}}})
+        );
+        assert_eq!(
+            template(&registry, "Update").unwrap(),
+            json!({"Update": {"sequence": 0, "chats": null}})
+        );
+        assert!(template(&registry, "Nonsense").is_err());
+    }
+
+    #[test]
+    fn nested_values_are_put_in_place() {
+        let registry = registry();
+        let chat_type = find_struct(&registry, |fields| {
+            fields.iter().any(|field| field.name == "message")
+        })
+        .unwrap();
+        assert_eq!(chat_type, "Chat");
+
+        let mut chat = zero_value(&registry, chat_type).unwrap();
+        assert!(set_field(&mut chat, "message", &json!("hi")));
+        assert!(!set_field(&mut chat, "chat_seq", &json!(1)));
+        let update = template_with(&registry, "Update", chat_type, &chat).unwrap();
+        assert_eq!(
+            update,
+            json!({"Update": {"sequence": 0, "chats": [{"name": "", "message": "hi"}]}})
+        );
+        assert!(template_with(&registry, "GetStatus", chat_type, &chat).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Capture timestamps as shown next to each packet, and the gaps between packets.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
+
+use crate::output::format_time;
+
+/// Names accepted by `TimeFormat::parse()` besides strftime strings, for the help text.
+pub const TIME_FORMATS: &str = "rfc3339, epoch, relative";
+
+#[derive(Debug, Clone)]
+pub enum TimeFormat {
+    /// `2023-04-01T12:34:56.789012Z`
+    Rfc3339,
+    /// Seconds since the UNIX epoch, with microseconds
+    Epoch,
+    /// Seconds since the first decoded packet
+    Relative,
+    /// A chrono strftime string, in UTC
+    Custom(String),
+}
+
+impl TimeFormat {
+    /// Meant to be used as a clap `value_parser`.
+    pub fn parse(s: &str) -> Result<TimeFormat, String> {
+        Ok(match s {
+            "rfc3339" => TimeFormat::Rfc3339,
+            "epoch" => TimeFormat::Epoch,
+            "relative" => TimeFormat::Relative,
+            custom => {
+                if StrftimeItems::new(custom).any(|item| item == Item::Error) {
+                    return Err(format!(
+                        "'{}' is neither one of {} nor a valid strftime string",
+                        custom, TIME_FORMATS
+                    ));
+                }
+                TimeFormat::Custom(custom.to_owned())
+            }
+        })
+    }
+}
+
+/// How one packet's capture time relates to those before it.
+pub struct Stamp {
+    pub time: String,
+    /// Since the previous decoded packet; `None` for the first
+    pub delta: Option<Duration>,
+    /// Since the previous decoded packet with the same source and destination
+    pub flow_delta: Option<Duration>,
+}
+
+pub struct Timing {
+    format: TimeFormat,
+    first: Option<Duration>,
+    last: Option<Duration>,
+    last_by_flow: HashMap<(SocketAddr, SocketAddr), Duration>,
+}
+
+impl Timing {
+    pub fn new(format: TimeFormat) -> Self {
+        Timing {
+            format,
+            first: None,
+            last: None,
+            last_by_flow: HashMap::new(),
+        }
+    }
+
+    /// Call once per decoded packet, in the order they're shown.
+    pub fn stamp(&mut self, ts: Duration, src: SocketAddr, dst: SocketAddr) -> Stamp {
+        let first = *self.first.get_or_insert(ts);
+        let time = match &self.format {
+            TimeFormat::Rfc3339 => format_time(ts).unwrap_or_default(),
+            TimeFormat::Epoch => format!("{}.{:06}", ts.as_secs(), ts.subsec_micros()),
+            TimeFormat::Relative => format!("{:.6}", since(first, ts).as_secs_f64()),
+            TimeFormat::Custom(format) => Utc
+                .timestamp_opt(ts.as_secs() as i64, ts.subsec_nanos())
+                .single()
+                .map(|time| time.format(format).to_string())
+                .unwrap_or_default(),
+        };
+        let delta = self.last.replace(ts).map(|last| since(last, ts));
+        let flow_delta = self
+            .last_by_flow
+            .insert((src, dst), ts)
+            .map(|last| since(last, ts));
+        Stamp {
+            time,
+            delta,
+            flow_delta,
+        }
+    }
+}
+
+/// Formats a gap as `+1.234567s`, or `-` where there's nothing to measure from.
+pub fn format_delta(delta: Option<Duration>) -> String {
+    match delta {
+        Some(delta) => format!("+{:.6}s", delta.as_secs_f64()),
+        None => "-".to_owned(),
+    }
+}
+
+/// Frames merged from several interfaces can be slightly out of order; call that no gap at all.
+fn since(earlier: Duration, later: Duration) -> Duration {
+    later.checked_sub(earlier).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_formats() {
+        assert!(matches!(TimeFormat::parse("epoch"), Ok(TimeFormat::Epoch)));
+        assert!(matches!(
+            TimeFormat::parse("%H:%M:%S"),
+            Ok(TimeFormat::Custom(_))
+        ));
+        assert!(TimeFormat::parse("%H:%").is_err());
+    }
+
+    #[test]
+    fn stamps() {
+        let a: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:2016".parse().unwrap();
+        let mut timing = Timing::new(TimeFormat::Relative);
+
+        let stamp = timing.stamp(Duration::from_millis(1000), a, b);
+        assert_eq!(stamp.time, "0.000000");
+        assert_eq!((stamp.delta, stamp.flow_delta), (None, None));
+
+        let stamp = timing.stamp(Duration::from_millis(1250), b, a);
+        assert_eq!(stamp.time, "0.250000");
+        assert_eq!(stamp.delta, Some(Duration::from_millis(250)));
+        assert_eq!(stamp.flow_delta, None);
+
+        // Slightly out of order counts as no gap
+        let stamp = timing.stamp(Duration::from_millis(1200), a, b);
+        assert_eq!(stamp.delta, Some(Duration::ZERO));
+        assert_eq!(stamp.flow_delta, Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn deltas() {
+        assert_eq!(
+            format_delta(Some(Duration::from_micros(1_234_567))),
+            "+1.234567s"
+        );
+        assert_eq!(format_delta(None), "-");
+    }
+}
@@ -0,0 +1,409 @@
+//! `--alerts`: a watchdog that runs a command or calls a webhook when the traffic looks wrong.
+//!
+//! Rules are read from a file, one per line, as `<measure> <op> <threshold> => <action>`:
+//!
+//! ```text
+//! # Blank lines and lines starting with # are ignored
+//! failure_rate > 5/s => exec /usr/local/bin/page-oncall
+//! server_silence > 10s => webhook https://hooks.example.com/netwayste
+//! rtt_p95 > 250ms => exec logger "netwayste is slow"
+//! ```
+//!
+//! The measures are `failure_rate` and `packet_rate` (failed and decoded packets per second
+//! over the last few seconds), `server_silence` (time since the server last sent anything) and
+//! `rtt_p95` (over round trips between clients and the server in the last few seconds, so it has
+//! no value while there are none). The op is `>` or `<`. Rules are checked once a second; a
+//! rule fires when it becomes true, and can fire again once it's been false.
+//!
+//! `exec` runs its command with `sh -c`, with `ALERT_RULE` and `ALERT_VALUE` set. `webhook`
+//! POSTs `{"rule": ..., "value": ...}` as JSON.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::ports::Ports;
+use netwaystev2::protocol::Packet;
+use serde_json::{json, Value};
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// How far back rates and round trip times are measured over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Measure {
+    FailureRate,
+    PacketRate,
+    ServerSilence,
+    RttP95,
+}
+
+impl Measure {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "failure_rate" => Ok(Measure::FailureRate),
+            "packet_rate" => Ok(Measure::PacketRate),
+            "server_silence" => Ok(Measure::ServerSilence),
+            "rtt_p95" => Ok(Measure::RttP95),
+            _ => Err(format!("unknown measure '{}'", name)),
+        }
+    }
+
+    fn is_rate(self) -> bool {
+        matches!(self, Measure::FailureRate | Measure::PacketRate)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Exec(String),
+    Webhook(String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// As written in the file, for logs and actions
+    text: String,
+    measure: Measure,
+    above: bool,
+    /// Per second for rates, seconds for durations
+    threshold: f64,
+    action: Action,
+    firing: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Self, String> {
+        let (condition, action) = line
+            .split_once("=>")
+            .ok_or("expected '<measure> <op> <threshold> => <action>'")?;
+        let words: Vec<&str> = condition.split_whitespace().collect();
+        let [measure, op, threshold] = words[..] else {
+            return Err("expected '<measure> <op> <threshold>' before '=>'".to_owned());
+        };
+        let measure = Measure::parse(measure)?;
+        let above = match op {
+            ">" => true,
+            "<" => false,
+            _ => return Err(format!("unknown comparison '{}', expected '>' or '<'", op)),
+        };
+        let threshold = if measure.is_rate() {
+            threshold
+                .trim_end_matches("/s")
+                .parse::<f64>()
+                .map_err(|e| format!("bad rate '{}': {}", threshold, e))?
+        } else {
+            humantime::parse_duration(threshold)
+                .map_err(|e| format!("bad duration '{}': {}", threshold, e))?
+                .as_secs_f64()
+        };
+        let action = match action.trim().split_once(char::is_whitespace) {
+            Some(("exec", command)) => Action::Exec(command.trim().to_owned()),
+            Some(("webhook", url)) => Action::Webhook(url.trim().to_owned()),
+            _ => return Err("expected 'exec <command>' or 'webhook <url>' after '=>'".to_owned()),
+        };
+        Ok(Rule {
+            text: condition.trim().to_owned(),
+            measure,
+            above,
+            threshold,
+            action,
+            firing: false,
+        })
+    }
+}
+
+pub struct Alerts {
+    server_ports: Ports,
+    rules: Vec<Rule>,
+    failures: VecDeque<Instant>,
+    decoded: VecDeque<Instant>,
+    /// When the server last sent anything, or when we started
+    server_heard: Instant,
+    /// Round trips, with when they completed
+    rtts: VecDeque<(Instant, Duration)>,
+}
+
+impl Alerts {
+    /// Reads the rules file, failing with the line at fault.
+    pub fn load(path: &Path, server_ports: Ports) -> Result<Self, String> {
+        Alerts::parse(
+            &fs::read_to_string(path).map_err(|e| e.to_string())?,
+            server_ports,
+        )
+    }
+
+    /// Reads the contents of a rules file.
+    pub fn parse(text: &str, server_ports: Ports) -> Result<Self, String> {
+        let mut rules = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(Rule::parse(line).map_err(|e| format!("line {}: {}", i + 1, e))?);
+        }
+        Ok(Alerts {
+            server_ports,
+            rules,
+            failures: VecDeque::new(),
+            decoded: VecDeque::new(),
+            server_heard: Instant::now(),
+            rtts: VecDeque::new(),
+        })
+    }
+
+    /// Call with every datagram on the netwayste port.
+    pub fn record(&mut self, src: SocketAddr, decoded: bool) {
+        let now = Instant::now();
+        if self.server_ports.contains(src.port()) {
+            self.server_heard = now;
+        }
+        if decoded {
+            self.decoded.push_back(now);
+        } else {
+            self.failures.push_back(now);
+        }
+    }
+
+    pub fn rtt(&mut self, rtt: Duration) {
+        self.rtts.push_back((Instant::now(), rtt));
+    }
+
+    /// Checks the rules once a second from a thread of its own.
+    pub fn watch(alerts: Arc<Mutex<Alerts>>) {
+        thread::spawn(move || loop {
+            thread::sleep(CHECK_INTERVAL);
+            alerts.lock().unwrap().check();
+        });
+    }
+
+    fn check(&mut self) {
+        for (i, shown) in self.update() {
+            fire(&self.rules[i], &shown);
+        }
+    }
+
+    /// Brings every rule up to date with the traffic, returning the ones that have just become
+    /// true, by index, with the value that set them off.
+    fn update(&mut self) -> Vec<(usize, String)> {
+        for times in [&mut self.failures, &mut self.decoded] {
+            while times
+                .front()
+                .map_or(false, |time| time.elapsed() > RATE_WINDOW)
+            {
+                times.pop_front();
+            }
+        }
+        while self
+            .rtts
+            .front()
+            .map_or(false, |(time, _)| time.elapsed() > RATE_WINDOW)
+        {
+            self.rtts.pop_front();
+        }
+        let window = RATE_WINDOW.as_secs_f64();
+        let mut fired = vec![];
+        for i in 0..self.rules.len() {
+            let value = match self.rules[i].measure {
+                Measure::FailureRate => Some(self.failures.len() as f64 / window),
+                Measure::PacketRate => Some(self.decoded.len() as f64 / window),
+                Measure::ServerSilence => Some(self.server_heard.elapsed().as_secs_f64()),
+                Measure::RttP95 => self.rtt_p95().map(|p95| p95.as_secs_f64()),
+            };
+            let rule = &mut self.rules[i];
+            let breached = value.map_or(false, |value| {
+                if rule.above {
+                    value > rule.threshold
+                } else {
+                    value < rule.threshold
+                }
+            });
+            if breached && !rule.firing {
+                let value = value.unwrap();
+                let shown = if rule.measure.is_rate() {
+                    format!("{:.1}/s", value)
+                } else {
+                    format!("{:.3?}", Duration::from_secs_f64(value))
+                };
+                warn!("Alert: {} (now {})", rule.text, shown);
+                fired.push((i, shown));
+            } else if !breached && rule.firing {
+                info!("Alert cleared: {}", rule.text);
+            }
+            rule.firing = breached;
+        }
+        fired
+    }
+
+    /// Over the round trips in the window, if there were any.
+    fn rtt_p95(&self) -> Option<Duration> {
+        let mut sorted: Vec<Duration> = self.rtts.iter().map(|(_, rtt)| *rtt).collect();
+        sorted.sort();
+        let index = (sorted.len() * 95).div_ceil(100).checked_sub(1)?;
+        Some(sorted[index])
+    }
+}
+
+/// Shared with the watch thread and `--rtt`'s analyzer.
+impl Analyzer for Arc<Mutex<Alerts>> {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, _value: &Value) {
+        self.lock().unwrap().record(context.src, true);
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        self.lock().unwrap().record(context.src, false);
+    }
+}
+
+/// Runs the rule's action in the background, so a slow command or webhook can't stall checks.
+fn fire(rule: &Rule, value: &str) {
+    let (text, value, action) = (rule.text.clone(), value.to_owned(), rule.action.clone());
+    thread::spawn(move || {
+        let result = match &action {
+            Action::Exec(command) => Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("ALERT_RULE", &text)
+                .env("ALERT_VALUE", &value)
+                .status()
+                .map_err(|e| e.to_string())
+                .and_then(|status| {
+                    if status.success() {
+                        Ok(())
+                    } else {
+                        Err(format!("exited with {}", status))
+                    }
+                }),
+            Action::Webhook(url) => ureq::post(url)
+                .timeout(Duration::from_secs(10))
+                .send_json(json!({ "rule": text, "value": value }))
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            error!("Alert action for '{}' failed: '{}'", text, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alerts(text: &str) -> Alerts {
+        Alerts::parse(text, Ports::from(2016)).unwrap()
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let alerts = alerts(
+            "# comment\n\
+             \n\
+             failure_rate > 5/s => exec /usr/local/bin/page-oncall\n\
+             packet_rate < 0.5 => webhook https://hooks.example.com/netwayste\n\
+             server_silence > 10s => exec logger 'quiet'\n\
+             rtt_p95 > 250ms => webhook http://localhost/\n",
+        );
+        let rules = &alerts.rules;
+        assert_eq!(rules.len(), 4);
+
+        assert_eq!(rules[0].measure, Measure::FailureRate);
+        assert!(rules[0].above);
+        assert_eq!(rules[0].threshold, 5.0);
+        assert_eq!(rules[0].text, "failure_rate > 5/s");
+        assert!(
+            matches!(&rules[0].action, Action::Exec(command) if command == "/usr/local/bin/page-oncall")
+        );
+
+        assert_eq!(rules[1].measure, Measure::PacketRate);
+        assert!(!rules[1].above);
+        assert_eq!(rules[1].threshold, 0.5);
+        assert!(
+            matches!(&rules[1].action, Action::Webhook(url) if url == "https://hooks.example.com/netwayste")
+        );
+
+        assert_eq!(rules[2].measure, Measure::ServerSilence);
+        assert_eq!(rules[2].threshold, 10.0);
+        assert!(matches!(&rules[2].action, Action::Exec(command) if command == "logger 'quiet'"));
+
+        assert_eq!(rules[3].measure, Measure::RttP95);
+        assert_eq!(rules[3].threshold, 0.25);
+    }
+
+    #[test]
+    fn bad_rules_are_rejected_with_their_line() {
+        let error = |line: &str| {
+            Alerts::parse(&format!("# comment\n{}", line), Ports::from(2016))
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            error("failure_rate > 5/s"),
+            "line 2: expected '<measure> <op> <threshold> => <action>'"
+        );
+        assert_eq!(
+            error("failure_rate > => exec true"),
+            "line 2: expected '<measure> <op> <threshold>' before '=>'"
+        );
+        assert_eq!(
+            error("loss_rate > 5/s => exec true"),
+            "line 2: unknown measure 'loss_rate'"
+        );
+        assert_eq!(
+            error("failure_rate >= 5/s => exec true"),
+            "line 2: unknown comparison '>=', expected '>' or '<'"
+        );
+        assert!(error("failure_rate > lots => exec true").starts_with("line 2: bad rate 'lots'"));
+        assert!(error("server_silence > 5 => exec true").starts_with("line 2: bad duration '5'"));
+        assert_eq!(
+            error("failure_rate > 5/s => page someone"),
+            "line 2: expected 'exec <command>' or 'webhook <url>' after '=>'"
+        );
+        assert_eq!(
+            error("failure_rate > 5/s => exec"),
+            "line 2: expected 'exec <command>' or 'webhook <url>' after '=>'"
+        );
+    }
+
+    #[test]
+    fn rules_fire_once_until_cleared() {
+        let mut alerts = alerts("packet_rate < 1/s => exec true");
+        let server: SocketAddr = "10.0.0.1:2016".parse().unwrap();
+
+        // Nothing yet, so the rate is under the threshold
+        let fired = alerts.update();
+        assert_eq!(fired, [(0, "0.0/s".to_owned())]);
+        assert!(alerts.update().is_empty());
+        assert!(alerts.rules[0].firing);
+
+        for _ in 0..10 {
+            alerts.record(server, true);
+        }
+        assert!(alerts.update().is_empty());
+        assert!(!alerts.rules[0].firing);
+
+        // And fires again once it's true again
+        alerts.decoded.clear();
+        assert_eq!(alerts.update().len(), 1);
+    }
+
+    #[test]
+    fn rtt_p95_covers_the_window() {
+        let mut alerts = alerts("");
+        assert_eq!(alerts.rtt_p95(), None);
+        for ms in 1..=20 {
+            alerts.rtt(Duration::from_millis(ms));
+        }
+        assert_eq!(alerts.rtt_p95(), Some(Duration::from_millis(19)));
+    }
+}
@@ -0,0 +1,139 @@
+//! Analyses that watch the decoded traffic alongside the packet output, each switched on by its
+//! own option. A new one needs only an implementation of `Analyzer` and a line registering it in
+//! `main()`, rather than another branch through the main loop.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dissect_netwayste_core::timing::format_delta;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+
+use crate::theme::Class;
+
+/// How often `Analyzer::on_tick()` is called.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Where and when a payload was seen.
+pub struct Context<'a> {
+    pub ts: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// The interface it was captured on, if the capture says
+    pub interface: Option<&'a str>,
+    /// The payload as captured, before any decompression
+    pub payload: &'a [u8],
+    /// Whether the packet passed the filters; analyzers that report on individual packets
+    /// should only report on these, but anything counting should count them all. Not yet
+    /// settled when marking, since marks can filter packets out.
+    pub shown: bool,
+}
+
+/// Something an analyzer noticed about a packet, added to the end of its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    Duplicate,
+    /// How far behind the highest sequence number on the flow it was
+    OutOfOrder(u64),
+    /// How long after the original
+    Retransmit(Duration),
+}
+
+impl Mark {
+    /// How `--color` shows a packet with this mark.
+    pub fn class(self) -> Class {
+        match self {
+            Mark::Duplicate => Class::Duplicate,
+            Mark::OutOfOrder(_) => Class::OutOfOrder,
+            Mark::Retransmit(_) => Class::Retransmit,
+        }
+    }
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mark::Duplicate => write!(f, "[duplicate]"),
+            Mark::OutOfOrder(behind) => write!(f, "[out of order, {} behind]", behind),
+            Mark::Retransmit(after) => {
+                write!(
+                    f,
+                    "[retransmit, {} after original]",
+                    format_delta(Some(*after))
+                )
+            }
+        }
+    }
+}
+
+pub trait Analyzer: Send {
+    /// Called with every payload before the filters, with the packet as returned by
+    /// `output::packet_value()` if it decoded. Returns what to mark its line with, if anything.
+    fn mark(&mut self, _context: &Context, _value: Option<&Value>) -> Option<Mark> {
+        None
+    }
+
+    /// Called with every decoded packet, in capture order. `value` is the packet as returned by
+    /// `output::packet_value()`.
+    fn on_packet(&mut self, _context: &Context, _packet: &Packet, _value: &Value) {}
+
+    /// Called with every payload that failed to de-serialize.
+    fn on_error(&mut self, _context: &Context, _error: &bincode::Error) {}
+
+    /// Called about once a second, from a thread of its own, however quiet the capture.
+    fn on_tick(&mut self) {}
+
+    /// Called once the capture has ended, however it ended.
+    fn on_shutdown(&mut self) {}
+}
+
+/// Every analyzer the options asked for.
+#[derive(Default)]
+pub struct Analyzers(Vec<Box<dyn Analyzer>>);
+
+impl Analyzers {
+    pub fn register(&mut self, analyzer: impl Analyzer + 'static) {
+        self.0.push(Box::new(analyzer));
+    }
+
+    /// Every analyzer's mark for the payload, in the order they were registered.
+    pub fn mark(&mut self, context: &Context, value: Option<&Value>) -> Vec<Mark> {
+        self.0
+            .iter_mut()
+            .filter_map(|analyzer| analyzer.mark(context, value))
+            .collect()
+    }
+
+    pub fn packet(&mut self, context: &Context, packet: &Packet, value: &Value) {
+        for analyzer in &mut self.0 {
+            analyzer.on_packet(context, packet, value);
+        }
+    }
+
+    pub fn error(&mut self, context: &Context, error: &bincode::Error) {
+        for analyzer in &mut self.0 {
+            analyzer.on_error(context, error);
+        }
+    }
+
+    /// Starts calling `on_tick()` on each analyzer in `analyzers`.
+    pub fn tick(analyzers: Arc<Mutex<Analyzers>>) {
+        thread::spawn(move || loop {
+            thread::sleep(TICK);
+            for analyzer in &mut analyzers.lock().unwrap().0 {
+                analyzer.on_tick();
+            }
+        });
+    }
+
+    /// Wraps up every analyzer. They're dropped afterwards, so only the first call does
+    /// anything.
+    pub fn shutdown(&mut self) {
+        for mut analyzer in self.0.drain(..) {
+            analyzer.on_shutdown();
+        }
+    }
+}
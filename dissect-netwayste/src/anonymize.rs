@@ -0,0 +1,369 @@
+//! `--anonymize`: consistent stand-ins for addresses, ports and player names, so a capture can
+//! be shared publicly.
+//!
+//! Each real value gets the next stand-in the first time it's seen and keeps it from then on.
+//! Frames saved to a pcap file are rewritten to match, with their checksums fixed up, and so
+//! are the raw payloads shown by `--hexdump`, the TUI and failure reports, and stored in SQLite.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use dissect_netwayste_core::output::packet_value;
+use dissect_netwayste_core::ports::Ports;
+use etherparse::{
+    ip_number,
+    InternetSlice::{Ipv4, Ipv6},
+    TransportSlice::{Tcp, Udp},
+};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::capture::Frame;
+use crate::dissect::slice_link_layer;
+
+/// Stand-in ports are taken from the dynamic range.
+const FIRST_PORT: u16 = 49152;
+
+/// Digits of the stand-ins for names too short for `playerN`.
+const SHORT_NAME_DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+pub struct Anonymizer {
+    /// Left alone, since they say nothing about anyone and the traffic is unreadable without it
+    server_ports: Ports,
+    ports: bool,
+    names: bool,
+    ips: HashMap<IpAddr, IpAddr>,
+    port_map: HashMap<u16, u16>,
+    name_map: HashMap<String, String>,
+    /// Every stand-in handed out, so none is handed out twice
+    stand_ins: HashSet<String>,
+    /// Stand-ins for short names handed out so far, by length
+    short_names: HashMap<usize, u64>,
+    /// Whether we've already said that some frames couldn't be rewritten
+    warned: bool,
+}
+
+impl Anonymizer {
+    pub fn new(server_ports: Ports, ports: bool, names: bool) -> Self {
+        Anonymizer {
+            server_ports,
+            ports,
+            names,
+            ips: HashMap::new(),
+            port_map: HashMap::new(),
+            name_map: HashMap::new(),
+            stand_ins: HashSet::new(),
+            short_names: HashMap::new(),
+            warned: false,
+        }
+    }
+
+    pub fn addr(&mut self, addr: SocketAddr) -> SocketAddr {
+        SocketAddr::new(self.ip(addr.ip()), self.port(addr.port()))
+    }
+
+    /// IPv4 addresses become 10.0.0.1, 10.0.0.2, ... and IPv6 ones fd00::1, fd00::2, ...
+    fn ip(&mut self, ip: IpAddr) -> IpAddr {
+        let n = self.ips.len() as u32 + 1;
+        *self.ips.entry(ip).or_insert_with(|| match ip {
+            IpAddr::V4(_) => Ipv4Addr::from(0x0a00_0000 + n).into(),
+            IpAddr::V6(_) => Ipv6Addr::from((0xfd00 << 112) | n as u128).into(),
+        })
+    }
+
+    fn port(&mut self, port: u16) -> u16 {
+        if !self.ports || self.server_ports.contains(port) {
+            return port;
+        }
+        let n = self.port_map.len() as u16;
+        *self
+            .port_map
+            .entry(port)
+            .or_insert_with(|| FIRST_PORT + n % (u16::MAX - FIRST_PORT))
+    }
+
+    /// Stand-ins are the same length in bytes as the name, so they fit in the frame in its place.
+    /// Names too short for `playerN` get a count in base 36 instead, e.g. `00`, `01`, ...
+    fn name(&mut self, name: &str) -> String {
+        if let Some(stand_in) = self.name_map.get(name) {
+            return stand_in.clone();
+        }
+        let len = name.len();
+        let long = format!("player{}", self.name_map.len() + 1);
+        let mut stand_in = format!("{:_<width$}", long, width = len);
+        // A short stand-in may happen to spell a long one
+        while stand_in.len() != len || self.stand_ins.contains(&stand_in) {
+            stand_in = self.short_name(len);
+        }
+        self.stand_ins.insert(stand_in.clone());
+        self.name_map.insert(name.to_owned(), stand_in.clone());
+        stand_in
+    }
+
+    fn short_name(&mut self, len: usize) -> String {
+        let n = self.short_names.entry(len).or_default();
+        let capacity = (SHORT_NAME_DIGITS.len() as u64).saturating_pow(len as u32);
+        if *n >= capacity {
+            warn!(
+                "More than {} player names of {} bytes; some now share a stand-in",
+                capacity, len
+            );
+            *n = 0;
+            self.stand_ins.retain(|stand_in| stand_in.len() != len);
+        }
+        let stand_in = base36(*n, len);
+        *n += 1;
+        stand_in
+    }
+
+    /// The packet with player names replaced, if that was asked for.
+    pub fn packet(&mut self, packet: Packet) -> Packet {
+        if !self.names {
+            return packet;
+        }
+        let mut value = packet_value(&packet);
+        self.rename(&mut value);
+        serde_json::from_value(value).expect("anonymized packet no longer de-serializes")
+    }
+
+    fn rename(&mut self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    match v {
+                        Value::String(name) if key == "name" => *name = self.name(name),
+                        _ => self.rename(v),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.rename(v)),
+            _ => {}
+        }
+    }
+
+    /// Rewrites a frame's addresses, ports and the player names seen so far to their stand-ins.
+    /// Returns false, leaving the frame untouched, if it's one we can't rewrite completely:
+    /// tunnelled, fragmented or cut short by the snap length.
+    pub fn frame(&mut self, frame: &mut Frame) -> bool {
+        match self.locate(frame) {
+            Some(layout) => {
+                self.rewrite(frame, layout);
+                true
+            }
+            None => {
+                if !self.warned {
+                    warn!("Leaving out frames that can't be anonymized (tunnelled, fragmented or truncated) from the pcap file");
+                    self.warned = true;
+                }
+                false
+            }
+        }
+    }
+
+    /// A netwayste payload with the player names seen so far replaced, for showing or storing
+    /// the raw bytes.
+    pub fn payload(&self, payload: &[u8]) -> Vec<u8> {
+        let mut payload = payload.to_vec();
+        self.rename_bytes(&mut payload);
+        payload
+    }
+
+    /// Like `payload()`, for the bytes as they were on the wire. Names can't be picked out of
+    /// compressed ones, so those are blanked instead.
+    pub fn wire_payload(&self, payload: &[u8], compressed: bool) -> Vec<u8> {
+        if compressed && self.names {
+            return vec![0; payload.len()];
+        }
+        self.payload(payload)
+    }
+
+    /// bincode writes a string as its length then its bytes, which is specific enough to find
+    /// the names without decoding anything.
+    fn rename_bytes(&self, data: &mut [u8]) {
+        for (name, stand_in) in &self.name_map {
+            let mut encoded = (name.len() as u64).to_le_bytes().to_vec();
+            encoded.extend_from_slice(name.as_bytes());
+            let mut i = 0;
+            while i + encoded.len() <= data.len() {
+                if data[i..].starts_with(&encoded) {
+                    data[i + 8..i + encoded.len()].copy_from_slice(stand_in.as_bytes());
+                    i += encoded.len();
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn locate(&self, frame: &Frame) -> Option<Layout> {
+        let sliced = slice_link_layer(frame.linktype, &frame.data).ok()??;
+        let base = frame.data.as_ptr() as usize;
+        let (ip, ip_end, v6) = match &sliced.ip {
+            Some(Ipv4(ipv4, _)) => {
+                if ipv4.more_fragments() || ipv4.fragments_offset() != 0 {
+                    return None;
+                }
+                let ip = ipv4.slice().as_ptr() as usize - base;
+                (ip, ip + ipv4.total_len() as usize, false)
+            }
+            Some(Ipv6(ipv6, _)) => {
+                let ip = ipv6.slice().as_ptr() as usize - base;
+                (ip, ip + 40 + ipv6.payload_length() as usize, true)
+            }
+            None => return None,
+        };
+        let (transport, protocol, ports) = match &sliced.transport {
+            Some(Udp(udp)) => (
+                udp.slice().as_ptr() as usize - base,
+                ip_number::UDP,
+                [udp.source_port(), udp.destination_port()],
+            ),
+            Some(Tcp(tcp)) => (
+                tcp.slice().as_ptr() as usize - base,
+                ip_number::TCP,
+                [tcp.source_port(), tcp.destination_port()],
+            ),
+            _ => return None,
+        };
+        // Anything else got here inside a tunnel, whose inner headers we'd miss
+        if !ports.iter().any(|port| self.server_ports.contains(*port)) || ip_end > frame.data.len()
+        {
+            return None;
+        }
+        Some(Layout {
+            ip,
+            ip_end,
+            v6,
+            transport,
+            protocol,
+        })
+    }
+
+    fn rewrite(&mut self, frame: &mut Frame, layout: Layout) {
+        let data = &mut frame.data;
+        let (src, dst) = if layout.v6 { (8, 24) } else { (12, 16) };
+        for at in [layout.ip + src, layout.ip + dst] {
+            let ip: IpAddr = if layout.v6 {
+                <[u8; 16]>::try_from(&data[at..at + 16]).unwrap().into()
+            } else {
+                <[u8; 4]>::try_from(&data[at..at + 4]).unwrap().into()
+            };
+            match self.ip(ip) {
+                IpAddr::V4(v4) => data[at..at + 4].copy_from_slice(&v4.octets()),
+                IpAddr::V6(v6) => data[at..at + 16].copy_from_slice(&v6.octets()),
+            }
+        }
+        if !layout.v6 {
+            let header_len = (data[layout.ip] & 0x0f) as usize * 4;
+            let header = &mut data[layout.ip..layout.ip + header_len];
+            header[10..12].fill(0);
+            let checksum = checksum(sum(header, 0));
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        for at in [layout.transport, layout.transport + 2] {
+            let port = u16::from_be_bytes([data[at], data[at + 1]]);
+            data[at..at + 2].copy_from_slice(&self.port(port).to_be_bytes());
+        }
+
+        self.rename_bytes(&mut data[layout.transport..layout.ip_end]);
+
+        let checksum_at = layout.transport
+            + if layout.protocol == ip_number::UDP {
+                6
+            } else {
+                16
+            };
+        data[checksum_at..checksum_at + 2].fill(0);
+        let len = layout.ip_end - layout.transport;
+        let addrs = if layout.v6 {
+            &data[layout.ip + 8..layout.ip + 40]
+        } else {
+            &data[layout.ip + 12..layout.ip + 20]
+        };
+        let pseudo = sum(
+            addrs,
+            layout.protocol as u32 + (len as u32 >> 16) + (len as u32 & 0xffff),
+        );
+        let mut checksum = checksum(sum(&data[layout.transport..layout.ip_end], pseudo));
+        // In UDP, all zeroes means no checksum at all
+        if checksum == 0 && layout.protocol == ip_number::UDP {
+            checksum = 0xffff;
+        }
+        data[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+/// Byte offsets into a frame of the headers `frame()` rewrites.
+struct Layout {
+    ip: usize,
+    /// Where the IP payload ends, which may be before the frame does (Ethernet padding)
+    ip_end: usize,
+    v6: bool,
+    transport: usize,
+    protocol: u8,
+}
+
+/// Adds up 16-bit big endian words the way the internet checksum (RFC 1071) does.
+fn sum(data: &[u8], mut sum: u32) -> u32 {
+    for word in data.chunks(2) {
+        sum += u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32;
+    }
+    sum
+}
+
+/// `n` in base 36, zero-padded to `len` digits.
+fn base36(mut n: u64, len: usize) -> String {
+    let mut digits = vec![b'0'; len];
+    for digit in digits.iter_mut().rev() {
+        *digit = SHORT_NAME_DIGITS[(n % SHORT_NAME_DIGITS.len() as u64) as usize];
+        n /= SHORT_NAME_DIGITS.len() as u64;
+    }
+    String::from_utf8(digits).unwrap()
+}
+
+fn checksum(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_stand_ins_fit_and_stay_unique() {
+        let mut anonymizer = Anonymizer::new(Ports::from(2016), false, true);
+        let names = ["alice_the_great", "bob", "bo", "eve", "b", "mallory"];
+        let stand_ins: Vec<String> = names.iter().map(|name| anonymizer.name(name)).collect();
+        for (name, stand_in) in names.iter().zip(&stand_ins) {
+            assert_eq!(name.len(), stand_in.len());
+        }
+        assert_eq!(stand_ins[0], "player1________");
+        assert_eq!(stand_ins[1], "000");
+        assert_eq!(stand_ins[3], "001");
+        assert_eq!(stand_ins[5], "player6");
+        assert_eq!(anonymizer.name("bob"), "000");
+        let unique: HashSet<&String> = stand_ins.iter().collect();
+        assert_eq!(unique.len(), names.len());
+    }
+
+    #[test]
+    fn names_are_replaced_in_raw_payloads() {
+        let mut anonymizer = Anonymizer::new(Ports::from(2016), false, true);
+        anonymizer.name("alice");
+        let mut payload = vec![7];
+        payload.extend_from_slice(&5u64.to_le_bytes());
+        payload.extend_from_slice(b"alice");
+        let anonymized = anonymizer.payload(&payload);
+        assert_eq!(&anonymized[9..], b"00000");
+        assert_eq!(anonymized[..9], payload[..9]);
+        assert_eq!(
+            anonymizer.wire_payload(&payload, true),
+            vec![0; payload.len()]
+        );
+    }
+}
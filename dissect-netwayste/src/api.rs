@@ -0,0 +1,45 @@
+//! `--api-listen`: the running totals as JSON over HTTP, for tools that would rather poll than
+//! parse the log.
+//!
+//! - `/stats`: frame and packet counts, and packets per variant
+//! - `/flows`: decoded packets and bytes per source and destination, busiest first
+//! - `/rooms`: traffic per game room, as `--room-stats` summarizes it
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tiny_http::{Header, Response, Server, StatusCode};
+use tracing::*;
+
+use crate::summary::Summary;
+
+pub fn serve(addr: SocketAddr, summary: Arc<Mutex<Summary>>) {
+    let server = Server::http(addr).expect("Failed to listen for stats API requests");
+    thread::spawn(move || {
+        let content_type =
+            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+        for request in server.incoming_requests() {
+            // Query strings aren't used, but shouldn't turn a request into a 404 either
+            let path = request.url().split('?').next().unwrap_or_default();
+            let body = {
+                let summary = summary.lock().unwrap();
+                match path {
+                    "/stats" => Some(summary.stats_json()),
+                    "/flows" => Some(summary.flows_json()),
+                    "/rooms" => summary.rooms.as_ref().map(|rooms| rooms.to_json()),
+                    _ => None,
+                }
+            };
+            let response = match body {
+                Some(body) => Response::from_string(body.to_string())
+                    .with_header(content_type.clone())
+                    .with_status_code(StatusCode(200)),
+                None => Response::from_string("not found").with_status_code(StatusCode(404)),
+            };
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to answer stats API request: '{}'", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,99 @@
+//! Per-client throughput, in each direction.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::ports::Ports;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+#[derive(Default)]
+struct Counters {
+    bytes: u64,
+    packets: u64,
+}
+
+#[derive(Default)]
+struct ClientTraffic {
+    /// Client to server
+    up: Counters,
+    /// Server to client
+    down: Counters,
+}
+
+pub struct BandwidthTracker {
+    server_ports: Ports,
+    interval: Duration,
+    /// Start of the interval being counted, in capture time
+    started: Option<Duration>,
+    clients: BTreeMap<SocketAddr, ClientTraffic>,
+}
+
+impl BandwidthTracker {
+    /// Reports every `interval` of capture time, like `JitterTracker`.
+    pub fn new(server_ports: Ports, interval: Duration) -> Self {
+        BandwidthTracker {
+            server_ports,
+            interval,
+            started: None,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    /// Call with every datagram on the netwayste port, decoded or not. `bytes` is the UDP
+    /// payload size, so headers (which vary with the link and any tunnels) aren't counted.
+    pub fn observe(&mut self, ts: Duration, src: SocketAddr, dst: SocketAddr, bytes: usize) {
+        let started = *self.started.get_or_insert(ts);
+        let elapsed = ts.saturating_sub(started);
+        if elapsed >= self.interval {
+            self.report(elapsed);
+            self.started = Some(ts);
+        }
+
+        let counters = if self.server_ports.contains(src.port()) {
+            &mut self.clients.entry(dst).or_default().down
+        } else {
+            &mut self.clients.entry(src).or_default().up
+        };
+        counters.bytes += bytes as u64;
+        counters.packets += 1;
+    }
+
+    fn report(&mut self, elapsed: Duration) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        info!("Throughput over the last {:.1?}:", elapsed);
+        info!(
+            "  {:<24} {:>10} {:>8} {:>10} {:>8}",
+            "client", "up B/s", "pkt/s", "down B/s", "pkt/s"
+        );
+        for (addr, traffic) in &self.clients {
+            info!(
+                "  {:<24} {:>10.0} {:>8.1} {:>10.0} {:>8.1}",
+                addr,
+                traffic.up.bytes as f64 / secs,
+                traffic.up.packets as f64 / secs,
+                traffic.down.bytes as f64 / secs,
+                traffic.down.packets as f64 / secs
+            );
+        }
+        // Only clients heard from in the next interval will be listed next time
+        self.clients.clear();
+    }
+}
+
+impl Analyzer for BandwidthTracker {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, _value: &Value) {
+        self.observe(context.ts, context.src, context.dst, context.payload.len());
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        self.observe(context.ts, context.src, context.dst, context.payload.len());
+    }
+}
@@ -0,0 +1,753 @@
+//! Packet sources: live devices, classic pcap files, and pcapng files.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pcap::{self, Activated, Active, BpfProgram, Capture, Linktype};
+use tracing::*;
+
+use crate::filter::Filter;
+use crate::logging::PACKETS;
+use crate::pcapng::{self, PcapngReader};
+
+/// Pseudo interface name selecting every device that is up and has an address.
+pub const ALL_INTERFACES: &str = "all";
+
+/// How long frames from several interfaces are held back so a late one can be slotted in ahead
+/// of them. Longer windows fix more reordering at the cost of output lagging behind the wire.
+const REORDER_WINDOW: Duration = Duration::from_millis(100);
+
+/// After this many read errors in a row, even ones that usually pass, the device is reopened.
+const MAX_TRANSIENT_ERRORS: u32 = 100;
+
+/// How long to wait before the first attempt at reopening a device, doubling after each failed
+/// attempt up to `MAX_REOPEN_DELAY`.
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(30);
+
+/// Knobs for opening live devices; `None` leaves libpcap's default in place.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOptions {
+    /// Bytes captured per frame
+    pub snaplen: Option<i32>,
+    /// Kernel buffer size in bytes
+    pub buffer_size: Option<i32>,
+    pub promisc: bool,
+    /// Read timeout in milliseconds. Setting this turns off immediate mode, so packets are
+    /// delivered in batches instead of one wakeup per packet.
+    pub timeout_ms: Option<i32>,
+}
+
+/// A captured link-layer frame, copied out of whichever source produced it.
+pub struct Frame {
+    /// Capture time relative to the UNIX epoch
+    pub ts: Duration,
+    pub linktype: Linktype,
+    /// Originating interface, when the source has more than one
+    pub interface: Option<String>,
+    pub data: Vec<u8>,
+}
+
+pub enum Source {
+    /// A file libpcap can read on its own, or a stream on stdin
+    Pcap(Capture<dyn Activated>),
+    /// One live device
+    Device(Device),
+    /// pcapng file read block-by-block; filtering happens here rather than in libpcap
+    Pcapng {
+        reader: PcapngReader<BufReader<File>>,
+        filter: Option<Filter>,
+        /// Compiled filters keyed by link type, since each interface may differ
+        programs: HashMap<i32, BpfProgram>,
+    },
+    /// Several live devices merged into one stream ordered by capture time
+    Merged(Merger),
+    /// tcpdump running on another machine, streaming back over SSH
+    Remote {
+        cap: Capture<dyn Activated>,
+        /// Only held so the session lives as long as the capture
+        _ssh: SshChild,
+    },
+}
+
+impl Source {
+    /// Opens the named devices, or libpcap's default pick if none are given. Naming more than
+    /// one, or `all`, merges their packets into a single stream. Returns the source along with
+    /// the name(s) of the device(s) actually opened.
+    pub fn from_devices(interfaces: &[String], options: &CaptureOptions) -> (Source, String) {
+        let devices: Vec<pcap::Device> = if interfaces.iter().any(|i| i == ALL_INTERFACES) {
+            // Pseudo devices like "any" and nflog have no addresses, and would either duplicate
+            // traffic or not be network interfaces at all.
+            pcap::Device::list()
+                .expect("Could not access network interface list")
+                .into_iter()
+                .filter(|d| d.flags.is_up() && !d.addresses.is_empty())
+                .collect()
+        } else if interfaces.is_empty() {
+            vec![pcap::Device::lookup()
+                .expect("Failed to look up default device")
+                .unwrap()]
+        } else {
+            interfaces.iter().map(|name| find_device(name)).collect()
+        };
+
+        let device_names = devices
+            .iter()
+            .map(|d| d.name.clone())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut captures: Vec<Device> = devices
+            .into_iter()
+            .map(|device| Device::open(device, options))
+            .collect();
+
+        if captures.len() == 1 {
+            (Source::Device(captures.pop().unwrap()), device_names)
+        } else {
+            (Source::Merged(Merger::new(captures)), device_names)
+        }
+    }
+
+    /// Opens a saved capture, picking the reader based on the file's magic number.
+    pub fn from_file(path: &Path) -> Source {
+        let mut magic = [0u8; 4];
+        let is_pcapng = File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .map(|_| magic == pcapng::MAGIC)
+            .unwrap_or(false);
+
+        if is_pcapng {
+            let file = File::open(path)
+                .expect(&format!("Failed to open capture file '{}'", path.display()));
+            Source::Pcapng {
+                reader: PcapngReader::new(BufReader::new(file)),
+                filter: None,
+                programs: HashMap::new(),
+            }
+        } else {
+            let cap = pcap::Capture::from_file(path)
+                .expect(&format!("Failed to open capture file '{}'", path.display()));
+            Source::Pcap(cap.into())
+        }
+    }
+
+    /// Reads a pcap stream from stdin. Unlike files, the stream can't be sniffed and rewound, so
+    /// this is left to libpcap, which handles pcap and single-link-type pcapng alike.
+    pub fn from_stdin() -> Source {
+        // Safety: stdin stays open for the life of the process, and nothing else reads from it
+        let cap = unsafe { pcap::Capture::from_raw_fd(io::stdin().as_raw_fd()) }
+            .expect("Failed to read a capture from stdin");
+        Source::Pcap(cap.into())
+    }
+
+    /// Runs tcpdump on `destination` over SSH and reads its output. `tcpdump` is the remote
+    /// command line to use, which lets callers prefix it with `sudo` or give a full path.
+    pub fn from_remote(
+        destination: &str,
+        tcpdump: &str,
+        interface: Option<&str>,
+        filter: &Filter,
+    ) -> Source {
+        let mut tcpdump = tcpdump.to_owned();
+        if let Some(interface) = interface {
+            tcpdump += &format!(" -i {}", shell_quote(interface));
+        }
+        // -U so packets are written as they arrive rather than when tcpdump's buffer fills
+        let capture = format!("exec {} -U -w -", tcpdump);
+        let plain = shell_quote(&filter.bpf(Linktype::LINUX_SLL));
+        // The remote link type isn't known yet, and libpcap refuses the VLAN clauses on anything
+        // but Ethernet. 'any' is always Linux cooked capture. Other interfaces are usually
+        // Ethernet, but may be e.g. tun devices, so tcpdump -d tries compiling the filter first.
+        let remote_command = if interface == Some("any") {
+            format!("{} {}", capture, plain)
+        } else {
+            let ethernet = shell_quote(&filter.bpf(Linktype::ETHERNET));
+            format!(
+                "if {} -d {} >/dev/null 2>&1; then {} {}; else {} {}; fi",
+                tcpdump, ethernet, capture, ethernet, capture, plain
+            )
+        };
+
+        let mut child = Command::new("ssh")
+            .arg("-T")
+            .arg(destination)
+            .arg(remote_command)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to run ssh");
+        let stdout = child.stdout.take().unwrap();
+        let ssh = SshChild(child);
+
+        // Safety: ownership of the pipe passes to libpcap, which closes it when the capture drops
+        let cap = unsafe { pcap::Capture::from_raw_fd(stdout.into_raw_fd()) }.expect(&format!(
+            "Failed to read a capture from tcpdump on '{}'",
+            destination
+        ));
+        Source::Remote {
+            cap: cap.into(),
+            _ssh: ssh,
+        }
+    }
+
+    pub fn filter(&mut self, new_filter: &Filter) -> Result<(), pcap::Error> {
+        match self {
+            Source::Pcap(cap) | Source::Remote { cap, .. } => {
+                let linktype = cap.get_datalink();
+                cap.filter(&new_filter.bpf(linktype), true)
+            }
+            Source::Device(device) => device.filter(new_filter),
+            Source::Pcapng {
+                filter, programs, ..
+            } => {
+                // Compiled lazily once we know which link types the file contains
+                *filter = Some(new_filter.clone());
+                programs.clear();
+                Ok(())
+            }
+            Source::Merged(merger) => merger.filter(new_filter),
+        }
+    }
+
+    /// Returns the next frame that passes the filter. Running out of packets in a file is
+    /// reported as `pcap::Error::NoMorePackets`, same as libpcap does. Live devices recover from
+    /// what errors they can, so an error from one means it can't be read any more.
+    pub fn next_frame(&mut self) -> Result<Frame, pcap::Error> {
+        match self {
+            Source::Pcap(cap) | Source::Remote { cap, .. } => {
+                let linktype = cap.get_datalink();
+                next_live_frame(cap, linktype, None)
+            }
+            Source::Device(device) => device.next_frame(),
+            Source::Merged(merger) => merger.next_frame(),
+            Source::Pcapng {
+                reader,
+                filter,
+                programs,
+            } => loop {
+                let record = match reader.next_record() {
+                    Ok(Some(record)) => record,
+                    Ok(None) => return Err(pcap::Error::NoMorePackets),
+                    Err(e) => return Err(pcap::Error::IoError(e.kind())),
+                };
+                let interfaces = reader.interfaces();
+                let iface = &interfaces[record.interface];
+
+                if let Some(filter) = filter {
+                    if !programs.contains_key(&iface.linktype) {
+                        let linktype = Linktype(iface.linktype);
+                        let program =
+                            Capture::dead(linktype)?.compile(&filter.bpf(linktype), true)?;
+                        programs.insert(iface.linktype, program);
+                    }
+                    if !programs[&iface.linktype].filter(&record.data) {
+                        continue;
+                    }
+                }
+
+                // Only worth tagging lines with the interface when there's a choice of them
+                let interface = if interfaces.len() > 1 {
+                    Some(
+                        iface
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| format!("if{}", record.interface)),
+                    )
+                } else {
+                    None
+                };
+
+                return Ok(Frame {
+                    ts: record.ts,
+                    linktype: Linktype(iface.linktype),
+                    interface,
+                    data: record.data,
+                });
+            },
+        }
+    }
+
+    /// libpcap's received and dropped counters. Only single live devices have them; files,
+    /// remote captures and merged devices give `None`.
+    pub fn stats(&mut self) -> Option<pcap::Stat> {
+        match self {
+            Source::Pcap(cap) => cap.stats().ok(),
+            Source::Device(device) => device.cap.stats().ok(),
+            _ => None,
+        }
+    }
+
+    /// Looks up a host name recorded in the capture itself (pcapng name resolution blocks).
+    pub fn resolved_name(&self, addr: IpAddr) -> Option<&str> {
+        match self {
+            Source::Pcap(_) | Source::Device(_) | Source::Merged(_) | Source::Remote { .. } => None,
+            Source::Pcapng { reader, .. } => reader.names().get(&addr).map(|name| name.as_str()),
+        }
+    }
+}
+
+/// Kills the SSH session when the capture is dropped, rather than leaving tcpdump running.
+pub struct SshChild(Child);
+
+impl Drop for SshChild {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+pub struct Merger {
+    /// Captures waiting for their reader threads, which start with the first `next_frame()`
+    idle: Vec<Device>,
+    rx: Option<Receiver<Result<Frame, pcap::Error>>>,
+    pending: BinaryHeap<Pending>,
+    /// Tie-breaker so frames with identical timestamps keep their arrival order
+    seq: u64,
+    /// Set once every reader thread has exited
+    done: bool,
+}
+
+impl Merger {
+    fn new(captures: Vec<Device>) -> Self {
+        Merger {
+            idle: captures,
+            rx: None,
+            pending: BinaryHeap::new(),
+            seq: 0,
+            done: false,
+        }
+    }
+
+    fn filter(&mut self, filter: &Filter) -> Result<(), pcap::Error> {
+        for device in self.idle.iter_mut() {
+            device.filter(filter)?;
+        }
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Frame, pcap::Error> {
+        if self.rx.is_none() {
+            self.start();
+        }
+        let rx = self.rx.as_ref().unwrap();
+
+        loop {
+            let wait = match self.pending.peek() {
+                Some(oldest) => {
+                    let held = since_epoch().saturating_sub(oldest.frame.ts);
+                    if self.done || held >= REORDER_WINDOW {
+                        return Ok(self.pending.pop().unwrap().frame);
+                    }
+                    REORDER_WINDOW - held
+                }
+                None if self.done => return Err(pcap::Error::NoMorePackets),
+                None => REORDER_WINDOW,
+            };
+
+            match rx.recv_timeout(wait) {
+                Ok(Ok(frame)) => {
+                    self.seq += 1;
+                    self.pending.push(Pending {
+                        seq: self.seq,
+                        frame,
+                    });
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => self.done = true,
+            }
+        }
+    }
+
+    fn start(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        for mut device in self.idle.drain(..) {
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                let result = device.next_frame().map(|frame| Frame {
+                    interface: Some(device.name.clone()),
+                    ..frame
+                });
+                let failed = result.is_err();
+                // Errors that get this far can't be recovered from, so end the capture
+                if tx.send(result).is_err() || failed {
+                    break;
+                }
+            });
+        }
+        self.rx = Some(rx);
+    }
+}
+
+/// Heap entry for `Merger`, ordered so the oldest frame is at the top of the (max-)heap.
+struct Pending {
+    seq: u64,
+    frame: Frame,
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.frame.ts, other.seq).cmp(&(self.frame.ts, self.seq))
+    }
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+/// Lists the devices `--interface` takes, marking the one used when it isn't given.
+pub fn print_interfaces() {
+    let devices = pcap::Device::list().expect("Could not access network interface list");
+    let default = pcap::Device::lookup().ok().flatten().map(|d| d.name);
+    for device in devices {
+        let is_default = default.as_deref() == Some(device.name.as_str());
+        let flags = &device.flags;
+        let mut notes = vec![if flags.is_up() { "up" } else { "down" }];
+        if flags.is_running() {
+            notes.push("running");
+        }
+        if flags.is_loopback() {
+            notes.push("loopback");
+        }
+        if flags.is_wireless() {
+            notes.push("wireless");
+        }
+        if is_default {
+            notes.push("default");
+        }
+        println!(
+            "{} {} ({})",
+            if is_default { '*' } else { ' ' },
+            device.name,
+            notes.join(", ")
+        );
+        if let Some(desc) = &device.desc {
+            println!("      {}", desc);
+        }
+        for address in &device.addresses {
+            match address.netmask {
+                Some(netmask) => println!("      {}/{}", address.addr, prefix_len(netmask)),
+                None => println!("      {}", address.addr),
+            }
+        }
+    }
+    println!(
+        "\n'{}' captures on every device that's up and has an address",
+        ALL_INTERFACES
+    );
+}
+
+fn prefix_len(netmask: IpAddr) -> u32 {
+    match netmask {
+        IpAddr::V4(mask) => u32::from(mask).count_ones(),
+        IpAddr::V6(mask) => u128::from(mask).count_ones(),
+    }
+}
+
+fn find_device(name: &str) -> pcap::Device {
+    // Verify we can find a device
+    let device_list = pcap::Device::list().expect("Could not access network interface list");
+    device_list
+        .into_iter()
+        .filter(|d| d.name == name)
+        .next()
+        .expect(&format!(
+            "Failed to find '{}' in network interface list",
+            name
+        ))
+}
+
+/// A live device, opened again if it fails, e.g. when it goes down or a USB adapter is replugged.
+pub struct Device {
+    name: String,
+    cap: Capture<Active>,
+    options: CaptureOptions,
+    /// Applied again on reopening
+    filter: Option<Filter>,
+    /// Read errors since the last frame
+    errors: u32,
+}
+
+impl Device {
+    fn open(device: pcap::Device, options: &CaptureOptions) -> Device {
+        let name = device.name.clone();
+        let cap = open_device(device, options)
+            .unwrap_or_else(|e| panic!("Failed to open device '{}': {}", name, e));
+        Device {
+            name,
+            cap,
+            options: options.clone(),
+            filter: None,
+            errors: 0,
+        }
+    }
+
+    fn filter(&mut self, filter: &Filter) -> Result<(), pcap::Error> {
+        let linktype = self.cap.get_datalink();
+        self.cap.filter(&filter.bpf(linktype), true)?;
+        self.filter = Some(filter.clone());
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Frame, pcap::Error> {
+        loop {
+            let linktype = self.cap.get_datalink();
+            let error = match next_live_frame(&mut self.cap, linktype, None) {
+                Ok(frame) => {
+                    self.errors = 0;
+                    return Ok(frame);
+                }
+                Err(e) => e,
+            };
+            self.errors += 1;
+            match severity(&error) {
+                Severity::Transient if self.errors < MAX_TRANSIENT_ERRORS => {
+                    warn!(
+                        "Error reading from device '{}', carrying on: {}",
+                        self.name, error
+                    );
+                }
+                Severity::Transient | Severity::Reopen => {
+                    // In with the packet lines, so a gap in the traffic explains itself
+                    warn!(
+                        target: PACKETS,
+                        "=== Interface '{}' went down ({}); waiting for it to come back",
+                        self.name,
+                        error
+                    );
+                    self.reopen()?;
+                }
+                Severity::Fatal => return Err(error),
+            }
+        }
+    }
+
+    /// Keeps trying to open the device again, until it works or can't ever work. Flow state
+    /// lives with the caller, so it carries on across the gap.
+    fn reopen(&mut self) -> Result<(), pcap::Error> {
+        let down = Instant::now();
+        let mut delay = REOPEN_DELAY;
+        loop {
+            thread::sleep(delay);
+            match self.try_reopen() {
+                Ok(()) => {
+                    info!(
+                        target: PACKETS,
+                        "=== Interface '{}' is back after {:.1?}; capture resumed{}",
+                        self.name,
+                        down.elapsed(),
+                        if self.filter.is_some() {
+                            " with the same filter"
+                        } else {
+                            ""
+                        }
+                    );
+                    self.errors = 0;
+                    return Ok(());
+                }
+                // Most likely privileges were dropped after the first open
+                Err(e) if is_permission_error(&e) => {
+                    error!(
+                        "Can't reopen device '{}' without the privileges it was opened with: {}",
+                        self.name, e
+                    );
+                    return Err(e);
+                }
+                Err(e) => {
+                    debug!(
+                        "Failed to reopen device '{}', trying again in {:?}: {}",
+                        self.name, delay, e
+                    );
+                    delay = (delay * 2).min(MAX_REOPEN_DELAY);
+                }
+            }
+        }
+    }
+
+    fn try_reopen(&mut self) -> Result<(), pcap::Error> {
+        // The device may have come back under a new index, so it's looked up afresh
+        let device = pcap::Device::list()?
+            .into_iter()
+            .find(|d| d.name == self.name)
+            .ok_or_else(|| pcap::Error::PcapError("no such device".to_owned()))?;
+        // Opening a device that's present but down works, only to fail on the first read
+        if !device.flags.is_up() {
+            return Err(pcap::Error::PcapError("interface is down".to_owned()));
+        }
+        self.cap = open_device(device, &self.options)?;
+        if let Some(filter) = self.filter.clone() {
+            self.filter(&filter)?;
+        }
+        Ok(())
+    }
+}
+
+/// How bad an error reading from a live device is.
+#[derive(Debug, PartialEq, Eq)]
+enum Severity {
+    /// Worth a warning, but the next read will probably work, e.g. a buffer overrun
+    Transient,
+    /// The device went away or broke, and has to be opened again
+    Reopen,
+    /// Nothing more can be read
+    Fatal,
+}
+
+fn severity(error: &pcap::Error) -> Severity {
+    match error {
+        pcap::Error::TimeoutExpired | pcap::Error::InsufficientMemory => Severity::Transient,
+        pcap::Error::IoError(
+            ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut,
+        ) => Severity::Transient,
+        pcap::Error::IoError(_) => Severity::Reopen,
+        // libpcap's errors only come as text
+        pcap::Error::PcapError(message) => {
+            let transient = [
+                "No buffer space available",
+                "Resource temporarily unavailable",
+                "Interrupted system call",
+            ];
+            if transient.iter().any(|text| message.contains(text)) {
+                Severity::Transient
+            } else {
+                // "The interface went down", "No such device exists" and the like
+                Severity::Reopen
+            }
+        }
+        _ => Severity::Fatal,
+    }
+}
+
+fn is_permission_error(error: &pcap::Error) -> bool {
+    match error {
+        pcap::Error::PcapError(message) => {
+            message.contains("permission") || message.contains("not permitted")
+        }
+        pcap::Error::IoError(kind) => *kind == ErrorKind::PermissionDenied,
+        _ => false,
+    }
+}
+
+fn open_device(
+    device: pcap::Device,
+    options: &CaptureOptions,
+) -> Result<Capture<Active>, pcap::Error> {
+    let mut cap = pcap::Capture::from_device(device)?.promisc(options.promisc);
+    if let Some(snaplen) = options.snaplen {
+        cap = cap.snaplen(snaplen);
+    }
+    if let Some(buffer_size) = options.buffer_size {
+        cap = cap.buffer_size(buffer_size);
+    }
+    cap = match options.timeout_ms {
+        Some(timeout_ms) => cap.timeout(timeout_ms),
+        None => cap.immediate_mode(true),
+    };
+
+    cap.open()
+}
+
+/// Like `next_packet()`, but quietly waits out read timeouts, which only mean nothing arrived
+/// within the `CaptureOptions::timeout_ms` window.
+fn next_live_frame<T: Activated + ?Sized>(
+    cap: &mut Capture<T>,
+    linktype: Linktype,
+    interface: Option<String>,
+) -> Result<Frame, pcap::Error> {
+    loop {
+        match cap.next_packet() {
+            Ok(packet) => return Ok(frame_from_packet(&packet, linktype, interface)),
+            Err(pcap::Error::TimeoutExpired) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn frame_from_packet(
+    packet: &pcap::Packet,
+    linktype: Linktype,
+    interface: Option<String>,
+) -> Frame {
+    let ts = &packet.header.ts;
+    Frame {
+        ts: Duration::new(ts.tv_sec as u64, ts.tv_usec as u32 * 1000),
+        linktype,
+        interface,
+        data: packet.data.to_vec(),
+    }
+}
+
+/// Single-quotes `s` for a POSIX shell, since ssh hands the remote command to one.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_errors_are_graded() {
+        let pcap_error = |message: &str| pcap::Error::PcapError(message.to_owned());
+        for error in [
+            pcap::Error::TimeoutExpired,
+            pcap::Error::InsufficientMemory,
+            pcap::Error::IoError(ErrorKind::Interrupted),
+            pcap::Error::IoError(ErrorKind::WouldBlock),
+            pcap::Error::IoError(ErrorKind::TimedOut),
+            pcap_error("read: No buffer space available"),
+            pcap_error("recvfrom: Resource temporarily unavailable"),
+            pcap_error("poll: Interrupted system call"),
+        ] {
+            assert_eq!(severity(&error), Severity::Transient, "{:?}", error);
+        }
+        for error in [
+            pcap::Error::IoError(ErrorKind::BrokenPipe),
+            pcap_error("The interface went down"),
+            pcap_error("No such device exists"),
+        ] {
+            assert_eq!(severity(&error), Severity::Reopen, "{:?}", error);
+        }
+        for error in [pcap::Error::NoMorePackets, pcap::Error::NonNonBlock] {
+            assert_eq!(severity(&error), Severity::Fatal, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn permission_errors_are_recognized() {
+        assert!(is_permission_error(&pcap::Error::PcapError(
+            "socket: Operation not permitted".to_owned()
+        )));
+        assert!(is_permission_error(&pcap::Error::IoError(
+            ErrorKind::PermissionDenied
+        )));
+        assert!(!is_permission_error(&pcap::Error::PcapError(
+            "No such device exists".to_owned()
+        )));
+    }
+}
@@ -0,0 +1,223 @@
+//! `diff`: lines up two captures of the same traffic, e.g. one taken at a client and one at
+//! the server, to show which packets made it from one to the other and how long they took.
+//!
+//! Addresses usually differ between the two ends (NAT), so packets are matched by direction,
+//! variant and sequence number. Resends of the same packet are matched up in order. Packets
+//! without a sequence number can't be matched and are left out.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::compression::decompress;
+use dissect_netwayste_core::output::{format_time, packet_value, sequence_number, variant_name};
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::protocol::Decoder;
+use tracing::*;
+
+use crate::capture::Source;
+use crate::dissect::Dissector;
+use crate::filter::Filter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Direction {
+    ToServer,
+    ToClient,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::ToServer => write!(f, "client -> server"),
+            Direction::ToClient => write!(f, "server -> client"),
+        }
+    }
+}
+
+/// What a packet is matched by.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    direction: Direction,
+    variant: String,
+    sequence: u64,
+}
+
+struct Sent {
+    ts: Duration,
+    src: SocketAddr,
+    dst: SocketAddr,
+    key: Key,
+}
+
+/// The decoded, sequenced packets in a capture file, in capture order.
+fn load(
+    path: &Path,
+    filter: &Filter,
+    server_ports: &Ports,
+    decoder: &dyn Decoder,
+    decapsulate: bool,
+) -> Vec<Sent> {
+    let mut source = Source::from_file(path);
+    source
+        .filter(filter)
+        .expect("Failed to filter for netwayste packets");
+    let mut dissector = Dissector::new(decapsulate);
+    let mut sent = vec![];
+    while let Ok(frame) = source.next_frame() {
+        let Ok(datagrams) = dissector.dissect(&frame) else {
+            continue;
+        };
+        for datagram in datagrams {
+            let packet = match decompress(&datagram.payload) {
+                Ok(Some((_, data))) => decoder.decode(&data),
+                _ => decoder.decode(&datagram.payload),
+            };
+            let Ok(packet) = packet else {
+                continue;
+            };
+            let value = packet_value(&packet);
+            let Some(sequence) = sequence_number(&value) else {
+                continue;
+            };
+            let direction = if server_ports.contains(datagram.dst.port()) {
+                Direction::ToServer
+            } else {
+                Direction::ToClient
+            };
+            sent.push(Sent {
+                ts: frame.ts,
+                src: datagram.src,
+                dst: datagram.dst,
+                key: Key {
+                    direction,
+                    variant: variant_name(&value),
+                    sequence,
+                },
+            });
+        }
+    }
+    sent
+}
+
+/// How much later packets showed up in the second capture than in the first, in one direction.
+#[derive(Default)]
+struct Deltas {
+    /// In seconds; negative if the second capture's clock is behind
+    samples: Vec<f64>,
+}
+
+impl fmt::Display for Deltas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let min = self.samples.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self
+            .samples
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let avg = self.samples.iter().sum::<f64>() / self.samples.len() as f64;
+        write!(
+            f,
+            "min {:+.3} ms, avg {:+.3} ms, max {:+.3} ms",
+            min * 1000.0,
+            avg * 1000.0,
+            max * 1000.0
+        )
+    }
+}
+
+/// Compares the two captures and logs the packets only one of them has, then the timing of the
+/// ones they share. With `verbose`, each shared packet's delta is logged too.
+pub fn run(
+    first: &Path,
+    second: &Path,
+    filter: &Filter,
+    server_ports: &Ports,
+    decoder: &dyn Decoder,
+    decapsulate: bool,
+    verbose: bool,
+) {
+    let a = load(first, filter, server_ports, decoder, decapsulate);
+    let b = load(second, filter, server_ports, decoder, decapsulate);
+
+    let mut in_b: HashMap<&Key, VecDeque<&Sent>> = HashMap::new();
+    for sent in &b {
+        in_b.entry(&sent.key).or_default().push_back(sent);
+    }
+
+    let mut deltas: HashMap<Direction, Deltas> = HashMap::new();
+    let mut only_a = 0;
+    for sent in &a {
+        match in_b.get_mut(&sent.key).and_then(VecDeque::pop_front) {
+            Some(other) => {
+                let delta = other.ts.as_secs_f64() - sent.ts.as_secs_f64();
+                if verbose {
+                    info!(
+                        "{} {} {} ({} -> {}): {:+.3} ms",
+                        format_time(sent.ts).unwrap_or_default(),
+                        sent.key.variant,
+                        sent.key.sequence,
+                        sent.src,
+                        sent.dst,
+                        delta * 1000.0
+                    );
+                }
+                deltas
+                    .entry(sent.key.direction)
+                    .or_default()
+                    .samples
+                    .push(delta);
+            }
+            None => {
+                only_a += 1;
+                warn!(
+                    "Only in {}: {} {} {} ({} -> {}, {})",
+                    first.display(),
+                    format_time(sent.ts).unwrap_or_default(),
+                    sent.key.variant,
+                    sent.key.sequence,
+                    sent.src,
+                    sent.dst,
+                    sent.key.direction
+                );
+            }
+        }
+    }
+
+    // Whatever wasn't matched is only in the second capture; report it in capture order
+    let mut only_b: Vec<&Sent> = in_b.into_values().flatten().collect();
+    only_b.sort_by_key(|sent| sent.ts);
+    for sent in &only_b {
+        warn!(
+            "Only in {}: {} {} {} ({} -> {}, {})",
+            second.display(),
+            format_time(sent.ts).unwrap_or_default(),
+            sent.key.variant,
+            sent.key.sequence,
+            sent.src,
+            sent.dst,
+            sent.key.direction
+        );
+    }
+
+    info!(
+        "{} packets in {}, {} in {}: {} only in the first, {} only in the second",
+        a.len(),
+        first.display(),
+        b.len(),
+        second.display(),
+        only_a,
+        only_b.len()
+    );
+    for direction in [Direction::ToServer, Direction::ToClient] {
+        if let Some(deltas) = deltas.get(&direction) {
+            info!(
+                "{}: {} in both, second capture {}",
+                direction,
+                deltas.samples.len(),
+                deltas
+            );
+        }
+    }
+}
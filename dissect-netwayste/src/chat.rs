@@ -0,0 +1,91 @@
+//! `--chat`: just what players say, for moderators.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use dissect_netwayste_core::output::{format_time, nested_variant, sequence_number, variant_name};
+use dissect_netwayste_core::packet_filter::{client_end, find_strings};
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::session::{SessionState, SessionTracker};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// One thing a player said.
+pub struct Chat {
+    /// `None` if the player's room couldn't be worked out
+    pub room: Option<String>,
+    /// The name the player connected with, or their address if that wasn't captured
+    pub player: String,
+    pub text: String,
+}
+
+pub struct ChatMonitor {
+    server_ports: Ports,
+    sessions: SessionTracker,
+    /// Chat requests already shown, by client and sequence number, so retransmissions aren't
+    seen: HashSet<(SocketAddr, u64)>,
+}
+
+impl ChatMonitor {
+    pub fn new(server_ports: Ports) -> Self {
+        ChatMonitor {
+            server_ports: server_ports.clone(),
+            sessions: SessionTracker::new(server_ports),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Call with every decoded packet, in capture order. Chats are taken from the requests
+    /// players send, since the server relays each one to everyone else in the room.
+    pub fn observe(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) -> Option<Chat> {
+        self.sessions.observe(src, dst, packet);
+        let client = client_end(src, dst, &self.server_ports);
+        if variant_name(packet) != "Request"
+            || nested_variant(packet, "action").as_deref() != Some("ChatMessage")
+        {
+            return None;
+        }
+        if let Some(seq) = sequence_number(packet) {
+            if !self.seen.insert((client, seq)) {
+                return None;
+            }
+        }
+
+        let mut strings = vec![];
+        find_strings(packet, "message", &mut strings);
+        let room = match self.sessions.state(client) {
+            Some(SessionState::InRoom(room)) | Some(SessionState::InGame(room)) => {
+                Some(room.clone())
+            }
+            _ => None,
+        };
+        Some(Chat {
+            room,
+            player: self
+                .sessions
+                .name(client)
+                .map_or_else(|| client.to_string(), str::to_owned),
+            text: strings.first()?.to_string(),
+        })
+    }
+}
+
+impl Analyzer for ChatMonitor {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let Some(chat) = self.observe(context.src, context.dst, value) else {
+            return;
+        };
+        if context.shown {
+            info!(
+                "{} [{}] {}: {}",
+                format_time(context.ts).unwrap_or_default(),
+                chat.room.as_deref().unwrap_or("?"),
+                chat.player,
+                chat.text
+            );
+        }
+    }
+}
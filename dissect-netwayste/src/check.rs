@@ -0,0 +1,123 @@
+//! Protocol rules every conversation should follow, checked against what was actually seen.
+//!
+//! A capture usually starts partway through some sessions, so the rules about requests and rooms
+//! only apply to clients whose session was seen starting.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::{sequence_number, variant_name};
+use dissect_netwayste_core::packet_filter::client_end;
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::sequence::RetransmitTracker;
+use dissect_netwayste_core::session::{SessionState, SessionTracker};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// How many recent request sequence numbers to remember per client.
+const RECENT_REQUESTS: usize = 256;
+
+pub struct Checker {
+    server_ports: Ports,
+    sessions: SessionTracker,
+    requests: HashMap<SocketAddr, VecDeque<u64>>,
+    highest: HashMap<(SocketAddr, SocketAddr), u64>,
+    retransmits: RetransmitTracker,
+}
+
+impl Checker {
+    pub fn new(server_ports: Ports) -> Self {
+        Checker {
+            server_ports: server_ports.clone(),
+            sessions: SessionTracker::new(server_ports),
+            requests: HashMap::new(),
+            highest: HashMap::new(),
+            retransmits: RetransmitTracker::default(),
+        }
+    }
+
+    /// Call with every decoded packet, in capture order. Returns a description of each rule
+    /// the packet breaks.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+    ) -> Vec<String> {
+        let mut violations = vec![];
+        let client = client_end(src, dst, &self.server_ports);
+        let state = self.sessions.state(client).cloned();
+        let variant = variant_name(packet);
+
+        if let Some(seq) = sequence_number(packet) {
+            let retransmit = self.retransmits.observe(src, dst, seq, ts).is_some();
+            let highest = self.highest.entry((src, dst)).or_insert(seq);
+            if seq < *highest && !retransmit {
+                violations.push(format!(
+                    "sequence number {} is lower than {}, seen earlier on this flow",
+                    seq, highest
+                ));
+            }
+            *highest = seq.max(*highest);
+        }
+
+        match variant.as_str() {
+            "Request" => {
+                if let Some(seq) = sequence_number(packet) {
+                    let requests = self.requests.entry(client).or_default();
+                    if requests.len() == RECENT_REQUESTS {
+                        requests.pop_front();
+                    }
+                    requests.push_back(seq);
+                }
+            }
+            "Response" if state.is_some() => {
+                let ack = packet
+                    .get("Response")
+                    .and_then(|body| body.get("request_ack"))
+                    .and_then(Value::as_u64);
+                if let Some(ack) = ack {
+                    let requested = self
+                        .requests
+                        .get(&client)
+                        .map_or(false, |requests| requests.contains(&ack));
+                    if !requested {
+                        violations.push(format!(
+                            "response acknowledges request {}, which {} never sent",
+                            ack, client
+                        ));
+                    }
+                }
+            }
+            "Update" | "UpdateReply" => match &state {
+                Some(SessionState::InRoom(_)) | Some(SessionState::InGame(_)) | None => {}
+                Some(state) => violations.push(format!(
+                    "game update while {} is {}, before joining a room",
+                    client, state
+                )),
+            },
+            _ => {}
+        }
+
+        self.sessions.observe(src, dst, packet);
+        violations
+    }
+}
+
+impl Analyzer for Checker {
+    fn on_packet(&mut self, context: &Context, packet: &Packet, value: &Value) {
+        for violation in self.observe(context.ts, context.src, context.dst, value) {
+            if context.shown {
+                warn!(
+                    "Protocol violation from {} to {}: {}\n  in {:?}",
+                    context.src, context.dst, violation, packet
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! `--config`: options kept in a TOML file instead of retyped on every run.
+//!
+//! Each key is the long name of an option, with dashes or underscores, and each value what would
+//! follow it on the command line. `true` passes a flag, and arrays repeat an option:
+//!
+//! ```toml
+//! interface = ["eth0", "lo"]
+//! only = ["Request", "Response"]
+//! custom-bpf = "udp port 2016 and not host 10.0.0.99"
+//! delta = true
+//! theme = "/etc/dissect-netwayste/theme.txt"
+//! ```
+//!
+//! A theme and alert rules can also be written in place, in the same syntax as their own files:
+//!
+//! ```toml
+//! alerts = ["failure_rate > 5/s => exec /usr/local/bin/page-oncall"]
+//!
+//! [theme]
+//! palette = ["cyan", "#ff8700", "141"]
+//! flow = { "10.0.0.5:2016" = "#5fd7ff" }
+//! class = { duplicate = "244" }
+//! ```
+//!
+//! Options given on the command line win over the file, apart from repeatable ones, which add
+//! to it.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+/// Looked for in the working directory when `--config` isn't given.
+const LOCAL_FILE: &str = "dissect.toml";
+
+/// What a config file adds to the command line.
+#[derive(Default)]
+pub struct Config {
+    /// To go before the options actually given
+    pub args: Vec<OsString>,
+    /// Theme settings written in place, as lines of a theme file
+    pub theme: Option<String>,
+    /// Alert rules written in place, as lines of a rules file
+    pub alerts: Option<String>,
+}
+
+/// The config file to read: the one named by `--config`, or the first of `./dissect.toml` and
+/// `$XDG_CONFIG_HOME/dissect-netwayste/config.toml` that exists, unless `--no-config` is given.
+pub fn path(argv: &[OsString]) -> Option<PathBuf> {
+    let mut argv = argv.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = argv.next() {
+        if arg == "--no-config" {
+            return None;
+        }
+        if arg == "--config" {
+            return argv.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+        // Anything after this is a positional value, not an option
+        if arg == "--" {
+            break;
+        }
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    [
+        Some(PathBuf::from(LOCAL_FILE)),
+        config_home.map(|dir| dir.join("dissect-netwayste/config.toml")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.is_file())
+}
+
+/// Reads a config file, checking each key against the options `command` knows.
+pub fn load(path: &Path, command: &clap::Command) -> Result<Config, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let table: Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let mut config = Config::default();
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+        match (name.as_str(), &value) {
+            ("theme", Value::Table(theme)) => {
+                config.theme = Some(theme_lines(theme)?);
+                continue;
+            }
+            ("alerts", Value::Array(rules)) => {
+                let rules = rules
+                    .iter()
+                    .map(|rule| rule.as_str().ok_or("'alerts' rules must be strings"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                config.alerts = Some(rules.join("\n"));
+                continue;
+            }
+            ("config" | "no-config", _) => {
+                return Err(format!("'{}' can only be given on the command line", key))
+            }
+            _ => {}
+        }
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+            .ok_or_else(|| format!("unknown option '{}'", key))?;
+        let option = OsString::from(format!("--{}", name));
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) if !arg.get_action().takes_values() => {
+                    config.args.push(option.clone())
+                }
+                Value::Boolean(false) if !arg.get_action().takes_values() => {}
+                Value::Table(_) | Value::Array(_) => {
+                    return Err(format!(
+                        "'{}' takes a value, not a table or nested array",
+                        key
+                    ))
+                }
+                Value::String(value) => {
+                    config.args.push(option.clone());
+                    config.args.push(value.into());
+                }
+                value => {
+                    config.args.push(option.clone());
+                    config.args.push(value.to_string().into());
+                }
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// A `[theme]` table, as the lines of a theme file.
+fn theme_lines(theme: &Table) -> Result<String, String> {
+    let color = |value: &Value| match value {
+        Value::String(color) => Ok(color.clone()),
+        // 256-color indexes are natural to write unquoted
+        Value::Integer(index) => Ok(index.to_string()),
+        _ => Err(format!("bad theme color '{}'", value)),
+    };
+    let mut lines = vec![];
+    for (key, value) in theme {
+        match (key.as_str(), value) {
+            ("palette", Value::Array(colors)) => {
+                let colors = colors.iter().map(color).collect::<Result<Vec<_>, _>>()?;
+                lines.push(format!("palette = {}", colors.join(", ")));
+            }
+            ("flow" | "class", Value::Table(entries)) => {
+                for (name, value) in entries {
+                    lines.push(format!("{} {} = {}", key, name, color(value)?));
+                }
+            }
+            _ => return Err(format!("unknown theme setting '{}'", key)),
+        }
+    }
+    Ok(lines.join("\n"))
+}
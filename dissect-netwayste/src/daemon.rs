@@ -0,0 +1,76 @@
+//! `--daemon` and `--pidfile`: running unattended in the background, e.g. as a protocol monitor
+//! started by an init script or by systemd with `Type=forking`.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use tracing::*;
+
+/// Set once the pidfile is written, so whichever way the process ends can remove it.
+static PIDFILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set once `daemonize()` has put stderr on /dev/null.
+static DETACHED: AtomicBool = AtomicBool::new(false);
+
+/// Detaches from the terminal and carries on in the background, with stdin, stdout and stderr
+/// on /dev/null. Panics are logged from then on, since their messages would otherwise go
+/// nowhere. The working directory is kept, so relative paths still work. Call it before
+/// starting any threads, since only the calling thread survives the fork.
+pub fn daemonize() -> io::Result<()> {
+    // Safety: nothing else is running yet, so the child has everything the parent had
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Forking again leaves a process that isn't a session leader, so can never pick up a
+        // controlling terminal by opening one
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+    }
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..=2 {
+        // Safety: both descriptors are open, and 0-2 are never used as Rust-owned handles
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    DETACHED.store(true, Ordering::Relaxed);
+    panic::set_hook(Box::new(|info| error!("{}", info)));
+    Ok(())
+}
+
+/// Whether `daemonize()` has run, so anything written to stderr is lost.
+pub fn detached() -> bool {
+    DETACHED.load(Ordering::Relaxed)
+}
+
+pub fn write_pidfile(path: &Path) -> io::Result<()> {
+    fs::write(path, format!("{}\n", process::id()))?;
+    PIDFILE.set(path.to_owned()).ok();
+    Ok(())
+}
+
+/// Removes the pidfile, if one was written. It may not be removable after `--drop-privs`, in
+/// which case it's left for the next start to overwrite.
+pub fn remove_pidfile() {
+    if let Some(path) = PIDFILE.get() {
+        fs::remove_file(path).ok();
+    }
+}
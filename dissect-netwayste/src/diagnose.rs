@@ -0,0 +1,603 @@
+//! Works out where and why a payload failed to de-serialize.
+//!
+//! bincode's own errors say what went wrong but not where, so this walks the payload again
+//! using the packet's traced layout (the same one the Lua dissector is generated from),
+//! keeping track of the byte offset and the field being read.
+//!
+//! The same walk over a payload that did decode gives `--tui` its tree of fields, each with the
+//! bytes it came from.
+
+use std::fmt;
+
+use serde_reflection::{ContainerFormat, Format, Named, Registry, VariantFormat};
+
+/// Where decoding went wrong.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Byte offset into the payload of the value that couldn't be read
+    pub offset: usize,
+    /// e.g. `Packet::Update.games[0].len`
+    pub path: String,
+    pub reason: String,
+}
+
+/// One field of a payload, with the fields inside it.
+#[derive(Debug, Clone)]
+pub struct Field {
+    /// Field name, variant name or `[index]`
+    pub name: String,
+    /// Set for plain values like numbers and strings
+    pub value: Option<String>,
+    /// Byte range of the payload the field was read from
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    fn new(name: &str, start: usize) -> Self {
+        Field {
+            name: name.to_owned(),
+            value: None,
+            start,
+            end: start,
+            children: vec![],
+        }
+    }
+}
+
+/// The payload's fields as a tree rooted at `Packet`, or `None` if it doesn't decode.
+pub fn fields(registry: &Registry, payload: &[u8]) -> Option<Field> {
+    let packet = registry.get("Packet")?;
+    let mut walker = Walker::new(registry, payload);
+    walker.tree = Some(vec![Field::new("Packet", 0)]);
+    walker.path.push("Packet".to_owned());
+    walker.container(packet).ok()?;
+    let mut root = walker.tree?.pop()?;
+    root.end = walker.offset;
+    Some(root)
+}
+
+/// How one variant fared when the payload was decoded as it.
+struct Attempt {
+    name: String,
+    /// `Ok` with the number of bytes used, or where it stopped
+    outcome: Result<usize, Divergence>,
+}
+
+pub struct Diagnosis {
+    len: usize,
+    /// The variant index from the first four bytes, and its name if it's a known one
+    discriminant: Option<(u32, Option<String>)>,
+    /// What went wrong decoding the payload as the variant it claims to be
+    pub divergence: Option<Divergence>,
+    /// The variant that got furthest when every one was tried in turn
+    closest: Option<Attempt>,
+}
+
+impl Diagnosis {
+    pub fn new(registry: &Registry, payload: &[u8]) -> Self {
+        let mut diagnosis = Diagnosis {
+            len: payload.len(),
+            discriminant: None,
+            divergence: None,
+            closest: None,
+        };
+        let Some(packet @ ContainerFormat::Enum(variants)) = registry.get("Packet") else {
+            return diagnosis;
+        };
+
+        let mut walker = Walker::new(registry, payload);
+        walker.path.push("Packet".to_owned());
+        if let Err(divergence) = walker.container(packet) {
+            diagnosis.divergence = Some(divergence);
+        }
+        if payload.len() >= 4 {
+            let index = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+            let name = variants.get(&index).map(|variant| variant.name.clone());
+            diagnosis.discriminant = Some((index, name));
+        }
+
+        // Whatever the discriminant says, see which variant the rest looks most like
+        for variant in variants.values() {
+            let mut walker = Walker::new(registry, payload);
+            walker.offset = 4.min(payload.len());
+            walker.path.push(format!("Packet::{}", variant.name));
+            let outcome = walker.variant(&variant.value).map(|()| walker.offset);
+            let attempt = Attempt {
+                name: variant.name.clone(),
+                outcome,
+            };
+            let better = match &diagnosis.closest {
+                None => true,
+                Some(best) => attempt.progress(payload.len()) > best.progress(payload.len()),
+            };
+            if better {
+                diagnosis.closest = Some(attempt);
+            }
+        }
+        diagnosis
+    }
+}
+
+impl Attempt {
+    /// How far the attempt got, ranking full decodes that use the whole payload first.
+    fn progress(&self, len: usize) -> (bool, usize) {
+        match &self.outcome {
+            Ok(used) => (*used == len, *used),
+            Err(divergence) => (false, divergence.offset),
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.discriminant {
+            None => return write!(f, "only {} bytes, too short for a variant index", self.len),
+            Some((index, Some(name))) => write!(f, "variant index {} ({})", index, name)?,
+            Some((index, None)) => write!(f, "variant index {}, which Packet doesn't have", index)?,
+        }
+        match &self.divergence {
+            Some(divergence) => write!(
+                f,
+                "; decoding stopped at byte {} of {} reading {}: {}",
+                divergence.offset, self.len, divergence.path, divergence.reason
+            )?,
+            None => write!(
+                f,
+                "; the layout decodes cleanly, so a value must have failed validation"
+            )?,
+        }
+        if let Some(closest) = &self.closest {
+            let claimed =
+                matches!(&self.discriminant, Some((_, Some(name))) if *name == closest.name);
+            if !claimed {
+                match &closest.outcome {
+                    Ok(used) => write!(
+                        f,
+                        "; the rest decodes as {} using {} of {} bytes",
+                        closest.name, used, self.len
+                    )?,
+                    Err(divergence) => write!(
+                        f,
+                        "; closest variant is {}, which gets to byte {}",
+                        closest.name, divergence.offset
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a payload the way bincode's default configuration lays it out: fixed-width little
+/// endian integers, u64 lengths, a u8 tag for options and a u32 index for enum variants.
+struct Walker<'a> {
+    registry: &'a Registry,
+    data: &'a [u8],
+    offset: usize,
+    path: Vec<String>,
+    /// Fields still being read, outermost first, when building a tree with `fields()`
+    tree: Option<Vec<Field>>,
+    /// The last plain value read, for the field it belongs to
+    leaf: Option<String>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(registry: &'a Registry, data: &'a [u8]) -> Self {
+        Walker {
+            registry,
+            data,
+            offset: 0,
+            path: vec![],
+            tree: None,
+            leaf: None,
+        }
+    }
+
+    fn diverge(&self, reason: String) -> Divergence {
+        Divergence {
+            offset: self.offset,
+            path: self.path.concat(),
+            reason,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Divergence> {
+        match self.data.get(self.offset..self.offset + len) {
+            Some(bytes) => {
+                self.offset += len;
+                Ok(bytes)
+            }
+            None => Err(self.diverge(format!(
+                "needed {} bytes, only {} left",
+                len,
+                self.data.len() - self.offset
+            ))),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, Divergence> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn seq_len(&mut self) -> Result<usize, Divergence> {
+        self.path.push(".len".to_owned());
+        let start = self.offset;
+        let b = self.take(8)?;
+        let len = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+        // Every element takes at least a byte except units, which nobody puts in a Vec
+        if len > (self.data.len() - self.offset) as u64 {
+            self.offset = start;
+            return Err(self.diverge(format!(
+                "length {} is more than the {} bytes left",
+                len,
+                self.data.len() - self.offset - 8
+            )));
+        }
+        self.path.pop();
+        Ok(len as usize)
+    }
+
+    fn scoped<T>(
+        &mut self,
+        segment: String,
+        read: impl FnOnce(&mut Self) -> Result<T, Divergence>,
+    ) -> Result<T, Divergence> {
+        if let Some(tree) = self.tree.as_mut() {
+            tree.push(Field::new(
+                segment.trim_start_matches(['.', ':']),
+                self.offset,
+            ));
+        }
+        self.path.push(segment);
+        let result = read(self)?;
+        self.path.pop();
+        if let Some(tree) = self.tree.as_mut() {
+            let mut field = tree.pop().unwrap();
+            field.end = self.offset;
+            field.value = self.leaf.take();
+            tree.last_mut().unwrap().children.push(field);
+        }
+        Ok(result)
+    }
+
+    fn container(&mut self, container: &ContainerFormat) -> Result<(), Divergence> {
+        match container {
+            ContainerFormat::UnitStruct => Ok(()),
+            ContainerFormat::NewTypeStruct(inner) => self.format(inner),
+            ContainerFormat::TupleStruct(elems) => self.tuple(elems),
+            ContainerFormat::Struct(fields) => self.fields(fields),
+            ContainerFormat::Enum(variants) => {
+                let start = self.offset;
+                let index = self.u32()?;
+                match variants.get(&index) {
+                    Some(variant) => {
+                        self.scoped(format!("::{}", variant.name), |w| w.variant(&variant.value))
+                    }
+                    None => {
+                        self.offset = start;
+                        Err(self.diverge(format!("no variant with index {}", index)))
+                    }
+                }
+            }
+        }
+    }
+
+    fn variant(&mut self, variant: &VariantFormat) -> Result<(), Divergence> {
+        match variant {
+            VariantFormat::Unit => Ok(()),
+            VariantFormat::NewType(inner) => self.format(inner),
+            VariantFormat::Tuple(elems) => self.tuple(elems),
+            VariantFormat::Struct(fields) => self.fields(fields),
+            VariantFormat::Variable(_) => Err(self.diverge("untraced variant".to_owned())),
+        }
+    }
+
+    fn fields(&mut self, fields: &[Named<Format>]) -> Result<(), Divergence> {
+        for field in fields {
+            self.scoped(format!(".{}", field.name), |w| w.format(&field.value))?;
+        }
+        Ok(())
+    }
+
+    fn tuple(&mut self, elems: &[Format]) -> Result<(), Divergence> {
+        for (i, elem) in elems.iter().enumerate() {
+            self.scoped(format!(".{}", i), |w| w.format(elem))?;
+        }
+        Ok(())
+    }
+
+    fn format(&mut self, format: &Format) -> Result<(), Divergence> {
+        match format {
+            Format::TypeName(name) => match self.registry.get(name) {
+                Some(container) => self.container(container),
+                None => Err(self.diverge(format!("unknown type {}", name))),
+            },
+            Format::Unit => Ok(()),
+            Format::Bool => {
+                let start = self.offset;
+                match self.take(1)?[0] {
+                    b @ (0 | 1) => {
+                        self.leaf = Some((b == 1).to_string());
+                        Ok(())
+                    }
+                    b => {
+                        self.offset = start;
+                        Err(self.diverge(format!("{} isn't a bool", b)))
+                    }
+                }
+            }
+            Format::I8 | Format::I16 | Format::I32 | Format::I64 | Format::I128 => {
+                let bytes = self.take(width(format))?;
+                // Sign-extend from the top byte
+                let fill = if bytes[bytes.len() - 1] & 0x80 != 0 {
+                    0xff
+                } else {
+                    0
+                };
+                let mut wide = [fill; 16];
+                wide[..bytes.len()].copy_from_slice(bytes);
+                self.leaf = Some(i128::from_le_bytes(wide).to_string());
+                Ok(())
+            }
+            Format::U8 | Format::U16 | Format::U32 | Format::U64 | Format::U128 => {
+                let bytes = self.take(width(format))?;
+                let mut wide = [0; 16];
+                wide[..bytes.len()].copy_from_slice(bytes);
+                self.leaf = Some(u128::from_le_bytes(wide).to_string());
+                Ok(())
+            }
+            Format::F32 => {
+                let b = self.take(4)?;
+                self.leaf = Some(f32::from_le_bytes([b[0], b[1], b[2], b[3]]).to_string());
+                Ok(())
+            }
+            Format::F64 => {
+                let b = self.take(8)?;
+                let bytes = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+                self.leaf = Some(f64::from_le_bytes(bytes).to_string());
+                Ok(())
+            }
+            Format::Char => {
+                let start = self.offset;
+                let first = self.take(1)?[0];
+                let width = match first {
+                    0x00..=0x7f => 1,
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 0,
+                };
+                self.offset = start;
+                if width == 0 {
+                    return Err(self.diverge(format!("{:#04x} can't start a char", first)));
+                }
+                let bytes = self.take(width)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(c) => {
+                        self.leaf = Some(format!("{:?}", c));
+                        Ok(())
+                    }
+                    Err(_) => {
+                        self.offset = start;
+                        Err(self.diverge("invalid UTF-8 in char".to_owned()))
+                    }
+                }
+            }
+            Format::Str => {
+                let len = self.seq_len()?;
+                let start = self.offset;
+                let bytes = self.take(len)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => {
+                        self.leaf = Some(format!("{:?}", s));
+                        Ok(())
+                    }
+                    Err(e) => {
+                        self.offset = start + e.valid_up_to();
+                        Err(self.diverge("invalid UTF-8 in string".to_owned()))
+                    }
+                }
+            }
+            Format::Bytes => {
+                let len = self.seq_len()?;
+                self.take(len)?;
+                self.leaf = Some(format!("{} bytes", len));
+                Ok(())
+            }
+            Format::Option(inner) => {
+                let start = self.offset;
+                match self.take(1)?[0] {
+                    0 => {
+                        self.leaf = Some("None".to_owned());
+                        Ok(())
+                    }
+                    1 => self.format(inner),
+                    tag => {
+                        self.offset = start;
+                        Err(self.diverge(format!("{} isn't an option tag", tag)))
+                    }
+                }
+            }
+            Format::Seq(elem) => {
+                let len = self.seq_len()?;
+                for i in 0..len {
+                    self.scoped(format!("[{}]", i), |w| w.format(elem))?;
+                }
+                Ok(())
+            }
+            Format::Map { key, value } => {
+                let len = self.seq_len()?;
+                for i in 0..len {
+                    self.scoped(format!("[{}].key", i), |w| w.format(key))?;
+                    self.scoped(format!("[{}].value", i), |w| w.format(value))?;
+                }
+                Ok(())
+            }
+            Format::Tuple(elems) => self.tuple(elems),
+            Format::TupleArray { content, size } => {
+                for i in 0..*size {
+                    self.scoped(format!("[{}]", i), |w| w.format(content))?;
+                }
+                Ok(())
+            }
+            Format::Variable(_) => Err(self.diverge("untraced format".to_owned())),
+        }
+    }
+}
+
+/// Bytes taken by an integer format.
+fn width(format: &Format) -> usize {
+    match format {
+        Format::I8 | Format::U8 => 1,
+        Format::I16 | Format::U16 => 2,
+        Format::I32 | Format::U32 => 4,
+        Format::I64 | Format::U64 => 8,
+        _ => 16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_reflection::{Tracer, TracerConfig};
+
+    use super::*;
+
+    /// Stands in for netwayste's, which is too big to lay out by hand.
+    #[derive(Serialize, Deserialize)]
+    enum Packet {
+        Ping {
+            id: u32,
+        },
+        Chat {
+            name: String,
+            lines: Vec<String>,
+            urgent: Option<bool>,
+        },
+    }
+
+    fn registry() -> Registry {
+        let mut tracer = Tracer::new(TracerConfig::default());
+        // One pass per variant
+        for _ in 0..2 {
+            tracer.trace_simple_type::<Packet>().unwrap();
+        }
+        tracer.registry().unwrap()
+    }
+
+    /// Laid out as the variant index at 0, `name` at 4, `lines` at 14, `lines[0]` at 22 and
+    /// `urgent` at 32, 34 bytes in all.
+    fn chat() -> Vec<u8> {
+        bincode::serialize(&Packet::Chat {
+            name: "al".to_owned(),
+            lines: vec!["hi".to_owned()],
+            urgent: Some(true),
+        })
+        .unwrap()
+    }
+
+    fn divergence(payload: &[u8]) -> Divergence {
+        Diagnosis::new(&registry(), payload).divergence.unwrap()
+    }
+
+    #[test]
+    fn fields_cover_their_bytes() {
+        let root = fields(&registry(), &chat()).unwrap();
+        assert_eq!(
+            (root.name.as_str(), root.start, root.end),
+            ("Packet", 0, 34)
+        );
+        let [chat] = &root.children[..] else {
+            panic!("expected one variant, got {:?}", root.children);
+        };
+        assert_eq!((chat.name.as_str(), chat.start, chat.end), ("Chat", 0, 34));
+
+        let summary: Vec<_> = chat
+            .children
+            .iter()
+            .map(|field| {
+                (
+                    field.name.as_str(),
+                    field.start,
+                    field.end,
+                    field.value.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("name", 4, 14, Some("\"al\"")),
+                ("lines", 14, 32, None),
+                ("urgent", 32, 34, Some("true")),
+            ]
+        );
+        let line = &chat.children[1].children[0];
+        assert_eq!(
+            (
+                line.name.as_str(),
+                line.start,
+                line.end,
+                line.value.as_deref()
+            ),
+            ("[0]", 22, 32, Some("\"hi\""))
+        );
+    }
+
+    #[test]
+    fn clean_payloads_have_no_divergence() {
+        for payload in [chat(), bincode::serialize(&Packet::Ping { id: 1 }).unwrap()] {
+            let diagnosis = Diagnosis::new(&registry(), &payload);
+            assert!(diagnosis.divergence.is_none());
+            assert!(diagnosis.to_string().contains("decodes cleanly"));
+        }
+    }
+
+    #[test]
+    fn truncation_is_found_where_it_cuts_a_value() {
+        let payload = chat();
+        let divergence = divergence(&payload[..31]);
+        assert_eq!(divergence.offset, 30);
+        assert_eq!(divergence.path, "Packet::Chat.lines[0]");
+        assert_eq!(divergence.reason, "needed 2 bytes, only 1 left");
+        assert_eq!(
+            fields(&registry(), &payload[..31]).map(|root| root.end),
+            None
+        );
+    }
+
+    #[test]
+    fn corrupt_values_are_found_at_their_offset() {
+        let mut payload = chat();
+        payload[33] = 2;
+        let divergence = divergence(&payload);
+        assert_eq!(divergence.offset, 33);
+        assert_eq!(divergence.path, "Packet::Chat.urgent");
+        assert_eq!(divergence.reason, "2 isn't a bool");
+
+        let mut payload = chat();
+        payload[21] = 1;
+        let divergence = divergence(&payload);
+        assert_eq!(divergence.offset, 14);
+        assert_eq!(divergence.path, "Packet::Chat.lines.len");
+    }
+
+    #[test]
+    fn unknown_variants_suggest_the_closest() {
+        let mut payload = chat();
+        payload[0] = 7;
+        let diagnosis = Diagnosis::new(&registry(), &payload);
+        let divergence = diagnosis.divergence.as_ref().unwrap();
+        assert_eq!(divergence.offset, 0);
+        assert_eq!(divergence.path, "Packet");
+        assert_eq!(
+            diagnosis.to_string(),
+            "variant index 7, which Packet doesn't have; decoding stopped at byte 0 of 34 \
+             reading Packet: no variant with index 7; the rest decodes as Chat using 34 of 34 \
+             bytes"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Peels a captured frame down to the payloads that might hold netwayste packets.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use pcap::Linktype;
+
+use etherparse::{
+    ether_type, ip_number,
+    InternetSlice::{Ipv4, Ipv6},
+    ReadError, SlicedPacket, TcpHeaderSlice, TransportSlice,
+    TransportSlice::{Tcp, Udp},
+    UdpHeaderSlice, VlanSlice,
+};
+
+use crate::capture::Frame;
+use crate::fragments::{Defragmenter, FragmentKey};
+use crate::tcp::{Reassembler, Segment};
+
+/// IANA-assigned VXLAN port (RFC 7348).
+const VXLAN_PORT: u16 = 4789;
+const VXLAN_HEADER_LEN: usize = 8;
+
+/// IP protocol numbers for the tunnels we unwrap.
+const IPPROTO_IPIP: u8 = 4;
+const IPPROTO_IPV6: u8 = 41;
+const IPPROTO_GRE: u8 = 47;
+
+/// GRE payload type for a whole Ethernet frame, as used by gretap.
+const ETHER_TYPE_TRANSPARENT_BRIDGING: u16 = 0x6558;
+
+const GRE_CHECKSUM_PRESENT: u16 = 0x8000;
+const GRE_KEY_PRESENT: u16 = 0x2000;
+const GRE_SEQUENCE_PRESENT: u16 = 0x1000;
+
+/// Tunnels nested deeper than this are most likely a malformed (or malicious) packet.
+const MAX_TUNNEL_DEPTH: usize = 4;
+
+/// A candidate netwayste payload along with the endpoints it travelled between.
+pub struct Datagram {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// 802.1Q VLAN identifiers, outermost first; empty for untagged frames
+    pub vlan_ids: Vec<u16>,
+    pub payload: Vec<u8>,
+}
+
+/// Holds the state needed across frames: IPv4 fragments and TCP streams in progress.
+#[derive(Default)]
+pub struct Dissector {
+    /// Whether to look inside GRE, VXLAN and IP-in-IP tunnels
+    decapsulate_tunnels: bool,
+    fragments: Defragmenter,
+    tcp: Reassembler,
+}
+
+impl Dissector {
+    pub fn new(decapsulate_tunnels: bool) -> Self {
+        Dissector {
+            decapsulate_tunnels,
+            ..Dissector::default()
+        }
+    }
+
+    /// Returns the candidate payloads carried by a frame: at most one for UDP, and any number
+    /// of complete messages for TCP. Frames that parsed fine but carry neither, including
+    /// frames of link types we don't know how to take apart and fragments of a datagram that
+    /// isn't complete yet, give an empty list.
+    pub fn dissect(&mut self, frame: &Frame) -> Result<Vec<Datagram>, ReadError> {
+        match slice_link_layer(frame.linktype, &frame.data)? {
+            Some(sliced) => self.dissect_sliced(frame.ts, sliced, vec![], 0),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// `vlan_ids` and `depth` carry over from the outer packet when this one came out of a
+    /// tunnel.
+    fn dissect_sliced(
+        &mut self,
+        ts: Duration,
+        sliced: SlicedPacket,
+        mut vlan_ids: Vec<u16>,
+        depth: usize,
+    ) -> Result<Vec<Datagram>, ReadError> {
+        let (src_ip, dst_ip, protocol, fragment): (IpAddr, IpAddr, u8, _) = match &sliced.ip {
+            Some(Ipv4(ipv4, _extensions)) => {
+                let fragment = if ipv4.more_fragments() || ipv4.fragments_offset() != 0 {
+                    let key = FragmentKey {
+                        src: ipv4.source_addr(),
+                        dst: ipv4.destination_addr(),
+                        id: ipv4.identification(),
+                        protocol: ipv4.protocol(),
+                    };
+                    // The header counts in 8-byte units
+                    let offset = ipv4.fragments_offset() as usize * 8;
+                    Some((key, offset, ipv4.more_fragments()))
+                } else {
+                    None
+                };
+                (
+                    ipv4.source_addr().into(),
+                    ipv4.destination_addr().into(),
+                    ipv4.protocol(),
+                    fragment,
+                )
+            }
+            Some(Ipv6(ipv6, _extensions)) => (
+                ipv6.source_addr().into(),
+                ipv6.destination_addr().into(),
+                ipv6.next_header(),
+                None,
+            ),
+            None => return Ok(vec![]),
+        };
+        match &sliced.vlan {
+            Some(VlanSlice::SingleVlan(single)) => vlan_ids.push(single.vlan_identifier()),
+            Some(VlanSlice::DoubleVlan(double)) => {
+                vlan_ids.push(double.outer().vlan_identifier());
+                vlan_ids.push(double.inner().vlan_identifier());
+            }
+            None => {}
+        }
+        let ips = (src_ip, dst_ip);
+
+        if let Some((key, offset, more_fragments)) = fragment {
+            // Whether etherparse took a transport header off the front depends on the fragment;
+            // stitch it back on to get this fragment's share of the IP payload.
+            let mut data = match &sliced.transport {
+                Some(Udp(udp)) => udp.slice().to_vec(),
+                Some(Tcp(tcp)) => tcp.slice().to_vec(),
+                _ => vec![],
+            };
+            data.extend_from_slice(sliced.payload);
+
+            return match self.fragments.push(ts, key, offset, more_fragments, &data) {
+                Some(reassembled) => {
+                    self.dissect_ip_payload(ts, ips, protocol, vlan_ids, &reassembled, depth)
+                }
+                None => Ok(vec![]),
+            };
+        }
+
+        match sliced.transport {
+            Some(transport @ Udp(_)) | Some(transport @ Tcp(_)) => {
+                self.transport_payloads(ts, ips, vlan_ids, transport, sliced.payload, depth)
+            }
+            _ => self.decapsulate(ts, protocol, vlan_ids, sliced.payload, depth),
+        }
+    }
+
+    /// Same as the tail end of `dissect_sliced()`, for a complete IP payload that we had to put
+    /// back together ourselves.
+    fn dissect_ip_payload(
+        &mut self,
+        ts: Duration,
+        ips: (IpAddr, IpAddr),
+        protocol: u8,
+        vlan_ids: Vec<u16>,
+        payload: &[u8],
+        depth: usize,
+    ) -> Result<Vec<Datagram>, ReadError> {
+        match protocol {
+            ip_number::UDP => {
+                let udp = UdpHeaderSlice::from_slice(payload)?;
+                let header_len = udp.slice().len();
+                self.transport_payloads(ts, ips, vlan_ids, Udp(udp), &payload[header_len..], depth)
+            }
+            ip_number::TCP => {
+                let tcp = TcpHeaderSlice::from_slice(payload)?;
+                let header_len = tcp.slice().len();
+                self.transport_payloads(ts, ips, vlan_ids, Tcp(tcp), &payload[header_len..], depth)
+            }
+            _ => self.decapsulate(ts, protocol, vlan_ids, payload, depth),
+        }
+    }
+
+    fn transport_payloads(
+        &mut self,
+        ts: Duration,
+        (src_ip, dst_ip): (IpAddr, IpAddr),
+        vlan_ids: Vec<u16>,
+        transport: TransportSlice,
+        payload: &[u8],
+        depth: usize,
+    ) -> Result<Vec<Datagram>, ReadError> {
+        match transport {
+            Udp(udp) => {
+                if udp.destination_port() == VXLAN_PORT && self.can_decapsulate(depth) {
+                    // 8-byte VXLAN header, then a whole Ethernet frame
+                    return match payload.get(VXLAN_HEADER_LEN..) {
+                        Some(inner) => {
+                            let inner = SlicedPacket::from_ethernet(inner)?;
+                            self.dissect_sliced(ts, inner, vlan_ids, depth + 1)
+                        }
+                        None => Ok(vec![]),
+                    };
+                }
+                Ok(vec![Datagram {
+                    src: SocketAddr::new(src_ip, udp.source_port()),
+                    dst: SocketAddr::new(dst_ip, udp.destination_port()),
+                    vlan_ids,
+                    payload: payload.to_vec(),
+                }])
+            }
+            Tcp(tcp) => {
+                let src = SocketAddr::new(src_ip, tcp.source_port());
+                let dst = SocketAddr::new(dst_ip, tcp.destination_port());
+                let messages = self.tcp.push(Segment {
+                    ts,
+                    src,
+                    dst,
+                    seq: tcp.sequence_number(),
+                    syn: tcp.syn(),
+                    fin: tcp.fin(),
+                    rst: tcp.rst(),
+                    payload,
+                });
+                Ok(messages
+                    .into_iter()
+                    .map(|payload| Datagram {
+                        src,
+                        dst,
+                        vlan_ids: vlan_ids.clone(),
+                        payload,
+                    })
+                    .collect())
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Unwraps IP-in-IP and GRE, whose IP protocol numbers etherparse doesn't know about.
+    fn decapsulate(
+        &mut self,
+        ts: Duration,
+        protocol: u8,
+        vlan_ids: Vec<u16>,
+        payload: &[u8],
+        depth: usize,
+    ) -> Result<Vec<Datagram>, ReadError> {
+        if !self.can_decapsulate(depth) {
+            return Ok(vec![]);
+        }
+        let inner = match protocol {
+            IPPROTO_IPIP | IPPROTO_IPV6 => SlicedPacket::from_ip(payload)?,
+            IPPROTO_GRE => match gre_payload(payload) {
+                Some((ether_type::IPV4, inner)) | Some((ether_type::IPV6, inner)) => {
+                    SlicedPacket::from_ip(inner)?
+                }
+                Some((ETHER_TYPE_TRANSPARENT_BRIDGING, inner)) => {
+                    SlicedPacket::from_ethernet(inner)?
+                }
+                _ => return Ok(vec![]),
+            },
+            _ => return Ok(vec![]),
+        };
+        self.dissect_sliced(ts, inner, vlan_ids, depth + 1)
+    }
+
+    fn can_decapsulate(&self, depth: usize) -> bool {
+        self.decapsulate_tunnels && depth < MAX_TUNNEL_DEPTH
+    }
+}
+
+/// Splits a GRE packet (RFC 2784, with the RFC 2890 key and sequence extensions) into the
+/// EtherType of its payload and the payload itself.
+fn gre_payload(data: &[u8]) -> Option<(u16, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([data[0], data[1]]);
+    let protocol = u16::from_be_bytes([data[2], data[3]]);
+    let mut header_len = 4;
+    for flag in [GRE_CHECKSUM_PRESENT, GRE_KEY_PRESENT, GRE_SEQUENCE_PRESENT] {
+        if flags & flag != 0 {
+            header_len += 4;
+        }
+    }
+    data.get(header_len..).map(|payload| (protocol, payload))
+}
+
+/// Hands the frame to the etherparse entry point that matches its link-layer header.
+pub fn slice_link_layer(
+    linktype: Linktype,
+    data: &[u8],
+) -> Result<Option<SlicedPacket>, ReadError> {
+    match linktype {
+        Linktype::ETHERNET => SlicedPacket::from_ethernet(data).map(Some),
+        // No link-layer header at all; the IP version nibble tells v4 and v6 apart
+        Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => SlicedPacket::from_ip(data).map(Some),
+        // BSD loopback: a 4-byte address family, whose values (and byte order, for NULL) depend
+        // on the capturing OS. Skipping it and sniffing the IP version is more portable.
+        Linktype::NULL | Linktype::LOOP => match data.get(4..) {
+            Some(ip) => SlicedPacket::from_ip(ip).map(Some),
+            None => Ok(None),
+        },
+        // Linux "cooked" captures, used for the "any" device. The protocol is an EtherType at the
+        // end of the 16-byte SLL header and at the start of the 20-byte SLL2 one.
+        Linktype::LINUX_SLL => sliced_from_ether_type(data, 14, 16),
+        Linktype::LINUX_SLL2 => sliced_from_ether_type(data, 0, 20),
+        _ => Ok(None),
+    }
+}
+
+fn sliced_from_ether_type(
+    data: &[u8],
+    type_offset: usize,
+    header_len: usize,
+) -> Result<Option<SlicedPacket>, ReadError> {
+    if data.len() < header_len {
+        return Ok(None);
+    }
+    let proto = u16::from_be_bytes([data[type_offset], data[type_offset + 1]]);
+    match proto {
+        ether_type::IPV4 | ether_type::IPV6 => SlicedPacket::from_ip(&data[header_len..]).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Formats an address for the source column, bracketing v6 addresses so the trailing `:port`
+/// stays unambiguous.
+pub fn display_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("[{}]", v6),
+    }
+}
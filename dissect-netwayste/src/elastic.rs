@@ -0,0 +1,113 @@
+//! Elasticsearch / OpenSearch bulk API export, either as NDJSON on stdout or pushed over HTTP.
+
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::output::json_line;
+use netwaystev2::protocol::Packet;
+use serde_json::{json, Value};
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// Documents sent per bulk request, unless `MAX_BATCH_AGE` passes first.
+const BATCH_DOCS: usize = 500;
+
+/// Quiet captures still show up in Kibana within this long.
+const MAX_BATCH_AGE: Duration = Duration::from_secs(5);
+
+/// The action line and document line for indexing one JSON document, each ending in a newline
+/// as the bulk API requires.
+pub fn bulk_lines(index: &str, document: &str) -> String {
+    format!(
+        "{}\n{}\n",
+        json!({ "index": { "_index": index } }),
+        document
+    )
+}
+
+/// Batches documents and sends them to a cluster's `_bulk` endpoint.
+pub struct ElasticPush {
+    agent: ureq::Agent,
+    bulk_url: String,
+    index: String,
+    pending: String,
+    docs: usize,
+    last_sent: Instant,
+}
+
+impl ElasticPush {
+    /// `url` is the cluster's base URL, e.g. `http://localhost:9200`.
+    pub fn new(url: &str, index: &str) -> Self {
+        ElasticPush {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+            bulk_url: format!("{}/_bulk", url.trim_end_matches('/')),
+            index: index.to_owned(),
+            pending: String::new(),
+            docs: 0,
+            last_sent: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, document: &str) {
+        self.pending.push_str(&bulk_lines(&self.index, document));
+        self.docs += 1;
+        if self.docs >= BATCH_DOCS || self.last_sent.elapsed() >= MAX_BATCH_AGE {
+            self.flush();
+        }
+    }
+
+    /// Sends whatever is batched. Failed batches are logged and dropped rather than retried, so
+    /// an unreachable cluster can't make us buffer without bound.
+    pub fn flush(&mut self) {
+        self.last_sent = Instant::now();
+        if self.docs == 0 {
+            return;
+        }
+        let body = std::mem::take(&mut self.pending);
+        let docs = std::mem::replace(&mut self.docs, 0);
+
+        let response = self
+            .agent
+            .post(&self.bulk_url)
+            .set("Content-Type", "application/x-ndjson")
+            .send_string(&body);
+        match response.map(|r| r.into_json::<Value>()) {
+            Ok(Ok(reply)) => {
+                // The request as a whole succeeds even when individual documents are rejected
+                if reply["errors"].as_bool() == Some(true) {
+                    let first_error = reply["items"]
+                        .as_array()
+                        .and_then(|items| items.iter().find_map(|item| item["index"].get("error")));
+                    warn!(
+                        "Elasticsearch rejected some of {} documents, e.g. {}",
+                        docs,
+                        first_error.unwrap_or(&Value::Null)
+                    );
+                }
+            }
+            Ok(Err(e)) => warn!("Unreadable reply from Elasticsearch bulk API: '{}'", e),
+            Err(e) => error!(
+                "Failed to send {} documents to Elasticsearch: '{}'",
+                docs, e
+            ),
+        }
+    }
+}
+
+impl Analyzer for ElasticPush {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        self.push(&json_line(
+            context.ts,
+            context.interface,
+            context.src,
+            context.dst,
+            value,
+        ));
+    }
+
+    fn on_shutdown(&mut self) {
+        self.flush();
+    }
+}
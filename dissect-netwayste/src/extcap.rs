@@ -0,0 +1,52 @@
+//! Wireshark extcap support: lets Wireshark list us as a capture interface and run us to capture,
+//! reading back a pcapng stream with netwayste decodes attached as packet comments.
+//!
+//! See <https://www.wireshark.org/docs/man-pages/extcap.html> for the protocol.
+
+use netwaystev2::DEFAULT_PORT as NETWAYSTE_PORT;
+
+/// The single interface we offer; the device to capture on is picked in its options.
+pub const INTERFACE: &str = "netwayste";
+
+/// Answers `--extcap-interfaces`.
+pub fn print_interfaces() {
+    println!(
+        "extcap {{version={}}}{{help=https://github.com/conwayste/conwayste-tools}}",
+        env!("CARGO_PKG_VERSION")
+    );
+    println!(
+        "interface {{value={}}}{{display=Netwayste capture with decoding}}",
+        INTERFACE
+    );
+}
+
+/// Answers `--extcap-dlts`. The stream describes each interface's real link type as it goes,
+/// so this is only what Wireshark shows before the capture starts.
+pub fn print_dlts() {
+    println!("dlt {{number=1}}{{name=EN10MB}}{{display=Ethernet}}");
+}
+
+/// Answers `--extcap-config` with the options Wireshark asks for before capturing. Each `call`
+/// is one of our own command line flags.
+pub fn print_config() {
+    println!(
+        "arg {{number=0}}{{call=--interface}}{{display=Network interface}}{{type=selector}}{{required=true}}"
+    );
+    let devices = pcap::Device::list().unwrap_or_default();
+    for (i, device) in devices.iter().enumerate() {
+        let display = device.desc.as_deref().unwrap_or(&device.name);
+        println!(
+            "value {{arg=0}}{{value={}}}{{display={}}}{{default={}}}",
+            device.name,
+            display,
+            i == 0
+        );
+    }
+    println!(
+        "arg {{number=1}}{{call=--port}}{{display=Netwayste port}}{{type=unsigned}}{{range=1,65535}}{{default={}}}",
+        NETWAYSTE_PORT
+    );
+    println!(
+        "arg {{number=2}}{{call=--decapsulate}}{{display=Decapsulate tunnels}}{{tooltip=Look inside GRE, VXLAN and IP-in-IP tunnels}}{{type=boolflag}}"
+    );
+}
@@ -0,0 +1,263 @@
+//! Builds the BPF expression handed to libpcap.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use clap::ValueEnum;
+use dissect_netwayste_core::ports::Ports;
+use pcap::Linktype;
+
+/// Which way packets travel, relative to the server.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter {
+    /// User-supplied BPF, used verbatim
+    Custom(String),
+    /// Netwayste traffic on some ports, over either UDP or the TCP fallback transport, optionally
+    /// narrowed to some hosts and one direction. With `tunnels`, also any GRE, VXLAN or IP-in-IP
+    /// traffic, whose inner ports and addresses BPF can't see.
+    Port {
+        ports: Ports,
+        tunnels: bool,
+        /// Packets to or from any of these
+        hosts: Vec<IpAddr>,
+        /// The server's address, which `direction` is judged by; the port alone otherwise
+        server: Option<IpAddr>,
+        direction: Option<Direction>,
+    },
+}
+
+impl Filter {
+    /// The expression to compile for a capture of the given link type.
+    pub fn bpf(&self, linktype: Linktype) -> String {
+        match self {
+            Filter::Custom(filter) => filter.clone(),
+            Filter::Port {
+                ports,
+                tunnels,
+                hosts,
+                server,
+                direction,
+            } => {
+                // Without the server's address, the server is whichever end has the port
+                let port_end = match (server, direction) {
+                    (None, Some(Direction::ToServer)) => "dst ",
+                    (None, Some(Direction::ToClient)) => "src ",
+                    _ => "",
+                };
+                // Fragments after the first carry no transport header to match a port against, so
+                // let all of them through for reassembly.
+                let mut expr =
+                    format!("{} or (ip[6:2] & 0x1fff != 0)", port_match(ports, port_end));
+                if let Some(server) = server {
+                    let server_end = match direction {
+                        Some(Direction::ToServer) => "dst ",
+                        Some(Direction::ToClient) => "src ",
+                        None => "",
+                    };
+                    expr = format!("({}) and {}host {}", expr, server_end, server);
+                }
+                if !hosts.is_empty() {
+                    let hosts: Vec<String> =
+                        hosts.iter().map(|host| format!("host {}", host)).collect();
+                    expr = format!("({}) and ({})", expr, hosts.join(" or "));
+                }
+                if *tunnels {
+                    expr.push_str(
+                        " or udp port 4789 or ip proto 4 or ip proto 41 or ip proto 47 \
+                         or ip6 proto 4 or ip6 proto 41 or ip6 proto 47",
+                    );
+                }
+                let expr = format!("({})", expr);
+                if linktype == Linktype::ETHERNET {
+                    // The `vlan` primitive shifts the offsets of everything after it in the whole
+                    // expression, `or` included, so each tag depth's copy of the match has to be
+                    // nested inside the one before rather than listed alongside it.
+                    format!("{0} or (vlan and ({0} or (vlan and {0})))", expr)
+                } else {
+                    // libpcap refuses `vlan` on anything but Ethernet-like link types
+                    expr
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Custom(filter) => write!(f, "{}", filter),
+            Filter::Port {
+                ports,
+                tunnels,
+                hosts,
+                server,
+                direction,
+            } => {
+                write!(f, "{}", port_match(ports, ""))?;
+                if let Some(server) = server {
+                    write!(f, ", server {}", server)?;
+                }
+                if let Some(direction) = direction {
+                    let direction = direction.to_possible_value().unwrap();
+                    write!(f, ", {}", direction.get_name())?;
+                }
+                if !hosts.is_empty() {
+                    let hosts: Vec<String> = hosts.iter().map(IpAddr::to_string).collect();
+                    write!(f, ", host {}", hosts.join(" or "))?;
+                }
+                if *tunnels {
+                    write!(f, ", plus tunnels")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// UDP or TCP traffic on any of `ports`, at the end given as `src `, `dst `, or nothing for either.
+fn port_match(ports: &Ports, end: &str) -> String {
+    let mut matches = vec![];
+    for protocol in ["udp", "tcp"] {
+        for range in ports.ranges() {
+            matches.push(if range.start() == range.end() {
+                format!("{} {}port {}", protocol, end, range.start())
+            } else {
+                format!(
+                    "{} {}portrange {}-{}",
+                    protocol,
+                    end,
+                    range.start(),
+                    range.end()
+                )
+            });
+        }
+    }
+    matches.join(" or ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(ports: Ports) -> Filter {
+        Filter::Port {
+            ports,
+            tunnels: false,
+            hosts: vec![],
+            server: None,
+            direction: None,
+        }
+    }
+
+    fn narrowed(hosts: &[&str], server: Option<&str>, direction: Option<Direction>) -> Filter {
+        Filter::Port {
+            ports: Ports::from(2016),
+            tunnels: false,
+            hosts: hosts.iter().map(|host| host.parse().unwrap()).collect(),
+            server: server.map(|server| server.parse().unwrap()),
+            direction,
+        }
+    }
+
+    const PORT_EXPR: &str = "udp port 2016 or tcp port 2016 or (ip[6:2] & 0x1fff != 0)";
+
+    #[test]
+    fn vlan_copies_are_nested() {
+        let filter = port(Ports::from(2016));
+        let expr = "(udp port 2016 or tcp port 2016 or (ip[6:2] & 0x1fff != 0))";
+        assert_eq!(
+            filter.bpf(Linktype::ETHERNET),
+            format!("{0} or (vlan and ({0} or (vlan and {0})))", expr)
+        );
+        assert_eq!(filter.bpf(Linktype::LINUX_SLL), expr);
+    }
+
+    #[test]
+    fn direction_without_server_picks_the_port_end() {
+        let to_server = narrowed(&[], None, Some(Direction::ToServer));
+        assert_eq!(
+            to_server.bpf(Linktype::LINUX_SLL),
+            "(udp dst port 2016 or tcp dst port 2016 or (ip[6:2] & 0x1fff != 0))"
+        );
+        let to_client = narrowed(&[], None, Some(Direction::ToClient));
+        assert_eq!(
+            to_client.bpf(Linktype::LINUX_SLL),
+            "(udp src port 2016 or tcp src port 2016 or (ip[6:2] & 0x1fff != 0))"
+        );
+    }
+
+    #[test]
+    fn direction_with_server_picks_the_host_end() {
+        let cases = [
+            (None, "host"),
+            (Some(Direction::ToServer), "dst host"),
+            (Some(Direction::ToClient), "src host"),
+        ];
+        for (direction, host) in cases {
+            let filter = narrowed(&[], Some("10.0.0.1"), direction);
+            assert_eq!(
+                filter.bpf(Linktype::LINUX_SLL),
+                format!("(({}) and {} 10.0.0.1)", PORT_EXPR, host)
+            );
+        }
+    }
+
+    #[test]
+    fn hosts_are_any_of() {
+        let filter = narrowed(&["10.0.0.2", "::1"], None, None);
+        assert_eq!(
+            filter.bpf(Linktype::LINUX_SLL),
+            format!("(({}) and (host 10.0.0.2 or host ::1))", PORT_EXPR)
+        );
+    }
+
+    #[test]
+    fn hosts_server_and_direction_combine() {
+        let filter = narrowed(&["10.0.0.2"], Some("10.0.0.1"), Some(Direction::ToClient));
+        assert_eq!(
+            filter.bpf(Linktype::LINUX_SLL),
+            format!(
+                "((({}) and src host 10.0.0.1) and (host 10.0.0.2))",
+                PORT_EXPR
+            )
+        );
+        assert_eq!(
+            filter.to_string(),
+            "udp port 2016 or tcp port 2016, server 10.0.0.1, to-client, host 10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn every_port_and_range_is_matched() {
+        let ports: Ports = ["2016", "3000-3002"]
+            .into_iter()
+            .map(|s| Ports::parse(s).unwrap())
+            .collect();
+        let expected = "udp port 2016 or udp portrange 3000-3002 \
+                        or tcp port 2016 or tcp portrange 3000-3002";
+        assert_eq!(
+            port(ports.clone()).bpf(Linktype::LINUX_SLL),
+            format!("({} or (ip[6:2] & 0x1fff != 0))", expected)
+        );
+        assert_eq!(port(ports.clone()).to_string(), expected);
+
+        let to_server = Filter::Port {
+            ports,
+            tunnels: false,
+            hosts: vec![],
+            server: None,
+            direction: Some(Direction::ToServer),
+        };
+        assert_eq!(
+            to_server.bpf(Linktype::LINUX_SLL),
+            "(udp dst port 2016 or udp dst portrange 3000-3002 \
+             or tcp dst port 2016 or tcp dst portrange 3000-3002 or (ip[6:2] & 0x1fff != 0))"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Sequence and payload checks on each flow: gaps in the sequence numbers are logged, while
+//! copies, retransmissions and reordering are marked on the packet's line.
+
+use dissect_netwayste_core::duplicates::DuplicateDetector;
+use dissect_netwayste_core::output::sequence_number;
+use dissect_netwayste_core::sequence::{ReorderTracker, RetransmitTracker, SequenceTracker};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context, Mark};
+
+/// `--seq-gaps`: tracked for every packet, but only reported for the ones being shown.
+#[derive(Default)]
+pub struct SequenceGaps(SequenceTracker);
+
+impl Analyzer for SequenceGaps {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let Some(seq) = sequence_number(value) else {
+            return;
+        };
+        let Some(gap) = self.0.observe(context.src, context.dst, seq, context.ts) else {
+            return;
+        };
+        if context.shown {
+            warn!(
+                "Sequence gap from {} to {}: {} missing ({}..={})",
+                context.src,
+                context.dst,
+                gap.missing(),
+                gap.first,
+                gap.last
+            );
+        }
+    }
+}
+
+/// Byte-for-byte copies, which `--suppress-dups` leaves out.
+#[derive(Default)]
+pub struct Duplicates(DuplicateDetector);
+
+impl Analyzer for Duplicates {
+    fn mark(&mut self, context: &Context, _value: Option<&Value>) -> Option<Mark> {
+        self.0
+            .observe(context.src, context.dst, context.payload)
+            .then_some(Mark::Duplicate)
+    }
+}
+
+/// `--reorder`
+#[derive(Default)]
+pub struct Reordering(ReorderTracker);
+
+impl Analyzer for Reordering {
+    fn mark(&mut self, context: &Context, value: Option<&Value>) -> Option<Mark> {
+        let seq = sequence_number(value?)?;
+        self.0
+            .observe(context.src, context.dst, seq, context.ts)
+            .map(Mark::OutOfOrder)
+    }
+}
+
+/// `--retransmits`
+#[derive(Default)]
+pub struct Retransmits(RetransmitTracker);
+
+impl Analyzer for Retransmits {
+    fn mark(&mut self, context: &Context, value: Option<&Value>) -> Option<Mark> {
+        let seq = sequence_number(value?)?;
+        self.0
+            .observe(context.src, context.dst, seq, context.ts)
+            .map(Mark::Retransmit)
+    }
+}
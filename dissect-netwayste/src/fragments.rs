@@ -0,0 +1,165 @@
+//! IPv4 fragment reassembly.
+//!
+//! Large `GenStateDiff` updates can exceed the path MTU and arrive in pieces, none of which
+//! decode on their own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// How long to wait for the rest of a datagram, in capture time. Matches Linux's default
+/// `ipfrag_time`.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A datagram can't be larger than the IPv4 total length field allows.
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// Fragments belong together when they share these (RFC 791).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub id: u16,
+    pub protocol: u8,
+}
+
+struct Partial {
+    first_seen: Duration,
+    /// Fragment data keyed by byte offset into the original IP payload
+    pieces: BTreeMap<usize, Vec<u8>>,
+    /// Known once the fragment without "more fragments" set arrives
+    total_len: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Defragmenter {
+    partials: HashMap<FragmentKey, Partial>,
+}
+
+impl Defragmenter {
+    pub fn new() -> Self {
+        Defragmenter::default()
+    }
+
+    /// Adds a fragment, returning the reassembled IP payload once every piece has arrived.
+    /// `offset` is in bytes, not the 8-byte units of the header field.
+    pub fn push(
+        &mut self,
+        ts: Duration,
+        key: FragmentKey,
+        offset: usize,
+        more_fragments: bool,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.partials
+            .retain(|_, partial| ts.saturating_sub(partial.first_seen) < REASSEMBLY_TIMEOUT);
+
+        if offset + data.len() > MAX_DATAGRAM_LEN {
+            return None;
+        }
+
+        let partial = self.partials.entry(key).or_insert_with(|| Partial {
+            first_seen: ts,
+            pieces: BTreeMap::new(),
+            total_len: None,
+        });
+        if !more_fragments {
+            partial.total_len = Some(offset + data.len());
+        }
+        partial.pieces.insert(offset, data.to_vec());
+
+        let total_len = partial.total_len?;
+        let mut covered = 0;
+        for (&piece_offset, piece) in partial.pieces.iter() {
+            if piece_offset > covered {
+                // Still waiting on a piece in the middle
+                return None;
+            }
+            covered = covered.max(piece_offset + piece.len());
+        }
+        if covered < total_len {
+            return None;
+        }
+
+        let partial = self.partials.remove(&key).unwrap();
+        let mut payload = vec![0u8; total_len];
+        for (piece_offset, piece) in partial.pieces {
+            // Overlapping fragments are legal; later data simply overwrites earlier data
+            let end = (piece_offset + piece.len()).min(total_len);
+            if piece_offset < end {
+                payload[piece_offset..end].copy_from_slice(&piece[..end - piece_offset]);
+            }
+        }
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: u16) -> FragmentKey {
+        FragmentKey {
+            src: Ipv4Addr::new(10, 0, 0, 1),
+            dst: Ipv4Addr::new(10, 0, 0, 2),
+            id,
+            protocol: 17,
+        }
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let mut defragmenter = Defragmenter::new();
+        let ts = Duration::from_secs(1);
+        assert_eq!(defragmenter.push(ts, key(1), 16, false, b"cc"), None);
+        assert_eq!(defragmenter.push(ts, key(1), 0, true, b"aaaaaaaa"), None);
+        // A fragment of another datagram doesn't complete this one
+        assert_eq!(defragmenter.push(ts, key(2), 8, true, b"xxxxxxxx"), None);
+        assert_eq!(
+            defragmenter.push(ts, key(1), 8, true, b"bbbbbbbb"),
+            Some(b"aaaaaaaabbbbbbbbcc".to_vec())
+        );
+        // Nothing is left over to be completed again
+        assert_eq!(defragmenter.push(ts, key(1), 8, true, b"bbbbbbbb"), None);
+    }
+
+    #[test]
+    fn overlapping_and_duplicate_fragments_are_reassembled() {
+        let mut defragmenter = Defragmenter::new();
+        let ts = Duration::from_secs(1);
+        assert_eq!(
+            defragmenter.push(ts, key(1), 0, true, b"aaaaaaaaaaaaaaaa"),
+            None
+        );
+        assert_eq!(
+            defragmenter.push(ts, key(1), 0, true, b"aaaaaaaaaaaaaaaa"),
+            None
+        );
+        assert_eq!(
+            defragmenter.push(ts, key(1), 8, false, b"bbbbbbbbcc"),
+            Some(b"aaaaaaaabbbbbbbbcc".to_vec())
+        );
+    }
+
+    #[test]
+    fn stale_and_oversized_fragments_are_dropped() {
+        let mut defragmenter = Defragmenter::new();
+        assert_eq!(
+            defragmenter.push(Duration::from_secs(1), key(1), 0, true, b"aaaaaaaa"),
+            None
+        );
+        // The first piece has been given up on by the time the last one arrives
+        let late = Duration::from_secs(1) + REASSEMBLY_TIMEOUT;
+        assert_eq!(defragmenter.push(late, key(1), 8, false, b"bb"), None);
+        assert_eq!(
+            defragmenter.push(late, key(1), 0, true, b"aaaaaaaa"),
+            Some(b"aaaaaaaabb".to_vec())
+        );
+
+        assert_eq!(
+            defragmenter.push(late, key(3), MAX_DATAGRAM_LEN - 1, false, b"zz"),
+            None
+        );
+        assert!(defragmenter.partials.is_empty());
+    }
+}
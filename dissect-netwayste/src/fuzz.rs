@@ -0,0 +1,287 @@
+//! `fuzz`: mutated netwayste packets fired at a server, to see what it makes of input no real
+//! client would send. Only point this at servers you run.
+//!
+//! Each input starts as one of the packets `generate` can build, then has one to three
+//! mutations applied: flipped bits, a tweaked length prefix, a swapped `Packet` variant index,
+//! truncation, or trailing junk. Replies are decoded, and an input is saved when it draws a
+//! reply that doesn't decode or a kind of reply not seen before. Every so often a valid probe
+//! checks the server is still answering; if it isn't, the inputs since the last good probe are
+//! saved and fuzzing stops.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use dissect_netwayste_core::output::{nested_variant, packet_value, variant_name};
+use dissect_netwayste_core::protocol::Protocol;
+use tracing::*;
+
+use crate::generate::{self, Generator, Mix};
+
+/// How many inputs go between liveness probes; also how many are saved if the server stops
+/// answering.
+const PROBE_EVERY: u64 = 100;
+
+/// How long a probe waits for an answer, and how many times it's tried.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const PROBE_TRIES: u32 = 3;
+
+pub struct Options {
+    pub server: SocketAddr,
+    pub rate: f64,
+    pub iterations: Option<u64>,
+    /// Name of the valid packet sent as a liveness probe
+    pub probe: String,
+    pub cookie: Option<String>,
+    /// Where interesting inputs are saved
+    pub out: PathBuf,
+    /// How long to wait for replies to each input
+    pub wait: Duration,
+    pub seed: Option<u64>,
+}
+
+/// xorshift64, seeded so a run can be repeated with `--seed`.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+}
+
+pub fn run(options: Options) -> Result<(), String> {
+    let seeds = generate::all_packets(options.cookie.as_deref())?;
+    if seeds.is_empty() {
+        return Err("no packets to start from".to_owned());
+    }
+    let mix = Mix::parse(&options.probe)?;
+    let mut probe = Generator::new(&mix, options.cookie.clone())?;
+    let (_, probe) = probe.pick(1, 0);
+    let probe = bincode::serialize(&probe).expect("netwayste packet not serializable");
+    // Past the last real variant too, to see what the server does with those
+    let variants = seeds
+        .iter()
+        .filter_map(|(_, payload)| payload.get(..4))
+        .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+        .max()
+        .unwrap_or(0)
+        + 3;
+
+    fs::create_dir_all(&options.out)
+        .map_err(|e| format!("can't create '{}': {}", options.out.display(), e))?;
+    let bind: SocketAddr = if options.server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't open a socket: {}", e))?;
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    }) | 1;
+    let mut rng = Rng(seed);
+    let decoder = Protocol::V2.decoder();
+    info!(
+        "Fuzzing {} from {} seed packets, with --seed {}",
+        options.server,
+        seeds.len(),
+        seed
+    );
+
+    if !answers(&socket, options.server, &probe) {
+        return Err(format!(
+            "{} doesn't answer a valid {}",
+            options.server, options.probe
+        ));
+    }
+
+    let interval = Duration::from_secs_f64(1.0 / options.rate);
+    let started = Instant::now();
+    let mut recent: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut replies_seen: HashSet<String> = HashSet::new();
+    let mut saved = 0;
+    let mut sent = 0u64;
+    while options
+        .iterations
+        .map_or(true, |iterations| sent < iterations)
+    {
+        if let Some(wait) = interval.mul_f64(sent as f64).checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+        let (name, seed_payload) = &seeds[rng.below(seeds.len())];
+        let mut input = seed_payload.clone();
+        let mut applied = vec![];
+        for _ in 0..1 + rng.below(3) {
+            applied.push(mutate(&mut input, &mut rng, variants));
+        }
+        let label = format!("{} {}", name, applied.join("+"));
+        if let Err(e) = socket.send_to(&input, options.server) {
+            warn!("Failed to send input {}: {}", sent, e);
+        }
+        sent += 1;
+        recent.push_back(input.clone());
+        if recent.len() > PROBE_EVERY as usize {
+            recent.pop_front();
+        }
+
+        for reply in replies(&socket, options.wait) {
+            let (kind, why) = match decoder.decode(&reply) {
+                Ok(packet) => {
+                    let value = packet_value(&packet);
+                    let kind = match nested_variant(&value, "code") {
+                        Some(code) => format!("{}-{}", variant_name(&value), code),
+                        None => variant_name(&value),
+                    };
+                    (kind, "a reply not seen before")
+                }
+                Err(_) => ("undecodable".to_owned(), "a reply that doesn't decode"),
+            };
+            if kind == "undecodable" || replies_seen.insert(kind.clone()) {
+                info!("Input {} ({}) drew {}: {}", sent, label, why, kind);
+                save(&options.out, &format!("{}-{}", sent, kind), &input);
+                save(&options.out, &format!("{}-{}-reply", sent, kind), &reply);
+                saved += 1;
+            }
+        }
+
+        if sent % PROBE_EVERY == 0 {
+            if !answers(&socket, options.server, &probe) {
+                error!(
+                    "{} stopped answering after input {}; saving the last {} inputs",
+                    options.server,
+                    sent,
+                    recent.len()
+                );
+                let first = sent + 1 - recent.len() as u64;
+                for (i, input) in recent.iter().enumerate() {
+                    save(
+                        &options.out,
+                        &format!("unanswered-{}", first + i as u64),
+                        input,
+                    );
+                }
+                return Ok(());
+            }
+            info!(
+                "{} inputs sent, {} saved, server still answering",
+                sent, saved
+            );
+        }
+    }
+    info!(
+        "Sent {} inputs in {:.1?}, {} saved to '{}'",
+        sent,
+        started.elapsed(),
+        saved,
+        options.out.display()
+    );
+    Ok(())
+}
+
+/// Applies one mutation, returning what it was.
+fn mutate(input: &mut Vec<u8>, rng: &mut Rng, variants: u32) -> &'static str {
+    match rng.below(5) {
+        0 if !input.is_empty() => {
+            let i = rng.below(input.len());
+            input[i] ^= 1 << rng.below(8);
+            "bit flip"
+        }
+        1 => {
+            // bincode puts a u64 length before each string and sequence; any small u64 is
+            // likely one of those
+            let lengths: Vec<usize> = (0..input.len().saturating_sub(7))
+                .filter(|&i| {
+                    let value = u64::from_le_bytes(input[i..i + 8].try_into().unwrap());
+                    value > 0 && value <= input.len() as u64
+                })
+                .collect();
+            if lengths.is_empty() {
+                input.truncate(rng.below(input.len()));
+                return "truncation";
+            }
+            let i = lengths[rng.below(lengths.len())];
+            let value = u64::from_le_bytes(input[i..i + 8].try_into().unwrap());
+            let tweaked = match rng.below(4) {
+                0 => value - 1,
+                1 => value + 1,
+                2 => u32::MAX as u64,
+                _ => u64::MAX,
+            };
+            input[i..i + 8].copy_from_slice(&tweaked.to_le_bytes());
+            "length tweak"
+        }
+        2 if input.len() >= 4 => {
+            let index = rng.below(variants as usize) as u32;
+            input[..4].copy_from_slice(&index.to_le_bytes());
+            "variant swap"
+        }
+        3 => {
+            input.truncate(rng.below(input.len()));
+            "truncation"
+        }
+        _ => {
+            let junk: Vec<u8> = (0..1 + rng.below(32))
+                .map(|_| rng.next_u64() as u8)
+                .collect();
+            input.extend(junk);
+            "trailing junk"
+        }
+    }
+}
+
+/// Whatever comes back within `wait`.
+fn replies(socket: &UdpSocket, wait: Duration) -> Vec<Vec<u8>> {
+    let mut replies = vec![];
+    let deadline = Instant::now() + wait;
+    let mut buf = [0; 65536];
+    loop {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        if left.is_zero() || socket.set_read_timeout(Some(left)).is_err() {
+            break;
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => replies.push(buf[..len].to_vec()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => {
+                // e.g. ICMP port unreachable, reported on the next read; the probe catches it
+                debug!("Reading replies failed: {}", e);
+                break;
+            }
+        }
+    }
+    replies
+}
+
+/// Whether the server replies to `probe` at all.
+fn answers(socket: &UdpSocket, server: SocketAddr, probe: &[u8]) -> bool {
+    for _ in 0..PROBE_TRIES {
+        // Leftover replies to fuzz inputs don't count
+        replies(socket, Duration::from_millis(1));
+        if socket.send_to(probe, server).is_ok() && !replies(socket, PROBE_TIMEOUT).is_empty() {
+            return true;
+        }
+    }
+    false
+}
+
+fn save(dir: &Path, name: &str, data: &[u8]) {
+    let path = dir.join(format!("{}.bin", name));
+    if let Err(e) = fs::write(&path, data) {
+        error!("Failed to save '{}': {}", path.display(), e);
+    }
+}
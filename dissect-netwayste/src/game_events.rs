@@ -0,0 +1,168 @@
+//! `--game-events`: what happened in each game, as JSON lines for analytics notebooks.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::gen_diff::{find_diffs, runs};
+use dissect_netwayste_core::output::format_time;
+use dissect_netwayste_core::packet_filter::{client_end, find_strings};
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::session::{SessionState, SessionTracker};
+use netwaystev2::protocol::Packet;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+use crate::chat::ChatMonitor;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Kind {
+    /// The room's universe reached a new generation
+    Generation {
+        generation: u64,
+    },
+    /// A player sent the server a pattern to place
+    Placed {
+        player: String,
+        cells: u64,
+    },
+    Chat {
+        player: String,
+        text: String,
+    },
+    /// A player joined, left, or started a game; see `Transition::event()`
+    Phase {
+        player: String,
+        phase: String,
+    },
+}
+
+#[derive(Serialize)]
+struct GameEvent<'a> {
+    timestamp: Option<String>,
+    /// `None` if it couldn't be worked out which room the event happened in
+    room: Option<&'a str>,
+    #[serde(flatten)]
+    kind: Kind,
+}
+
+pub struct GameEvents {
+    server_ports: Ports,
+    sessions: SessionTracker,
+    chat: ChatMonitor,
+    /// Latest generation seen in each room
+    generations: HashMap<String, u64>,
+    out: BufWriter<File>,
+}
+
+impl GameEvents {
+    pub fn create(path: &Path, server_ports: Ports) -> io::Result<Self> {
+        Ok(GameEvents {
+            server_ports: server_ports.clone(),
+            sessions: SessionTracker::new(server_ports.clone()),
+            chat: ChatMonitor::new(server_ports),
+            generations: HashMap::new(),
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Call with every decoded packet, in capture order.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+    ) -> io::Result<()> {
+        let client = client_end(src, dst, &self.server_ports);
+        let room_before = room(self.sessions.state(client));
+        let transition = self.sessions.observe(src, dst, packet);
+        let room = room(self.sessions.state(client)).or(room_before);
+        let player = self
+            .sessions
+            .name(client)
+            .map_or_else(|| client.to_string(), str::to_owned);
+
+        let mut kinds = vec![];
+        if let Some(transition) = transition {
+            kinds.push(Kind::Phase {
+                player: player.clone(),
+                phase: transition.event(),
+            });
+        }
+        if let Some(chat) = self.chat.observe(src, dst, packet) {
+            kinds.push(Kind::Chat {
+                player: chat.player,
+                text: chat.text,
+            });
+        }
+        if self.server_ports.contains(src.port()) {
+            if let Some(room) = &room {
+                for diff in find_diffs(packet) {
+                    let latest = self.generations.entry(room.clone()).or_default();
+                    if diff.gen1 > *latest {
+                        *latest = diff.gen1;
+                        kinds.push(Kind::Generation {
+                            generation: diff.gen1,
+                        });
+                    }
+                }
+            }
+        } else if find_diffs(packet).is_empty() {
+            let mut patterns = vec![];
+            find_strings(packet, "pattern", &mut patterns);
+            // A pattern that can't be parsed is left out rather than counted wrong
+            for runs in patterns.into_iter().filter_map(runs) {
+                kinds.push(Kind::Placed {
+                    player: player.clone(),
+                    cells: runs.iter().map(|(_, _, run)| run).sum(),
+                });
+            }
+        }
+
+        for kind in kinds {
+            let event = GameEvent {
+                timestamp: format_time(ts),
+                room: room.as_deref(),
+                kind,
+            };
+            let line = serde_json::to_string(&event).expect("failed to serialize game event");
+            writeln!(self.out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn room(state: Option<&SessionState>) -> Option<String> {
+    match state {
+        Some(SessionState::InRoom(room)) | Some(SessionState::InGame(room)) => Some(room.clone()),
+        _ => None,
+    }
+}
+
+impl Analyzer for GameEvents {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        if !context.shown {
+            return;
+        }
+        if let Err(e) = self.observe(context.ts, context.src, context.dst, value) {
+            error!("Failed to write game event: '{}'", e);
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to finish game events file: '{}'", e);
+        }
+    }
+}
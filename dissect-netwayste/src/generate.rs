@@ -0,0 +1,214 @@
+//! `generate`: synthetic netwayste traffic at a steady rate, as background load for working on
+//! a server.
+//!
+//! Packets start out as the all-zero `templates` of the core crate, so any `Packet` variant or
+//! request action can be asked for by name. Then `sequence` counts up per client, `cookie` is
+//! set if one is given, and `message` says which packet it is.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::templates::{names, template, trace_packet};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+/// How often the totals so far are logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Which packets to send, by name, and how many of each relative to the others.
+#[derive(Debug, Clone)]
+pub struct Mix(Vec<(String, u32)>);
+
+impl Mix {
+    /// Parses e.g. `KeepAlive=10,SendChatMessage=1`. Meant to be used as a clap `value_parser`.
+    pub fn parse(s: &str) -> Result<Mix, String> {
+        let mut mix = vec![];
+        for part in s.split(',') {
+            let (name, weight) = part.split_once('=').unwrap_or((part, "1"));
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("'{}' has a bad weight: {}", part, e))?;
+            if weight > 0 {
+                mix.push((name.trim().to_owned(), weight));
+            }
+        }
+        if mix.is_empty() {
+            return Err("nothing to send".to_owned());
+        }
+        Ok(Mix(mix))
+    }
+}
+
+/// Traffic to send.
+#[derive(Clone)]
+pub struct Generator {
+    /// Each kind of packet in the mix, as serde lays it out, with its weight
+    templates: Vec<(String, Value, u32)>,
+    cookie: Option<String>,
+    /// Running totals for smooth weighted round-robin, which spreads each kind evenly
+    current: Vec<i64>,
+}
+
+impl Generator {
+    pub fn new(mix: &Mix, cookie: Option<String>) -> Result<Self, String> {
+        let registry = trace_packet()?;
+        let mut templates = vec![];
+        for (name, weight) in &mix.0 {
+            let template = template(&registry, name)?;
+            // Fail now, rather than on the first send, if serde won't take it back
+            build(&template, cookie.as_deref(), 0, 0)
+                .map_err(|e| format!("can't build a {}: {}", name, e))?;
+            templates.push((name.clone(), template, *weight));
+        }
+        Ok(Generator {
+            current: vec![0; templates.len()],
+            templates,
+            cookie,
+        })
+    }
+
+    /// For a client that logged in after the generator was made.
+    pub fn set_cookie(&mut self, cookie: Option<String>) {
+        self.cookie = cookie;
+    }
+
+    /// The next packet in the mix, and its name.
+    pub fn pick(&mut self, sequence: u64, n: u64) -> (&str, Packet) {
+        let total: i64 = self.templates.iter().map(|(_, _, w)| *w as i64).sum();
+        for (current, (_, _, weight)) in self.current.iter_mut().zip(&self.templates) {
+            *current += *weight as i64;
+        }
+        let (i, _) = self
+            .current
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, current)| (**current, std::cmp::Reverse(*i)))
+            .unwrap();
+        self.current[i] -= total;
+        let (name, template, _) = &self.templates[i];
+        let packet = build(template, self.cookie.as_deref(), sequence, n)
+            .expect("template checked when loading");
+        (name, packet)
+    }
+}
+
+/// The packet `template` describes, as the `n`th sent and with the client's `sequence`.
+pub fn build(
+    template: &Value,
+    cookie: Option<&str>,
+    sequence: u64,
+    n: u64,
+) -> Result<Packet, String> {
+    let mut value = template.clone();
+    fill(&mut value, cookie, sequence, n);
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn fill(value: &mut Value, cookie: Option<&str>, sequence: u64, n: u64) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                let filled: Option<Value> = match (key.as_str(), &*field) {
+                    ("sequence", Value::Number(_)) => Some(sequence.into()),
+                    ("cookie", _) => cookie.map(Value::from),
+                    ("message", Value::String(_)) => {
+                        Some(format!("generated message {}", n).into())
+                    }
+                    _ => None,
+                };
+                match filled {
+                    Some(filled) => *field = filled,
+                    None => fill(field, cookie, sequence, n),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                fill(value, cookie, sequence, n);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Every packet that can be asked for by name, serialized, with its name. `fuzz` starts from
+/// these.
+pub fn all_packets(cookie: Option<&str>) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let registry = trace_packet()?;
+    let mut packets = vec![];
+    for name in names(&registry) {
+        // Variants of enums that aren't actions, e.g. response codes, have no template
+        let Ok(template) = template(&registry, &name) else {
+            continue;
+        };
+        if let Ok(packet) = build(&template, cookie, 1, 0) {
+            let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+            packets.push((name, payload));
+        }
+    }
+    Ok(packets)
+}
+
+/// Sends `count` packets, or until stopped, at `rate` a second to `server`, spread over
+/// `clients` sockets.
+pub fn run(
+    mut generator: Generator,
+    server: SocketAddr,
+    rate: f64,
+    clients: u16,
+    count: Option<u64>,
+) {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let sockets: Vec<UdpSocket> = (0..clients)
+        .map(|_| UdpSocket::bind(bind).expect("Failed to open a UDP socket"))
+        .collect();
+    let mut sequences = vec![0u64; sockets.len()];
+    info!(
+        "Sending {:.1} packets a second to {} from {} clients",
+        rate,
+        server,
+        sockets.len()
+    );
+
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let started = Instant::now();
+    let mut progress = started;
+    let mut sent = 0u64;
+    let mut failed = 0u64;
+    while count.map_or(true, |count| sent + failed < count) {
+        let n = sent + failed;
+        if let Some(wait) = interval.mul_f64(n as f64).checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+        let client = (n % sockets.len() as u64) as usize;
+        sequences[client] += 1;
+        let (name, packet) = generator.pick(sequences[client], n);
+        let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+        match sockets[client].send_to(&payload, server) {
+            Ok(_) => sent += 1,
+            Err(e) => {
+                if failed == 0 {
+                    warn!("Failed to send a {}: {}", name, e);
+                }
+                failed += 1;
+            }
+        }
+        if progress.elapsed() >= PROGRESS_INTERVAL {
+            progress = Instant::now();
+            info!("Sent {} packets so far, {} failed", sent, failed);
+        }
+    }
+    info!(
+        "Sent {} packets in {:.1?}, {} failed",
+        sent,
+        started.elapsed(),
+        failed
+    );
+}
@@ -0,0 +1,153 @@
+//! Where clients are, from MaxMind GeoLite2 databases, and how each country's players fare.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::latency::RttStats;
+use dissect_netwayste_core::packet_filter::client_end;
+use dissect_netwayste_core::ports::Ports;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use tracing::*;
+
+/// Stands in for the country of addresses the database doesn't know, like private ones.
+const UNKNOWN: &str = "??";
+
+#[derive(Clone, Default)]
+pub struct Location {
+    /// ISO 3166 code
+    pub country: Option<String>,
+    /// Number and organization
+    pub asn: Option<(u32, String)>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.country.as_deref().unwrap_or(UNKNOWN))?;
+        if let Some((number, organization)) = &self.asn {
+            write!(f, ", AS{} {}", number, organization)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct CountryTraffic {
+    clients: HashSet<IpAddr>,
+    packets: u64,
+    bytes: u64,
+    /// Only filled in with `--rtt`
+    rtt: RttStats,
+    rtt_samples: u64,
+}
+
+pub struct GeoIp {
+    server_ports: Ports,
+    /// A Country or City database; both have the country
+    countries: Option<Reader<Vec<u8>>>,
+    asns: Option<Reader<Vec<u8>>>,
+    clients: HashMap<IpAddr, Location>,
+    traffic: BTreeMap<String, CountryTraffic>,
+}
+
+impl GeoIp {
+    pub fn open(
+        server_ports: Ports,
+        country_db: Option<&Path>,
+        asn_db: Option<&Path>,
+    ) -> Result<Self, MaxMindDBError> {
+        Ok(GeoIp {
+            server_ports,
+            countries: country_db.map(Reader::open_readfile).transpose()?,
+            asns: asn_db.map(Reader::open_readfile).transpose()?,
+            clients: HashMap::new(),
+            traffic: BTreeMap::new(),
+        })
+    }
+
+    /// Call with every datagram on the netwayste port. Returns the client's location the first
+    /// time it's seen.
+    pub fn observe(&mut self, src: SocketAddr, dst: SocketAddr, size: usize) -> Option<Location> {
+        let client = client_end(src, dst, &self.server_ports).ip();
+        let mut new = None;
+        if !self.clients.contains_key(&client) {
+            let location = self.locate(client);
+            new = Some(location.clone());
+            self.clients.insert(client, location);
+        }
+        let country = self.clients[&client]
+            .country
+            .clone()
+            .unwrap_or_else(|| UNKNOWN.to_owned());
+        let traffic = self.traffic.entry(country).or_default();
+        traffic.clients.insert(client);
+        traffic.packets += 1;
+        traffic.bytes += size as u64;
+        new
+    }
+
+    /// Call with each round trip measured, to be counted towards the client's country.
+    pub fn rtt(&mut self, client: SocketAddr, rtt: Duration) {
+        let country = self
+            .clients
+            .get(&client.ip())
+            .and_then(|location| location.country.clone())
+            .unwrap_or_else(|| UNKNOWN.to_owned());
+        let traffic = self.traffic.entry(country).or_default();
+        traffic.rtt.add(rtt);
+        traffic.rtt_samples += 1;
+    }
+
+    fn locate(&self, ip: IpAddr) -> Location {
+        let country = self
+            .countries
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Country>(ip).ok())
+            .and_then(|found| found.country?.iso_code.map(str::to_owned));
+        let asn = self
+            .asns
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Asn>(ip).ok())
+            .and_then(|found| {
+                Some((
+                    found.autonomous_system_number?,
+                    found
+                        .autonomous_system_organization
+                        .unwrap_or_default()
+                        .to_owned(),
+                ))
+            });
+        Location { country, asn }
+    }
+
+    pub fn log(&self) {
+        if self.traffic.is_empty() {
+            return;
+        }
+        info!(
+            "  {:<7} {:>7} {:>9} {:>12} {:>10} {:>10}",
+            "country", "clients", "packets", "bytes", "avg RTT", "p95 RTT"
+        );
+        for (country, traffic) in &self.traffic {
+            let (avg, p95) = if traffic.rtt_samples > 0 {
+                (
+                    format!("{:.1?}", traffic.rtt.avg()),
+                    format!("{:.1?}", traffic.rtt.p95()),
+                )
+            } else {
+                ("-".to_owned(), "-".to_owned())
+            };
+            info!(
+                "  {:<7} {:>7} {:>9} {:>12} {:>10} {:>10}",
+                country,
+                traffic.clients.len(),
+                traffic.packets,
+                traffic.bytes,
+                avg,
+                p95
+            );
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! A one-line pulse of everything on the wire, whatever the filters let through.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::ports::Ports;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+#[derive(Default, Clone, Copy)]
+struct Direction {
+    decoded: u64,
+    errors: u64,
+    bytes: u64,
+}
+
+pub struct Heartbeat {
+    server_ports: Ports,
+    interval: Duration,
+    since: Instant,
+    to_server: Direction,
+    from_server: Direction,
+    flows: HashSet<(SocketAddr, SocketAddr)>,
+}
+
+impl Heartbeat {
+    /// Logs a pulse every `interval` of wall-clock time.
+    pub fn new(server_ports: Ports, interval: Duration) -> Self {
+        Heartbeat {
+            server_ports,
+            interval,
+            since: Instant::now(),
+            to_server: Direction::default(),
+            from_server: Direction::default(),
+            flows: HashSet::new(),
+        }
+    }
+
+    /// Call with every datagram on the netwayste port.
+    pub fn record(&mut self, src: SocketAddr, dst: SocketAddr, bytes: usize, decoded: bool) {
+        let direction = if self.server_ports.contains(src.port()) {
+            &mut self.from_server
+        } else {
+            &mut self.to_server
+        };
+        if decoded {
+            direction.decoded += 1;
+        } else {
+            direction.errors += 1;
+        }
+        direction.bytes += bytes as u64;
+        self.flows.insert((src, dst));
+    }
+
+    /// Logs the rates since the last call, then starts counting afresh.
+    pub fn log(&mut self) {
+        let secs = self.since.elapsed().as_secs_f64().max(f64::EPSILON);
+        let rates = |d: &Direction| {
+            format!(
+                "{:.1} pkt/s, {:.1} err/s, {:.0} B/s",
+                d.decoded as f64 / secs,
+                d.errors as f64 / secs,
+                d.bytes as f64 / secs
+            )
+        };
+        info!(
+            "{} active flows; to server {}; from server {}",
+            self.flows.len(),
+            rates(&self.to_server),
+            rates(&self.from_server)
+        );
+        *self = Heartbeat::new(self.server_ports.clone(), self.interval);
+    }
+}
+
+impl Analyzer for Heartbeat {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, _value: &Value) {
+        self.record(context.src, context.dst, context.payload.len(), true);
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        self.record(context.src, context.dst, context.payload.len(), false);
+    }
+
+    fn on_tick(&mut self) {
+        if self.since.elapsed() >= self.interval {
+            self.log();
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Wireshark-style hex and ASCII dumps of raw payloads.
+
+use std::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Formats `data` as lines of offset, 16 hex bytes in two groups of 8, and the same bytes as
+/// ASCII with anything unprintable shown as `.`:
+///
+/// ```text
+/// 0000  00 00 00 01 00 00 00 00  05 00 00 00 00 00 00 00   ........ ........
+/// ```
+pub fn hexdump(data: &[u8]) -> String {
+    dump(data, None)
+}
+
+/// Like `hexdump()`, with a `^^` under the byte at `offset` followed by `note`:
+///
+/// ```text
+/// 0000  00 00 00 01 00 00 00 00  ff ff 00 00 00 00 00 00   ........ ........
+///                                ^^ Packet::Update.games.len: length 65535 is more than ...
+/// ```
+///
+/// An offset just past the end (where data ran out) gets marked too.
+pub fn hexdump_marked(data: &[u8], offset: usize, note: &str) -> String {
+    dump(data, Some((offset, note)))
+}
+
+fn dump(data: &[u8], mark: Option<(usize, &str)>) -> String {
+    let mut out = String::new();
+    let mut marked = false;
+    for (i, line) in data.chunks(BYTES_PER_LINE).enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        write!(out, "{:04x} ", i * BYTES_PER_LINE).unwrap();
+        for j in 0..BYTES_PER_LINE {
+            if j == BYTES_PER_LINE / 2 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => write!(out, " {:02x}", b).unwrap(),
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("   ");
+        for (j, &b) in line.iter().enumerate() {
+            if j == BYTES_PER_LINE / 2 {
+                out.push(' ');
+            }
+            out.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        if let Some((offset, note)) = mark {
+            if offset / BYTES_PER_LINE == i {
+                out.push('\n');
+                out.push_str(&marker(offset % BYTES_PER_LINE, note));
+                marked = true;
+            }
+        }
+    }
+    if let Some((offset, note)) = mark {
+        if !marked {
+            out.push('\n');
+            out.push_str(&marker(offset % BYTES_PER_LINE, note));
+        }
+    }
+    out
+}
+
+/// A line pointing at column `column` of the hex bytes on the line above.
+fn marker(column: usize, note: &str) -> String {
+    let gap = if column >= BYTES_PER_LINE / 2 { 1 } else { 0 };
+    format!("{}^^ {}", " ".repeat(6 + column * 3 + gap), note)
+}
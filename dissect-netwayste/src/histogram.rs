@@ -0,0 +1,92 @@
+//! Payload size distributions, to see how close packets come to needing IP fragmentation.
+
+use std::collections::BTreeMap;
+
+use tracing::*;
+
+/// Width of each histogram bar, in bytes.
+const BUCKET_SIZE: usize = 128;
+
+/// The largest UDP payload that fits a 1500 byte Ethernet MTU over IPv4 without fragmenting.
+const MAX_UNFRAGMENTED: usize = 1500 - 20 - 8;
+
+/// Longest bar drawn, in characters.
+const BAR_WIDTH: u64 = 40;
+
+#[derive(Default)]
+struct Histogram {
+    buckets: BTreeMap<usize, u64>,
+    count: u64,
+    max: usize,
+    over_mtu: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, size: usize) {
+        *self.buckets.entry(size / BUCKET_SIZE).or_default() += 1;
+        self.count += 1;
+        self.max = self.max.max(size);
+        if size > MAX_UNFRAGMENTED {
+            self.over_mtu += 1;
+        }
+    }
+
+    /// The size `fraction` of the packets are no bigger than, to the nearest bucket.
+    fn percentile(&self, fraction: f64) -> usize {
+        let wanted = (self.count as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in &self.buckets {
+            seen += count;
+            if seen >= wanted {
+                return ((bucket + 1) * BUCKET_SIZE - 1).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Payload sizes across all datagrams, and per packet variant for those that decoded.
+#[derive(Default)]
+pub struct SizeHistograms {
+    overall: Histogram,
+    by_variant: BTreeMap<String, Histogram>,
+}
+
+impl SizeHistograms {
+    pub fn record(&mut self, variant: Option<String>, size: usize) {
+        self.overall.record(size);
+        if let Some(variant) = variant {
+            self.by_variant.entry(variant).or_default().record(size);
+        }
+    }
+
+    pub fn log(&self) {
+        if self.overall.count == 0 {
+            return;
+        }
+        info!(
+            "Payload sizes ({} bytes fit a 1500 byte MTU):",
+            MAX_UNFRAGMENTED
+        );
+        let tallest = self.overall.buckets.values().copied().max().unwrap_or(1);
+        for (bucket, count) in &self.overall.buckets {
+            info!(
+                "  {:>5}-{:<5} {:>8} {}",
+                bucket * BUCKET_SIZE,
+                (bucket + 1) * BUCKET_SIZE - 1,
+                count,
+                "#".repeat((count * BAR_WIDTH).div_ceil(tallest) as usize)
+            );
+        }
+        for (variant, histogram) in &self.by_variant {
+            info!(
+                "  {:<20} p50 <= {:<5} p99 <= {:<5} max {:<5} over MTU {}",
+                variant,
+                histogram.percentile(0.5),
+                histogram.percentile(0.99),
+                histogram.max,
+                histogram.over_mtu
+            );
+        }
+    }
+}
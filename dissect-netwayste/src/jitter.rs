@@ -0,0 +1,95 @@
+//! Inter-arrival jitter of the updates the server streams to each client.
+//!
+//! Packets carry no send time, so this is the RFC 3550 estimator applied to the gaps between
+//! arrivals: each gap is compared with the one before it, and the differences are smoothed.
+//! A steady stream scores near zero however slow it is; a bursty one doesn't.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::variant_name;
+use dissect_netwayste_core::ports::Ports;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+#[derive(Default)]
+struct ClientJitter {
+    last_arrival: Option<Duration>,
+    last_gap: Option<Duration>,
+    /// Smoothed difference between consecutive gaps, in seconds
+    jitter: f64,
+    updates: u64,
+}
+
+pub struct JitterTracker {
+    server_ports: Ports,
+    interval: Duration,
+    next_report: Option<Duration>,
+    clients: BTreeMap<SocketAddr, ClientJitter>,
+}
+
+impl JitterTracker {
+    /// Reports every `interval` of capture time, so saved captures get the same reports a live
+    /// one would have.
+    pub fn new(server_ports: Ports, interval: Duration) -> Self {
+        JitterTracker {
+            server_ports,
+            interval,
+            next_report: None,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    /// Call with every decoded packet's variant name, in capture order.
+    pub fn observe(&mut self, ts: Duration, src: SocketAddr, dst: SocketAddr, variant: &str) {
+        let next_report = *self.next_report.get_or_insert(ts + self.interval);
+        if ts >= next_report {
+            self.report();
+            self.next_report = Some(ts + self.interval);
+        }
+
+        if !self.server_ports.contains(src.port()) || variant != "Update" {
+            return;
+        }
+        let client = self.clients.entry(dst).or_default();
+        client.updates += 1;
+        if let Some(last_arrival) = client.last_arrival.replace(ts) {
+            let gap = ts.saturating_sub(last_arrival);
+            if let Some(last_gap) = client.last_gap.replace(gap) {
+                let difference = (gap.as_secs_f64() - last_gap.as_secs_f64()).abs();
+                client.jitter += (difference - client.jitter) / 16.0;
+            }
+        }
+    }
+
+    fn report(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        info!("Update jitter per client:");
+        for (addr, client) in &self.clients {
+            info!(
+                "  {:<24} {:>9.3}ms, {} updates since the last report",
+                addr,
+                client.jitter * 1000.0,
+                client.updates
+            );
+        }
+        // Clients that went quiet shouldn't be listed forever
+        self.clients.retain(|_, client| client.updates > 0);
+        for client in self.clients.values_mut() {
+            client.updates = 0;
+        }
+    }
+}
+
+impl Analyzer for JitterTracker {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        // Measured over everything, not just what's shown, since the stream is the point
+        self.observe(context.ts, context.src, context.dst, &variant_name(value));
+    }
+}
@@ -0,0 +1,111 @@
+//! How regularly each client sends its keepalives, and which ones come close to being timed
+//! out by the server.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::{nested_variant, variant_name};
+use serde_json::Value;
+use tracing::*;
+
+/// Only the most recent intervals count towards a client's distribution.
+const MAX_INTERVALS: usize = 1000;
+
+struct ClientKeepalives {
+    last: Duration,
+    sent: u64,
+    intervals: VecDeque<Duration>,
+    late: u64,
+    /// Set once a silence longer than the timeout has been reported, so it's reported once
+    stopped: bool,
+}
+
+pub struct KeepaliveTracker {
+    /// How long the server waits for a keepalive before dropping a client
+    timeout: Duration,
+    clients: BTreeMap<SocketAddr, ClientKeepalives>,
+}
+
+impl KeepaliveTracker {
+    pub fn new(timeout: Duration) -> Self {
+        KeepaliveTracker {
+            timeout,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    /// Call with every decoded packet, in capture order; any of them moves the clock along.
+    pub fn observe(&mut self, ts: Duration, src: SocketAddr, packet: &Value) {
+        for (addr, client) in self.clients.iter_mut() {
+            if !client.stopped && ts.saturating_sub(client.last) > self.timeout {
+                client.stopped = true;
+                warn!(
+                    "{} has sent no keepalive for over {:.1?}, the server's timeout",
+                    addr, self.timeout
+                );
+            }
+        }
+
+        if variant_name(packet) != "Request"
+            || nested_variant(packet, "action").as_deref() != Some("KeepAlive")
+        {
+            return;
+        }
+        let Some(client) = self.clients.get_mut(&src) else {
+            self.clients.insert(
+                src,
+                ClientKeepalives {
+                    last: ts,
+                    sent: 1,
+                    intervals: VecDeque::new(),
+                    late: 0,
+                    stopped: false,
+                },
+            );
+            return;
+        };
+        let interval = ts.saturating_sub(client.last);
+        client.last = ts;
+        client.sent += 1;
+        client.stopped = false;
+        // Past half the timeout, one lost keepalive is enough to get the client dropped
+        if interval > self.timeout / 2 {
+            client.late += 1;
+            warn!(
+                "Late keepalive from {}: {:.3?} since the last one",
+                src, interval
+            );
+        }
+        if client.intervals.len() == MAX_INTERVALS {
+            client.intervals.pop_front();
+        }
+        client.intervals.push_back(interval);
+    }
+
+    pub fn log(&self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        info!("Keepalive intervals (timeout {:.1?}):", self.timeout);
+        for (addr, client) in &self.clients {
+            let mut sorted: Vec<_> = client.intervals.iter().copied().collect();
+            sorted.sort();
+            let at = |fraction: f64| {
+                let index = ((sorted.len() as f64 * fraction).ceil() as usize).saturating_sub(1);
+                sorted.get(index).copied().unwrap_or_default()
+            };
+            info!(
+                "  {:<24} {:>6} sent, min {:.3?}, p50 {:.3?}, p95 {:.3?}, max {:.3?}, {} late{}",
+                addr.to_string(),
+                client.sent,
+                sorted.first().copied().unwrap_or_default(),
+                at(0.5),
+                at(0.95),
+                sorted.last().copied().unwrap_or_default(),
+                client.late,
+                if client.stopped { ", then stopped" } else { "" }
+            );
+        }
+    }
+}
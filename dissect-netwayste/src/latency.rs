@@ -0,0 +1,87 @@
+//! `--rtt`: round trips from each request to the response acknowledging it. Each one is logged,
+//! saved to `--rtt-file`, and passed on to `--alerts` and the per-country summary.
+
+use std::sync::{Arc, Mutex};
+
+use dissect_netwayste_core::latency::LatencyTracker;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::alerts::Alerts;
+use crate::analyzer::{Analyzer, Context};
+use crate::rtt_export::RttExport;
+use crate::summary::Summary;
+
+pub struct Latency {
+    tracker: LatencyTracker,
+    /// Whether to log each round trip
+    log: bool,
+    export: Option<RttExport>,
+    alerts: Option<Arc<Mutex<Alerts>>>,
+    /// For its GeoIP lookups, if there are any
+    summary: Arc<Mutex<Summary>>,
+}
+
+impl Latency {
+    pub fn new(
+        log: bool,
+        export: Option<RttExport>,
+        alerts: Option<Arc<Mutex<Alerts>>>,
+        summary: Arc<Mutex<Summary>>,
+    ) -> Self {
+        Latency {
+            tracker: LatencyTracker::default(),
+            log,
+            export,
+            alerts,
+            summary,
+        }
+    }
+}
+
+impl Analyzer for Latency {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let Some(exchange) = self
+            .tracker
+            .observe(context.ts, context.src, context.dst, value)
+        else {
+            return;
+        };
+        if let Some(geoip) = self.summary.lock().unwrap().geoip.as_mut() {
+            geoip.rtt(exchange.client, exchange.rtt);
+        }
+        if let Some(alerts) = &self.alerts {
+            alerts.lock().unwrap().rtt(exchange.rtt);
+        }
+        if !context.shown {
+            return;
+        }
+        if let Some(export) = self.export.as_mut() {
+            if let Err(e) = export.write(context.ts, &exchange) {
+                error!("Failed to write RTT sample: '{}'", e);
+            }
+        }
+        if self.log {
+            let stats = self.tracker.stats(exchange.client).unwrap();
+            info!(
+                "RTT {} <-> {} request {}: {:.3?} (min {:.3?}, avg {:.3?}, p95 {:.3?})",
+                exchange.client,
+                exchange.server,
+                exchange.sequence,
+                exchange.rtt,
+                stats.min,
+                stats.avg(),
+                stats.p95()
+            );
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        if let Some(export) = self.export.as_mut() {
+            if let Err(e) = export.flush() {
+                error!("Failed to finish RTT file: '{}'", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! `load-test`: many simulated players on one server, to find out how many it can handle.
+//!
+//! Each client gets a thread and a UDP socket of its own, logs in the way `probe` does, then
+//! sends a keepalive every so often and a request from the mix every so often until the test
+//! ends. Logins are spread over the ramp-up time, so the server sees players arrive rather than
+//! a burst. Round trips are timed from each request to the response acknowledging it, and
+//! responses are counted by code, so a server that stays fast by answering with errors doesn't
+//! look healthy.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::latency::{LatencyTracker, RttStats};
+use dissect_netwayste_core::output::{nested_variant, packet_value, variant_name};
+use dissect_netwayste_core::protocol::Protocol;
+use dissect_netwayste_core::templates::{template, trace_packet};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use serde_reflection::Registry;
+use tracing::*;
+
+use crate::generate::{self, Generator, Mix};
+use crate::probe;
+use crate::shutdown;
+
+/// How often the totals so far are logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long clients get to disconnect after Ctrl-C, before the summary is logged regardless.
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+pub struct Options {
+    pub server: SocketAddr,
+    pub clients: u16,
+    /// Time over which the clients log in
+    pub ramp_up: Duration,
+    /// How long the test runs, from the first login; until Ctrl-C if `None`
+    pub duration: Option<Duration>,
+    pub keepalive: Duration,
+    /// Requests sent besides keepalives
+    pub mix: Mix,
+    pub action_interval: Duration,
+    /// How long to wait for a login to be answered
+    pub timeout: Duration,
+    pub client_version: Option<String>,
+}
+
+#[derive(Default)]
+struct Totals {
+    logged_in: u64,
+    /// Logins that failed, by why
+    failed: BTreeMap<String, u64>,
+    /// From the first try at logging in to the cookie arriving
+    login: RttStats,
+    sent: u64,
+    rtt: RttStats,
+    /// Responses by code
+    codes: BTreeMap<String, u64>,
+    undecodable: u64,
+    reported: bool,
+}
+
+/// What every client thread works from.
+struct Shared {
+    options: Options,
+    registry: Registry,
+    keepalive: Value,
+    disconnect: Option<Value>,
+    generator: Generator,
+    started: Instant,
+    totals: Mutex<Totals>,
+    /// Set on Ctrl-C
+    stop: AtomicBool,
+    running: AtomicUsize,
+}
+
+impl Shared {
+    fn stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self
+                .options
+                .duration
+                .is_some_and(|duration| self.started.elapsed() >= duration)
+    }
+
+    fn progress(&self) {
+        let totals = self.totals.lock().unwrap();
+        info!(
+            "{} clients logged in, {} failed; {} requests sent, {} answered; rtt p50 {:.1?}, p95 {:.1?}",
+            totals.logged_in,
+            totals.failed.values().sum::<u64>(),
+            totals.sent,
+            totals.rtt.count(),
+            totals.rtt.percentile(50),
+            totals.rtt.p95()
+        );
+    }
+
+    /// Logs the summary, unless it already has been.
+    fn report(&self) {
+        let mut totals = self.totals.lock().unwrap();
+        if totals.reported {
+            return;
+        }
+        totals.reported = true;
+        let failed: u64 = totals.failed.values().sum();
+        let tried = totals.logged_in + failed;
+        info!(
+            "--- {} load test statistics, {:.1?} ---",
+            self.options.server,
+            self.started.elapsed()
+        );
+        info!(
+            "{} of {} clients logged in ({:.1}%)",
+            totals.logged_in,
+            tried,
+            totals.logged_in as f64 * 100.0 / tried.max(1) as f64
+        );
+        if totals.logged_in > 0 {
+            info!(
+                "login time p50/p95/max = {:.1?}/{:.1?}/{:.1?}",
+                totals.login.percentile(50),
+                totals.login.p95(),
+                totals.login.max
+            );
+        }
+        for (why, count) in &totals.failed {
+            warn!("{} logins failed: {}", count, why);
+        }
+        let answered = totals.rtt.count() as u64;
+        info!(
+            "{} requests sent, {} answered, {:.1}% lost",
+            totals.sent,
+            answered,
+            totals.sent.saturating_sub(answered) as f64 * 100.0 / totals.sent.max(1) as f64
+        );
+        if answered > 0 {
+            info!(
+                "rtt min/p50/avg/p95/p99/max = {:.1?}/{:.1?}/{:.1?}/{:.1?}/{:.1?}/{:.1?}",
+                totals.rtt.min,
+                totals.rtt.percentile(50),
+                totals.rtt.avg(),
+                totals.rtt.p95(),
+                totals.rtt.percentile(99),
+                totals.rtt.max
+            );
+        }
+        if !totals.codes.is_empty() {
+            let codes: Vec<String> = totals
+                .codes
+                .iter()
+                .map(|(code, count)| format!("{} {}", count, code))
+                .collect();
+            info!("Responses: {}", codes.join(", "));
+        }
+        if totals.undecodable > 0 {
+            warn!("{} replies didn't decode", totals.undecodable);
+        }
+    }
+}
+
+pub fn run(options: Options) -> Result<(), String> {
+    let registry = trace_packet()?;
+    // Fail now, rather than in every client, if these can't be built
+    probe::connect_packet(&registry, "load-0", options.client_version.as_deref())?;
+    let keepalive = template(&registry, "KeepAlive")?;
+    let generator = Generator::new(&options.mix, None)?;
+    let shared = Arc::new(Shared {
+        disconnect: template(&registry, "Disconnect").ok(),
+        registry,
+        keepalive,
+        generator,
+        started: Instant::now(),
+        totals: Mutex::new(Totals {
+            login: RttStats::unbounded(),
+            rtt: RttStats::unbounded(),
+            ..Totals::default()
+        }),
+        stop: AtomicBool::new(false),
+        running: AtomicUsize::new(0),
+        options,
+    });
+    info!(
+        "Load testing {} with {} clients, logging in over {:.1?}",
+        shared.options.server, shared.options.clients, shared.options.ramp_up
+    );
+    {
+        let shared = shared.clone();
+        shutdown::on_signal_exit(move || {
+            shared.stop.store(true, Ordering::Relaxed);
+            let stopping = Instant::now();
+            while shared.running.load(Ordering::Relaxed) > 0 && stopping.elapsed() < STOP_GRACE {
+                thread::sleep(Duration::from_millis(50));
+            }
+            shared.report();
+        });
+    }
+
+    let mut progress = Instant::now();
+    let clients = shared.options.clients;
+    for n in 0..clients {
+        let due = shared.options.ramp_up.mul_f64(n as f64 / clients as f64);
+        while shared.started.elapsed() < due && !shared.stopping() {
+            thread::sleep(
+                due.saturating_sub(shared.started.elapsed())
+                    .min(Duration::from_millis(100)),
+            );
+        }
+        if shared.stopping() {
+            break;
+        }
+        shared.running.fetch_add(1, Ordering::Relaxed);
+        let client_shared = shared.clone();
+        thread::Builder::new()
+            .name(format!("client-{}", n))
+            .spawn(move || {
+                client(n, &client_shared);
+                client_shared.running.fetch_sub(1, Ordering::Relaxed);
+            })
+            .expect("Failed to start a client thread");
+        if progress.elapsed() >= PROGRESS_INTERVAL {
+            progress = Instant::now();
+            shared.progress();
+        }
+    }
+    while shared.running.load(Ordering::Relaxed) > 0 {
+        thread::sleep(Duration::from_millis(100));
+        if progress.elapsed() >= PROGRESS_INTERVAL {
+            progress = Instant::now();
+            shared.progress();
+        }
+    }
+    shared.report();
+    Ok(())
+}
+
+/// One simulated player, from login to disconnect.
+fn client(n: u16, shared: &Shared) {
+    let options = &shared.options;
+    let failed = |why: String| {
+        debug!("Client {} failed to log in: {}", n, why);
+        *shared.totals.lock().unwrap().failed.entry(why).or_default() += 1;
+    };
+    let connect = probe::connect_packet(
+        &shared.registry,
+        &format!("load-{}", n),
+        options.client_version.as_deref(),
+    )
+    .expect("checked before starting");
+    let bind: SocketAddr = if options.server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = match UdpSocket::bind(bind).and_then(|socket| {
+        socket.connect(options.server)?;
+        Ok(socket)
+    }) {
+        Ok(socket) => socket,
+        Err(e) => return failed(format!("can't open a socket: {}", e)),
+    };
+    let logging_in = Instant::now();
+    let cookie = match probe::login(&socket, &connect, options.server, options.timeout) {
+        Ok(cookie) => cookie,
+        Err(e) => return failed(e),
+    };
+    {
+        let mut totals = shared.totals.lock().unwrap();
+        totals.logged_in += 1;
+        totals.login.add(logging_in.elapsed());
+    }
+
+    let mut generator = shared.generator.clone();
+    generator.set_cookie(Some(cookie.clone()));
+    let local = socket.local_addr().unwrap();
+    let decoder = Protocol::V2.decoder();
+    let mut tracker = LatencyTracker::default();
+    // The login was 1
+    let mut sequence = 1u64;
+    let mut next_keepalive = Instant::now();
+    let mut next_action = next_keepalive + options.action_interval;
+    let mut buf = [0; 65536];
+    while !shared.stopping() {
+        let now = Instant::now();
+        let packet: Option<Packet> = if now >= next_keepalive {
+            next_keepalive += options.keepalive;
+            Some(
+                generate::build(&shared.keepalive, Some(&cookie), sequence + 1, 0)
+                    .expect("template checked when loading"),
+            )
+        } else if now >= next_action {
+            next_action += options.action_interval;
+            Some(generator.pick(sequence + 1, sequence).1)
+        } else {
+            None
+        };
+        if let Some(packet) = packet {
+            sequence += 1;
+            let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+            if let Err(e) = socket.send(&payload) {
+                debug!("Client {} failed to send: {}", n, e);
+            }
+            tracker.observe(
+                shared.started.elapsed(),
+                local,
+                options.server,
+                &packet_value(&packet),
+            );
+            shared.totals.lock().unwrap().sent += 1;
+            continue;
+        }
+
+        let wait = next_keepalive
+            .min(next_action)
+            .saturating_duration_since(now)
+            .max(Duration::from_millis(1));
+        socket
+            .set_read_timeout(Some(wait))
+            .expect("Failed to set the socket's timeout");
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                // e.g. ICMP port unreachable; the request it was for counts as lost
+                debug!("Client {} failed to read: {}", n, e);
+                thread::sleep(wait);
+                continue;
+            }
+        };
+        let Ok(reply) = decoder.decode(&buf[..len]) else {
+            shared.totals.lock().unwrap().undecodable += 1;
+            continue;
+        };
+        let value = packet_value(&reply);
+        if variant_name(&value) != "Response" {
+            continue;
+        }
+        let exchange = tracker.observe(shared.started.elapsed(), options.server, local, &value);
+        let code = nested_variant(&value, "code").unwrap_or_else(|| "?".to_owned());
+        let mut totals = shared.totals.lock().unwrap();
+        *totals.codes.entry(code).or_default() += 1;
+        if let Some(exchange) = exchange {
+            totals.rtt.add(exchange.rtt);
+        }
+    }
+    probe::send_disconnect(&socket, shared.disconnect.as_ref(), &cookie, sequence + 1);
+}
@@ -0,0 +1,84 @@
+//! The server's rooms as its room lists describe them.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::format_time;
+use serde_json::Value;
+use tracing::*;
+
+struct RoomStatus {
+    players: u64,
+    /// `None` if the server doesn't say
+    in_progress: Option<bool>,
+    /// Capture time of the latest room list mentioning the room
+    updated: Duration,
+}
+
+#[derive(Default)]
+pub struct Lobby {
+    rooms: BTreeMap<String, RoomStatus>,
+    /// Capture time of the latest room list
+    updated: Option<Duration>,
+}
+
+impl Lobby {
+    /// Call with every decoded packet. Any entry with a `room_name` and a `player_count`, as in
+    /// a room list response, updates that room.
+    pub fn observe(&mut self, ts: Duration, packet: &Value) {
+        let mut entries = vec![];
+        find_rooms(packet, &mut entries);
+        for entry in entries {
+            let (Some(name), Some(players)) = (
+                entry.get("room_name").and_then(Value::as_str),
+                entry.get("player_count").and_then(Value::as_u64),
+            ) else {
+                continue;
+            };
+            self.rooms.insert(
+                name.to_owned(),
+                RoomStatus {
+                    players,
+                    in_progress: entry.get("in_progress").and_then(Value::as_bool),
+                    updated: ts,
+                },
+            );
+            self.updated = Some(ts);
+        }
+    }
+
+    pub fn log(&self) {
+        let Some(updated) = self.updated else {
+            return;
+        };
+        info!("Rooms as of {}:", format_time(updated).unwrap_or_default());
+        for (name, room) in &self.rooms {
+            let phase = match room.in_progress {
+                Some(true) => "in game",
+                Some(false) => "waiting",
+                None => "-",
+            };
+            // Rooms missing from the latest list have probably closed
+            let stale = if room.updated < updated {
+                " (not in the latest list)"
+            } else {
+                ""
+            };
+            info!(
+                "  {:<20} {:>3} players  {}{}",
+                name, room.players, phase, stale
+            );
+        }
+    }
+}
+
+fn find_rooms<'a>(value: &'a Value, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) if map.contains_key("room_name") && map.contains_key("player_count") => {
+            found.push(value)
+        }
+        Value::Object(map) => map.values().for_each(|v| find_rooms(v, found)),
+        Value::Array(values) => values.iter().for_each(|v| find_rooms(v, found)),
+        _ => {}
+    }
+}
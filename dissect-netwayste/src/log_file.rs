@@ -0,0 +1,104 @@
+//! Copies the log, decoded packets included, to a file that can rotate like `--write-pcap`.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::rotation::{ring_path, Rotation};
+
+const ESC: u8 = 0x1b;
+
+/// Where we are in an ANSI escape sequence, which may in theory span several writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Just saw ESC
+    Start,
+    /// Inside `ESC [ ...`, waiting for the final byte
+    Csi,
+}
+
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    /// Strip colors and other ANSI escapes before writing
+    plain: bool,
+    file: Option<File>,
+    /// Bytes written to the current file
+    written: u64,
+    /// Position in the ring of the current file
+    index: usize,
+    escape: Escape,
+}
+
+impl LogFile {
+    pub fn new(path: PathBuf, rotation: Option<Rotation>, plain: bool) -> Self {
+        LogFile {
+            path,
+            rotation,
+            plain,
+            file: None,
+            written: 0,
+            index: 0,
+            escape: Escape::None,
+        }
+    }
+
+    fn strip_escapes(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &b in buf {
+            self.escape = match (self.escape, b) {
+                (Escape::None, ESC) => Escape::Start,
+                (Escape::None, _) => {
+                    out.push(b);
+                    Escape::None
+                }
+                (Escape::Start, b'[') => Escape::Csi,
+                // Two-byte sequences; nothing we emit uses them, but don't leave them half-eaten
+                (Escape::Start, _) => Escape::None,
+                (Escape::Csi, 0x40..=0x7e) => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+            };
+        }
+        out
+    }
+}
+
+impl Write for LogFile {
+    /// Each call is one complete log event, so rotating between calls never splits a line.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            let path = match self.rotation {
+                Some(_) => ring_path(&self.path, self.index),
+                None => self.path.clone(),
+            };
+            self.file = Some(File::create(path)?);
+            self.written = 0;
+        }
+
+        let stripped;
+        let data = if self.plain {
+            stripped = self.strip_escapes(buf);
+            &stripped[..]
+        } else {
+            buf
+        };
+        self.file.as_mut().unwrap().write_all(data)?;
+        self.written += data.len() as u64;
+
+        if let Some(rotation) = &self.rotation {
+            if self.written >= rotation.max_bytes {
+                self.file = None;
+                self.index = rotation.next_index(self.index);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Where logs go and which ones are kept: `--log-level`, `--log-format` and `--tool-log`.
+//!
+//! Decoded packet lines are logged under their own target, `packets`, so they can be kept apart
+//! from the tool's own logs: with `--tool-log` only they go to the terminal.
+
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use tracing_subscriber::{
+    filter::{filter_fn, EnvFilter},
+    fmt::{self, writer::BoxMakeWriter, MakeWriter},
+    prelude::*,
+    Layer, Registry,
+};
+
+/// Tracing target of decoded packet lines.
+pub const PACKETS: &str = "packets";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per event, as `tracing-subscriber` lays them out
+    Json,
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn layer<W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
+
+/// Packet lines are kept whatever `levels` says, unless it mentions them itself.
+fn env_filter(levels: &str) -> Result<EnvFilter, String> {
+    let filter =
+        EnvFilter::try_new(levels).map_err(|e| format!("bad log level '{}': {}", levels, e))?;
+    if levels.contains(PACKETS) {
+        return Ok(filter);
+    }
+    let directive = format!("{}=trace", PACKETS).parse().unwrap();
+    Ok(filter.add_directive(directive))
+}
+
+/// Installs the global subscriber. `writer` takes everything, or only packet lines when
+/// `tool_log` is given. `levels` is in `RUST_LOG` syntax, and falls back on `RUST_LOG`, then on
+/// keeping everything.
+pub fn init(
+    writer: BoxMakeWriter,
+    ansi: bool,
+    format: LogFormat,
+    levels: Option<&str>,
+    tool_log: Option<&Path>,
+) -> Result<(), String> {
+    let levels = levels
+        .map(str::to_owned)
+        .or_else(|| env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| "trace".to_owned());
+    let filter = env_filter(&levels)?;
+
+    let mut layers: Vec<BoxedLayer> = vec![];
+    match tool_log {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("can't create '{}': {}", path.display(), e))?;
+            layers.push(
+                layer(writer, format, ansi)
+                    .with_filter(filter_fn(|meta| meta.target() == PACKETS))
+                    .boxed(),
+            );
+            layers.push(
+                layer(Mutex::new(file), format, false)
+                    .with_filter(filter_fn(|meta| meta.target() != PACKETS))
+                    .boxed(),
+            );
+        }
+        None => layers.push(layer(writer, format, ansi)),
+    }
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .map_err(|e| e.to_string())
+}
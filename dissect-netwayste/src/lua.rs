@@ -0,0 +1,337 @@
+//! `generate-lua-dissector`: writes a Wireshark Lua dissector for netwayste from the Rust
+//! `Packet` type itself, so it can't drift out of sync the way a hand-written one does.
+//!
+//! serde-reflection records the shape of every type `Packet` is built from, and each shape
+//! maps onto how bincode lays it out on the wire: fixed-size little-endian integers, a `u32`
+//! variant index for enums, a `u8` tag for options, and a `u64` length before strings and
+//! sequences.
+
+use std::fmt::Write;
+
+use dissect_netwayste_core::templates::trace_packet;
+use serde_reflection::{ContainerFormat, Format, Named, VariantFormat};
+
+/// Helpers the generated code is built from. Each reader takes the buffer, an offset, the tree
+/// to add to and a label, and returns the offset just past what it read.
+const LUA_RUNTIME: &str = r#"
+local function uint(size)
+    return function(tvb, off, tree, label)
+        local r = tvb(off, size)
+        tree:add(r, label .. ": " .. tostring(r:le_uint64()))
+        return off + size
+    end
+end
+
+local function int(size)
+    return function(tvb, off, tree, label)
+        local r = tvb(off, size)
+        tree:add(r, label .. ": " .. tostring(r:le_int64()))
+        return off + size
+    end
+end
+
+local function wide(size)
+    return function(tvb, off, tree, label)
+        local r = tvb(off, size)
+        tree:add(r, label .. ": 0x" .. r:bytes():tohex())
+        return off + size
+    end
+end
+
+local function float(size)
+    return function(tvb, off, tree, label)
+        local r = tvb(off, size)
+        tree:add(r, label .. ": " .. tostring(r:le_float()))
+        return off + size
+    end
+end
+
+local function bool()
+    return function(tvb, off, tree, label)
+        local r = tvb(off, 1)
+        tree:add(r, label .. ": " .. tostring(r:uint() ~= 0))
+        return off + 1
+    end
+end
+
+local function unit()
+    return function(tvb, off, tree, label)
+        return off
+    end
+end
+
+local function char()
+    return function(tvb, off, tree, label)
+        local first = tvb(off, 1):uint()
+        local size = 1
+        if first >= 0xf0 then size = 4 elseif first >= 0xe0 then size = 3 elseif first >= 0xc0 then size = 2 end
+        local r = tvb(off, size)
+        tree:add(r, label .. ": '" .. r:string(ENC_UTF_8) .. "'")
+        return off + size
+    end
+end
+
+local function length(tvb, off)
+    return tvb(off, 8):le_uint64():tonumber()
+end
+
+local function str()
+    return function(tvb, off, tree, label)
+        local len = length(tvb, off)
+        local r = tvb(off, 8 + len)
+        if len == 0 then
+            tree:add(r, label .. ': ""')
+        else
+            tree:add(r, label .. ': "' .. tvb(off + 8, len):string(ENC_UTF_8) .. '"')
+        end
+        return off + 8 + len
+    end
+end
+
+local function bytes()
+    return function(tvb, off, tree, label)
+        local len = length(tvb, off)
+        local r = tvb(off, 8 + len)
+        if len == 0 then
+            tree:add(r, label .. ": (empty)")
+        else
+            tree:add(r, label .. ": " .. tvb(off + 8, len):bytes():tohex())
+        end
+        return off + 8 + len
+    end
+end
+
+local function option(inner)
+    return function(tvb, off, tree, label)
+        if tvb(off, 1):uint() == 0 then
+            tree:add(tvb(off, 1), label .. ": None")
+            return off + 1
+        end
+        return inner(tvb, off + 1, tree, label)
+    end
+end
+
+local function seq(elem)
+    return function(tvb, off, tree, label)
+        local len = length(tvb, off)
+        local sub = tree:add(tvb(off, 8), label .. " [" .. len .. "]")
+        local o = off + 8
+        for i = 0, len - 1 do
+            o = elem(tvb, o, sub, "[" .. i .. "]")
+        end
+        sub:set_len(o - off)
+        return o
+    end
+end
+
+local function map(key, value)
+    return function(tvb, off, tree, label)
+        local len = length(tvb, off)
+        local sub = tree:add(tvb(off, 8), label .. " [" .. len .. "]")
+        local o = off + 8
+        for i = 0, len - 1 do
+            o = key(tvb, o, sub, "key[" .. i .. "]")
+            o = value(tvb, o, sub, "value[" .. i .. "]")
+        end
+        sub:set_len(o - off)
+        return o
+    end
+end
+
+local function array(elem, size)
+    return function(tvb, off, tree, label)
+        local sub = tree:add(tvb(off, 0), label)
+        local o = off
+        for i = 0, size - 1 do
+            o = elem(tvb, o, sub, "[" .. i .. "]")
+        end
+        sub:set_len(o - off)
+        return o
+    end
+end
+
+-- fields is a list of { name, reader }
+local function struct(fields)
+    return function(tvb, off, tree, label)
+        local sub = tree:add(tvb(off, 0), label)
+        local o = off
+        for _, field in ipairs(fields) do
+            o = field[2](tvb, o, sub, field[1])
+        end
+        sub:set_len(o - off)
+        return o
+    end
+end
+
+local function tuple(elems)
+    local fields = {}
+    for i, elem in ipairs(elems) do
+        fields[i] = { tostring(i - 1), elem }
+    end
+    return struct(fields)
+end
+
+-- variants maps a variant index to { name, reader }
+local function enum(variants)
+    return function(tvb, off, tree, label)
+        local index = tvb(off, 4):le_uint()
+        local variant = variants[index]
+        if variant == nil then
+            tree:add_expert_info(PI_MALFORMED, PI_ERROR, label .. ": unknown variant " .. index)
+            return tvb:len()
+        end
+        local sub = tree:add(tvb(off, 4), label .. ": " .. variant[1])
+        local o = variant[2](tvb, off + 4, sub, variant[1])
+        sub:set_len(o - off)
+        return o
+    end
+end
+
+-- Named types are looked up when read rather than when defined, so they can refer to each
+-- other in any order, and to themselves.
+local types = {}
+local variant_names = {}
+
+local function named(name)
+    return function(tvb, off, tree, label)
+        return types[name](tvb, off, tree, label)
+    end
+end
+"#;
+
+/// Returns the source of a Lua dissector for netwayste over UDP on `port`.
+pub fn generate(port: u16) -> Result<String, String> {
+    let registry = trace_packet()?;
+
+    let mut lua = String::new();
+    writeln!(
+        lua,
+        "-- Wireshark dissector for netwayste, generated by {} {} from netwaystev2::protocol::Packet.",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    lua.push_str("-- Don't edit this by hand; run `dissect-netwayste generate-lua-dissector` again instead.\n");
+    lua.push_str(LUA_RUNTIME);
+    lua.push('\n');
+
+    for (name, container) in &registry {
+        writeln!(lua, "types[{:?}] = {}", name, container_reader(container)).unwrap();
+        if let ContainerFormat::Enum(variants) = container {
+            let names: Vec<String> = variants
+                .iter()
+                .map(|(index, variant)| format!("[{}] = {:?}", index, variant.name))
+                .collect();
+            writeln!(
+                lua,
+                "variant_names[{:?}] = {{ {} }}",
+                name,
+                names.join(", ")
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        lua,
+        r#"
+local netwayste = Proto("netwayste", "Netwayste")
+netwayste.prefs.port = Pref.uint("UDP port", {port}, "Port netwayste servers listen on")
+
+function netwayste.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "Netwayste"
+    local variant = variant_names["Packet"][tvb(0, 4):le_uint()] or "?"
+    pinfo.cols.info = variant
+    local sub = tree:add(netwayste, tvb(), "Netwayste " .. variant)
+    types["Packet"](tvb, 0, sub, "Packet")
+end
+
+local registered_port = netwayste.prefs.port
+DissectorTable.get("udp.port"):add(registered_port, netwayste)
+
+function netwayste.prefs_changed()
+    DissectorTable.get("udp.port"):remove(registered_port, netwayste)
+    registered_port = netwayste.prefs.port
+    DissectorTable.get("udp.port"):add(registered_port, netwayste)
+end"#,
+        port = port
+    )
+    .unwrap();
+
+    Ok(lua)
+}
+
+fn container_reader(container: &ContainerFormat) -> String {
+    match container {
+        ContainerFormat::UnitStruct => "unit()".to_owned(),
+        ContainerFormat::NewTypeStruct(inner) => reader(inner),
+        ContainerFormat::TupleStruct(elems) => tuple_reader(elems),
+        ContainerFormat::Struct(fields) => struct_reader(fields),
+        ContainerFormat::Enum(variants) => {
+            let variants: Vec<String> = variants
+                .iter()
+                .map(|(index, variant)| {
+                    format!(
+                        "[{}] = {{ {:?}, {} }}",
+                        index,
+                        variant.name,
+                        variant_reader(&variant.value)
+                    )
+                })
+                .collect();
+            format!("enum({{ {} }})", variants.join(", "))
+        }
+    }
+}
+
+fn variant_reader(variant: &VariantFormat) -> String {
+    match variant {
+        VariantFormat::Unit => "unit()".to_owned(),
+        VariantFormat::NewType(inner) => reader(inner),
+        VariantFormat::Tuple(elems) => tuple_reader(elems),
+        VariantFormat::Struct(fields) => struct_reader(fields),
+        // Only left behind by an incomplete trace, which registry() already rules out
+        VariantFormat::Variable(_) => unreachable!("untraced variant"),
+    }
+}
+
+fn struct_reader(fields: &[Named<Format>]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| format!("{{ {:?}, {} }}", field.name, reader(&field.value)))
+        .collect();
+    format!("struct({{ {} }})", fields.join(", "))
+}
+
+fn tuple_reader(elems: &[Format]) -> String {
+    let elems: Vec<String> = elems.iter().map(reader).collect();
+    format!("tuple({{ {} }})", elems.join(", "))
+}
+
+fn reader(format: &Format) -> String {
+    match format {
+        Format::TypeName(name) => format!("named({:?})", name),
+        Format::Unit => "unit()".to_owned(),
+        Format::Bool => "bool()".to_owned(),
+        Format::I8 => "int(1)".to_owned(),
+        Format::I16 => "int(2)".to_owned(),
+        Format::I32 => "int(4)".to_owned(),
+        Format::I64 => "int(8)".to_owned(),
+        Format::I128 | Format::U128 => "wide(16)".to_owned(),
+        Format::U8 => "uint(1)".to_owned(),
+        Format::U16 => "uint(2)".to_owned(),
+        Format::U32 => "uint(4)".to_owned(),
+        Format::U64 => "uint(8)".to_owned(),
+        Format::F32 => "float(4)".to_owned(),
+        Format::F64 => "float(8)".to_owned(),
+        Format::Char => "char()".to_owned(),
+        Format::Str => "str()".to_owned(),
+        Format::Bytes => "bytes()".to_owned(),
+        Format::Option(inner) => format!("option({})", reader(inner)),
+        Format::Seq(elem) => format!("seq({})", reader(elem)),
+        Format::Map { key, value } => format!("map({}, {})", reader(key), reader(value)),
+        Format::Tuple(elems) => tuple_reader(elems),
+        Format::TupleArray { content, size } => format!("array({}, {})", reader(content), size),
+        Format::Variable(_) => unreachable!("untraced format"),
+    }
+}
@@ -1,47 +1,1207 @@
+mod alerts;
+mod analyzer;
+mod anonymize;
+mod api;
+mod bandwidth;
+mod capture;
+mod capture_diff;
+mod chat;
+mod check;
+mod config;
+mod daemon;
+mod diagnose;
+mod dissect;
+mod elastic;
+mod extcap;
+mod filter;
+mod flows;
+mod fragments;
+mod fuzz;
+mod game_events;
+mod generate;
+mod geoip;
+mod heartbeat;
+mod hexdump;
+mod histogram;
+mod jitter;
+mod keepalive;
+mod latency;
+mod load_test;
+mod lobby;
+mod log_file;
+mod logging;
+mod lua;
+mod metrics;
+mod parquet_export;
+mod pause;
+mod pcap_writer;
+mod pcapng;
+mod pcapng_writer;
+mod pipeline;
+mod privs;
+mod probe;
+mod render;
+mod repeats;
+mod replay;
+mod resend;
+mod resolve;
+mod rooms;
+mod rotation;
+mod rtt_export;
+mod script;
+mod sessions;
+mod shutdown;
+mod sqlite;
+mod statsd;
+mod summary;
+mod syslog_sink;
+mod tcp;
+mod template;
+mod theme;
+mod throttle;
+mod timeline;
+mod top;
+mod tui;
+mod universe;
+mod version;
+mod web;
+
+use std::env;
+use std::ffi::OsString;
 use std::fmt;
-use std::{collections::HashMap, net::Ipv4Addr, vec};
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    process, thread, vec,
+};
+
+use circular_vec::CircularVec;
+use clap::{
+    self, error::ErrorKind, Arg, ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use clap_complete::Shell;
+use dissect_netwayste_core::{
+    gen_diff::readable,
+    output::{csv_line, json_line, packet_value, variant_name, OutputFormat, CSV_HEADER},
+    packet_filter::{FollowFilter, FollowTarget, PacketFilter, PlayerFilter, RoomFilter, Selector},
+    ports::Ports,
+    protocol::Protocol,
+    templates,
+    timing::{format_delta, TimeFormat, Timing, TIME_FORMATS},
+};
+use netwaystev2::DEFAULT_PORT as NETWAYSTE_PORT;
+use pcap;
+use tracing::*;
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+
+use alerts::Alerts;
+use analyzer::{Analyzers, Context, Mark};
+use anonymize::Anonymizer;
+use bandwidth::BandwidthTracker;
+use capture::{CaptureOptions, Source};
+use chat::ChatMonitor;
+use check::Checker;
+use config::Config;
+use diagnose::{fields, Diagnosis};
+use dissect::{display_ip, Dissector};
+use elastic::{bulk_lines, ElasticPush};
+use filter::{Direction, Filter};
+use flows::{Duplicates, Reordering, Retransmits, SequenceGaps};
+use game_events::GameEvents;
+use generate::{Generator, Mix};
+use geoip::GeoIp;
+use heartbeat::Heartbeat;
+use hexdump::{hexdump, hexdump_marked};
+use histogram::SizeHistograms;
+use jitter::JitterTracker;
+use keepalive::KeepaliveTracker;
+use latency::Latency;
+use lobby::Lobby;
+use log_file::LogFile;
+use logging::LogFormat;
+use metrics::Metrics;
+use parquet_export::ParquetExport;
+use pause::{Line, Pause};
+use pcap_writer::PcapWriter;
+use pcapng_writer::PcapngWriter;
+use pipeline::{Decoded, Payload, Pipeline};
+use render::Window;
+use repeats::Repeats;
+use replay::ReplayRecorder;
+use resolve::Resolver;
+use rooms::RoomStats;
+use rotation::Rotation;
+use rtt_export::RttExport;
+use script::Script;
+use sessions::Sessions;
+use sqlite::SqliteExport;
+use statsd::Statsd;
+use summary::{Summary, SummaryRecorder};
+use syslog_sink::{SyslogLog, SyslogSink};
+use template::{Fields, Template};
+use theme::{Class, Theme, ThemeColor};
+use throttle::{Sample, Skip, Throttle};
+use timeline::TimelineExport;
+use top::{TopBy, TopTalkers};
+use tui::Tui;
+use universe::Universes;
+use version::VersionWatch;
+use web::WebDashboard;
+
+/// How often `--quiet` logs a summary unless told otherwise.
+const DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, help = "Log all failed de-serialization attempts")]
+    verbose: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "The network interface name. Repeat to capture on several at once, or pass 'all'"
+    )]
+    interface: Vec<String>,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "interface",
+        help = "Read packets from a saved capture file instead of a live interface"
+    )]
+    read_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interface", "read_file"],
+        help = "Read pcap data from stdin, e.g. piped from 'tcpdump -w -'"
+    )]
+    stdin: bool,
+
+    #[arg(
+        long,
+        value_name = "USER@HOST",
+        conflicts_with_all = ["read_file", "stdin"],
+        help = "Capture on a remote machine by running tcpdump over SSH. 'interface' then names the remote interface"
+    )]
+    remote: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "tcpdump",
+        requires = "remote",
+        help = "Command used to run tcpdump on the remote machine, e.g. 'sudo tcpdump'"
+    )]
+    remote_tcpdump: String,
+
+    #[arg(long, help = "Bytes to capture per frame (libpcap default if unset)")]
+    snaplen: Option<i32>,
+
+    #[arg(
+        long,
+        help = "Kernel capture buffer size in bytes; raise this if frames are being dropped"
+    )]
+    buffer_size: Option<i32>,
+
+    #[arg(long, help = "Put the interface(s) into promiscuous mode")]
+    promisc: bool,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Read timeout in milliseconds. Disables immediate mode, batching packets for higher rates"
+    )]
+    timeout: Option<i32>,
+
+    #[arg(
+        short,
+        long,
+        value_parser = Ports::parse,
+        default_values_t = [Ports::from(NETWAYSTE_PORT)],
+        help = "The server's port, or a range of them, e.g. '2016-2020'. Repeat to watch several. Tells the server end of a packet from the client end, and picks the traffic to capture unless 'custom-bpf' is provided"
+    )]
+    port: Vec<Ports>,
+
+    #[arg(
+        long,
+        default_value = "ip-and-port",
+        help = "Control how packets are colorized: by source address, or by packet type (requests, responses, updates, errors)"
+    )]
+    color_option: ColorOption,
+
+    #[arg(
+        long,
+        default_value = "auto",
+        help = "Whether to use colors at all. 'auto' leaves them out when NO_COLOR is set or the output isn't a terminal; 'always' keeps them for piping into 'less -R'"
+    )]
+    color: ColorWhen,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read the color palette, and colors for particular sources and for duplicate, out-of-order and retransmitted packets, from this file"
+    )]
+    theme: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "text",
+        help = "How decoded packets are written out. With anything but 'text', logs go to stderr"
+    )]
+    output: OutputFormat,
+
+    #[arg(
+        long,
+        required_if_eq("output", "parquet"),
+        help = "File to write 'parquet' output to"
+    )]
+    output_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser = Template::parse,
+        help = format!("Template for each packet line with 'text' output, e.g. '{{time}} {{src}}:{{sport}} -> {{dst}}:{{dport}} {{variant}} {{seq}}'. Fields: {}", template::FIELDS)
+    )]
+    format: Option<Template>,
+
+    #[arg(
+        long,
+        value_parser = TimeFormat::parse,
+        help = format!("Show each packet's capture time in this format: one of {}, or a strftime string. Shown by default for saved captures, as rfc3339", TIME_FORMATS)
+    )]
+    time_format: Option<TimeFormat>,
+
+    #[arg(
+        long,
+        help = "Show the time since the previous packet, and since the previous packet with the same source and destination"
+    )]
+    delta: bool,
+
+    #[arg(
+        long,
+        help = "Show a hex and ASCII dump of each payload below the decoded packet, and of failed payloads with 'verbose'"
+    )]
+    hexdump: bool,
+
+    #[arg(
+        long,
+        help = "Show how each compressed packet was compressed, and its size on the wire and decompressed"
+    )]
+    compression_sizes: bool,
+
+    #[arg(
+        long,
+        help = "Warn when sequence numbers on a flow skip ahead, which means packets were lost"
+    )]
+    seq_gaps: bool,
+
+    #[arg(
+        long,
+        help = "Mark packets that repeat a recently seen sequence number on the same flow, with the delay since the original"
+    )]
+    retransmits: bool,
+
+    #[arg(
+        long,
+        help = "Mark packets that arrive after a later one on the same flow, with how many sequence numbers behind they are"
+    )]
+    reorder: bool,
+
+    #[arg(
+        long,
+        help = "Match responses to the requests they acknowledge and log each round-trip time, with running min/avg/p95 per client"
+    )]
+    rtt: bool,
+
+    #[arg(
+        long,
+        help = "Write every round-trip time sample with its capture time to this file, as JSON lines if it ends in .json or .jsonl and CSV otherwise"
+    )]
+    rtt_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Log the jitter of each client's stream of updates from the server at this interval of capture time, e.g. '10s'"
+    )]
+    jitter: Option<Duration>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Log each client's throughput in both directions at this interval of capture time, e.g. '10s'"
+    )]
+    bandwidth: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Instead of listing packets, periodically list the N busiest flows with their most common packet variant"
+    )]
+    top: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "packets",
+        help = "What 'top' ranks flows by"
+    )]
+    top_by: TopBy,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "v2",
+        help = "Which netwayste protocol version to decode packets as"
+    )]
+    protocol: Protocol,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "5s",
+        help = "How much capture time each 'top' listing covers"
+    )]
+    top_interval: Duration,
+
+    #[arg(
+        long,
+        help = "Include histograms of payload sizes, overall and per packet variant, in the summary"
+    )]
+    size_histogram: bool,
+
+    #[arg(
+        long,
+        value_name = "TIMEOUT",
+        value_parser = humantime::parse_duration,
+        help = "Check each client's keepalives against the server's timeout, e.g. '10s', warning about late or missing ones and including their intervals in the summary"
+    )]
+    keepalives: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Log each client's session as it moves between connecting, connected, in a room, in a game and disconnecting"
+    )]
+    sessions: bool,
+
+    #[arg(
+        long,
+        help = "Log an event line whenever a player connects, authenticates, joins or leaves a room, starts a game or disconnects"
+    )]
+    timeline: bool,
+
+    #[arg(
+        long,
+        help = "Write the events 'timeline' shows to this file as JSON lines"
+    )]
+    timeline_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write game events (new generations, cells placed, chat, players joining, leaving and starting games) to this file as JSON lines"
+    )]
+    game_events: Option<PathBuf>,
+
+    #[arg(long, help = "Only show chat messages, as '[room] player: text' lines")]
+    chat: bool,
+
+    #[arg(
+        long,
+        help = "Include a table of players, update rate, chat messages and average update size per game room in the summary"
+    )]
+    room_stats: bool,
+
+    #[arg(
+        long,
+        help = "Include the server's rooms, with their player counts and whether a game is on, as its room lists last described them in the summary"
+    )]
+    lobby: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "anonymize",
+        help = "MaxMind GeoLite2 Country or City database to look up which country each client is in, logged when it's first seen and summarized per country"
+    )]
+    geoip_db: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "anonymize",
+        help = "MaxMind GeoLite2 ASN database to look up each client's network, logged alongside its country"
+    )]
+    asn_db: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Rebuild each client's game universe from the diffs the server sends it, warning about diffs that don't apply and including the result in the summary"
+    )]
+    universe: bool,
+
+    #[arg(
+        long,
+        value_name = "GENERATION",
+        help = "Log each client's rebuilt universe as RLE when it reaches this generation; implies 'universe'"
+    )]
+    dump_universe: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Instead of listing packets, draw the rebuilt universe in the terminal as new generations arrive; implies 'universe'"
+    )]
+    render: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "render",
+        help = "Show packets in a scrollable list next to the running totals, instead of logging them; space pauses, arrow keys scroll, q quits"
+    )]
+    tui: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "tui",
+        help = "While the output is paused, stop reading packets too, rather than holding their output; live captures may drop what the kernel can't buffer"
+    )]
+    pause_capture: bool,
+
+    #[arg(
+        long,
+        value_parser = Window::parse,
+        value_name = "LEFT,TOP,WIDTHxHEIGHT",
+        help = "The part of the universe 'render' draws, in cells. Defaults to 120x60 from the top left live cell"
+    )]
+    render_window: Option<Window>,
+
+    #[arg(
+        long,
+        help = "Leave out exact copies of a payload recently seen on the same flow, which are otherwise marked as duplicates"
+    )]
+    suppress_dups: bool,
+
+    #[arg(
+        long,
+        value_parser = Sample::parse,
+        help = "Print only this fraction of the packets, picked at random, e.g. '1/100'. Everything is still counted"
+    )]
+    sample: Option<Sample>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Print no more than this many packets a second, leaving out the rest. Everything is still counted"
+    )]
+    max_lines_per_sec: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Show a run of packets of the same type on the same flow, e.g. keepalives, as its first line and a count"
+    )]
+    collapse_repeats: bool,
+
+    #[arg(
+        long,
+        help = "Check traffic against the protocol's rules, e.g. responses follow requests and no game updates before joining a room, and warn about every violation"
+    )]
+    check: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Don't show individual packets, only periodic and final summaries"
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "How often to log a summary of the capture so far, e.g. '1m'. Defaults to 10s with 'quiet', never otherwise"
+    )]
+    summary_interval: Option<Duration>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Log a line of packet, error and byte rates in each direction at this interval, e.g. '5s', counting everything the filters hide too"
+    )]
+    stats_interval: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "USER[:GROUP]",
+        help = "Switch to this user, and group, once the capture is open, so the rest of the run isn't root. Files are then written as that user"
+    )]
+    drop_privs: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write files even when running as root without 'drop-privs'"
+    )]
+    allow_root: bool,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        value_name = "N",
+        help = "Decode this many frames at once, ahead of the output; defaults to the number of CPUs"
+    )]
+    decode_workers: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Print a man page for this tool in roff format and exit, e.g. for 'man -l -'"
+    )]
+    generate_man: bool,
+
+    // Wireshark's extcap interface; see the extcap module. Not meant to be typed by hand.
+    #[arg(long, hide = true)]
+    extcap_interfaces: bool,
+
+    /// Wireshark's own version, which makes no difference to us
+    #[arg(long = "extcap-version", hide = true)]
+    _extcap_version: Option<String>,
+
+    #[arg(long, hide = true)]
+    extcap_interface: Option<String>,
+
+    #[arg(long, hide = true, requires = "extcap_interface")]
+    extcap_dlts: bool,
+
+    #[arg(long, hide = true, requires = "extcap_interface")]
+    extcap_config: bool,
+
+    #[arg(long, hide = true, requires_all = ["extcap_interface", "fifo"])]
+    capture: bool,
+
+    #[arg(long, hide = true, requires = "capture")]
+    fifo: Option<PathBuf>,
+
+    #[arg(long, hide = true)]
+    extcap_capture_filter: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Specify a custom, valid Berkeley Packet Filter (BPF) string. Default is 'udp port <port> or tcp port <port>', also matching 802.1Q/QinQ tagged frames"
+    )]
+    custom_bpf: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "custom_bpf",
+        help = "Only packets to or from this address. Repeat for any of several"
+    )]
+    host: Vec<IpAddr>,
+
+    #[arg(
+        long,
+        conflicts_with = "custom_bpf",
+        help = "Only packets to or from the server at this address; '--direction' then goes by it rather than by the port"
+    )]
+    server: Option<IpAddr>,
+
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with = "custom_bpf",
+        help = "Only packets going this way"
+    )]
+    direction: Option<Direction>,
+
+    #[arg(
+        long,
+        help = "Look inside GRE, VXLAN and IP-in-IP tunnels for netwayste packets"
+    )]
+    decapsulate: bool,
+
+    #[arg(
+        long,
+        help = "Only show packets carried in this VLAN (outer or inner tag)"
+    )]
+    vlan: Option<u16>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "VARIANTS",
+        help = "Only show packets of these Packet variants, e.g. 'Request,Update'"
+    )]
+    only: Vec<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "VARIANTS",
+        help = "Don't show packets of these Packet variants, e.g. 'GetStatus,Status'"
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "ACTIONS",
+        help = "Only show requests with these RequestAction variants, e.g. 'JoinRoom,LeaveRoom'"
+    )]
+    action: Vec<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "CODES",
+        help = "Only show responses with these ResponseCode variants, e.g. 'BadRequest'. Combines with 'action' to show matches of either"
+    )]
+    response_code: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Only show traffic to and from this player, following them across reconnects"
+    )]
+    player: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Only show traffic about this game room: joining and leaving it, and its chats and updates"
+    )]
+    room: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "IP[:PORT]",
+        value_parser = FollowTarget::parse,
+        help = "Only show one client's conversation with the server, following it to new ports when its cookie matches"
+    )]
+    follow: Option<FollowTarget>,
+
+    #[arg(
+        long,
+        help = "Save the frames of packets that de-serialized as netwayste to a pcap file"
+    )]
+    write_pcap: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "write_pcap",
+        help = "Also save frames that failed netwayste de-serialization to the 'write-pcap' file"
+    )]
+    write_failures: bool,
+
+    #[arg(
+        long,
+        help = "Replace IP addresses with consistent stand-ins in all output, including the 'write-pcap' file, so the capture can be shared"
+    )]
+    anonymize: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "anonymize",
+        help = "Look up host names for source and destination addresses in the background, and show the source's alongside its address once known"
+    )]
+    resolve: bool,
+
+    #[arg(
+        long,
+        default_value = "2s",
+        value_parser = humantime::parse_duration,
+        help = "Give up on 'resolve' lookups that take longer than this, leaving the address bare"
+    )]
+    resolve_timeout: Duration,
+
+    #[arg(
+        long,
+        requires = "anonymize",
+        help = "Also replace ports other than the server's"
+    )]
+    anonymize_ports: bool,
+
+    #[arg(
+        long,
+        requires = "anonymize",
+        help = "Also replace player names, with stand-ins of the same length"
+    )]
+    anonymize_names: bool,
+
+    #[arg(
+        long,
+        requires = "write_pcap",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Start a new 'write-pcap' file once the current one reaches this many megabytes"
+    )]
+    rotate_size: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "rotate_size",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Keep at most this many rotated files, overwriting the oldest"
+    )]
+    rotate_count: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Index decoded packets into Elasticsearch or OpenSearch through the bulk API at this base URL, e.g. 'http://localhost:9200'"
+    )]
+    elastic_url: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "netwayste",
+        help = "Index used by 'elastic-url' and '--output elastic-bulk'"
+    )]
+    elastic_index: String,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve Prometheus metrics over HTTP on this address, e.g. '0.0.0.0:9184'"
+    )]
+    metrics_listen: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve a dashboard streaming decoded packets and running totals to browsers on this address, e.g. '127.0.0.1:8080'"
+    )]
+    web_listen: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Serve the running totals as JSON on /stats, /flows and /rooms over HTTP on this address, e.g. '127.0.0.1:9185'; implies 'room-stats'"
+    )]
+    api_listen: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Watch the traffic with the rules in this file, running a command or calling a webhook when one fires; see the alerts module for the format"
+    )]
+    alerts: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Run the Rhai script in this file on every decoded packet, to hide packets, count them or print lines of its own; see the script module for what it can define"
+    )]
+    script: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "Send the same counters as 'metrics-listen' to a StatsD daemon"
+    )]
+    statsd: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "10s",
+        requires = "statsd",
+        value_parser = humantime::parse_duration,
+        help = "How often to send counters to 'statsd'"
+    )]
+    statsd_interval: Duration,
+
+    #[arg(
+        long,
+        default_value = "netwayste",
+        requires = "statsd",
+        help = "Prefix for every 'statsd' metric name"
+    )]
+    statsd_prefix: String,
+
+    #[arg(
+        long,
+        help = "Forward decoded packets and de-serialization failures to the local syslog daemon as RFC 5424 messages"
+    )]
+    syslog: bool,
+
+    #[arg(
+        long,
+        default_value = "daemon",
+        value_parser = parse_facility,
+        help = "Syslog facility for 'syslog' and 'log-syslog' to log under, e.g. 'local0'"
+    )]
+    syslog_facility: syslog::Facility,
+
+    #[arg(
+        long,
+        help = "Insert decoded packets into this SQLite database, creating it if needed"
+    )]
+    sqlite: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "sqlite",
+        help = "Also insert payloads that failed netwayste de-serialization into the 'sqlite' database"
+    )]
+    sqlite_failures: bool,
+
+    #[arg(
+        long,
+        help = "Also write the log, decoded packets included, to this file"
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "log_file",
+        help = "Leave colors out of the 'log-file', while keeping them on the terminal"
+    )]
+    log_plain: bool,
+
+    #[arg(
+        long,
+        requires = "log_file",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Start a new 'log-file' once the current one reaches this many megabytes"
+    )]
+    log_rotate_size: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "log_rotate_size",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Keep at most this many rotated log files, overwriting the oldest"
+    )]
+    log_rotate_count: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "DIRECTIVES",
+        help = "Which logs to keep, in RUST_LOG syntax, e.g. 'warn,dissect_netwayste::alerts=info'. Defaults to RUST_LOG, or everything. Decoded packets are kept unless 'packets' is named, e.g. 'packets=off'"
+    )]
+    log_level: Option<String>,
 
-use bincode::deserialize;
-use circular_vec::CircularVec;
-use clap::{self, Parser, ValueEnum};
-use colored::*;
-use etherparse::{InternetSlice::Ipv4, SlicedPacket, TransportSlice::Udp};
-use netwaystev2::{protocol::Packet, DEFAULT_PORT as NETWAYSTE_PORT};
-use pcap;
-use tracing::*;
-use tracing_subscriber::FmtSubscriber;
+    #[arg(
+        long,
+        default_value = "text",
+        help = "Write logs as plain text, or as one JSON object per line"
+    )]
+    log_format: LogFormat,
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(short, long, help = "Log all failed de-serialization attempts")]
-    verbose: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the tool's own logs to this file, leaving only decoded packets on the terminal"
+    )]
+    tool_log: Option<PathBuf>,
 
-    #[arg(short, long, help = "The network interface name")]
-    interface: Option<String>,
+    #[arg(
+        long,
+        help = "Also send everything that's logged to the local syslog daemon, at matching severities"
+    )]
+    log_syslog: bool,
 
-    #[arg(short, long, default_value_t = NETWAYSTE_PORT, help = "This has no effect if 'custom-bpf' is provided")]
-    port: u16,
+    #[arg(
+        long,
+        conflicts_with_all = ["tui", "stdin"],
+        help = "Detach from the terminal and keep running in the background. Needs 'log-file', 'tool-log' or 'log-syslog' to log anywhere"
+    )]
+    daemon: bool,
 
     #[arg(
         long,
-        default_value = "ip-and-port",
-        help = "Control how packets are colorized"
+        value_name = "PATH",
+        help = "Write the process ID to this file, removing it on the way out"
     )]
-    color_option: ColorOption,
+    pidfile: Option<PathBuf>,
 
     #[arg(
-        short,
         long,
-        help = "Specify a custom, valid Berkeley Packet Filter (BPF) string. Default is 'udp port <port>'"
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Stop after this many netwayste packets have been decoded"
     )]
-    custom_bpf: Option<String>,
+    count: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Stop after this much time has passed, e.g. '30s' or '5m'"
+    )]
+    duration: Option<Duration>,
+}
+
+/// Things to do instead of listing packets.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Record one game from the capture as a replay file; see the replay module for the format
+    ExportReplay {
+        #[arg(help = "File to write the replay to")]
+        output: PathBuf,
+
+        #[arg(
+            long,
+            help = "Room whose game to record; the first game captured from its start if not given"
+        )]
+        room: Option<String>,
+    },
+
+    /// Match up the packets in two captures of the same traffic, e.g. one taken at a client and one
+    /// at the server, and report the ones only one of them has
+    Diff {
+        #[arg(help = "First capture file")]
+        first: PathBuf,
+
+        #[arg(help = "Second capture file, compared against the first")]
+        second: PathBuf,
+    },
+
+    /// List the devices '--interface' takes, with their addresses and flags; the one used when
+    /// it's not given is marked with '*'
+    Interfaces,
+
+    /// Send the client side of a capture to a live server over UDP, with the timing it was
+    /// captured with, e.g. to reproduce a bug against a dev server
+    Resend {
+        #[arg(help = "Capture file to take the client packets from")]
+        file: PathBuf,
+
+        #[arg(help = "Server to send them to, e.g. '127.0.0.1:2016'")]
+        server: SocketAddr,
+
+        #[arg(
+            long,
+            default_value_t = 1.0,
+            value_parser = parse_positive,
+            help = "How much faster than captured to send, e.g. '2' for twice as fast"
+        )]
+        speed: f64,
+    },
+
+    /// Send made-up netwayste traffic to a server at a steady rate, e.g. as background load
+    Generate {
+        #[arg(help = "Server to send it to, e.g. '127.0.0.1:2016'")]
+        server: SocketAddr,
+
+        #[arg(
+            long,
+            default_value_t = 100.0,
+            value_parser = parse_positive,
+            help = "Packets to send a second, across all clients"
+        )]
+        rate: f64,
+
+        #[arg(
+            long,
+            default_value_t = 10,
+            value_parser = clap::value_parser!(u16).range(1..),
+            help = "How many clients to spread the packets over, each sending from a port of its own"
+        )]
+        clients: u16,
+
+        #[arg(
+            long,
+            default_value = "KeepAlive=10,SendChatMessage=1",
+            value_parser = Mix::parse,
+            help = "Packet variants or request actions to send, with how many of each relative to the others"
+        )]
+        mix: Mix,
+
+        #[arg(
+            long,
+            help = "Cookie to put in every packet that has one, e.g. from a real login"
+        )]
+        cookie: Option<String>,
+
+        #[arg(
+            long,
+            help = "Stop after sending this many packets, rather than on Ctrl-C"
+        )]
+        packets: Option<u64>,
+    },
+
+    /// Log in to a server and time keepalives to it, like ping: RTT, loss and response codes
+    Probe {
+        #[arg(help = "Server to probe, e.g. '203.0.113.5:2016'")]
+        server: SocketAddr,
+
+        #[arg(long, default_value = "probe", help = "Player name to log in as")]
+        name: String,
+
+        #[arg(
+            long,
+            help = "Client version to announce when logging in, for servers that check it"
+        )]
+        client_version: Option<String>,
+
+        #[arg(
+            long,
+            default_value = "1s",
+            value_parser = humantime::parse_duration,
+            help = "Time between keepalives"
+        )]
+        interval: Duration,
+
+        #[arg(
+            long,
+            default_value = "1s",
+            value_parser = humantime::parse_duration,
+            help = "How long to wait for a response before counting a keepalive lost"
+        )]
+        timeout: Duration,
+
+        #[arg(long, help = "Stop after this many keepalives, rather than on Ctrl-C")]
+        count: Option<u64>,
+    },
+
+    /// Simulate many players on a server at once, to see how many it can handle
+    LoadTest {
+        #[arg(help = "Server to load, e.g. '127.0.0.1:2016'")]
+        server: SocketAddr,
+
+        #[arg(
+            long,
+            default_value_t = 100,
+            value_parser = clap::value_parser!(u16).range(1..),
+            help = "How many clients to simulate, each with a socket and login of its own"
+        )]
+        clients: u16,
+
+        #[arg(
+            long,
+            default_value = "10s",
+            value_parser = humantime::parse_duration,
+            help = "Time over which the clients log in, evenly spread"
+        )]
+        ramp_up: Duration,
+
+        #[arg(
+            long,
+            value_parser = humantime::parse_duration,
+            help = "How long to run for, from the first login, rather than until Ctrl-C"
+        )]
+        duration: Option<Duration>,
+
+        #[arg(
+            long,
+            default_value = "1s",
+            value_parser = humantime::parse_duration,
+            help = "Time between each client's keepalives"
+        )]
+        keepalive: Duration,
+
+        #[arg(
+            long,
+            default_value = "SendChatMessage=1",
+            value_parser = Mix::parse,
+            help = "Request actions each client sends besides keepalives, with how many of each relative to the others"
+        )]
+        mix: Mix,
+
+        #[arg(
+            long,
+            default_value = "5s",
+            value_parser = humantime::parse_duration,
+            help = "Time between each client's requests from the mix"
+        )]
+        action_interval: Duration,
+
+        #[arg(
+            long,
+            default_value = "1s",
+            value_parser = humantime::parse_duration,
+            help = "How long to wait for a login to be answered before trying again"
+        )]
+        timeout: Duration,
+
+        #[arg(
+            long,
+            help = "Client version to announce when logging in, for servers that check it"
+        )]
+        client_version: Option<String>,
+    },
+
+    /// Send mutated packets to a server you run and save the ones it reacts to oddly, or that
+    /// stop it answering
+    Fuzz {
+        #[arg(help = "Server to fuzz, e.g. '127.0.0.1:2016'")]
+        server: SocketAddr,
+
+        #[arg(
+            long,
+            default_value_t = 50.0,
+            value_parser = parse_positive,
+            help = "Inputs to send a second"
+        )]
+        rate: f64,
+
+        #[arg(long, help = "Stop after this many inputs, rather than on Ctrl-C")]
+        iterations: Option<u64>,
+
+        #[arg(
+            long,
+            default_value = "GetStatus",
+            help = "Packet variant or request action sent now and then to check the server still answers"
+        )]
+        probe: String,
+
+        #[arg(
+            long,
+            help = "Cookie to put in every packet that has one, e.g. from a real login"
+        )]
+        cookie: Option<String>,
+
+        #[arg(
+            long,
+            default_value = "fuzz-findings",
+            help = "Directory to save interesting inputs, and the replies they drew, in"
+        )]
+        out: PathBuf,
+
+        #[arg(
+            long,
+            default_value = "20ms",
+            value_parser = humantime::parse_duration,
+            help = "How long to wait for replies to each input"
+        )]
+        wait: Duration,
+
+        #[arg(
+            long,
+            help = "Seed for the mutations, to repeat an earlier run; logged at the start"
+        )]
+        seed: Option<u64>,
+    },
+
+    /// Write a Wireshark Lua dissector generated from the netwayste protocol types
+    GenerateLuaDissector {
+        #[arg(
+            short,
+            long,
+            help = "File to write the dissector to; stdout if not given"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(short, long, default_value_t = NETWAYSTE_PORT, help = "UDP port the dissector registers itself on by default")]
+        port: u16,
+    },
+
+    /// Print a completion script for a shell, e.g. to save as
+    /// /usr/share/bash-completion/completions/dissect-netwayste
+    Completions {
+        #[arg(help = "Shell to complete options for")]
+        shell: Shell,
+    },
 }
 
 #[derive(Parser, ValueEnum, Debug, Clone)]
 enum ColorOption {
     IPAndPort,
     OnlyIP,
+    /// By `Packet` variant, with failed responses set apart
+    PacketType,
     NoColor,
 }
 
@@ -52,6 +1212,17 @@ impl ColorOption {
             _ => true,
         }
     }
+
+    fn by_source(&self) -> bool {
+        matches!(self, ColorOption::IPAndPort | ColorOption::OnlyIP)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorWhen {
+    Always,
+    Auto,
+    Never,
 }
 
 impl fmt::Display for ColorOption {
@@ -60,135 +1231,1210 @@ impl fmt::Display for ColorOption {
     }
 }
 
+/// The options `Args` declares, plus the ones only `config::path()` looks at, which still
+/// belong in --help, completions and the man page.
+fn command() -> clap::Command {
+    Args::command()
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .help("Read options from this TOML file. Defaults to ./dissect.toml, then $XDG_CONFIG_HOME/dissect-netwayste/config.toml, if either exists"),
+        )
+        .arg(
+            Arg::new("no_config")
+                .long("no-config")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("config")
+                .help("Don't read a config file, even if one of the default ones exists"),
+        )
+}
+
+/// The command line, after whatever the config file adds in front of it.
+fn parse_args() -> (Args, Config) {
+    let argv: Vec<OsString> = env::args_os().collect();
+    let config = match config::path(&argv) {
+        Some(path) => config::load(&path, &Args::command()).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("bad config file '{}': {}", path.display(), e),
+                )
+                .exit()
+        }),
+        None => Config::default(),
+    };
+    let argv = argv[..1].iter().chain(&config.args).chain(&argv[1..]);
+    let matches = command().args_override_self(true).get_matches_from(argv);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    (args, config)
+}
+
 fn main() {
-    let subscriber = FmtSubscriber::builder()
-        // All spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.) will be written to stdout.
-        .with_max_level(Level::TRACE)
-        .finish();
+    let (args, config) = parse_args();
+
+    if let Some(Command::Completions { shell }) = &args.command {
+        let mut command = command();
+        let name = command.get_name().to_owned();
+        clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+        return;
+    }
+    if args.generate_man {
+        clap_mangen::Man::new(command())
+            .render(&mut io::stdout())
+            .expect("Failed to write man page");
+        return;
+    }
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    if let Some(Command::Interfaces) = &args.command {
+        capture::print_interfaces();
+        return;
+    }
 
-    let args = Args::parse();
+    if let Some(Command::GenerateLuaDissector { output, port }) = &args.command {
+        let lua =
+            lua::generate(*port).unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
+        match output {
+            Some(path) => fs::write(path, lua).unwrap_or_else(|e| {
+                setup_error(
+                    ErrorKind::Io,
+                    format!("can't write Lua dissector '{}': {}", path.display(), e),
+                )
+            }),
+            None => print!("{}", lua),
+        }
+        return;
+    }
 
-    // Setup Capture
-    let device = if let Some(interface) = args.interface {
-        // Verify we can find a device
-        let device_list = pcap::Device::list().expect("Could not access network interface list");
-        device_list
-            .into_iter()
-            .filter(|d| d.name == interface)
-            .next()
-            .expect(&format!(
-                "Failed to find '{}' in network interface list",
-                interface
-            ))
+    if args.extcap_interfaces {
+        extcap::print_interfaces();
+        return;
+    }
+    if let Some(interface) = &args.extcap_interface {
+        if interface != extcap::INTERFACE {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("unknown extcap interface '{}'", interface),
+                )
+                .exit();
+        }
+    }
+    if args.extcap_dlts {
+        extcap::print_dlts();
+        return;
+    }
+    if args.extcap_config {
+        extcap::print_config();
+        return;
+    }
+
+    let writes_files = args.output_file.is_some()
+        || args.rtt_file.is_some()
+        || args.timeline_file.is_some()
+        || args.game_events.is_some()
+        || args.write_pcap.is_some()
+        || args.sqlite.is_some()
+        || args.log_file.is_some()
+        || args.tool_log.is_some()
+        || matches!(
+            args.command,
+            Some(Command::ExportReplay { .. } | Command::Fuzz { .. })
+        );
+    if writes_files && args.drop_privs.is_none() && !args.allow_root && privs::is_root() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "refusing to write files as root; pass 'drop-privs' to write them as another user, or 'allow-root' to write them as root anyway",
+            )
+            .exit();
+    }
+
+    if args.daemon && args.log_file.is_none() && args.tool_log.is_none() && !args.log_syslog {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "'daemon' leaves nothing on the terminal; pass 'log-file', 'tool-log' or 'log-syslog' to keep its logs",
+            )
+            .exit();
+    }
+
+    if args.tui && args.output.is_machine_readable() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "'tui' only works with the text output",
+            )
+            .exit();
+    }
+    let tui = args.tui.then(Tui::default);
+    let logs_to_stderr = args.output.is_machine_readable() || args.capture;
+    // The TUI list can't show colors, only the escapes that make them
+    let color = tui.is_none()
+        && match args.color {
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+            // See https://no-color.org; an empty NO_COLOR doesn't count
+            ColorWhen::Auto => {
+                let terminal = if logs_to_stderr {
+                    io::stderr().is_terminal()
+                } else {
+                    io::stdout().is_terminal()
+                };
+                terminal && env::var_os("NO_COLOR").map_or(true, |value| value.is_empty())
+            }
+        };
+    colored::control::set_override(color);
+
+    // Keep stdout clean for whatever is consuming machine-readable output
+    let writer = if let Some(tui) = &tui {
+        let writer = tui.writer();
+        BoxMakeWriter::new(move || writer.clone())
+    } else if logs_to_stderr {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    let writer = match &args.log_file {
+        Some(path) => {
+            let rotation = args.log_rotate_size.map(|megabytes| Rotation {
+                max_bytes: megabytes * 1_000_000,
+                max_files: args.log_rotate_count.map(|count| count as usize),
+            });
+            let log_file = LogFile::new(path.clone(), rotation, args.log_plain);
+            BoxMakeWriter::new(writer.and(Mutex::new(log_file)))
+        }
+        None => writer,
+    };
+    let writer = if args.log_syslog {
+        let syslog = SyslogLog::connect(args.syslog_facility).unwrap_or_else(|e| {
+            setup_error(ErrorKind::Io, format!("can't connect to syslog: {}", e))
+        });
+        BoxMakeWriter::new(writer.and(syslog))
     } else {
-        pcap::Device::lookup()
-            .expect("Failed to look up default device")
-            .unwrap()
+        writer
     };
+    if let Err(e) = logging::init(
+        writer,
+        color,
+        args.log_format,
+        args.log_level.as_deref(),
+        args.tool_log.as_deref(),
+    ) {
+        Args::command().error(ErrorKind::InvalidValue, e).exit();
+    }
+
+    if let Some(Command::Generate {
+        server,
+        rate,
+        clients,
+        mix,
+        cookie,
+        packets,
+    }) = &args.command
+    {
+        let generator = Generator::new(mix, cookie.clone())
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::InvalidValue, e).exit());
+        generate::run(generator, *server, *rate, *clients, *packets);
+        return;
+    }
 
-    let device_name = device.name.clone();
+    if let Some(Command::Probe {
+        server,
+        name,
+        client_version,
+        interval,
+        timeout,
+        count,
+    }) = &args.command
+    {
+        if let Err(e) = probe::run(probe::Options {
+            server: *server,
+            name: name.clone(),
+            client_version: client_version.clone(),
+            interval: *interval,
+            timeout: *timeout,
+            count: *count,
+        }) {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    // Unwrap okay because of device verification above
-    let mut cap = pcap::Capture::from_device(device)
-        .unwrap()
-        .immediate_mode(true)
-        .open()
-        .unwrap();
+    if let Some(Command::LoadTest {
+        server,
+        clients,
+        ramp_up,
+        duration,
+        keepalive,
+        mix,
+        action_interval,
+        timeout,
+        client_version,
+    }) = &args.command
+    {
+        if let Err(e) = load_test::run(load_test::Options {
+            server: *server,
+            clients: *clients,
+            ramp_up: *ramp_up,
+            duration: *duration,
+            keepalive: *keepalive,
+            mix: mix.clone(),
+            action_interval: *action_interval,
+            timeout: *timeout,
+            client_version: client_version.clone(),
+        }) {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let mut filter_string = format!("udp port {:?}", args.port);
-    if let Some(filter) = args.custom_bpf {
+    let ports: Ports = args.port.iter().cloned().collect();
+    let mut filter = Filter::Port {
+        ports: ports.clone(),
+        tunnels: args.decapsulate,
+        hosts: args.host.clone(),
+        server: args.server,
+        direction: args.direction,
+    };
+    // Wireshark passes an empty capture filter when none was entered
+    let custom_bpf = args.custom_bpf.clone().or(args
+        .extcap_capture_filter
+        .clone()
+        .filter(|custom| !custom.is_empty()));
+    if let Some(custom) = custom_bpf {
         let dead_capture = pcap::Capture::dead(pcap::Linktype::ETHERNET).unwrap();
         dead_capture
-            .compile(&filter, true)
+            .compile(&custom, true)
             .ok()
             .expect("Failed to compile custom-bpf");
-        filter_string = filter;
+        filter = Filter::Custom(custom);
+    }
+
+    if let Some(Command::Fuzz {
+        server,
+        rate,
+        iterations,
+        probe,
+        cookie,
+        out,
+        wait,
+        seed,
+    }) = &args.command
+    {
+        if let Err(e) = fuzz::run(fuzz::Options {
+            server: *server,
+            rate: *rate,
+            iterations: *iterations,
+            probe: probe.clone(),
+            cookie: cookie.clone(),
+            out: out.clone(),
+            wait: *wait,
+            seed: *seed,
+        }) {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Diff { first, second }) = &args.command {
+        capture_diff::run(
+            first,
+            second,
+            &filter,
+            &ports,
+            args.protocol.decoder().as_ref(),
+            args.decapsulate,
+            args.verbose,
+        );
+        return;
+    }
+
+    if let Some(Command::Resend {
+        file,
+        server,
+        speed,
+    }) = &args.command
+    {
+        resend::run(
+            file,
+            &filter,
+            &ports,
+            *server,
+            *speed,
+            args.decapsulate,
+            args.verbose,
+        );
+        return;
     }
 
-    cap.filter(&filter_string, true)
+    // Setup Capture
+    let (mut source, source_name) = if let Some(path) = &args.read_file {
+        (Source::from_file(path), path.display().to_string())
+    } else if args.stdin {
+        (Source::from_stdin(), "<stdin>".to_owned())
+    } else if let Some(destination) = &args.remote {
+        if args.interface.len() > 1 {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "only one 'interface' can be captured on with 'remote'",
+                )
+                .exit();
+        }
+        let source = Source::from_remote(
+            destination,
+            &args.remote_tcpdump,
+            args.interface.first().map(|i| i.as_str()),
+            &filter,
+        );
+        (source, destination.clone())
+    } else {
+        let options = CaptureOptions {
+            snaplen: args.snaplen,
+            buffer_size: args.buffer_size,
+            promisc: args.promisc,
+            timeout_ms: args.timeout,
+        };
+        Source::from_devices(&args.interface, &options)
+    };
+
+    source
+        .filter(&filter)
         .expect("Failed to filter for netwayste packets");
 
-    info!(
-        "Listening to device '{}' with filter '{}'",
-        device_name, filter_string
-    );
+    // With the capture open, any problem with it has been reported on the terminal
+    if args.daemon {
+        daemon::daemonize().expect("Failed to run in the background");
+    }
+    if let Some(path) = &args.pidfile {
+        daemon::write_pidfile(path).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::Io,
+                format!("can't write pidfile '{}': {}", path.display(), e),
+            )
+        });
+    }
 
-    let mut ip_color_map = HashMap::<(Ipv4Addr, Option<u16>), Color>::new();
-
-    // Colors are specified to reduce adjacent similarity.
-    // This may appear differently depending on one's terminal settings.
-    let mut color_list: CircularVec<Color> = vec![
-        Color::Cyan,
-        Color::Yellow,
-        Color::Red,
-        Color::Magenta,
-        Color::Green,
-        Color::Blue,
-    ]
-    .into_iter()
-    .collect();
-
-    // TODO: some next_packet() errors should just be logged, rather than breaking out of the loop.
-    while let Ok(packet) = cap.next_packet() {
-        match SlicedPacket::from_ethernet(packet.data) {
+    // Only opening the capture needs root
+    if let Some(spec) = &args.drop_privs {
+        if let Err(e) = privs::drop_to(spec) {
+            error!("Failed to drop privileges to '{}': {}", spec, e);
+            process::exit(1);
+        }
+        info!("Dropped privileges to '{}'", spec);
+    }
+
+    let is_live = args.read_file.is_none() && !args.stdin && args.remote.is_none();
+
+    // Live captures are stamped by the subscriber; saved or piped ones need their original time
+    let show_capture_time = !is_live || args.time_format.is_some();
+
+    if !is_live {
+        info!("Reading '{}' with filter '{}'", source_name, filter);
+    } else {
+        info!(
+            "Listening to device(s) '{}' with filter '{}'",
+            source_name, filter
+        );
+    }
+
+    let theme = match (&args.theme, &config.theme) {
+        (Some(path), _) => Theme::load(path).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("bad theme file '{}': {}", path.display(), e),
+            )
+        }),
+        (None, Some(text)) => Theme::parse(text).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("bad theme in config file: {}", e),
+            )
+        }),
+        (None, None) => Theme::default(),
+    };
+    let mut ip_color_map = HashMap::<(IpAddr, Option<u16>), ThemeColor>::new();
+    let mut color_list: CircularVec<ThemeColor> = theme.palette.iter().copied().collect();
+
+    let rotation = args.rotate_size.map(|megabytes| Rotation {
+        max_bytes: megabytes * 1_000_000,
+        max_files: args.rotate_count.map(|count| count as usize),
+    });
+    let mut selector = Selector {
+        content: PacketFilter {
+            only: args.only.clone(),
+            exclude: args.exclude.clone(),
+            actions: args.action.clone(),
+            response_codes: args.response_code.clone(),
+        },
+        player: args
+            .player
+            .clone()
+            .map(|name| PlayerFilter::new(name, ports.clone())),
+        room: args
+            .room
+            .clone()
+            .map(|name| RoomFilter::new(name, ports.clone())),
+        follow: args
+            .follow
+            .clone()
+            .map(|target| FollowFilter::new(target, ports.clone())),
+    };
+    let mut throttle = (args.sample.is_some() || args.max_lines_per_sec.is_some())
+        .then(|| Throttle::new(args.sample, args.max_lines_per_sec));
+    let mut repeats = (args.collapse_repeats && args.output == OutputFormat::Text && !args.tui)
+        .then(Repeats::default);
+    // Diagnoses follow the v2 layout, which would only mislead about v1 packets
+    let layout = if (args.verbose || args.tui) && args.protocol != Protocol::V1 {
+        templates::trace_packet()
+            .map_err(|e| warn!("Failed de-serializations won't be diagnosed: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let mut timing = Timing::new(args.time_format.clone().unwrap_or(TimeFormat::Rfc3339));
+    let mut anonymizer = args
+        .anonymize
+        .then(|| Anonymizer::new(ports.clone(), args.anonymize_ports, args.anonymize_names));
+    let resolver = args.resolve.then(|| Resolver::new(args.resolve_timeout));
+    let mut pcap_writer = args
+        .write_pcap
+        .clone()
+        .map(|path| PcapWriter::new(path, rotation));
+    let metrics = args.metrics_listen.map(|addr| {
+        let metrics = Metrics::new();
+        metrics.serve(addr);
+        info!("Serving Prometheus metrics on http://{}/metrics", addr);
+        metrics
+    });
+    let statsd = args
+        .statsd
+        .as_ref()
+        .map(|addr| Statsd::start(addr, &args.statsd_prefix, args.statsd_interval));
+    let mut analyzers = Analyzers::default();
+    if args.check {
+        analyzers.register(Checker::new(ports.clone()));
+    }
+    if args.chat {
+        analyzers.register(ChatMonitor::new(ports.clone()));
+    }
+    if let Some(interval) = args.jitter {
+        analyzers.register(JitterTracker::new(ports.clone(), interval));
+    }
+    if let Some(interval) = args.bandwidth {
+        analyzers.register(BandwidthTracker::new(ports.clone(), interval));
+    }
+    if let Some(count) = args.top {
+        analyzers.register(TopTalkers::new(count, args.top_by, args.top_interval));
+    }
+    if let Some(interval) = args.stats_interval {
+        analyzers.register(Heartbeat::new(ports.clone(), interval));
+    }
+    if let Some(statsd) = &statsd {
+        analyzers.register(statsd.clone());
+    }
+    if args.syslog {
+        let syslog = SyslogSink::connect(args.syslog_facility).unwrap_or_else(|e| {
+            setup_error(ErrorKind::Io, format!("can't connect to syslog: {}", e))
+        });
+        analyzers.register(syslog);
+    }
+    if let Some(path) = &args.sqlite {
+        let sqlite = SqliteExport::open(path, args.sqlite_failures).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("can't open SQLite database '{}': {}", path.display(), e),
+            )
+        });
+        analyzers.register(sqlite);
+    }
+    if let Some(metrics) = &metrics {
+        analyzers.register(metrics.clone());
+    }
+    analyzers.register(Duplicates::default());
+    if args.reorder {
+        analyzers.register(Reordering::default());
+    }
+    if args.retransmits {
+        analyzers.register(Retransmits::default());
+    }
+    if args.seq_gaps {
+        analyzers.register(SequenceGaps::default());
+    }
+    let versions = Arc::new(Mutex::new(VersionWatch::default()));
+    analyzers.register(Arc::clone(&versions));
+
+    // Width of the source address column. Starts out fitting any IPv4 address and widens the
+    // first time a longer (IPv6) address shows up, so v4-only output looks the same as ever.
+    let mut addr_width = 15;
+
+    if args.output == OutputFormat::Csv {
+        println!("{}", CSV_HEADER);
+    }
+
+    let mut summary = Summary::new();
+    summary.sizes = args.size_histogram.then(SizeHistograms::default);
+    summary.keepalives = args.keepalives.map(KeepaliveTracker::new);
+    summary.rooms =
+        (args.room_stats || args.api_listen.is_some()).then(|| RoomStats::new(ports.clone()));
+    summary.lobby = args.lobby.then(Lobby::default);
+    if args.geoip_db.is_some() || args.asn_db.is_some() {
+        let geoip = GeoIp::open(
+            ports.clone(),
+            args.geoip_db.as_deref(),
+            args.asn_db.as_deref(),
+        )
+        .unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("can't open GeoIP database: {}", e),
+            )
+        });
+        summary.geoip = Some(geoip);
+    }
+    summary.universes = (args.universe || args.dump_universe.is_some() || args.render)
+        .then(|| Universes::new(ports.clone(), args.dump_universe));
+    let summary = Arc::new(Mutex::new(summary));
+    let summary_interval = args
+        .summary_interval
+        .or(args.quiet.then_some(DEFAULT_SUMMARY_INTERVAL));
+    // Under Wireshark the decodes end up in packet comments, and nobody is watching stdout.
+    // With --top, --render or --chat, what they show takes the place of the packets.
+    let quiet = args.quiet
+        || args.capture
+        || args.top.is_some()
+        || args.render
+        || args.chat
+        || matches!(args.command, Some(Command::ExportReplay { .. }));
+    let mut extcap_writer = args.fifo.as_ref().map(|fifo| {
+        OpenOptions::new()
+            .write(true)
+            .open(fifo)
+            .and_then(PcapngWriter::new)
+            .unwrap_or_else(|e| {
+                setup_error(
+                    ErrorKind::Io,
+                    format!("can't write to extcap fifo '{}': {}", fifo.display(), e),
+                )
+            })
+    });
+    if let Some(interval) = summary_interval {
+        let summary = Arc::clone(&summary);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            summary.lock().unwrap().log_progress();
+        });
+    }
+    let alerts = match (&args.alerts, &config.alerts) {
+        (Some(path), _) => Some(Alerts::load(path, ports.clone()).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("bad alerts file '{}': {}", path.display(), e),
+            )
+        })),
+        (None, Some(text)) => Some(Alerts::parse(text, ports.clone()).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("bad alerts in config file: {}", e),
+            )
+        })),
+        (None, None) => None,
+    };
+    let alerts = alerts.map(|alerts| {
+        let alerts = Arc::new(Mutex::new(alerts));
+        Alerts::watch(Arc::clone(&alerts));
+        analyzers.register(Arc::clone(&alerts));
+        alerts
+    });
+    analyzers.register(SummaryRecorder::new(
+        Arc::clone(&summary),
+        ports.clone(),
+        !quiet,
+        args.render,
+        args.render_window,
+    ));
+    if args.rtt || args.rtt_file.is_some() || alerts.is_some() {
+        let export = args.rtt_file.as_ref().map(|path| {
+            RttExport::create(path).unwrap_or_else(|e| {
+                setup_error(
+                    ErrorKind::Io,
+                    format!("can't create RTT file '{}': {}", path.display(), e),
+                )
+            })
+        });
+        analyzers.register(Latency::new(
+            args.rtt,
+            export,
+            alerts.clone(),
+            Arc::clone(&summary),
+        ));
+    }
+    if args.sessions || args.timeline || args.timeline_file.is_some() {
+        let export = args.timeline_file.as_ref().map(|path| {
+            TimelineExport::create(path).unwrap_or_else(|e| {
+                setup_error(
+                    ErrorKind::Io,
+                    format!("can't create timeline file '{}': {}", path.display(), e),
+                )
+            })
+        });
+        analyzers.register(Sessions::new(
+            ports.clone(),
+            args.sessions,
+            args.timeline,
+            export,
+        ));
+    }
+    if let Some(path) = &args.game_events {
+        let game_events = GameEvents::create(path, ports.clone()).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::Io,
+                format!("can't create game events file '{}': {}", path.display(), e),
+            )
+        });
+        analyzers.register(game_events);
+    }
+    if let Some(Command::ExportReplay { output, room }) = &args.command {
+        analyzers.register(ReplayRecorder::new(
+            output.clone(),
+            ports.clone(),
+            room.clone(),
+        ));
+    }
+    if let Some(url) = &args.elastic_url {
+        analyzers.register(ElasticPush::new(url, &args.elastic_index));
+    }
+    let mut parquet = match args.output {
+        OutputFormat::Parquet => {
+            let path = args.output_file.as_ref().unwrap();
+            Some(ParquetExport::create(path).unwrap_or_else(|e| {
+                setup_error(
+                    ErrorKind::Io,
+                    format!("can't create Parquet file '{}': {}", path.display(), e),
+                )
+            }))
+        }
+        _ => None,
+    };
+    if let Some(addr) = args.api_listen {
+        api::serve(addr, Arc::clone(&summary));
+        info!("Serving the stats API on http://{}/stats", addr);
+    }
+    if let Some(addr) = args.web_listen {
+        analyzers.register(WebDashboard::serve(addr, Arc::clone(&summary)));
+        info!("Serving the web dashboard on http://{}/", addr);
+    }
+    let analyzers = Arc::new(Mutex::new(analyzers));
+    Analyzers::tick(Arc::clone(&analyzers));
+    // Set by --duration, quitting the TUI or a signal; the main loop then ends as if the
+    // capture had
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some(duration) = args.duration {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            thread::sleep(duration);
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
+
+    // The TUI has its own pause key, and needs stdin to itself
+    let pause = tui
+        .is_none()
+        .then(|| Pause::start(!args.stdin && io::stdin().is_terminal()));
+
+    let script = args.script.as_ref().map(|path| {
+        let script = Script::load(path, pause.clone()).unwrap_or_else(|e| {
+            setup_error(
+                ErrorKind::InvalidValue,
+                format!("bad script '{}': {}", path.display(), e),
+            )
+        });
+        let script = Arc::new(Mutex::new(script));
+        analyzers.lock().unwrap().register(Arc::clone(&script));
+        script
+    });
+
+    let tui_thread = tui.as_ref().map(|tui| {
+        let stop = Arc::clone(&stop);
+        tui.start(source_name.clone(), Arc::clone(&summary), move || {
+            stop.store(true, Ordering::Relaxed);
+        })
+    });
+    {
+        let stop = Arc::clone(&stop);
+        let pause = pause.clone();
+        let tui = tui.clone();
+        shutdown::on_signal(move || {
+            stop.store(true, Ordering::Relaxed);
+            // In the TUI Ctrl-C is a key press, but SIGTERM still gets here
+            if let Some(tui) = &tui {
+                tui.close();
+            }
+            if let Some(pause) = &pause {
+                pause.resume();
+            }
+        });
+    }
+
+    let mut pipeline = Pipeline::start(
+        source,
+        Dissector::new(args.decapsulate),
+        Arc::from(args.protocol.decoder()),
+        pause.clone().filter(|_| args.pause_capture),
+        args.decode_workers.map_or_else(
+            || thread::available_parallelism().map_or(1, |workers| workers.get()),
+            |workers| workers as usize,
+        ),
+        Arc::clone(&summary),
+        Arc::clone(&stop),
+    );
+    while let Some(Decoded {
+        mut frame,
+        datagrams,
+    }) = pipeline.next()
+    {
+        summary.lock().unwrap().frames += 1;
+        let datagrams = match datagrams {
             Err(err) => {
+                summary.lock().unwrap().malformed += 1;
+                if let Some(metrics) = &metrics {
+                    metrics.record_malformed();
+                }
+                if let Some(statsd) = &statsd {
+                    statsd.record_malformed();
+                }
                 if args.verbose {
-                    error!("Failed EthernetII packet de-serialization: '{}'", err);
+                    error!(
+                        "Failed {:?} packet de-serialization: '{}'",
+                        frame.linktype, err
+                    );
+                }
+                // Wireshark still gets to show it, just without a decode
+                if let Some(writer) = extcap_writer.as_mut() {
+                    if writer.write(&frame, &[]).is_err() {
+                        break;
+                    }
                 }
+                continue;
             }
-            Ok(ethernet) => {
-                let src_port;
-                let src_ip;
-
-                // Ignore non-UDP.
-                match ethernet.transport {
-                    Some(Udp(udp)) => {
-                        src_port = udp.source_port();
+            Ok(datagrams) => datagrams,
+        };
+
+        // A TCP segment may complete several messages, so whether the frame gets saved depends
+        // on all of them.
+        let mut any_decoded = false;
+        let mut any_failed = false;
+        let mut reached_count = false;
+        let mut comments = vec![];
+
+        for Payload {
+            mut datagram,
+            decompressed,
+            compression,
+            decompress_error,
+            result,
+            name: capture_name,
+        } in datagrams
+        {
+            if let Some(vlan) = args.vlan {
+                if !datagram.vlan_ids.contains(&vlan) {
+                    continue;
+                }
+            }
+            if let Some(anonymizer) = anonymizer.as_mut() {
+                datagram.src = anonymizer.addr(datagram.src);
+                datagram.dst = anonymizer.addr(datagram.dst);
+            }
+
+            let src_ip = datagram.src.ip();
+            let src_port = datagram.src.port();
+
+            // With packet-type coloring, the color is only known once the packet is decoded
+            let mut message_color: Option<ThemeColor> = None;
+            if let Some(color) = theme
+                .flow(datagram.src)
+                .filter(|_| args.color_option.by_source())
+            {
+                message_color = Some(color);
+            } else if args.color_option.by_source() {
+                let key = match args.color_option {
+                    ColorOption::IPAndPort => (src_ip, Some(src_port)),
+                    _ => (src_ip, None),
+                };
+                match ip_color_map.get_mut(&key) {
+                    Some(entry) => message_color = Some(*entry),
+                    None => {
+                        message_color = Some(*color_list.next());
+                        ip_color_map.insert(key.clone(), message_color.unwrap());
                     }
-                    _ => continue,
-                }
-                let mut message_color: Option<Color> = None;
-                match ethernet.ip {
-                    Some(Ipv4(ipv4, _extensions)) => {
-                        src_ip = ipv4.source_addr();
-                        let key = match args.color_option {
-                            ColorOption::IPAndPort => (src_ip, Some(src_port)),
-                            _ => (src_ip, None),
-                        };
-
-                        if args.color_option.color_enabled() {
-                            match ip_color_map.get_mut(&key) {
-                                Some(entry) => message_color = Some(*entry),
-                                None => {
-                                    message_color = Some(*color_list.next());
-                                    ip_color_map.insert(key.clone(), message_color.unwrap());
-                                }
-                            }
-                        }
+                }
+            }
+
+            // There's a packet that is candidate for matching netwayste
+            if let Some(e) = decompress_error.filter(|_| args.verbose) {
+                error!("Failed decompression: '{}'", e);
+            }
+            let payload = decompressed.as_deref().unwrap_or(&datagram.payload[..]);
+            let result = match anonymizer.as_mut() {
+                Some(anonymizer) => result.map(|nw_packet| anonymizer.packet(nw_packet)),
+                None => result,
+            };
+            // The raw bytes are shown and stored too, so the names mustn't survive in them
+            let (anonymized, anonymized_wire) = match anonymizer.as_ref() {
+                Some(anonymizer) => (
+                    Some(anonymizer.payload(payload)),
+                    Some(anonymizer.wire_payload(&datagram.payload, compression.is_some())),
+                ),
+                None => (None, None),
+            };
+            let payload = anonymized.as_deref().unwrap_or(payload);
+            let wire = anonymized_wire.as_deref().unwrap_or(&datagram.payload[..]);
+            any_decoded |= result.is_ok();
+            any_failed |= result.is_err();
+            // Turned into a value once, for everything below that reads the packet
+            let result = result.map(|nw_packet| {
+                let value = packet_value(&nw_packet);
+                (nw_packet, value)
+            });
+
+            if extcap_writer.is_some() {
+                comments.push(match &result {
+                    Ok((nw_packet, _)) => format!("netwayste: {}", readable(nw_packet)),
+                    Err(e) => format!("netwayste: failed de-serialization: {}", e),
+                });
+            }
+
+            let mut context = Context {
+                ts: frame.ts,
+                src: datagram.src,
+                dst: datagram.dst,
+                interface: frame.interface.as_deref(),
+                payload: wire,
+                shown: false,
+            };
+            let value = result.as_ref().ok().map(|(_, value)| value);
+            let marks = analyzers.lock().unwrap().mark(&context, value);
+            let duplicate = marks.contains(&Mark::Duplicate);
+            if duplicate {
+                summary.lock().unwrap().duplicates += 1;
+            }
+
+            let shown = match &result {
+                Ok((_, value)) => selector.shows(datagram.src, datagram.dst, value),
+                Err(_) => true,
+            };
+            let shown = shown && !(duplicate && args.suppress_dups);
+            let shown = match (&script, value) {
+                (Some(script), Some(value)) if shown => script.lock().unwrap().shows(
+                    frame.ts,
+                    datagram.src,
+                    datagram.dst,
+                    value,
+                    datagram.payload.len(),
+                ),
+                _ => shown,
+            };
+
+            context.shown = shown;
+            match &result {
+                Ok((nw_packet, value)) => {
+                    analyzers.lock().unwrap().packet(&context, nw_packet, value);
+                }
+                Err(e) => analyzers.lock().unwrap().error(&context, e),
+            }
+            let decoded = summary.lock().unwrap().decoded;
+            let already_reported =
+                result.is_err() && versions.lock().unwrap().reported(datagram.src);
+
+            // Thins out the printed lines only, so it comes after everything that counts packets
+            let printed = shown && !quiet && result.is_ok() && args.output != OutputFormat::Parquet;
+            let throttled = match throttle.as_mut().filter(|_| printed).map(Throttle::admit) {
+                Some(Err(Skip::Sampled)) => {
+                    summary.lock().unwrap().sampled_out += 1;
+                    true
+                }
+                Some(Err(Skip::Limited)) => {
+                    summary.lock().unwrap().rate_limited += 1;
+                    true
+                }
+                _ => false,
+            };
+
+            let repeated = match (repeats.as_mut(), &result) {
+                (Some(repeats), Ok((_, value))) if shown && !throttled && !quiet => {
+                    let variant = variant_name(value);
+                    let (ended, repeated) =
+                        repeats.observe(frame.ts, datagram.src, datagram.dst, variant);
+                    if let Some(line) = ended {
+                        emit(&pause, Line::Log(line));
                     }
-                    _ => continue,
+                    repeated
                 }
+                _ => false,
+            };
 
-                // There's a packet that is candidate for matching netwayste
-                match deserialize::<Packet>(ethernet.payload) {
-                    Ok(nw_packet) => {
-                        let message = format!("{:>15?}:{:<5} {:?}", src_ip, src_port, nw_packet);
-                        if args.color_option.color_enabled() {
-                            info!("{}", message.color(message_color.unwrap()));
-                        } else {
-                            info!("{}", message);
+            match result {
+                _ if !shown || throttled || repeated => {}
+                Ok((nw_packet, _)) if args.output == OutputFormat::Parquet => {
+                    if let Some(export) = parquet.as_mut() {
+                        if let Err(e) = export.push(
+                            frame.ts,
+                            frame.interface.as_deref(),
+                            datagram.src,
+                            datagram.dst,
+                            &nw_packet,
+                            datagram.payload.len(),
+                        ) {
+                            error!("Failed to write packet to Parquet file: '{}'", e);
+                        }
+                    }
+                }
+                Ok(_) if quiet => {}
+                Ok((_, value)) if args.output == OutputFormat::ElasticBulk => {
+                    let document = json_line(
+                        frame.ts,
+                        frame.interface.as_deref(),
+                        datagram.src,
+                        datagram.dst,
+                        &value,
+                    );
+                    let lines = bulk_lines(&args.elastic_index, &document);
+                    emit(&pause, Line::Print(lines));
+                }
+                Ok((_, value)) if args.output == OutputFormat::Json => {
+                    let line = json_line(
+                        frame.ts,
+                        frame.interface.as_deref(),
+                        datagram.src,
+                        datagram.dst,
+                        &value,
+                    );
+                    emit(&pause, Line::Print(format!("{}\n", line)));
+                }
+                Ok((_, value)) if args.output == OutputFormat::Csv => {
+                    let line = csv_line(
+                        frame.ts,
+                        datagram.src,
+                        datagram.dst,
+                        &value,
+                        datagram.payload.len(),
+                    );
+                    emit(&pause, Line::Print(format!("{}\n", line)));
+                }
+                Ok((nw_packet, value)) => {
+                    let stamp = timing.stamp(frame.ts, datagram.src, datagram.dst);
+                    // Names recorded in the capture file win over ones looked up now. They're
+                    // for the real address, so anonymized output goes without.
+                    let capture_name = capture_name.filter(|_| anonymizer.is_none());
+                    let name = capture_name.or_else(|| {
+                        let resolver = resolver.as_ref()?;
+                        resolver.name(datagram.dst.ip());
+                        resolver.name(src_ip)
+                    });
+                    let mut message = match &args.format {
+                        Some(template) => template.render(&Fields {
+                            time: &stamp.time,
+                            delta: stamp.delta,
+                            flow_delta: stamp.flow_delta,
+                            src: datagram.src,
+                            dst: datagram.dst,
+                            packet: &nw_packet,
+                            size: datagram.payload.len(),
+                            interface: frame.interface.as_deref(),
+                            name: name.as_deref(),
+                        }),
+                        None => {
+                            let src_addr = display_ip(src_ip);
+                            addr_width = addr_width.max(src_addr.len());
+                            let mut message = match &name {
+                                Some(name) => format!(
+                                    "{:>width$}:{:<5} ({}) {}",
+                                    src_addr,
+                                    src_port,
+                                    name,
+                                    readable(&nw_packet),
+                                    width = addr_width
+                                ),
+                                None => format!(
+                                    "{:>width$}:{:<5} {}",
+                                    src_addr,
+                                    src_port,
+                                    readable(&nw_packet),
+                                    width = addr_width
+                                ),
+                            };
+                            if let Some(interface) = &frame.interface {
+                                message = format!("[{}] {}", interface, message);
+                            }
+                            if args.delta {
+                                message = format!(
+                                    "{} (flow {}) {}",
+                                    format_delta(stamp.delta),
+                                    format_delta(stamp.flow_delta),
+                                    message
+                                );
+                            }
+                            if show_capture_time {
+                                message = format!("{} {}", stamp.time, message);
+                            }
+                            message
                         }
+                    };
+                    for mark in &marks {
+                        message = format!("{} {}", message, mark);
+                    }
+                    if let Some(compression) = compression.filter(|_| args.compression_sizes) {
+                        message = format!(
+                            "{} [{}, {} -> {} bytes]",
+                            message,
+                            compression,
+                            datagram.payload.len(),
+                            payload.len()
+                        );
                     }
-                    Err(e) => {
-                        if args.verbose {
-                            error!("Failed de-serialization: '{}'", e);
-                            error!("Failed packet contents: '{:?}'", ethernet.payload);
+                    if args.hexdump {
+                        message = format!("{}\n{}", message, hexdump(&payload));
+                    }
+                    if let Some(tui) = &tui {
+                        let tree = layout.as_ref().and_then(|layout| fields(layout, &payload));
+                        tui.packet(&message, &payload, tree);
+                    } else if args.color_option.color_enabled() {
+                        // A copy matters most, then a retransmission, then reordering
+                        let class = marks
+                            .iter()
+                            .min_by_key(|mark| match mark {
+                                Mark::Duplicate => 0,
+                                Mark::Retransmit(_) => 1,
+                                Mark::OutOfOrder(_) => 2,
+                            })
+                            .map(|mark| mark.class());
+                        let color = class
+                            .and_then(|class| theme.class(class))
+                            .or(message_color)
+                            .or_else(|| theme.class(Class::of(&value)))
+                            .unwrap();
+                        emit(&pause, Line::Log(color.paint(&message)));
+                    } else {
+                        emit(&pause, Line::Log(message));
+                    }
+                }
+                Err(e) => {
+                    // Once a source is known to speak another version, its failures are noise
+                    if args.verbose && !already_reported {
+                        error!("Failed de-serialization: '{}'", e);
+                        let diagnosis = layout
+                            .as_ref()
+                            .map(|layout| Diagnosis::new(layout, &payload));
+                        if let Some(diagnosis) = &diagnosis {
+                            error!("  {}", diagnosis);
+                        }
+                        if let Some(divergence) =
+                            diagnosis.and_then(|diagnosis| diagnosis.divergence)
+                        {
+                            let note = format!("{}: {}", divergence.path, divergence.reason);
+                            error!(
+                                "Failed packet contents:\n{}",
+                                hexdump_marked(&payload, divergence.offset, &note)
+                            );
+                        } else if args.hexdump {
+                            error!("Failed packet contents:\n{}", hexdump(&payload));
+                        } else {
+                            error!("Failed packet contents: '{:?}'", payload);
                         }
                     }
                 }
             }
+
+            if args.count.map_or(false, |count| decoded >= count) {
+                reached_count = true;
+                break;
+            }
+        }
+
+        if let Some(writer) = pcap_writer.as_mut() {
+            let wanted = any_decoded || (any_failed && args.write_failures);
+            // Frames that can't be anonymized are left out rather than leaked
+            if wanted
+                && anonymizer
+                    .as_mut()
+                    .map_or(true, |anonymizer| anonymizer.frame(&mut frame))
+            {
+                if let Err(e) = writer.write(&frame) {
+                    error!("Failed to write packet to pcap file: '{}'", e);
+                }
+            }
+        }
+
+        if let Some(writer) = extcap_writer.as_mut() {
+            // Most likely Wireshark stopped the capture and closed its end of the fifo
+            if writer.write(&frame, &comments).is_err() {
+                break;
+            }
+        }
+
+        if reached_count {
+            break;
+        }
+    }
+
+    // Leave what was captured on screen until the user quits
+    if let (Some(tui), Some(thread)) = (&tui, tui_thread) {
+        tui.ended();
+        thread.join().ok();
+    }
+    if let Some(line) = repeats.as_mut().and_then(Repeats::finish) {
+        emit(&pause, Line::Log(line));
+    }
+    // Whatever was held back is still worth seeing
+    if let Some(pause) = &pause {
+        pause.resume();
+    }
+
+    finish(&summary, parquet, &analyzers);
+}
+
+fn emit(pause: &Option<Arc<Pause>>, line: Line) {
+    match pause {
+        Some(pause) => pause.emit(line),
+        None => line.emit(),
+    }
+}
+
+/// Exits over a bad option found during setup, the way clap reports its own. Once daemonized
+/// nobody sees stderr, so it's logged too.
+fn setup_error(kind: ErrorKind, message: impl fmt::Display) -> ! {
+    if daemon::detached() {
+        error!("{}", message);
+    }
+    Args::command().error(kind, message).exit()
+}
+
+/// For rates and speeds, which can't be zero.
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(n),
+        _ => Err(format!("'{}' is not a positive number", s)),
+    }
+}
+
+/// Accepts facility names as syslog.conf spells them, e.g. `local0`.
+fn parse_facility(name: &str) -> Result<syslog::Facility, String> {
+    name.parse()
+        .map_err(|_| format!("unknown syslog facility '{}'", name))
+}
+
+/// Wraps up a capture, however it ended.
+fn finish(summary: &Mutex<Summary>, parquet: Option<ParquetExport>, analyzers: &Mutex<Analyzers>) {
+    analyzers.lock().unwrap().shutdown();
+    if let Some(export) = parquet {
+        if let Err(e) = export.close() {
+            error!("Failed to finish Parquet file: '{}'", e);
         }
     }
+    summary.lock().unwrap().log();
+    daemon::remove_pidfile();
 }
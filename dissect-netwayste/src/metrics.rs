@@ -0,0 +1,150 @@
+//! `--metrics-listen`: Prometheus metrics about the traffic seen, served over HTTP for scraping.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::variant_name;
+use netwaystev2::protocol::Packet;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+use serde_json::Value;
+use tiny_http::{Header, Response, Server};
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// Clones share the counters, so one can be registered as an analyzer while another counts
+/// malformed frames.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    decoded: IntCounterVec,
+    failures: IntCounter,
+    malformed: IntCounter,
+    client_bytes: IntCounterVec,
+    gaps: Histogram,
+    last_by_flow: HashMap<(SocketAddr, SocketAddr), Duration>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("netwayste".to_owned()), None)
+            .expect("invalid metrics prefix");
+        let decoded = IntCounterVec::new(
+            Opts::new("packets_decoded_total", "Packets decoded, by variant"),
+            &["variant"],
+        )
+        .unwrap();
+        let failures = IntCounter::new(
+            "deserialization_failures_total",
+            "Payloads that failed netwayste de-serialization",
+        )
+        .unwrap();
+        let malformed = IntCounter::new(
+            "malformed_frames_total",
+            "Frames that couldn't be taken apart down to a UDP or TCP payload",
+        )
+        .unwrap();
+        // Labelled by IP only; ports would give every reconnect a new series
+        let client_bytes = IntCounterVec::new(
+            Opts::new(
+                "client_bytes_total",
+                "Bytes of decoded packets, by source address",
+            ),
+            &["client"],
+        )
+        .unwrap();
+        let gaps = Histogram::with_opts(
+            HistogramOpts::new(
+                "interpacket_gap_seconds",
+                "Time between consecutive decoded packets with the same source and destination",
+            )
+            // 1ms up to about a minute
+            .buckets(exponential_buckets(0.001, 2.0, 17).unwrap()),
+        )
+        .unwrap();
+
+        registry.register(Box::new(decoded.clone())).unwrap();
+        registry.register(Box::new(failures.clone())).unwrap();
+        registry.register(Box::new(malformed.clone())).unwrap();
+        registry.register(Box::new(client_bytes.clone())).unwrap();
+        registry.register(Box::new(gaps.clone())).unwrap();
+
+        Metrics {
+            registry,
+            decoded,
+            failures,
+            malformed,
+            client_bytes,
+            gaps,
+            last_by_flow: HashMap::new(),
+        }
+    }
+
+    /// Serves `/metrics` (or any other path, really) from a background thread.
+    pub fn serve(&self, addr: SocketAddr) {
+        let server = Server::http(addr).expect("Failed to listen for metrics scrapes");
+        let registry = self.registry.clone();
+        thread::spawn(move || {
+            let encoder = TextEncoder::new();
+            let content_type =
+                Header::from_bytes(&b"Content-Type"[..], encoder.format_type().as_bytes()).unwrap();
+            for request in server.incoming_requests() {
+                let mut body = vec![];
+                if let Err(e) = encoder.encode(&registry.gather(), &mut body) {
+                    error!("Failed to encode metrics: '{}'", e);
+                }
+                let response = Response::from_data(body).with_header(content_type.clone());
+                if let Err(e) = request.respond(response) {
+                    warn!("Failed to answer metrics scrape: '{}'", e);
+                }
+            }
+        });
+    }
+
+    pub fn record_decoded(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        variant: &str,
+        size: usize,
+    ) {
+        self.decoded.with_label_values(&[variant]).inc();
+        self.client_bytes
+            .with_label_values(&[&src.ip().to_string()])
+            .inc_by(size as u64);
+        if let Some(last) = self.last_by_flow.insert((src, dst), ts) {
+            self.gaps
+                .observe(ts.checked_sub(last).unwrap_or_default().as_secs_f64());
+        }
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.inc();
+    }
+
+    pub fn record_malformed(&self) {
+        self.malformed.inc();
+    }
+}
+
+impl Analyzer for Metrics {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        self.record_decoded(
+            context.ts,
+            context.src,
+            context.dst,
+            &variant_name(value),
+            context.payload.len(),
+        );
+    }
+
+    fn on_error(&mut self, _context: &Context, _error: &bincode::Error) {
+        self.record_failure();
+    }
+}
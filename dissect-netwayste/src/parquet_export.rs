@@ -0,0 +1,132 @@
+//! `--output parquet`: decoded packets as a columnar file for polars, pandas and friends.
+
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow_array::builder::{
+    StringBuilder, TimestampMicrosecondBuilder, UInt16Builder, UInt32Builder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use dissect_netwayste_core::output::{packet_value, sequence_number, variant_name};
+use netwaystev2::protocol::Packet;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::Result;
+use parquet::file::properties::WriterProperties;
+
+/// Rows buffered before being handed to the writer as one record batch. The writer groups
+/// batches into row groups on its own.
+const BATCH_ROWS: usize = 8192;
+
+pub struct ParquetExport {
+    schema: SchemaRef,
+    writer: ArrowWriter<File>,
+    rows: usize,
+    time: TimestampMicrosecondBuilder,
+    interface: StringBuilder,
+    src_ip: StringBuilder,
+    src_port: UInt16Builder,
+    dst_ip: StringBuilder,
+    dst_port: UInt16Builder,
+    variant: StringBuilder,
+    sequence: UInt64Builder,
+    size: UInt32Builder,
+}
+
+impl ParquetExport {
+    pub fn create(path: &Path) -> Result<Self> {
+        let utc = Some("UTC".into());
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Microsecond, utc),
+                false,
+            ),
+            Field::new("interface", DataType::Utf8, true),
+            Field::new("src_ip", DataType::Utf8, false),
+            Field::new("src_port", DataType::UInt16, false),
+            Field::new("dst_ip", DataType::Utf8, false),
+            Field::new("dst_port", DataType::UInt16, false),
+            Field::new("variant", DataType::Utf8, false),
+            Field::new("sequence", DataType::UInt64, true),
+            Field::new("size", DataType::UInt32, false),
+        ]));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(props))?;
+        Ok(ParquetExport {
+            schema,
+            writer,
+            rows: 0,
+            time: TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            interface: StringBuilder::new(),
+            src_ip: StringBuilder::new(),
+            src_port: UInt16Builder::new(),
+            dst_ip: StringBuilder::new(),
+            dst_port: UInt16Builder::new(),
+            variant: StringBuilder::new(),
+            sequence: UInt64Builder::new(),
+            size: UInt32Builder::new(),
+        })
+    }
+
+    pub fn push(
+        &mut self,
+        ts: Duration,
+        interface: Option<&str>,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Packet,
+        size: usize,
+    ) -> Result<()> {
+        let value = packet_value(packet);
+        self.time.append_value(ts.as_micros() as i64);
+        self.interface.append_option(interface);
+        self.src_ip.append_value(src.ip().to_string());
+        self.src_port.append_value(src.port());
+        self.dst_ip.append_value(dst.ip().to_string());
+        self.dst_port.append_value(dst.port());
+        self.variant.append_value(variant_name(&value));
+        self.sequence.append_option(sequence_number(&value));
+        self.size.append_value(size as u32);
+        self.rows += 1;
+        if self.rows >= BATCH_ROWS {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever is buffered along with the file footer. Until this is called the file
+    /// isn't readable.
+    pub fn close(mut self) -> Result<()> {
+        self.flush_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.time.finish()),
+            Arc::new(self.interface.finish()),
+            Arc::new(self.src_ip.finish()),
+            Arc::new(self.src_port.finish()),
+            Arc::new(self.dst_ip.finish()),
+            Arc::new(self.dst_port.finish()),
+            Arc::new(self.variant.finish()),
+            Arc::new(self.sequence.finish()),
+            Arc::new(self.size.finish()),
+        ];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+        self.rows = 0;
+        Ok(())
+    }
+}
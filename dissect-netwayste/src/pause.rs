@@ -0,0 +1,155 @@
+//! Pausing the packet output without stopping the capture: SIGUSR1 pauses and SIGUSR2 resumes,
+//! and when stdin is a terminal, Enter toggles between the two. Packets that arrive while paused
+//! are held and printed on resume.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::*;
+
+use crate::logging::PACKETS;
+
+/// Beyond this many held lines, the oldest are dropped, so a long pause can't exhaust memory.
+const MAX_HELD: usize = 100_000;
+
+/// How often the signal flag is checked.
+const SIGNAL_POLL: Duration = Duration::from_millis(100);
+
+const PAUSE: u8 = 1;
+const RESUME: u8 = 2;
+
+/// The last pause or resume signal that hasn't been acted on yet. Signal handlers can't safely
+/// take locks, so they only set this.
+static SIGNALLED: AtomicU8 = AtomicU8::new(0);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let request = if signal == libc::SIGUSR1 {
+        PAUSE
+    } else {
+        RESUME
+    };
+    SIGNALLED.store(request, Ordering::Relaxed);
+}
+
+/// One line of packet output.
+pub enum Line {
+    /// Logged, like the text output
+    Log(String),
+    /// Written to stdout as is, like the machine-readable outputs; includes its own newline
+    Print(String),
+}
+
+impl Line {
+    pub fn emit(self) {
+        match self {
+            Line::Log(line) => info!(target: PACKETS, "{}", line),
+            Line::Print(line) => print!("{}", line),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Held {
+    paused: bool,
+    lines: VecDeque<Line>,
+    dropped: u64,
+}
+
+#[derive(Default)]
+pub struct Pause {
+    held: Mutex<Held>,
+    resumed: Condvar,
+}
+
+impl Pause {
+    /// Starts listening for SIGUSR1 and SIGUSR2, and for Enter when `keys` is set.
+    pub fn start(keys: bool) -> Arc<Pause> {
+        let pause = Arc::new(Pause::default());
+        // Safety: the handler only stores to an atomic
+        unsafe {
+            libc::signal(libc::SIGUSR1, on_signal as libc::sighandler_t);
+            libc::signal(libc::SIGUSR2, on_signal as libc::sighandler_t);
+        }
+        let shared = Arc::clone(&pause);
+        thread::spawn(move || loop {
+            thread::sleep(SIGNAL_POLL);
+            match SIGNALLED.swap(0, Ordering::Relaxed) {
+                PAUSE => shared.pause(),
+                RESUME => shared.resume(),
+                _ => {}
+            }
+        });
+        if keys {
+            let shared = Arc::clone(&pause);
+            thread::spawn(move || {
+                for _ in io::stdin().lines().map_while(Result::ok) {
+                    if shared.is_paused() {
+                        shared.resume();
+                    } else {
+                        shared.pause();
+                    }
+                }
+            });
+        }
+        pause
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.held.lock().unwrap().paused
+    }
+
+    pub fn pause(&self) {
+        let mut held = self.held.lock().unwrap();
+        if !held.paused {
+            held.paused = true;
+            info!("Output paused; press Enter or send SIGUSR2 to resume");
+        }
+    }
+
+    /// Prints everything held while paused, then carries on as usual.
+    pub fn resume(&self) {
+        let mut held = self.held.lock().unwrap();
+        if !held.paused {
+            return;
+        }
+        held.paused = false;
+        if held.dropped > 0 {
+            warn!(
+                "Output resumed; {} packets were dropped while paused, only the last {} are shown",
+                held.dropped, MAX_HELD
+            );
+        } else {
+            info!("Output resumed; {} packets were held", held.lines.len());
+        }
+        held.dropped = 0;
+        for line in held.lines.drain(..) {
+            line.emit();
+        }
+        self.resumed.notify_all();
+    }
+
+    /// Shows a line now, or holds it until the output resumes.
+    pub fn emit(&self, line: Line) {
+        // Printing under the lock keeps new lines from overtaking ones being flushed on resume
+        let mut held = self.held.lock().unwrap();
+        if !held.paused {
+            line.emit();
+            return;
+        }
+        if held.lines.len() == MAX_HELD {
+            held.lines.pop_front();
+            held.dropped += 1;
+        }
+        held.lines.push_back(line);
+    }
+
+    /// Blocks for as long as the output is paused; used to pause the capture along with it.
+    pub fn wait(&self) {
+        let held = self.held.lock().unwrap();
+        drop(self.resumed.wait_while(held, |held| held.paused).unwrap());
+    }
+}
@@ -0,0 +1,96 @@
+//! Saves selected frames to a classic pcap file, optionally as a ring of bounded files.
+
+use std::path::PathBuf;
+
+use pcap::{Linktype, PacketHeader, Savefile};
+use tracing::*;
+
+use crate::capture::Frame;
+use crate::rotation::{ring_path, Rotation};
+
+/// Size of the pcap global header and of each per-record header.
+const FILE_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 16;
+
+pub struct PcapWriter {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    /// Taken from the first frame, since a pcapng or merged source doesn't know its link type
+    /// until then. Every file of a ring shares it.
+    linktype: Option<Linktype>,
+    savefile: Option<Savefile>,
+    /// Bytes written to the current file
+    written: u64,
+    /// Position in the ring of the current file
+    index: usize,
+    /// Whether we've already complained about frames of a second link type
+    warned: bool,
+}
+
+impl PcapWriter {
+    pub fn new(path: PathBuf, rotation: Option<Rotation>) -> Self {
+        PcapWriter {
+            path,
+            rotation,
+            linktype: None,
+            savefile: None,
+            written: 0,
+            index: 0,
+            warned: false,
+        }
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> Result<(), pcap::Error> {
+        let linktype = *self.linktype.get_or_insert(frame.linktype);
+
+        // A classic pcap file has a single link type for the whole file
+        if linktype != frame.linktype {
+            if !self.warned {
+                warn!(
+                    "Not writing {:?} frames to '{}', which already holds {:?} frames",
+                    frame.linktype,
+                    self.path.display(),
+                    linktype
+                );
+                self.warned = true;
+            }
+            return Ok(());
+        }
+
+        if self.savefile.is_none() {
+            let path = self.current_path();
+            self.savefile = Some(pcap::Capture::dead(linktype)?.savefile(&path)?);
+            self.written = FILE_HEADER_LEN;
+        }
+        let savefile = self.savefile.as_mut().unwrap();
+
+        let header = PacketHeader {
+            ts: libc::timeval {
+                tv_sec: frame.ts.as_secs() as libc::time_t,
+                tv_usec: frame.ts.subsec_micros() as libc::suseconds_t,
+            },
+            caplen: frame.data.len() as u32,
+            len: frame.data.len() as u32,
+        };
+        savefile.write(&pcap::Packet::new(&header, &frame.data));
+        // Flush as we go so the file is usable even if we're killed mid-capture
+        savefile.flush()?;
+        self.written += RECORD_HEADER_LEN + frame.data.len() as u64;
+
+        if let Some(rotation) = &self.rotation {
+            if self.written >= rotation.max_bytes {
+                // Dropping the savefile closes it; the next write opens the next file in the ring
+                self.savefile = None;
+                self.index = rotation.next_index(self.index);
+            }
+        }
+        Ok(())
+    }
+
+    fn current_path(&self) -> PathBuf {
+        match self.rotation {
+            Some(_) => ring_path(&self.path, self.index),
+            None => self.path.clone(),
+        }
+    }
+}
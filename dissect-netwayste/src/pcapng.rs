@@ -0,0 +1,542 @@
+//! Minimal pcapng reader.
+//!
+//! libpcap can open pcapng files, but only if every interface in the file shares one link type,
+//! and it throws away the interface names and name resolution records. Wireshark happily writes
+//! files with several interfaces, so we walk the blocks ourselves.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// First four bytes of every pcapng file (the Section Header Block type).
+pub const MAGIC: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_PACKET: u32 = 0x00000002; // Obsolete, but still found in old captures
+const BLOCK_SIMPLE_PACKET: u32 = 0x00000003;
+const BLOCK_NAME_RESOLUTION: u32 = 0x00000004;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_IF_TSOFFSET: u16 = 14;
+
+const NRB_RECORD_END: u16 = 0;
+const NRB_RECORD_IPV4: u16 = 1;
+const NRB_RECORD_IPV6: u16 = 2;
+
+/// Refuse blocks larger than this rather than trying to allocate whatever a corrupt length says.
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: Option<String>,
+    pub linktype: i32,
+    pub snaplen: u32,
+    /// Timestamp units per second, from `if_tsresol` (microseconds unless specified)
+    ts_units: u64,
+    /// Seconds to add to every timestamp, from `if_tsoffset`
+    ts_offset: i64,
+}
+
+#[derive(Debug)]
+pub struct Record {
+    /// Index into `PcapngReader::interfaces()`
+    pub interface: usize,
+    /// Capture time relative to the UNIX epoch
+    pub ts: Duration,
+    pub data: Vec<u8>,
+}
+
+pub struct PcapngReader<R> {
+    inner: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+    names: HashMap<IpAddr, String>,
+    /// Simple Packet Blocks carry no timestamp, so they inherit the last one seen
+    last_ts: Duration,
+}
+
+impl<R: Read> PcapngReader<R> {
+    pub fn new(inner: R) -> Self {
+        PcapngReader {
+            inner,
+            big_endian: false,
+            interfaces: vec![],
+            names: HashMap::new(),
+            last_ts: Duration::ZERO,
+        }
+    }
+
+    /// Interfaces described so far in the current section.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Host names from every Name Resolution Block read so far.
+    pub fn names(&self) -> &HashMap<IpAddr, String> {
+        &self.names
+    }
+
+    /// Reads blocks until the next packet, returning `None` at the end of the file.
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let mut head = [0u8; 8];
+            if !read_exact_or_eof(&mut self.inner, &mut head)? {
+                return Ok(None);
+            }
+
+            let block_type;
+            let body;
+            if head[0..4] == MAGIC {
+                // The section header type is a palindrome, but its length isn't; we need the
+                // byte-order magic before we can make sense of anything else.
+                let mut magic = [0u8; 4];
+                self.inner.read_exact(&mut magic)?;
+                self.big_endian = match u32::from_le_bytes(magic) {
+                    BYTE_ORDER_MAGIC => false,
+                    m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    _ => return Err(invalid("bad byte-order magic in section header")),
+                };
+                block_type = BLOCK_SECTION_HEADER;
+                let total_len = self.u32_from(&head[4..8]) as usize;
+                body = self.read_body(total_len, 12)?;
+            } else {
+                block_type = self.u32_from(&head[0..4]);
+                let total_len = self.u32_from(&head[4..8]) as usize;
+                body = self.read_body(total_len, 8)?;
+            }
+
+            match block_type {
+                BLOCK_SECTION_HEADER => {
+                    // Interface IDs are scoped to a section
+                    self.interfaces.clear();
+                }
+                BLOCK_INTERFACE_DESCRIPTION => self.read_interface(&body)?,
+                BLOCK_NAME_RESOLUTION => self.read_names(&body)?,
+                BLOCK_ENHANCED_PACKET => {
+                    let interface = self.u32_at(&body, 0)? as usize;
+                    let ts =
+                        self.timestamp(interface, self.u32_at(&body, 4)?, self.u32_at(&body, 8)?)?;
+                    let caplen = self.u32_at(&body, 12)? as usize;
+                    let data = slice_at(&body, 20, caplen)?.to_vec();
+                    self.last_ts = ts;
+                    return Ok(Some(Record {
+                        interface,
+                        ts,
+                        data,
+                    }));
+                }
+                BLOCK_PACKET => {
+                    let interface = self.u16_at(&body, 0)? as usize;
+                    let ts =
+                        self.timestamp(interface, self.u32_at(&body, 4)?, self.u32_at(&body, 8)?)?;
+                    let caplen = self.u32_at(&body, 12)? as usize;
+                    let data = slice_at(&body, 20, caplen)?.to_vec();
+                    self.last_ts = ts;
+                    return Ok(Some(Record {
+                        interface,
+                        ts,
+                        data,
+                    }));
+                }
+                BLOCK_SIMPLE_PACKET => {
+                    let orig_len = self.u32_at(&body, 0)? as usize;
+                    let snaplen = match self.interfaces.first() {
+                        Some(iface) if iface.snaplen != 0 => iface.snaplen as usize,
+                        Some(_) => usize::MAX,
+                        None => return Err(invalid("simple packet block before any interface")),
+                    };
+                    let caplen = orig_len.min(snaplen).min(body.len().saturating_sub(4));
+                    let data = slice_at(&body, 4, caplen)?.to_vec();
+                    return Ok(Some(Record {
+                        interface: 0,
+                        ts: self.last_ts,
+                        data,
+                    }));
+                }
+                // Statistics, decryption secrets, custom blocks, etc.
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the rest of a block whose first `consumed` bytes were already read, returning the
+    /// body without the trailing length field.
+    fn read_body(&mut self, total_len: usize, consumed: usize) -> io::Result<Vec<u8>> {
+        if total_len < consumed + 4 || total_len % 4 != 0 || total_len > MAX_BLOCK_LEN {
+            return Err(invalid(&format!("bad block length {}", total_len)));
+        }
+        let mut rest = vec![0u8; total_len - consumed];
+        self.inner.read_exact(&mut rest)?;
+        rest.truncate(total_len - consumed - 4);
+        Ok(rest)
+    }
+
+    fn read_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut iface = Interface {
+            name: None,
+            linktype: self.u16_at(body, 0)? as i32,
+            snaplen: self.u32_at(body, 4)?,
+            ts_units: 1_000_000,
+            ts_offset: 0,
+        };
+
+        let mut offset = 8;
+        while offset + 4 <= body.len() {
+            let code = self.u16_at(body, offset)?;
+            let len = self.u16_at(body, offset + 2)? as usize;
+            let value = slice_at(body, offset + 4, len)?;
+            match code {
+                OPT_END_OF_OPT => break,
+                OPT_IF_NAME => {
+                    iface.name = Some(
+                        String::from_utf8_lossy(value)
+                            .trim_end_matches('\0')
+                            .to_owned(),
+                    )
+                }
+                OPT_IF_TSRESOL if len >= 1 => {
+                    let exponent = (value[0] & 0x7f) as u32;
+                    let base: u64 = if value[0] & 0x80 == 0 { 10 } else { 2 };
+                    iface.ts_units = base
+                        .checked_pow(exponent)
+                        .ok_or_else(|| invalid("unsupported timestamp resolution"))?;
+                }
+                OPT_IF_TSOFFSET if len >= 8 => {
+                    let raw = self.u64_at(value, 0)?;
+                    iface.ts_offset = raw as i64;
+                }
+                _ => {}
+            }
+            offset += 4 + padded(len);
+        }
+
+        self.interfaces.push(iface);
+        Ok(())
+    }
+
+    fn read_names(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset + 4 <= body.len() {
+            let record_type = self.u16_at(body, offset)?;
+            let len = self.u16_at(body, offset + 2)? as usize;
+            let value = slice_at(body, offset + 4, len)?;
+            let addr_len = match record_type {
+                NRB_RECORD_END => break,
+                NRB_RECORD_IPV4 => 4,
+                NRB_RECORD_IPV6 => 16,
+                _ => 0,
+            };
+            if addr_len != 0 && len > addr_len {
+                let addr = if addr_len == 4 {
+                    IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                } else {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(&value[..16]);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                };
+                // A record may list several names; the first is the canonical one
+                if let Some(name) = value[addr_len..].split(|b| *b == 0).next() {
+                    if !name.is_empty() {
+                        self.names
+                            .insert(addr, String::from_utf8_lossy(name).into_owned());
+                    }
+                }
+            }
+            offset += 4 + padded(len);
+        }
+        Ok(())
+    }
+
+    fn timestamp(&self, interface: usize, high: u32, low: u32) -> io::Result<Duration> {
+        let iface = self.interfaces.get(interface).ok_or_else(|| {
+            invalid(&format!(
+                "packet references unknown interface {}",
+                interface
+            ))
+        })?;
+        let raw = ((high as u64) << 32) | low as u64;
+        // Both come from the file, so a corrupt one mustn't overflow
+        let secs = (raw / iface.ts_units) as i128 + iface.ts_offset as i128;
+        let secs = u64::try_from(secs.max(0)).map_err(|_| invalid("timestamp out of range"))?;
+        let nanos =
+            ((raw % iface.ts_units) as u128 * 1_000_000_000 / iface.ts_units as u128) as u32;
+        Ok(Duration::new(secs, nanos))
+    }
+
+    fn u16_at(&self, buf: &[u8], offset: usize) -> io::Result<u16> {
+        let b = slice_at(buf, offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> io::Result<u32> {
+        Ok(self.u32_from(slice_at(buf, offset, 4)?))
+    }
+
+    fn u64_at(&self, buf: &[u8], offset: usize) -> io::Result<u64> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(slice_at(buf, offset, 8)?);
+        Ok(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+
+    fn u32_from(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+/// Like `read_exact`, but a clean EOF before the first byte returns `false` instead of an error.
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn slice_at(buf: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    buf.get(offset..offset + len)
+        .ok_or_else(|| invalid("block is shorter than its contents claim"))
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("pcapng: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds pcapng files in memory, in either byte order.
+    struct Writer {
+        big_endian: bool,
+        out: Vec<u8>,
+    }
+
+    impl Writer {
+        fn new(big_endian: bool) -> Self {
+            let mut writer = Writer {
+                big_endian,
+                out: vec![],
+            };
+            let mut body = writer.u32(BYTE_ORDER_MAGIC);
+            body.extend(writer.u16(1));
+            body.extend(writer.u16(0));
+            // Section length unknown
+            body.extend([0xff; 8]);
+            writer.block(BLOCK_SECTION_HEADER, &body);
+            writer
+        }
+
+        fn u16(&self, n: u16) -> Vec<u8> {
+            if self.big_endian {
+                n.to_be_bytes().to_vec()
+            } else {
+                n.to_le_bytes().to_vec()
+            }
+        }
+
+        fn u32(&self, n: u32) -> Vec<u8> {
+            if self.big_endian {
+                n.to_be_bytes().to_vec()
+            } else {
+                n.to_le_bytes().to_vec()
+            }
+        }
+
+        fn u64(&self, n: u64) -> Vec<u8> {
+            if self.big_endian {
+                n.to_be_bytes().to_vec()
+            } else {
+                n.to_le_bytes().to_vec()
+            }
+        }
+
+        /// Options or name records, each padded, then the end marker.
+        fn options(&self, options: &[(u16, &[u8])]) -> Vec<u8> {
+            let mut out = vec![];
+            for (code, value) in options {
+                out.extend(self.u16(*code));
+                out.extend(self.u16(value.len() as u16));
+                out.extend(*value);
+                out.resize(padded(out.len()), 0);
+            }
+            out.extend([0; 4]);
+            out
+        }
+
+        fn block(&mut self, block_type: u32, body: &[u8]) {
+            let total_len = (12 + padded(body.len())) as u32;
+            let mut block = self.u32(block_type);
+            block.extend(self.u32(total_len));
+            block.extend(body);
+            block.resize(padded(block.len()), 0);
+            block.extend(self.u32(total_len));
+            self.out.extend(block);
+        }
+
+        fn interface(&mut self, linktype: u16, snaplen: u32, options: &[(u16, &[u8])]) {
+            let mut body = self.u16(linktype);
+            body.extend([0; 2]);
+            body.extend(self.u32(snaplen));
+            body.extend(self.options(options));
+            self.block(BLOCK_INTERFACE_DESCRIPTION, &body);
+        }
+
+        fn enhanced(&mut self, interface: u32, ts: u64, data: &[u8]) {
+            let mut body = self.u32(interface);
+            body.extend(self.u32((ts >> 32) as u32));
+            body.extend(self.u32(ts as u32));
+            body.extend(self.u32(data.len() as u32));
+            body.extend(self.u32(data.len() as u32));
+            body.extend(data);
+            self.block(BLOCK_ENHANCED_PACKET, &body);
+        }
+
+        fn simple(&mut self, data: &[u8]) {
+            let mut body = self.u32(data.len() as u32);
+            body.extend(data);
+            self.block(BLOCK_SIMPLE_PACKET, &body);
+        }
+
+        fn reader(&self) -> PcapngReader<&[u8]> {
+            PcapngReader::new(&self.out[..])
+        }
+    }
+
+    #[test]
+    fn reads_either_byte_order() {
+        for big_endian in [false, true] {
+            let mut writer = Writer::new(big_endian);
+            writer.interface(1, 65535, &[(OPT_IF_NAME, b"eth0")]);
+            writer.enhanced(0, 1_500_000, &[1, 2, 3]);
+            let mut reader = writer.reader();
+            let record = reader.next_record().unwrap().unwrap();
+            assert_eq!(record.interface, 0);
+            assert_eq!(record.ts, Duration::from_millis(1500));
+            assert_eq!(record.data, [1, 2, 3]);
+            let iface = &reader.interfaces()[0];
+            assert_eq!(iface.name.as_deref(), Some("eth0"));
+            assert_eq!((iface.linktype, iface.snaplen), (1, 65535));
+            assert!(reader.next_record().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn timestamp_resolution_and_offset() {
+        let mut writer = Writer::new(false);
+        let offset = writer.u64(100);
+        // Nanoseconds, and 100s later than the timestamps say
+        writer.interface(1, 0, &[(OPT_IF_TSRESOL, &[9]), (OPT_IF_TSOFFSET, &offset)]);
+        // 1/1024ths of a second
+        writer.interface(1, 0, &[(OPT_IF_TSRESOL, &[0x80 | 10])]);
+        writer.enhanced(0, 2_500_000_000, &[]);
+        writer.enhanced(1, 1536, &[]);
+        let mut reader = writer.reader();
+        let ts = |reader: &mut PcapngReader<&[u8]>| reader.next_record().unwrap().unwrap().ts;
+        assert_eq!(ts(&mut reader), Duration::from_millis(102_500));
+        assert_eq!(ts(&mut reader), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn timestamp_overflow_is_an_error() {
+        let mut writer = Writer::new(false);
+        let offset = writer.u64(i64::MAX as u64);
+        writer.interface(1, 0, &[(OPT_IF_TSRESOL, &[0]), (OPT_IF_TSOFFSET, &offset)]);
+        writer.enhanced(0, u64::MAX, &[]);
+        let e = writer.reader().next_record().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn simple_packets_are_cut_to_the_snaplen_and_keep_the_last_time() {
+        let mut writer = Writer::new(false);
+        writer.interface(1, 2, &[]);
+        writer.enhanced(0, 3_000_000, &[1]);
+        writer.simple(&[9, 8, 7, 6]);
+        let mut reader = writer.reader();
+        reader.next_record().unwrap();
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(record.data, [9, 8]);
+        assert_eq!(record.ts, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn names_are_resolved() {
+        let mut writer = Writer::new(true);
+        let body = writer.options(&[
+            (NRB_RECORD_IPV4, b"\x0a\x00\x00\x01server\0alias\0"),
+            (
+                NRB_RECORD_IPV6,
+                b"\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01localhost\0",
+            ),
+        ]);
+        writer.block(BLOCK_NAME_RESOLUTION, &body);
+        writer.interface(1, 0, &[]);
+        writer.enhanced(0, 0, &[]);
+        let mut reader = writer.reader();
+        reader.next_record().unwrap();
+        let names = reader.names();
+        assert_eq!(names[&"10.0.0.1".parse::<IpAddr>().unwrap()], "server");
+        assert_eq!(names[&"::1".parse::<IpAddr>().unwrap()], "localhost");
+    }
+
+    #[test]
+    fn unknown_interface_is_an_error() {
+        let mut writer = Writer::new(false);
+        writer.interface(1, 0, &[]);
+        writer.enhanced(3, 0, &[]);
+        let e = writer.reader().next_record().unwrap_err();
+        assert!(e.to_string().contains("unknown interface 3"));
+    }
+
+    #[test]
+    fn truncated_blocks_are_errors() {
+        let mut writer = Writer::new(false);
+        writer.interface(1, 0, &[]);
+        writer.enhanced(0, 0, &[1, 2, 3, 4]);
+        let cut = &writer.out[..writer.out.len() - 6];
+        let e = PcapngReader::new(cut).next_record().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A captured length past the end of its block
+        let mut writer = Writer::new(false);
+        writer.interface(1, 0, &[]);
+        let mut body = writer.u32(0);
+        body.extend([0; 8]);
+        body.extend(writer.u32(100));
+        body.extend(writer.u32(100));
+        writer.block(BLOCK_ENHANCED_PACKET, &body);
+        let e = writer.reader().next_record().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,113 @@
+//! Minimal pcapng writer, for streams where frames need comments attached (see `extcap`).
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use pcap::Linktype;
+
+use crate::capture::Frame;
+use crate::pcapng::MAGIC;
+
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_IF_NAME: u16 = 2;
+const OPT_SHB_USERAPPL: u16 = 4;
+
+pub struct PcapngWriter<W> {
+    inner: W,
+    /// Interface IDs already described, keyed by name and link type, since a merged capture
+    /// can have several of each
+    interfaces: HashMap<(Option<String>, i32), u32>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header straight away, so a reader on the other end of a pipe knows
+    /// what it's getting before the first frame arrives.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let mut body = vec![];
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // section length unknown
+        let app = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+        push_option(&mut body, OPT_SHB_USERAPPL, app.as_bytes());
+        push_option(&mut body, OPT_END_OF_OPT, &[]);
+        write_block(&mut inner, u32::from_le_bytes(MAGIC), &body)?;
+        inner.flush()?;
+        Ok(PcapngWriter {
+            inner,
+            interfaces: HashMap::new(),
+        })
+    }
+
+    /// Writes the frame, with each of `comments` attached as an `opt_comment`.
+    pub fn write(&mut self, frame: &Frame, comments: &[String]) -> io::Result<()> {
+        let interface = self.interface_id(&frame.interface, frame.linktype)?;
+
+        // Timestamps are in the default resolution of microseconds
+        let ts = frame.ts.as_micros() as u64;
+        let mut body = vec![];
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.data.len() as u32).to_le_bytes()); // captured length
+        body.extend_from_slice(&(frame.data.len() as u32).to_le_bytes()); // original length
+        body.extend_from_slice(&frame.data);
+        pad(&mut body);
+        for comment in comments {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+        }
+        if !comments.is_empty() {
+            push_option(&mut body, OPT_END_OF_OPT, &[]);
+        }
+        write_block(&mut self.inner, BLOCK_ENHANCED_PACKET, &body)?;
+        // Whoever is reading wants to see frames as they arrive
+        self.inner.flush()
+    }
+
+    fn interface_id(&mut self, name: &Option<String>, linktype: Linktype) -> io::Result<u32> {
+        let key = (name.clone(), linktype.0);
+        if let Some(id) = self.interfaces.get(&key) {
+            return Ok(*id);
+        }
+        let id = self.interfaces.len() as u32;
+        let mut body = vec![];
+        body.extend_from_slice(&(linktype.0 as u16).to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        body.extend_from_slice(&0u32.to_le_bytes()); // no snap length limit
+        if let Some(name) = name {
+            push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+            push_option(&mut body, OPT_END_OF_OPT, &[]);
+        }
+        write_block(&mut self.inner, BLOCK_INTERFACE_DESCRIPTION, &body)?;
+        self.interfaces.insert(key, id);
+        Ok(id)
+    }
+}
+
+/// Wraps a body, which must already be padded to 32 bits, in the type and both length fields.
+fn write_block(w: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    w.write_all(&block_type.to_le_bytes())?;
+    w.write_all(&total_len.to_le_bytes())?;
+    w.write_all(body)?;
+    w.write_all(&total_len.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    while body.len() % 4 != 0 {
+        body.push(0);
+    }
+}
@@ -0,0 +1,211 @@
+//! Everything ahead of the main loop, as an async pipeline: a capture task reads and dissects
+//! frames, decode workers decompress and de-serialize their payloads in parallel, and the main
+//! loop receives the results in capture order.
+//!
+//! The stages are joined by bounded channels, so a stage that falls behind holds up the ones
+//! before it, and in the end libpcap's buffer, rather than growing without bound. Capturing no
+//! longer waits on output, so a slow terminal or exporter only costs drops once the buffers in
+//! between are full.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::compression::{decompress, Compression};
+use dissect_netwayste_core::protocol::Decoder;
+use etherparse::ReadError;
+use futures::stream::{self, StreamExt};
+use netwaystev2::protocol::Packet;
+use tokio::runtime::{self, Runtime};
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::*;
+
+use crate::capture::{Frame, Source};
+use crate::dissect::{Datagram, Dissector};
+use crate::pause::Pause;
+use crate::summary::Summary;
+
+/// Frames read and dissected but not yet handed to a decode worker.
+const CAPTURE_QUEUE: usize = 1024;
+
+/// Frames decoded but not yet taken by the main loop.
+const DECODED_QUEUE: usize = 1024;
+
+/// How often libpcap's drop counters are copied into the summary.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the main loop checks whether it's been told to stop while no frames arrive.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+/// One datagram, decoded.
+pub struct Payload {
+    pub datagram: Datagram,
+    /// The payload as decoded, if it had to be decompressed first
+    pub decompressed: Option<Vec<u8>>,
+    pub compression: Option<Compression>,
+    /// Why a payload that looked compressed couldn't be decompressed; it was decoded as is
+    pub decompress_error: Option<io::Error>,
+    pub result: bincode::Result<Packet>,
+    /// Host name the capture file records for the source, if any
+    pub name: Option<String>,
+}
+
+/// One frame and whatever it carried.
+pub struct Decoded {
+    pub frame: Frame,
+    pub datagrams: Result<Vec<Payload>, ReadError>,
+}
+
+type Dissected = (Frame, Result<Vec<(Datagram, Option<String>)>, ReadError>);
+
+pub struct Pipeline {
+    /// Only an `Option` so `Drop` can take it
+    runtime: Option<Runtime>,
+    decoded: mpsc::Receiver<Decoded>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Pipeline {
+    /// Starts capturing from `source`, with `workers` frames decoding at once. Capturing stops
+    /// while `pause` is paused, if given, and for good once `stop` is set. The capture's drop
+    /// counters are kept in `summary`.
+    pub fn start(
+        mut source: Source,
+        mut dissector: Dissector,
+        decoder: Arc<dyn Decoder>,
+        pause: Option<Arc<Pause>>,
+        workers: usize,
+        summary: Arc<Mutex<Summary>>,
+        stop: Arc<AtomicBool>,
+    ) -> Pipeline {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .enable_time()
+            .thread_name("pipeline")
+            .build()
+            .expect("Failed to start decode workers");
+
+        // Reassembly needs frames in order, so dissecting stays with the capture. libpcap
+        // blocks, so the capture gets a thread of its own.
+        let (dissected_tx, dissected_rx) = mpsc::channel::<Dissected>(CAPTURE_QUEUE);
+        let capture_stop = Arc::clone(&stop);
+        runtime.spawn_blocking(move || {
+            let mut stats_taken = Instant::now();
+            while !capture_stop.load(Ordering::Relaxed) {
+                let frame = match source.next_frame() {
+                    Ok(frame) => frame,
+                    Err(pcap::Error::NoMorePackets) => break,
+                    // Live devices have already recovered from what they could
+                    Err(e) => {
+                        error!("Capture failed, stopping: {}", e);
+                        break;
+                    }
+                };
+                if let Some(pause) = &pause {
+                    pause.wait();
+                }
+                if stats_taken.elapsed() >= STATS_INTERVAL {
+                    stats_taken = Instant::now();
+                    if let Some(stats) = source.stats() {
+                        summary.lock().unwrap().capture_stats = Some(stats);
+                    }
+                }
+                let datagrams = dissector.dissect(&frame).map(|datagrams| {
+                    datagrams
+                        .into_iter()
+                        .map(|datagram| {
+                            let name = source.resolved_name(datagram.src.ip()).map(str::to_owned);
+                            (datagram, name)
+                        })
+                        .collect()
+                });
+                if dissected_tx.blocking_send((frame, datagrams)).is_err() {
+                    break;
+                }
+            }
+            if let Some(stats) = source.stats() {
+                summary.lock().unwrap().capture_stats = Some(stats);
+            }
+        });
+
+        // buffered() runs several decodes at once but yields them in the order they went in
+        let (decoded_tx, decoded_rx) = mpsc::channel(DECODED_QUEUE);
+        runtime.spawn(async move {
+            let dissected = stream::unfold(dissected_rx, |mut rx| async move {
+                rx.recv().await.map(|dissected| (dissected, rx))
+            });
+            let mut decoded = dissected
+                .map(|(frame, datagrams)| {
+                    let decoder = Arc::clone(&decoder);
+                    tokio::spawn(async move { decode(frame, datagrams, decoder.as_ref()) })
+                })
+                .buffered(workers);
+            while let Some(result) = decoded.next().await {
+                let decoded = result.expect("Decode worker panicked");
+                if decoded_tx.send(decoded).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Pipeline {
+            runtime: Some(runtime),
+            decoded: decoded_rx,
+            stop,
+        }
+    }
+
+    /// The next frame in capture order, or `None` once the capture has ended or been stopped.
+    /// A quiet interface doesn't hold up stopping.
+    pub fn next(&mut self) -> Option<Decoded> {
+        let runtime = self.runtime.as_ref().expect("only taken on drop");
+        while !self.stop.load(Ordering::Relaxed) {
+            if let Ok(decoded) = runtime.block_on(time::timeout(STOP_POLL, self.decoded.recv())) {
+                return decoded;
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Pipeline {
+    /// The capture thread may be blocked waiting on a quiet interface, so don't wait for it.
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn decode(
+    frame: Frame,
+    datagrams: Result<Vec<(Datagram, Option<String>)>, ReadError>,
+    decoder: &dyn Decoder,
+) -> Decoded {
+    let datagrams = datagrams.map(|datagrams| {
+        datagrams
+            .into_iter()
+            .map(|(datagram, name)| {
+                let (decompressed, compression, decompress_error) =
+                    match decompress(&datagram.payload) {
+                        Ok(Some((compression, data))) => (Some(data), Some(compression), None),
+                        Ok(None) => (None, None, None),
+                        Err(e) => (None, None, Some(e)),
+                    };
+                let result =
+                    decoder.decode(decompressed.as_deref().unwrap_or(&datagram.payload[..]));
+                Payload {
+                    datagram,
+                    decompressed,
+                    compression,
+                    decompress_error,
+                    result,
+                    name,
+                }
+            })
+            .collect()
+    });
+    Decoded { frame, datagrams }
+}
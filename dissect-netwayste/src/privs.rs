@@ -0,0 +1,66 @@
+//! `--drop-privs`: giving up root once the capture is open, so the long-lived parsing of
+//! untrusted packets runs as an ordinary user.
+
+use std::ffi::CString;
+use std::io;
+
+pub fn is_root() -> bool {
+    // Safety: geteuid can't fail and touches no memory
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Switches to `spec`, either `user` or `user:group`, for good. Without a group, the user's
+/// primary group is used. Call it before starting any threads, since only the calling thread's
+/// credentials are guaranteed to change.
+pub fn drop_to(spec: &str) -> Result<(), String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+    // Groups before the user, since changing them needs the privileges setuid gives up
+    // Safety: plain system calls, with a pointer to a single gid that outlives the call
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 {
+            return Err(format!("setgroups failed: {}", io::Error::last_os_error()));
+        }
+        if libc::setgid(gid) != 0 {
+            return Err(format!("setgid failed: {}", io::Error::last_os_error()));
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(format!("setuid failed: {}", io::Error::last_os_error()));
+        }
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err("root could be regained after switching user".to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let c_name = CString::new(name).map_err(|_| format!("bad user name '{}'", name))?;
+    // Safety: getpwnam's result is only valid until the next lookup, so it's copied out at once
+    unsafe {
+        let entry = libc::getpwnam(c_name.as_ptr());
+        if entry.is_null() {
+            return Err(format!("unknown user '{}'", name));
+        }
+        Ok(((*entry).pw_uid, (*entry).pw_gid))
+    }
+}
+
+fn lookup_group(name: &str) -> Result<libc::gid_t, String> {
+    let c_name = CString::new(name).map_err(|_| format!("bad group name '{}'", name))?;
+    // Safety: as for getpwnam
+    unsafe {
+        let entry = libc::getgrnam(c_name.as_ptr());
+        if entry.is_null() {
+            return Err(format!("unknown group '{}'", name));
+        }
+        Ok((*entry).gr_gid)
+    }
+}
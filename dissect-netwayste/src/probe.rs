@@ -0,0 +1,302 @@
+//! `probe`: a ping for netwayste servers, for players wondering how good their connection to
+//! one is.
+//!
+//! The probe logs in like a client would, then sends a `KeepAlive` request every interval and
+//! times the response acknowledging it. Requests with no response by the timeout are reported
+//! as lost, though one that turns up later still counts. On the way out, after `count`
+//! requests or on Ctrl-C, it disconnects and logs a summary like ping's.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::latency::{LatencyTracker, RttStats};
+use dissect_netwayste_core::output::{nested_variant, packet_value, variant_name};
+use dissect_netwayste_core::packet_filter::find_strings;
+use dissect_netwayste_core::protocol::Protocol;
+use dissect_netwayste_core::templates::{set_field, template, trace_packet};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use serde_reflection::Registry;
+use tracing::*;
+
+use crate::generate;
+use crate::shutdown;
+
+/// How many times the login is sent before giving up on the server.
+const CONNECT_TRIES: u32 = 3;
+
+pub struct Options {
+    pub server: SocketAddr,
+    pub name: String,
+    pub client_version: Option<String>,
+    pub interval: Duration,
+    /// How long to wait for a response before calling a request lost
+    pub timeout: Duration,
+    pub count: Option<u64>,
+}
+
+/// Everything the summary is made from.
+#[derive(Default)]
+struct Totals {
+    sent: u64,
+    rtt: RttStats,
+    /// Responses by code
+    codes: BTreeMap<String, u64>,
+}
+
+impl Totals {
+    fn report(&self, server: SocketAddr) {
+        let answered = self.rtt.count() as u64;
+        let lost = self.sent.saturating_sub(answered);
+        info!("--- {} probe statistics ---", server);
+        info!(
+            "{} requests sent, {} answered, {:.1}% lost",
+            self.sent,
+            answered,
+            lost as f64 * 100.0 / self.sent.max(1) as f64
+        );
+        if answered > 0 {
+            info!(
+                "rtt min/p50/avg/p95/p99/max = {:.1?}/{:.1?}/{:.1?}/{:.1?}/{:.1?}/{:.1?}",
+                self.rtt.min,
+                self.rtt.percentile(50),
+                self.rtt.avg(),
+                self.rtt.p95(),
+                self.rtt.percentile(99),
+                self.rtt.max
+            );
+        }
+        if !self.codes.is_empty() {
+            let codes: Vec<String> = self
+                .codes
+                .iter()
+                .map(|(code, count)| format!("{} {}", count, code))
+                .collect();
+            info!("Responses: {}", codes.join(", "));
+        }
+    }
+}
+
+pub fn run(options: Options) -> Result<(), String> {
+    let registry = trace_packet()?;
+    let connect = connect_packet(&registry, &options.name, options.client_version.as_deref())?;
+    let keepalive = template(&registry, "KeepAlive")?;
+    let disconnect = template(&registry, "Disconnect").ok();
+
+    let bind: SocketAddr = if options.server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("can't open a socket: {}", e))?;
+    socket
+        .connect(options.server)
+        .map_err(|e| format!("can't reach {}: {}", options.server, e))?;
+    let local = socket.local_addr().unwrap();
+
+    let logging_in = Instant::now();
+    let cookie = login(&socket, &connect, options.server, options.timeout)?;
+    info!(
+        "Connected to {} as '{}' in {:.1?}",
+        options.server,
+        options.name,
+        logging_in.elapsed()
+    );
+    let totals = Arc::new(Mutex::new(Totals {
+        rtt: RttStats::unbounded(),
+        ..Totals::default()
+    }));
+    let sequence = Arc::new(Mutex::new(1));
+    {
+        let socket = socket
+            .try_clone()
+            .expect("Failed to clone the probe socket");
+        let (totals, sequence) = (totals.clone(), sequence.clone());
+        let (server, cookie, disconnect) = (options.server, cookie.clone(), disconnect.clone());
+        shutdown::on_signal_exit(move || {
+            let sequence = *sequence.lock().unwrap() + 1;
+            send_disconnect(&socket, disconnect.as_ref(), &cookie, sequence);
+            totals.lock().unwrap().report(server);
+        });
+    }
+
+    let decoder = Protocol::V2.decoder();
+    let mut tracker = LatencyTracker::default();
+    let started = Instant::now();
+    // Sent at, by sequence, until answered or reported lost
+    let mut outstanding: BTreeMap<u64, Instant> = BTreeMap::new();
+    let mut buf = [0; 65536];
+    let mut n = 0u64;
+    loop {
+        let done = options.count.is_some_and(|count| n >= count);
+        if done {
+            // Give the last request its chance to be answered
+            if outstanding.is_empty() {
+                break;
+            }
+        } else if started.elapsed() >= options.interval.mul_f64(n as f64) {
+            let seq = {
+                let mut sequence = sequence.lock().unwrap();
+                *sequence += 1;
+                *sequence
+            };
+            let packet = generate::build(&keepalive, Some(&cookie), seq, n)?;
+            let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+            match socket.send(&payload) {
+                Ok(_) => {
+                    tracker.observe(
+                        started.elapsed(),
+                        local,
+                        options.server,
+                        &packet_value(&packet),
+                    );
+                    outstanding.insert(seq, Instant::now());
+                }
+                Err(e) => warn!("Failed to send seq={}: {}", seq, e),
+            }
+            totals.lock().unwrap().sent += 1;
+            n += 1;
+        }
+
+        outstanding.retain(|seq, sent| {
+            let waiting = sent.elapsed() < options.timeout;
+            if !waiting {
+                info!("No reply to seq={} within {:.1?}", seq, options.timeout);
+            }
+            waiting
+        });
+
+        // Read until the next request is due, or the oldest one times out
+        let mut next = if done {
+            options.timeout
+        } else {
+            options
+                .interval
+                .mul_f64(n as f64)
+                .saturating_sub(started.elapsed())
+        };
+        if let Some(sent) = outstanding.values().min() {
+            next = next.min(options.timeout.saturating_sub(sent.elapsed()));
+        }
+        socket
+            .set_read_timeout(Some(next.max(Duration::from_millis(1))))
+            .expect("Failed to set the probe socket's timeout");
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                // e.g. ICMP port unreachable; the request it was for will time out
+                debug!("Reading from {} failed: {}", options.server, e);
+                thread::sleep(next);
+                continue;
+            }
+        };
+        let Ok(packet) = decoder.decode(&buf[..len]) else {
+            warn!("Undecodable {} byte reply from {}", len, options.server);
+            continue;
+        };
+        let value = packet_value(&packet);
+        if variant_name(&value) != "Response" {
+            continue;
+        }
+        let code = nested_variant(&value, "code").unwrap_or_else(|| "?".to_owned());
+        let mut totals = totals.lock().unwrap();
+        *totals.codes.entry(code.clone()).or_default() += 1;
+        if let Some(exchange) = tracker.observe(started.elapsed(), options.server, local, &value) {
+            let late = outstanding.remove(&exchange.sequence).is_none();
+            totals.rtt.add(exchange.rtt);
+            info!(
+                "Reply from {}: seq={} code={} time={:.1?}{}",
+                options.server,
+                exchange.sequence,
+                code,
+                exchange.rtt,
+                if late { " (late)" } else { "" }
+            );
+        }
+    }
+
+    send_disconnect(
+        &socket,
+        disconnect.as_ref(),
+        &cookie,
+        *sequence.lock().unwrap() + 1,
+    );
+    totals.lock().unwrap().report(options.server);
+    Ok(())
+}
+
+/// A request to log in as `name`.
+pub fn connect_packet(
+    registry: &Registry,
+    name: &str,
+    client_version: Option<&str>,
+) -> Result<Packet, String> {
+    let mut connect = template(registry, "Connect")?;
+    set_field(&mut connect, "name", &name.into());
+    if let Some(version) = client_version {
+        set_field(&mut connect, "client_version", &version.into());
+    }
+    generate::build(&connect, None, 1, 0)
+}
+
+/// Sends `connect` on `socket`, connected to `server`, until the server answers, returning the
+/// cookie it hands out.
+pub fn login(
+    socket: &UdpSocket,
+    connect: &Packet,
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<String, String> {
+    let payload = bincode::serialize(connect).expect("netwayste packet not serializable");
+    let decoder = Protocol::V2.decoder();
+    let mut buf = [0; 65536];
+    for _ in 0..CONNECT_TRIES {
+        socket
+            .send(&payload)
+            .map_err(|e| format!("can't send to {}: {}", server, e))?;
+        socket
+            .set_read_timeout(Some(timeout))
+            .expect("Failed to set the socket's timeout");
+        while let Ok(len) = socket.recv(&mut buf) {
+            let Ok(packet) = decoder.decode(&buf[..len]) else {
+                continue;
+            };
+            let value = packet_value(&packet);
+            if variant_name(&value) != "Response" {
+                continue;
+            }
+            let mut cookies = vec![];
+            find_strings(&value, "cookie", &mut cookies);
+            let code = nested_variant(&value, "code").unwrap_or_else(|| "?".to_owned());
+            return match cookies.first() {
+                Some(cookie) => Ok(cookie.to_string()),
+                None => Err(format!("{} refused the login: {}", server, code)),
+            };
+        }
+    }
+    Err(format!(
+        "no answer from {} after {} tries",
+        server, CONNECT_TRIES
+    ))
+}
+
+pub fn send_disconnect(
+    socket: &UdpSocket,
+    disconnect: Option<&Value>,
+    cookie: &str,
+    sequence: u64,
+) {
+    let Some(packet) = disconnect.and_then(|d| generate::build(d, Some(cookie), sequence, 0).ok())
+    else {
+        return;
+    };
+    let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+    if let Err(e) = socket.send(&payload) {
+        debug!("Failed to disconnect: {}", e);
+    }
+}
@@ -0,0 +1,91 @@
+//! `--render`: the rebuilt universe drawn in the terminal, two cells to a character.
+
+use std::io::{self, Write};
+
+use crate::universe::Universe;
+
+/// How much of the universe fits on screen when no window is given, in cells.
+const DEFAULT_WIDTH: u64 = 120;
+const DEFAULT_HEIGHT: u64 = 60;
+
+/// A region of the universe, in cells.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub left: u64,
+    pub top: u64,
+    pub width: u64,
+    pub height: u64,
+}
+
+impl Window {
+    /// Parses `LEFT,TOP,WIDTHxHEIGHT`, e.g. `0,0,80x40`. Meant to be used as a clap
+    /// `value_parser`.
+    pub fn parse(s: &str) -> Result<Window, String> {
+        let err = || format!("'{}' isn't LEFT,TOP,WIDTHxHEIGHT, e.g. 0,0,80x40", s);
+        let mut parts = s.split(',');
+        let (Some(left), Some(top), Some(size), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+        let (width, height) = size.split_once('x').ok_or_else(err)?;
+        let window = Window {
+            left: left.trim().parse().map_err(|_| err())?,
+            top: top.trim().parse().map_err(|_| err())?,
+            width: width.trim().parse().map_err(|_| err())?,
+            height: height.trim().parse().map_err(|_| err())?,
+        };
+        if window.width == 0 || window.height == 0 {
+            return Err(err());
+        }
+        Ok(window)
+    }
+
+    /// The top left of the live cells, as much as fits by default.
+    fn around(universe: &Universe) -> Window {
+        let (left, top) = universe
+            .bounds()
+            .map_or((0, 0), |(left, top, _, _)| (left, top));
+        Window {
+            left,
+            top,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+        }
+    }
+}
+
+/// Clears the terminal and draws `window` of the universe, or as much of it as fits the
+/// default size.
+pub fn render(universe: &Universe, window: Option<Window>) -> io::Result<()> {
+    let window = window.unwrap_or_else(|| Window::around(universe));
+    let alive = |x: u64, y: u64| universe.live.contains(&(window.left + x, window.top + y));
+
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    screen.push_str(&format!(
+        "generation {}, {} live cells, showing {}x{} from ({}, {})\n",
+        universe.generation,
+        universe.live.len(),
+        window.width,
+        window.height,
+        window.left,
+        window.top
+    ));
+    // Each character covers a cell and the one below it
+    for row in (0..window.height).step_by(2) {
+        for x in 0..window.width {
+            let bottom = row + 1 < window.height && alive(x, row + 1);
+            screen.push(match (alive(x, row), bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        screen.push('\n');
+    }
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(screen.as_bytes())?;
+    stdout.flush()
+}
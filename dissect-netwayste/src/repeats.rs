@@ -0,0 +1,71 @@
+//! `--collapse-repeats`: runs of the same packet type on the same flow, such as keepalives on
+//! an idle connection, shown as one line and a count, the way journald does.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A run this long is reported and a new one started, so a busy flow still shows signs of life.
+const MAX_RUN: Duration = Duration::from_secs(60);
+
+struct Run {
+    variant: String,
+    src: SocketAddr,
+    dst: SocketAddr,
+    first: Duration,
+    last: Duration,
+    /// Packets after the one that was shown
+    repeats: u64,
+}
+
+impl Run {
+    fn report(&self) -> Option<String> {
+        (self.repeats > 0).then(|| {
+            format!(
+                "... repeated {} times over {:.1?}",
+                self.repeats,
+                self.last.saturating_sub(self.first)
+            )
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Repeats {
+    run: Option<Run>,
+}
+
+impl Repeats {
+    /// Call with each packet about to be shown. Returns the report on the run it ended, if any,
+    /// to show first, and whether the packet itself is a repeat to leave out.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        variant: String,
+    ) -> (Option<String>, bool) {
+        if let Some(run) = self.run.as_mut() {
+            let same = run.variant == variant && run.src == src && run.dst == dst;
+            if same && ts.saturating_sub(run.first) < MAX_RUN {
+                run.last = ts;
+                run.repeats += 1;
+                return (None, true);
+            }
+        }
+        let ended = self.run.take().and_then(|run| run.report());
+        self.run = Some(Run {
+            variant,
+            src,
+            dst,
+            first: ts,
+            last: ts,
+            repeats: 0,
+        });
+        (ended, false)
+    }
+
+    /// The report on the run still going, once the capture has ended.
+    pub fn finish(&mut self) -> Option<String> {
+        self.run.take().and_then(|run| run.report())
+    }
+}
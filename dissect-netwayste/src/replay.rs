@@ -0,0 +1,220 @@
+//! `export-replay`: one game, as the diffs and chat that made it up, in a file a replay viewer
+//! can step through.
+//!
+//! The file is JSON lines. The first line describes the game:
+//!
+//! ```text
+//! {"format":"conwayste-replay","version":1,"room":"lobby","players":["alice","bob"],"started":"2023-04-01T12:34:56.789012Z"}
+//! ```
+//!
+//! Every line after it is an event, `t` seconds into the game, in order:
+//!
+//! ```text
+//! {"t":0.0,"diff":{"gen0":0,"gen1":1,"pattern":"3o$bo!"}}
+//! {"t":2.5,"chat":{"player":"bob","text":"gg"}}
+//! ```
+//!
+//! Diffs are the ones one client in the room received, skipping resends, so applying them in
+//! order rebuilds the universe generation by generation.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use dissect_netwayste_core::gen_diff::find_diffs;
+use dissect_netwayste_core::output::format_time;
+use dissect_netwayste_core::packet_filter::client_end;
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::session::{SessionState, SessionTracker};
+use netwaystev2::protocol::Packet;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+use crate::chat::ChatMonitor;
+
+const FORMAT: &str = "conwayste-replay";
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Header<'a> {
+    format: &'a str,
+    version: u32,
+    room: &'a str,
+    players: &'a BTreeSet<String>,
+    started: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Payload {
+    Diff {
+        gen0: u64,
+        gen1: u64,
+        pattern: String,
+    },
+    Chat {
+        player: String,
+        text: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Event {
+    t: f64,
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+struct Recording {
+    room: String,
+    /// The client whose diffs are recorded
+    client: SocketAddr,
+    started: Duration,
+    generation: u64,
+    players: BTreeSet<String>,
+    events: Vec<Event>,
+}
+
+pub struct ReplayRecorder {
+    path: PathBuf,
+    server_ports: Ports,
+    /// The room to record; the first game to start if `None`
+    room: Option<String>,
+    sessions: SessionTracker,
+    chat: ChatMonitor,
+    recording: Option<Recording>,
+    /// Set once the recorded client leaves the room
+    finished: bool,
+}
+
+impl ReplayRecorder {
+    /// Records the game in `room`, or the first one seen, to be written to `path` at the end.
+    pub fn new(path: PathBuf, server_ports: Ports, room: Option<String>) -> Self {
+        ReplayRecorder {
+            path,
+            server_ports: server_ports.clone(),
+            room,
+            sessions: SessionTracker::new(server_ports.clone()),
+            chat: ChatMonitor::new(server_ports),
+            recording: None,
+            finished: false,
+        }
+    }
+
+    /// Call with every decoded packet, in capture order.
+    pub fn observe(&mut self, ts: Duration, src: SocketAddr, dst: SocketAddr, packet: &Value) {
+        self.sessions.observe(src, dst, packet);
+        let chat = self.chat.observe(src, dst, packet);
+        if self.finished {
+            return;
+        }
+
+        if self.recording.is_none() && self.server_ports.contains(src.port()) {
+            let room = match self.sessions.state(dst) {
+                Some(SessionState::InRoom(room)) | Some(SessionState::InGame(room)) => room,
+                _ => return,
+            };
+            let wanted = self.room.as_ref().map_or(true, |wanted| wanted == room);
+            // Only a diff carrying the whole universe gives a replay something to start from
+            if wanted && find_diffs(packet).iter().any(|diff| diff.gen0 == 0) {
+                self.recording = Some(Recording {
+                    room: room.clone(),
+                    client: dst,
+                    started: ts,
+                    generation: 0,
+                    players: BTreeSet::new(),
+                    events: vec![],
+                });
+            }
+        }
+        let Some(recording) = self.recording.as_mut() else {
+            return;
+        };
+
+        let client = client_end(src, dst, &self.server_ports);
+        match self.sessions.state(client) {
+            Some(SessionState::InRoom(room)) | Some(SessionState::InGame(room))
+                if *room == recording.room =>
+            {
+                if let Some(name) = self.sessions.name(client) {
+                    recording.players.insert(name.to_owned());
+                }
+            }
+            _ if client == recording.client => {
+                self.finished = true;
+                return;
+            }
+            _ => {}
+        }
+
+        let t = ts.saturating_sub(recording.started).as_secs_f64();
+        if self.server_ports.contains(src.port()) && dst == recording.client {
+            for diff in find_diffs(packet) {
+                let applies = diff.gen0 == 0 || diff.gen0 == recording.generation;
+                if applies && diff.gen1 > recording.generation {
+                    recording.generation = diff.gen1;
+                    recording.events.push(Event {
+                        t,
+                        payload: Payload::Diff {
+                            gen0: diff.gen0,
+                            gen1: diff.gen1,
+                            pattern: diff.pattern.to_owned(),
+                        },
+                    });
+                }
+            }
+        }
+        if let Some(chat) = chat.filter(|chat| chat.room.as_ref() == Some(&recording.room)) {
+            recording.events.push(Event {
+                t,
+                payload: Payload::Chat {
+                    player: chat.player,
+                    text: chat.text,
+                },
+            });
+        }
+    }
+
+    /// Writes the replay, failing if no game was captured from its start.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let recording = self.recording.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no game was captured from its first generation",
+            )
+        })?;
+        let mut out = BufWriter::new(File::create(path)?);
+        let header = Header {
+            format: FORMAT,
+            version: VERSION,
+            room: &recording.room,
+            players: &recording.players,
+            started: format_time(recording.started),
+        };
+        let line = serde_json::to_string(&header).expect("failed to serialize replay header");
+        writeln!(out, "{}", line)?;
+        for event in &recording.events {
+            let line = serde_json::to_string(event).expect("failed to serialize replay event");
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+impl Analyzer for ReplayRecorder {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        self.observe(context.ts, context.src, context.dst, value);
+    }
+
+    fn on_shutdown(&mut self) {
+        match self.write(&self.path) {
+            Ok(()) => info!("Wrote replay to {}", self.path.display()),
+            Err(e) => error!("Failed to write replay: '{}'", e),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! `resend`: plays the client side of a capture back at a live server, e.g. to reproduce a
+//! reported bug against a dev server from the reporter's capture.
+//!
+//! Each client in the capture gets a UDP socket of its own, so the server sees as many clients
+//! as were captured. Packets go out with the gaps between them they were captured with, scaled
+//! by `speed`. What the server sends back is left unread.
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::ports::Ports;
+use tracing::*;
+
+use crate::capture::Source;
+use crate::dissect::Dissector;
+use crate::filter::Filter;
+
+/// Sends every payload in the capture at `path` headed for one of `server_ports` to `server`.
+pub fn run(
+    path: &Path,
+    filter: &Filter,
+    server_ports: &Ports,
+    server: SocketAddr,
+    speed: f64,
+    decapsulate: bool,
+    verbose: bool,
+) {
+    let mut source = Source::from_file(path);
+    source
+        .filter(filter)
+        .expect("Failed to filter for netwayste packets");
+    let mut dissector = Dissector::new(decapsulate);
+    let mut sockets: HashMap<SocketAddr, UdpSocket> = HashMap::new();
+    let started = Instant::now();
+    let mut first_ts = None;
+    let mut sent = 0;
+    while let Ok(frame) = source.next_frame() {
+        let Ok(datagrams) = dissector.dissect(&frame) else {
+            continue;
+        };
+        for datagram in datagrams {
+            if !server_ports.contains(datagram.dst.port()) {
+                continue;
+            }
+            // Wait until as long after the start as this packet came after the first one
+            let since_first = frame.ts.saturating_sub(*first_ts.get_or_insert(frame.ts));
+            let due = since_first.div_f64(speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+
+            let socket = sockets.entry(datagram.src).or_insert_with(|| {
+                let bind: SocketAddr = if server.is_ipv4() {
+                    "0.0.0.0:0".parse().unwrap()
+                } else {
+                    "[::]:0".parse().unwrap()
+                };
+                let socket = UdpSocket::bind(bind).expect("Failed to open a UDP socket");
+                info!(
+                    "Resending {}'s packets from {}",
+                    datagram.src,
+                    socket.local_addr().unwrap()
+                );
+                socket
+            });
+            match socket.send_to(&datagram.payload, server) {
+                Ok(_) => {
+                    sent += 1;
+                    if verbose {
+                        info!(
+                            "+{:.3?} {} -> {}: {} bytes",
+                            since_first,
+                            datagram.src,
+                            server,
+                            datagram.payload.len()
+                        );
+                    }
+                }
+                Err(e) => warn!("Failed to send {}'s packet: {}", datagram.src, e),
+            }
+        }
+    }
+    info!(
+        "Resent {} packets from {} clients to {} in {:.1?}",
+        sent,
+        sockets.len(),
+        server,
+        started.elapsed()
+    );
+}
@@ -0,0 +1,55 @@
+//! `--resolve`: host names for addresses, looked up in the background so a slow DNS server
+//! never holds up the capture.
+//!
+//! The first time an address is seen a lookup is started and the address is shown bare; once
+//! the lookup finishes its name is shown from then on. Lookups that take longer than the
+//! timeout are given up on, leaving the address bare for good.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+enum Lookup {
+    Pending,
+    Done(Option<String>),
+}
+
+pub struct Resolver {
+    timeout: Duration,
+    cache: Arc<Mutex<HashMap<IpAddr, Lookup>>>,
+}
+
+impl Resolver {
+    pub fn new(timeout: Duration) -> Self {
+        Resolver {
+            timeout,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The address's host name if it's been looked up already, starting the lookup if not.
+    pub fn name(&self, ip: IpAddr) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(&ip) {
+            Some(Lookup::Done(name)) => return name.clone(),
+            Some(Lookup::Pending) => return None,
+            None => {}
+        }
+        cache.insert(ip, Lookup::Pending);
+
+        let cache = Arc::clone(&self.cache);
+        let timeout = self.timeout;
+        let started = Instant::now();
+        thread::spawn(move || {
+            let name = dns_lookup::lookup_addr(&ip)
+                .ok()
+                // A PTR-less address comes back as itself
+                .filter(|name| *name != ip.to_string())
+                .filter(|_| started.elapsed() <= timeout);
+            cache.lock().unwrap().insert(ip, Lookup::Done(name));
+        });
+        None
+    }
+}
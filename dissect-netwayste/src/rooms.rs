@@ -0,0 +1,119 @@
+//! Traffic per game room, to find which game is behind a load spike.
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::{format_time, nested_variant, variant_name};
+use dissect_netwayste_core::packet_filter::client_end;
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::session::{SessionState, SessionTracker};
+use serde_json::{json, Value};
+use tracing::*;
+
+#[derive(Default)]
+struct RoomTraffic {
+    players: HashSet<SocketAddr>,
+    updates: u64,
+    update_bytes: u64,
+    chats: u64,
+    /// Capture times of the room's first and latest packets
+    first: Option<Duration>,
+    last: Duration,
+}
+
+pub struct RoomStats {
+    server_ports: Ports,
+    sessions: SessionTracker,
+    rooms: BTreeMap<String, RoomTraffic>,
+}
+
+impl RoomStats {
+    pub fn new(server_ports: Ports) -> Self {
+        RoomStats {
+            server_ports: server_ports.clone(),
+            sessions: SessionTracker::new(server_ports),
+            rooms: BTreeMap::new(),
+        }
+    }
+
+    /// Call with every decoded packet, in capture order. Packets count towards the room the
+    /// client is in, as far as can be told from the joins and leaves seen so far.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+        size: usize,
+    ) {
+        self.sessions.observe(src, dst, packet);
+        let client = client_end(src, dst, &self.server_ports);
+        let room = match self.sessions.state(client) {
+            Some(SessionState::InRoom(room)) | Some(SessionState::InGame(room)) => room,
+            _ => return,
+        };
+
+        let traffic = self.rooms.entry(room.clone()).or_default();
+        traffic.players.insert(client);
+        traffic.first.get_or_insert(ts);
+        traffic.last = ts;
+        match variant_name(packet).as_str() {
+            "Update" | "UpdateReply" => {
+                traffic.updates += 1;
+                traffic.update_bytes += size as u64;
+            }
+            "Request" if nested_variant(packet, "action").as_deref() == Some("ChatMessage") => {
+                traffic.chats += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Each room's traffic, for `/rooms`.
+    pub fn to_json(&self) -> Value {
+        self.rooms
+            .iter()
+            .map(|(name, traffic)| {
+                json!({
+                    "room": name,
+                    "players": traffic.players,
+                    "updates": traffic.updates,
+                    "update_bytes": traffic.update_bytes,
+                    "chats": traffic.chats,
+                    "first_seen": traffic.first.and_then(format_time),
+                    "last_seen": format_time(traffic.last),
+                })
+            })
+            .collect()
+    }
+
+    pub fn log(&self) {
+        if self.rooms.is_empty() {
+            return;
+        }
+        info!(
+            "  {:<20} {:>7} {:>10} {:>6} {:>12}",
+            "room", "players", "updates/s", "chats", "avg update"
+        );
+        for (name, traffic) in &self.rooms {
+            let secs = traffic
+                .last
+                .saturating_sub(traffic.first.unwrap_or_default())
+                .as_secs_f64();
+            let rate = if secs > 0.0 {
+                format!("{:.1}", traffic.updates as f64 / secs)
+            } else {
+                "-".to_owned()
+            };
+            info!(
+                "  {:<20} {:>7} {:>10} {:>6} {:>10} B",
+                name,
+                traffic.players.len(),
+                rate,
+                traffic.chats,
+                traffic.update_bytes / traffic.updates.max(1)
+            );
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Size-based rotation through a numbered ring of files, shared by the pcap and log writers.
+
+use std::path::{Path, PathBuf};
+
+/// When to move on to the next file of a ring.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    /// Once this many files exist the oldest is overwritten; unbounded if `None`
+    pub max_files: Option<usize>,
+}
+
+impl Rotation {
+    /// The ring position that follows `index`.
+    pub fn next_index(&self, index: usize) -> usize {
+        match self.max_files {
+            Some(max_files) => (index + 1) % max_files,
+            None => index + 1,
+        }
+    }
+}
+
+/// Files of a ring are numbered like `trace.000.pcap`, `trace.001.pcap`, etc.
+pub fn ring_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{:03}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.{:03}", stem, index),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,72 @@
+//! `--rtt-file`: every round-trip time sample with its capture time, for plotting latency over
+//! the course of a match.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::latency::Exchange;
+use dissect_netwayste_core::output::format_time;
+use serde::Serialize;
+
+const CSV_HEADER: &str = "time,client,server,request,rtt_ms";
+
+#[derive(Serialize)]
+struct Sample {
+    timestamp: Option<String>,
+    client: String,
+    server: String,
+    request: u64,
+    rtt_ms: f64,
+}
+
+pub struct RttExport {
+    out: BufWriter<File>,
+    /// JSON lines rather than CSV
+    json: bool,
+}
+
+impl RttExport {
+    /// Writes JSON lines if `path` ends in `.json` or `.jsonl`, CSV otherwise.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let json = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("json") | Some("jsonl")
+        );
+        let mut out = BufWriter::new(File::create(path)?);
+        if !json {
+            writeln!(out, "{}", CSV_HEADER)?;
+        }
+        Ok(RttExport { out, json })
+    }
+
+    /// `ts` is the capture time of the response that completed the exchange.
+    pub fn write(&mut self, ts: Duration, exchange: &Exchange) -> io::Result<()> {
+        let sample = Sample {
+            timestamp: format_time(ts),
+            client: exchange.client.to_string(),
+            server: exchange.server.to_string(),
+            request: exchange.sequence,
+            rtt_ms: exchange.rtt.as_secs_f64() * 1000.0,
+        };
+        if self.json {
+            let line = serde_json::to_string(&sample).expect("failed to serialize RTT sample");
+            writeln!(self.out, "{}", line)
+        } else {
+            writeln!(
+                self.out,
+                "{},{},{},{},{:.3}",
+                sample.timestamp.unwrap_or_default(),
+                sample.client,
+                sample.server,
+                sample.request,
+                sample.rtt_ms
+            )
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
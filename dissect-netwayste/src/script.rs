@@ -0,0 +1,166 @@
+//! `--script`: per-packet logic written in [Rhai](https://rhai.rs), for one-off investigations
+//! that don't deserve a rebuild.
+//!
+//! A script defines whichever of these functions it needs:
+//!
+//! ```rhai
+//! // Called once, before the first packet; what it returns is `this` in the other two
+//! fn init() {
+//!     #{ joins: 0 }
+//! }
+//!
+//! // Called with every decoded packet the other filters let through; returning false hides it
+//! fn on_packet(packet) {
+//!     if packet.action == "JoinRoom" {
+//!         this.joins += 1;
+//!         print(`${packet.src} joined ${packet.body.Request.action.JoinRoom.room_name}`);
+//!     }
+//!     packet.variant != "Update"
+//! }
+//!
+//! // Called once the capture has ended, however it ended
+//! fn on_end() {
+//!     print(`${this.joins} joins`);
+//! }
+//! ```
+//!
+//! `packet` is a map of `time` (RFC 3339), `src` and `dst` (as `ip:port` strings), `src_port`,
+//! `dst_port`, `size`, `variant`, `action` and `code` (the `RequestAction` and `ResponseCode`
+//! variants, or `()`), `sequence` (or `()`), and `body`: the whole packet, as serde lays it out.
+//! Whatever is passed to `print()` is shown as a line of output.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dissect_netwayste_core::output::{format_time, nested_variant, sequence_number, variant_name};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use serde_json::{json, Value};
+use tracing::*;
+
+use crate::analyzer::Analyzer;
+use crate::pause::{Line, Pause};
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    /// `this` in the script's functions
+    state: Dynamic,
+    on_packet: bool,
+    on_end: bool,
+    /// Calls that failed; only the first is reported as it happens
+    failures: u64,
+}
+
+impl Script {
+    /// Compiles the script and runs its `init()`. Lines it prints go through `pause`, if given.
+    pub fn load(path: &Path, pause: Option<Arc<Pause>>) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.on_print(move |text| {
+            let line = Line::Log(text.to_owned());
+            match &pause {
+                Some(pause) => pause.emit(line),
+                None => line.emit(),
+            }
+        });
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| e.to_string())?;
+        let defines = |name: &str| ast.iter_functions().any(|function| function.name == name);
+        let (on_packet, on_end) = (defines("on_packet"), defines("on_end"));
+        // Top-level statements run once, here, rather than before every call
+        let state = if defines("init") {
+            engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &ast, "init", ())
+                .map_err(|e| format!("init() failed: {}", e))?
+        } else {
+            engine.run_ast(&ast).map_err(|e| format!("failed: {}", e))?;
+            Map::new().into()
+        };
+        Ok(Script {
+            engine,
+            ast,
+            state,
+            on_packet,
+            on_end,
+            failures: 0,
+        })
+    }
+
+    /// Runs `on_packet()`, returning whether the packet is still to be shown. A packet the
+    /// script fails on is shown.
+    pub fn shows(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+        size: usize,
+    ) -> bool {
+        if !self.on_packet {
+            return true;
+        }
+        let fields = json!({
+            "time": format_time(ts),
+            "src": src.to_string(),
+            "dst": dst.to_string(),
+            "src_port": src.port(),
+            "dst_port": dst.port(),
+            "size": size,
+            "variant": variant_name(packet),
+            "action": nested_variant(packet, "action"),
+            "code": nested_variant(packet, "code"),
+            "sequence": sequence_number(packet),
+            "body": packet,
+        });
+        let fields = rhai::serde::to_dynamic(fields).expect("packet fields not convertible");
+        match self.call("on_packet", (fields,)) {
+            Some(shown) => shown.as_bool().unwrap_or(true),
+            None => true,
+        }
+    }
+
+    /// Runs `on_end()`.
+    pub fn end(&mut self) {
+        if self.on_end {
+            self.call("on_end", ());
+        }
+        if self.failures > 1 {
+            warn!("The script failed {} times", self.failures);
+        }
+    }
+
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            name,
+            args,
+        );
+        match result {
+            Ok(result) => Some(result),
+            Err(e) => {
+                self.failures += 1;
+                if self.failures == 1 {
+                    warn!(
+                        "Script {}() failed, reporting no further failures: {}",
+                        name, e
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Registered alongside the analyzers, so `on_end()` runs however the capture ends.
+impl Analyzer for Arc<Mutex<Script>> {
+    fn on_shutdown(&mut self) {
+        self.lock().unwrap().end();
+    }
+}
@@ -0,0 +1,83 @@
+//! `--sessions` and `--timeline`: each client's way through connecting, rooms and games, logged
+//! as it changes and saved to `--timeline-file`.
+
+use dissect_netwayste_core::output::format_time;
+use dissect_netwayste_core::ports::Ports;
+use dissect_netwayste_core::session::SessionTracker;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+use crate::timeline::TimelineExport;
+
+pub struct Sessions {
+    tracker: SessionTracker,
+    /// `--sessions`: log each change of state
+    log_states: bool,
+    /// `--timeline`: log each change as what the player did
+    log_events: bool,
+    export: Option<TimelineExport>,
+}
+
+impl Sessions {
+    pub fn new(
+        server_ports: Ports,
+        log_states: bool,
+        log_events: bool,
+        export: Option<TimelineExport>,
+    ) -> Self {
+        Sessions {
+            tracker: SessionTracker::new(server_ports),
+            log_states,
+            log_events,
+            export,
+        }
+    }
+}
+
+impl Analyzer for Sessions {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let Some(transition) = self.tracker.observe(context.src, context.dst, value) else {
+            return;
+        };
+        if !context.shown {
+            return;
+        }
+        let time = format_time(context.ts).unwrap_or_default();
+        if self.log_states {
+            let from = match &transition.from {
+                Some(state) => state.to_string(),
+                None => "new".to_owned(),
+            };
+            info!(
+                "{} Session {}: {} -> {}",
+                time, transition.client, from, transition.to
+            );
+        }
+        let player = self.tracker.name(transition.client);
+        let event = transition.event();
+        if self.log_events {
+            info!(
+                "{} {} ({}) {}",
+                time,
+                player.unwrap_or("?"),
+                transition.client,
+                event
+            );
+        }
+        if let Some(export) = self.export.as_mut() {
+            if let Err(e) = export.write(context.ts, transition.client, player, &event) {
+                error!("Failed to write timeline event: '{}'", e);
+            }
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        if let Some(export) = self.export.as_mut() {
+            if let Err(e) = export.flush() {
+                error!("Failed to finish timeline file: '{}'", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Ctrl-C and SIGTERM end the run cleanly: exporters are flushed and the final summary logged,
+//! rather than the process dying mid-line. A second signal while that's under way ends it at once.
+
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How often the signal flag is checked.
+const SIGNAL_POLL: Duration = Duration::from_millis(100);
+
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal_handler(_signal: libc::c_int) {
+    if SIGNALLED.swap(true, Ordering::Relaxed) {
+        // Safety: _exit is async-signal-safe, unlike process::exit
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Calls `stop` once SIGINT or SIGTERM arrives, leaving it to end the run.
+pub fn on_signal(stop: impl FnOnce() + Send + 'static) {
+    // Safety: the handler only touches an atomic and calls _exit
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal_handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal_handler as libc::sighandler_t);
+    }
+    thread::spawn(move || {
+        while !SIGNALLED.load(Ordering::Relaxed) {
+            thread::sleep(SIGNAL_POLL);
+        }
+        stop();
+    });
+}
+
+/// Calls `finish` and exits once SIGINT or SIGTERM arrives.
+pub fn on_signal_exit(finish: impl FnOnce() + Send + 'static) {
+    on_signal(move || {
+        finish();
+        process::exit(0);
+    });
+}
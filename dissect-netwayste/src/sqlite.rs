@@ -0,0 +1,156 @@
+//! `--sqlite` export: decoded packets, and optionally failures, in tables for ad-hoc SQL.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::{packet_value, sequence_number, variant_name};
+use netwaystev2::protocol::Packet;
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// Times are seconds since the UNIX epoch, as a REAL so they can be subtracted directly.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS flows (
+    id INTEGER PRIMARY KEY,
+    src TEXT NOT NULL,
+    dst TEXT NOT NULL,
+    first_seen REAL NOT NULL,
+    last_seen REAL NOT NULL,
+    packets INTEGER NOT NULL DEFAULT 0,
+    errors INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (src, dst)
+);
+CREATE TABLE IF NOT EXISTS packets (
+    id INTEGER PRIMARY KEY,
+    flow_id INTEGER NOT NULL REFERENCES flows (id),
+    time REAL NOT NULL,
+    variant TEXT NOT NULL,
+    sequence INTEGER,
+    size INTEGER NOT NULL,
+    body TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS errors (
+    id INTEGER PRIMARY KEY,
+    flow_id INTEGER NOT NULL REFERENCES flows (id),
+    time REAL NOT NULL,
+    error TEXT NOT NULL,
+    payload BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS packets_flow ON packets (flow_id);
+CREATE INDEX IF NOT EXISTS errors_flow ON errors (flow_id);
+";
+
+pub struct SqliteExport {
+    conn: Connection,
+    /// Row IDs of flows already in the database, keyed by (source, destination)
+    flows: HashMap<(SocketAddr, SocketAddr), i64>,
+    /// Whether payloads that failed to de-serialize are stored too
+    failures: bool,
+}
+
+impl SqliteExport {
+    /// Opens or creates the database. Rows are added to any already there, so one file can
+    /// collect several sessions.
+    pub fn open(path: &Path, failures: bool) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // Every row commits on its own so nothing is lost if we're killed, which WAL keeps cheap
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteExport {
+            conn,
+            flows: HashMap::new(),
+            failures,
+        })
+    }
+
+    pub fn insert_packet(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Packet,
+        size: usize,
+    ) -> rusqlite::Result<()> {
+        let flow_id = self.flow_id(ts, src, dst)?;
+        let value = packet_value(packet);
+        self.conn.execute(
+            "INSERT INTO packets (flow_id, time, variant, sequence, size, body) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                flow_id,
+                ts.as_secs_f64(),
+                variant_name(&value),
+                sequence_number(&value).map(|seq| seq as i64),
+                size as i64,
+                value.to_string()
+            ],
+        )?;
+        self.conn.execute(
+            "UPDATE flows SET packets = packets + 1, last_seen = MAX(last_seen, ?2) WHERE id = ?1",
+            params![flow_id, ts.as_secs_f64()],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_error(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        error: &str,
+        payload: &[u8],
+    ) -> rusqlite::Result<()> {
+        let flow_id = self.flow_id(ts, src, dst)?;
+        self.conn.execute(
+            "INSERT INTO errors (flow_id, time, error, payload) VALUES (?1, ?2, ?3, ?4)",
+            params![flow_id, ts.as_secs_f64(), error, payload],
+        )?;
+        self.conn.execute(
+            "UPDATE flows SET errors = errors + 1, last_seen = MAX(last_seen, ?2) WHERE id = ?1",
+            params![flow_id, ts.as_secs_f64()],
+        )?;
+        Ok(())
+    }
+
+    fn flow_id(&mut self, ts: Duration, src: SocketAddr, dst: SocketAddr) -> rusqlite::Result<i64> {
+        if let Some(id) = self.flows.get(&(src, dst)) {
+            return Ok(*id);
+        }
+        // The flow may already be in the file from an earlier session
+        self.conn.execute(
+            "INSERT OR IGNORE INTO flows (src, dst, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)",
+            params![src.to_string(), dst.to_string(), ts.as_secs_f64()],
+        )?;
+        let id = self.conn.query_row(
+            "SELECT id FROM flows WHERE src = ?1 AND dst = ?2",
+            params![src.to_string(), dst.to_string()],
+            |row| row.get(0),
+        )?;
+        self.flows.insert((src, dst), id);
+        Ok(id)
+    }
+}
+
+impl Analyzer for SqliteExport {
+    fn on_packet(&mut self, context: &Context, packet: &Packet, _value: &Value) {
+        let (ts, src, dst) = (context.ts, context.src, context.dst);
+        if let Err(e) = self.insert_packet(ts, src, dst, packet, context.payload.len()) {
+            error!("Failed to write packet to SQLite database: '{}'", e);
+        }
+    }
+
+    fn on_error(&mut self, context: &Context, error: &bincode::Error) {
+        if !self.failures {
+            return;
+        }
+        let (ts, src, dst) = (context.ts, context.src, context.dst);
+        if let Err(e) = self.insert_error(ts, src, dst, &error.to_string(), context.payload) {
+            error!("Failed to write packet to SQLite database: '{}'", e);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! `--statsd`: the counters behind `--metrics-listen`, pushed to a StatsD daemon instead.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::variant_name;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// Stay under the usual 1500 byte MTU once headers are added.
+const MAX_DATAGRAM_LEN: usize = 1400;
+
+/// Counts since the last flush.
+#[derive(Default)]
+struct Counts {
+    decoded: u64,
+    failures: u64,
+    malformed: u64,
+    variants: BTreeMap<String, u64>,
+}
+
+#[derive(Clone)]
+pub struct Statsd {
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Statsd {
+    /// Starts a thread that sends whatever was counted to `addr` every `interval`, with every
+    /// name under `prefix`.
+    pub fn start(addr: &str, prefix: &str, interval: Duration) -> Self {
+        let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to open StatsD socket");
+        socket
+            .connect(addr)
+            .expect("Failed to resolve StatsD address");
+        let statsd = Statsd {
+            counts: Arc::new(Mutex::new(Counts::default())),
+        };
+        let counts = Arc::clone(&statsd.counts);
+        let prefix = prefix.to_owned();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let counts = mem::take(&mut *counts.lock().unwrap());
+            send(&socket, &lines(&prefix, &counts));
+        });
+        statsd
+    }
+
+    pub fn record_decoded(&self, variant: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.decoded += 1;
+        *counts.variants.entry(variant.to_owned()).or_default() += 1;
+    }
+
+    pub fn record_failure(&self) {
+        self.counts.lock().unwrap().failures += 1;
+    }
+
+    pub fn record_malformed(&self) {
+        self.counts.lock().unwrap().malformed += 1;
+    }
+}
+
+impl Analyzer for Statsd {
+    fn on_packet(&mut self, _context: &Context, _packet: &Packet, value: &Value) {
+        self.record_decoded(&variant_name(value));
+    }
+
+    fn on_error(&mut self, _context: &Context, _error: &bincode::Error) {
+        self.record_failure();
+    }
+}
+
+/// Counters are sent even when zero, so graphs show a flat line rather than a gap.
+fn lines(prefix: &str, counts: &Counts) -> Vec<String> {
+    let mut lines = vec![
+        format!("{}.packets_decoded:{}|c", prefix, counts.decoded),
+        format!("{}.deserialization_failures:{}|c", prefix, counts.failures),
+        format!("{}.malformed_frames:{}|c", prefix, counts.malformed),
+    ];
+    for (variant, count) in &counts.variants {
+        lines.push(format!("{}.variant.{}:{}|c", prefix, variant, count));
+    }
+    lines
+}
+
+/// Packs the lines into as few datagrams as fit.
+fn send(socket: &UdpSocket, lines: &[String]) {
+    let mut datagram = String::new();
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_LEN {
+            send_datagram(socket, &datagram);
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(line);
+    }
+    if !datagram.is_empty() {
+        send_datagram(socket, &datagram);
+    }
+}
+
+fn send_datagram(socket: &UdpSocket, datagram: &str) {
+    if let Err(e) = socket.send(datagram.as_bytes()) {
+        warn!("Failed to send to StatsD: '{}'", e);
+    }
+}
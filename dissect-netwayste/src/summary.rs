@@ -0,0 +1,316 @@
+//! Running totals for a capture, logged periodically with `--quiet` and once it stops.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use dissect_netwayste_core::output::variant_name;
+use dissect_netwayste_core::packet_filter::client_end;
+use dissect_netwayste_core::ports::Ports;
+use netwaystev2::protocol::Packet;
+use serde_json::{json, Value};
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+use crate::geoip::GeoIp;
+use crate::histogram::SizeHistograms;
+use crate::keepalive::KeepaliveTracker;
+use crate::lobby::Lobby;
+use crate::render::{render, Window};
+use crate::rooms::RoomStats;
+use crate::universe::Universes;
+
+/// Decoded traffic in one direction between two endpoints.
+#[derive(Default)]
+struct FlowTotals {
+    packets: u64,
+    bytes: u64,
+}
+
+/// Only the busiest clients are listed, so a server with thousands of them stays readable.
+const MAX_CLIENTS_SHOWN: usize = 10;
+
+pub struct Summary {
+    started: Instant,
+    /// Frames that made it through the capture filter
+    pub frames: u64,
+    /// Frames that couldn't be taken apart down to a UDP or TCP payload
+    pub malformed: u64,
+    /// Payloads that de-serialized as netwayste packets
+    pub decoded: u64,
+    /// Payloads that didn't
+    pub failed: u64,
+    /// Exact copies of a payload recently seen on the same flow
+    pub duplicates: u64,
+    /// Packets left out of the output by `--sample`
+    pub sampled_out: u64,
+    /// Packets left out of the output by `--max-lines-per-sec`
+    pub rate_limited: u64,
+    variants: BTreeMap<String, u64>,
+    /// Decoded packets by source address
+    clients: HashMap<SocketAddr, u64>,
+    /// Decoded packets by source and destination, for `--api-listen`
+    flows: HashMap<(SocketAddr, SocketAddr), FlowTotals>,
+    /// Only kept with `--size-histogram`
+    pub sizes: Option<SizeHistograms>,
+    /// Only kept with `--keepalives`
+    pub keepalives: Option<KeepaliveTracker>,
+    /// Only kept with `--room-stats`
+    pub rooms: Option<RoomStats>,
+    /// Only kept with `--lobby`
+    pub lobby: Option<Lobby>,
+    /// Only kept with `--universe`
+    pub universes: Option<Universes>,
+    /// Only kept with `--geoip-db` or `--asn-db`
+    pub geoip: Option<GeoIp>,
+    /// libpcap's counters, for live captures that have them
+    pub capture_stats: Option<pcap::Stat>,
+    logged: bool,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Summary {
+            started: Instant::now(),
+            frames: 0,
+            malformed: 0,
+            decoded: 0,
+            failed: 0,
+            duplicates: 0,
+            sampled_out: 0,
+            rate_limited: 0,
+            variants: BTreeMap::new(),
+            clients: HashMap::new(),
+            flows: HashMap::new(),
+            sizes: None,
+            keepalives: None,
+            rooms: None,
+            lobby: None,
+            universes: None,
+            geoip: None,
+            capture_stats: None,
+            logged: false,
+        }
+    }
+
+    /// `packet` is as returned by `output::packet_value()`.
+    pub fn record_decoded(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        packet: &Value,
+        size: usize,
+    ) {
+        self.decoded += 1;
+        *self.variants.entry(variant_name(packet)).or_default() += 1;
+        *self.clients.entry(src).or_default() += 1;
+        let flow = self.flows.entry((src, dst)).or_default();
+        flow.packets += 1;
+        flow.bytes += size as u64;
+    }
+
+    /// The totals, for `/stats`.
+    pub fn stats_json(&self) -> Value {
+        json!({
+            "running_secs": self.started.elapsed().as_secs_f64(),
+            "frames": self.frames,
+            "malformed": self.malformed,
+            "decoded": self.decoded,
+            "failed": self.failed,
+            "duplicates": self.duplicates,
+            "variants": self.variants,
+        })
+    }
+
+    /// Decoded traffic per flow, busiest first, for `/flows`.
+    pub fn flows_json(&self) -> Value {
+        let mut flows: Vec<_> = self.flows.iter().collect();
+        flows.sort_by(|a, b| b.1.packets.cmp(&a.1.packets).then(a.0.cmp(b.0)));
+        flows
+            .into_iter()
+            .map(|((src, dst), totals)| {
+                json!({ "src": src, "dst": dst, "packets": totals.packets, "bytes": totals.bytes })
+            })
+            .collect()
+    }
+
+    /// Logs the totals so far without ending anything; used for the periodic summaries.
+    pub fn log_progress(&self) {
+        self.log_totals("Running");
+    }
+
+    /// Logs the final totals. Only the first call does anything, since both the main loop and
+    /// the `--duration` timer may try to have the last word.
+    pub fn log(&mut self) {
+        if self.logged {
+            return;
+        }
+        self.logged = true;
+        self.log_totals("Stopped");
+    }
+
+    /// The main totals, one per line, for `--tui`'s side pane.
+    pub fn overview(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("running   {:.0?}", self.started.elapsed()),
+            format!("frames    {}", self.frames),
+            format!("malformed {}", self.malformed),
+            format!("decoded   {}", self.decoded),
+            format!("failed    {}", self.failed),
+        ];
+        if self.duplicates > 0 {
+            lines.push(format!("dups      {}", self.duplicates));
+        }
+        lines.push(String::new());
+        for (variant, count) in &self.variants {
+            lines.push(format!("{:<14} {}", variant, count));
+        }
+        lines
+    }
+
+    fn log_totals(&self, state: &str) {
+        info!(
+            "{} for {:.1?}: {} frames, {} malformed, {} netwayste packets decoded, {} failed de-serialization",
+            state,
+            self.started.elapsed(),
+            self.frames,
+            self.malformed,
+            self.decoded,
+            self.failed
+        );
+        if let Some(stats) = &self.capture_stats {
+            info!(
+                "  libpcap received {} frames, dropped {} for lack of buffer space and {} at the interface",
+                stats.received, stats.dropped, stats.if_dropped
+            );
+        }
+        if self.duplicates > 0 {
+            info!("  {} of them were duplicates", self.duplicates);
+        }
+        if self.sampled_out > 0 || self.rate_limited > 0 {
+            info!(
+                "  {} left out of the output by sampling and {} by the rate limit",
+                self.sampled_out, self.rate_limited
+            );
+        }
+        for (variant, count) in &self.variants {
+            info!("  {:<20} {}", variant, count);
+        }
+
+        let mut clients: Vec<_> = self.clients.iter().collect();
+        clients.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (client, count) in clients.iter().take(MAX_CLIENTS_SHOWN) {
+            info!("  {:<46} {}", client.to_string(), count);
+        }
+        if clients.len() > MAX_CLIENTS_SHOWN {
+            info!(
+                "  ...and {} more clients",
+                clients.len() - MAX_CLIENTS_SHOWN
+            );
+        }
+
+        if let Some(sizes) = &self.sizes {
+            sizes.log();
+        }
+        if let Some(keepalives) = &self.keepalives {
+            keepalives.log();
+        }
+        if let Some(rooms) = &self.rooms {
+            rooms.log();
+        }
+        if let Some(lobby) = &self.lobby {
+            lobby.log();
+        }
+        if let Some(universes) = &self.universes {
+            universes.log();
+        }
+        if let Some(geoip) = &self.geoip {
+            geoip.log();
+        }
+    }
+}
+
+/// Keeps the totals, and the trackers kept in the summary, up to date with every payload.
+pub struct SummaryRecorder {
+    summary: Arc<Mutex<Summary>>,
+    server_ports: Ports,
+    /// Whether to log where each new client is, with `--geoip-db`
+    log_locations: bool,
+    /// `--render`
+    render: bool,
+    render_window: Option<Window>,
+    rendered_generation: Option<u64>,
+}
+
+impl SummaryRecorder {
+    pub fn new(
+        summary: Arc<Mutex<Summary>>,
+        server_ports: Ports,
+        log_locations: bool,
+        render: bool,
+        render_window: Option<Window>,
+    ) -> Self {
+        SummaryRecorder {
+            summary,
+            server_ports,
+            log_locations,
+            render,
+            render_window,
+            rendered_generation: None,
+        }
+    }
+
+    fn locate(&self, summary: &mut Summary, context: &Context) {
+        let Some(geoip) = summary.geoip.as_mut() else {
+            return;
+        };
+        let location = geoip.observe(context.src, context.dst, context.payload.len());
+        if let Some(location) = location.filter(|_| self.log_locations) {
+            let client = client_end(context.src, context.dst, &self.server_ports);
+            info!("Client {}: {}", client, location);
+        }
+    }
+}
+
+impl Analyzer for SummaryRecorder {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let mut summary = self.summary.lock().unwrap();
+        let size = context.payload.len();
+        summary.record_decoded(context.src, context.dst, value, size);
+        if let Some(sizes) = summary.sizes.as_mut() {
+            sizes.record(Some(variant_name(value)), size);
+        }
+        if let Some(keepalives) = summary.keepalives.as_mut() {
+            keepalives.observe(context.ts, context.src, value);
+        }
+        if let Some(universes) = summary.universes.as_mut() {
+            universes.observe(context.src, context.dst, value);
+            if let Some(latest) = universes.latest().filter(|_| self.render) {
+                // Every client gets each generation, so only draw it the first time
+                if self.rendered_generation < Some(latest.generation) {
+                    self.rendered_generation = Some(latest.generation);
+                    if let Err(e) = render(latest, self.render_window) {
+                        error!("Failed to render universe: '{}'", e);
+                    }
+                }
+            }
+        }
+        self.locate(&mut summary, context);
+        if let Some(lobby) = summary.lobby.as_mut() {
+            lobby.observe(context.ts, value);
+        }
+        if let Some(rooms) = summary.rooms.as_mut() {
+            rooms.observe(context.ts, context.src, context.dst, value, size);
+        }
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        let mut summary = self.summary.lock().unwrap();
+        summary.failed += 1;
+        if let Some(sizes) = summary.sizes.as_mut() {
+            sizes.record(None, context.payload.len());
+        }
+        self.locate(&mut summary, context);
+    }
+}
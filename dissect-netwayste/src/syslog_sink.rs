@@ -0,0 +1,181 @@
+//! `--syslog`: decoded packets and de-serialization failures as RFC 5424 messages to the local
+//! syslog daemon, with the interesting fields in structured data.
+//!
+//! `--log-syslog` is separate: everything that's logged, as plain RFC 3164 messages.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use dissect_netwayste_core::output::{packet_value, sequence_number, variant_name};
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use syslog::{Facility, Formatter3164, Formatter5424, Logger, LoggerBackend};
+use tracing::*;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// SD-IDs need an enterprise number; this is the one RFC 5612 sets aside for documentation.
+const SD_ID: &str = "netwayste@32473";
+
+/// RFC 5424 MSGIDs, so pipelines can route the two kinds of message apart.
+const MSGID_PACKET: u32 = 1;
+const MSGID_FAILURE: u32 = 2;
+
+pub struct SyslogSink {
+    logger: Logger<LoggerBackend, Formatter5424>,
+    /// Whether we've already complained about the daemon not taking messages
+    warned: bool,
+}
+
+impl SyslogSink {
+    /// Connects to the local daemon through `/dev/log` or one of its usual alternatives.
+    pub fn connect(facility: Facility) -> Result<Self, syslog::Error> {
+        let formatter = Formatter5424 {
+            facility,
+            hostname: None,
+            process: env!("CARGO_PKG_NAME").to_owned(),
+            pid: process::id(),
+        };
+        Ok(SyslogSink {
+            logger: syslog::unix(formatter)?,
+            warned: false,
+        })
+    }
+
+    pub fn packet(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Packet) {
+        let value = packet_value(packet);
+        let mut params = endpoints(src, dst);
+        params.insert("variant".to_owned(), variant_name(&value));
+        if let Some(seq) = sequence_number(&value) {
+            params.insert("seq".to_owned(), seq.to_string());
+        }
+        let message = format!("{} -> {} {:?}", src, dst, packet);
+        let result = self
+            .logger
+            .info((MSGID_PACKET, structured(params), message));
+        self.check(result);
+    }
+
+    pub fn failure(&mut self, src: SocketAddr, dst: SocketAddr, error: &str) {
+        let mut params = endpoints(src, dst);
+        params.insert("error".to_owned(), error.to_owned());
+        let message = format!("{} -> {} failed de-serialization: {}", src, dst, error);
+        let result = self
+            .logger
+            .err((MSGID_FAILURE, structured(params), message));
+        self.check(result);
+    }
+
+    fn check(&mut self, result: Result<(), syslog::Error>) {
+        if let Err(e) = result {
+            if !self.warned {
+                warn!("Failed to send to syslog, dropping messages: '{}'", e);
+                self.warned = true;
+            }
+        }
+    }
+}
+
+impl Analyzer for SyslogSink {
+    fn on_packet(&mut self, context: &Context, packet: &Packet, _value: &Value) {
+        self.packet(context.src, context.dst, packet);
+    }
+
+    fn on_error(&mut self, context: &Context, error: &bincode::Error) {
+        self.failure(context.src, context.dst, &error.to_string());
+    }
+}
+
+/// A log writer sending each event to syslog at its own severity.
+#[derive(Clone)]
+pub struct SyslogLog {
+    logger: Arc<Mutex<Logger<LoggerBackend, Formatter3164>>>,
+}
+
+impl SyslogLog {
+    pub fn connect(facility: Facility) -> Result<Self, syslog::Error> {
+        let formatter = Formatter3164 {
+            facility,
+            hostname: None,
+            process: env!("CARGO_PKG_NAME").to_owned(),
+            pid: process::id(),
+        };
+        Ok(SyslogLog {
+            logger: Arc::new(Mutex::new(syslog::unix(formatter)?)),
+        })
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogLog {
+    type Writer = SyslogEvent;
+
+    fn make_writer(&'a self) -> SyslogEvent {
+        SyslogEvent {
+            logger: Arc::clone(&self.logger),
+            level: Level::INFO,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> SyslogEvent {
+        SyslogEvent {
+            logger: Arc::clone(&self.logger),
+            level: *meta.level(),
+        }
+    }
+}
+
+pub struct SyslogEvent {
+    logger: Arc<Mutex<Logger<LoggerBackend, Formatter3164>>>,
+    level: Level,
+}
+
+impl io::Write for SyslogEvent {
+    /// Each call is one complete log event.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = strip_escapes(String::from_utf8_lossy(buf).trim_end());
+        let mut logger = self.logger.lock().unwrap();
+        let result = match self.level {
+            Level::ERROR => logger.err(message),
+            Level::WARN => logger.warning(message),
+            Level::INFO => logger.info(message),
+            _ => logger.debug(message),
+        };
+        // Failures can't be logged, since this is where logs go
+        result.ok();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Drops the color escapes meant for the terminal.
+fn strip_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end at the first byte in '@'..='~' after the '['
+            chars.find(|c| ('@'..='~').contains(c) && *c != '[');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn endpoints(src: SocketAddr, dst: SocketAddr) -> HashMap<String, String> {
+    HashMap::from([
+        ("src".to_owned(), src.to_string()),
+        ("dst".to_owned(), dst.to_string()),
+    ])
+}
+
+fn structured(params: HashMap<String, String>) -> HashMap<String, HashMap<String, String>> {
+    HashMap::from([(SD_ID.to_owned(), params)])
+}
@@ -0,0 +1,251 @@
+//! TCP stream reassembly for netwaystev2's experimental TCP transport.
+//!
+//! Each message on the stream is a bincode-encoded `Packet` preceded by its length as a
+//! big-endian u32, the same framing as tokio's `LengthDelimitedCodec` defaults.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tracing::*;
+
+const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Anything claiming to be larger than this means we've lost track of the framing.
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+/// Out-of-order segments held per stream while waiting for the gap to be filled. Once this
+/// many are held, the gap is given up on.
+const MAX_HELD_SEGMENTS: usize = 64;
+
+/// Streams with no segments for this long, in capture time, are forgotten, since clients that
+/// vanish never send a FIN or RST.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The parts of a TCP segment reassembly cares about.
+pub struct Segment<'a> {
+    pub ts: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub payload: &'a [u8],
+}
+
+/// One direction of a TCP connection.
+struct Stream {
+    /// Sequence number of the next byte we expect
+    next_seq: u32,
+    /// In-order bytes not yet framed into a complete message
+    buf: Vec<u8>,
+    /// Segments that arrived ahead of `next_seq`
+    held: BTreeMap<u32, Vec<u8>>,
+    last_seen: Duration,
+}
+
+#[derive(Default)]
+pub struct Reassembler {
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    /// Feeds in a segment and returns the payloads of any messages it completed.
+    pub fn push(&mut self, segment: Segment) -> Vec<Vec<u8>> {
+        let key = (segment.src, segment.dst);
+        self.streams
+            .retain(|_, stream| segment.ts.saturating_sub(stream.last_seen) < IDLE_TIMEOUT);
+
+        if segment.rst {
+            self.streams.remove(&key);
+            self.streams.remove(&(segment.dst, segment.src));
+            return vec![];
+        }
+
+        // The SYN occupies one sequence number. Streams picked up mid-connection are assumed to
+        // start on a message boundary, which is the best we can do without the handshake.
+        let seq = if segment.syn {
+            self.streams.remove(&key);
+            segment.seq.wrapping_add(1)
+        } else {
+            segment.seq
+        };
+        let stream = self.streams.entry(key).or_insert_with(|| Stream {
+            next_seq: seq,
+            buf: vec![],
+            held: BTreeMap::new(),
+            last_seen: segment.ts,
+        });
+        stream.last_seen = segment.ts;
+        let lost = stream.accept(seq, segment.payload);
+        if lost > 0 {
+            warn!(
+                "Gave up waiting for {} bytes on the TCP stream {} -> {}; messages around the gap are lost",
+                lost, segment.src, segment.dst
+            );
+        }
+
+        let messages = stream.take_messages();
+        if segment.fin {
+            self.streams.remove(&key);
+        }
+        messages
+    }
+}
+
+impl Stream {
+    /// Returns how many bytes were given up on to make room, if any.
+    fn accept(&mut self, seq: u32, payload: &[u8]) -> u32 {
+        if payload.is_empty() {
+            return 0;
+        }
+
+        // Signed distance from what we expect, so wraparound at 2^32 works out
+        let offset = seq.wrapping_sub(self.next_seq) as i32;
+        if offset > 0 {
+            let mut lost = 0;
+            if self.held.len() >= MAX_HELD_SEGMENTS {
+                // The missing segment isn't coming; carry on from the earliest one we have
+                lost = self.skip_gap();
+                if (seq.wrapping_sub(self.next_seq) as i32) <= 0 {
+                    return lost + self.accept(seq, payload);
+                }
+            }
+            self.held.insert(seq, payload.to_vec());
+            return lost;
+        }
+
+        // Retransmissions may overlap bytes we already have
+        let overlap = offset.unsigned_abs() as usize;
+        if overlap >= payload.len() {
+            return 0;
+        }
+        self.append(&payload[overlap..]);
+        self.drain_held();
+        0
+    }
+
+    /// Appends any held segments the stream has now caught up with.
+    fn drain_held(&mut self) {
+        while let Some(held_seq) = self.earliest_held() {
+            let offset = held_seq.wrapping_sub(self.next_seq) as i32;
+            if offset > 0 {
+                break;
+            }
+            let data = self.held.remove(&held_seq).unwrap();
+            let overlap = offset.unsigned_abs() as usize;
+            if overlap < data.len() {
+                self.append(&data[overlap..]);
+            }
+        }
+    }
+
+    /// Jumps over the missing bytes in front of the earliest held segment, returning how many.
+    /// The message in progress can't be completed, so it goes too.
+    fn skip_gap(&mut self) -> u32 {
+        let Some(earliest) = self.earliest_held() else {
+            return 0;
+        };
+        let lost = earliest.wrapping_sub(self.next_seq);
+        self.buf.clear();
+        self.next_seq = earliest;
+        self.drain_held();
+        lost
+    }
+
+    /// Relative to `next_seq`, so wraparound at 2^32 works out.
+    fn earliest_held(&self) -> Option<u32> {
+        self.held
+            .keys()
+            .copied()
+            .min_by_key(|seq| seq.wrapping_sub(self.next_seq) as i32)
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.next_seq = self.next_seq.wrapping_add(data.len() as u32);
+    }
+
+    fn take_messages(&mut self) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        let mut start = 0;
+        while self.buf.len() - start >= LENGTH_PREFIX_LEN {
+            let prefix = &self.buf[start..start + LENGTH_PREFIX_LEN];
+            let len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+            if len > MAX_MESSAGE_LEN {
+                // Lost the framing; drop what we have and hope to resync on a later segment
+                self.buf.clear();
+                return messages;
+            }
+            let end = start + LENGTH_PREFIX_LEN + len;
+            if self.buf.len() < end {
+                break;
+            }
+            messages.push(self.buf[start + LENGTH_PREFIX_LEN..end].to_vec());
+            start = end;
+        }
+        self.buf.drain(..start);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(ts: u64, seq: u32, payload: &[u8]) -> Segment<'_> {
+        Segment {
+            ts: Duration::from_secs(ts),
+            src: "10.0.0.1:5000".parse().unwrap(),
+            dst: "10.0.0.2:2016".parse().unwrap(),
+            seq,
+            syn: false,
+            fin: false,
+            rst: false,
+            payload,
+        }
+    }
+
+    fn message(body: &[u8]) -> Vec<u8> {
+        let mut framed = (body.len() as u32).to_be_bytes().to_vec();
+        framed.extend_from_slice(body);
+        framed
+    }
+
+    #[test]
+    fn a_lost_segment_is_skipped_once_enough_are_held() {
+        let mut reassembler = Reassembler::new();
+        let first = message(b"a");
+        assert_eq!(reassembler.push(segment(0, 0, &first)), vec![b"a".to_vec()]);
+
+        // The segment at 5 never arrives
+        let later = message(b"b");
+        let mut seq = 5 + later.len() as u32;
+        for _ in 0..MAX_HELD_SEGMENTS {
+            assert!(reassembler.push(segment(1, seq, &later)).is_empty());
+            seq += later.len() as u32;
+        }
+        let messages = reassembler.push(segment(1, seq, &later));
+        assert_eq!(messages.len(), MAX_HELD_SEGMENTS + 1);
+    }
+
+    #[test]
+    fn idle_streams_are_forgotten() {
+        let mut reassembler = Reassembler::new();
+        reassembler.push(segment(0, 0, &[0, 0]));
+        assert_eq!(reassembler.streams.len(), 1);
+        let mut other = segment(IDLE_TIMEOUT.as_secs(), 0, &[0, 0]);
+        other.src = "10.0.0.3:5000".parse().unwrap();
+        reassembler.push(other);
+        assert_eq!(reassembler.streams.len(), 1);
+        assert!(reassembler.streams.contains_key(&(
+            "10.0.0.3:5000".parse().unwrap(),
+            "10.0.0.2:2016".parse().unwrap()
+        )));
+    }
+}
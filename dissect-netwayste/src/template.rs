@@ -0,0 +1,162 @@
+//! `--format` templates: literal text with `{field}` placeholders filled in per packet.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dissect_netwayste_core::gen_diff::readable;
+use dissect_netwayste_core::output::{packet_value, sequence_number, variant_name};
+use dissect_netwayste_core::timing::format_delta;
+use netwaystev2::protocol::Packet;
+
+use crate::dissect::display_ip;
+
+/// Names accepted between braces, for the help text and error messages.
+pub const FIELDS: &str =
+    "time, delta, flow_delta, src, sport, dst, dport, variant, seq, size, iface, name, packet";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Time,
+    Delta,
+    FlowDelta,
+    Src,
+    SrcPort,
+    Dst,
+    DstPort,
+    Variant,
+    Sequence,
+    Size,
+    Interface,
+    Name,
+    Packet,
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+/// Everything a template can refer to for one decoded packet.
+pub struct Fields<'a> {
+    /// Capture time, already formatted
+    pub time: &'a str,
+    pub delta: Option<Duration>,
+    pub flow_delta: Option<Duration>,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub packet: &'a Packet,
+    /// Length of the serialized packet
+    pub size: usize,
+    pub interface: Option<&'a str>,
+    /// Host name of the source, from the capture file's name resolution records or `--resolve`
+    pub name: Option<&'a str>,
+}
+
+impl Template {
+    /// Parses a template such as `"{time} {src}:{sport} {variant}"`. Literal braces are written
+    /// `{{` and `}}`. Meant to be used as a clap `value_parser`.
+    pub fn parse(s: &str) -> Result<Template, String> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed '{{{}'", name)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Field(field_named(&name)?));
+                }
+                '}' => return Err("unmatched '}'; write '}}' for a literal brace".to_owned()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template { pieces })
+    }
+
+    pub fn render(&self, fields: &Fields) -> String {
+        // Only pay for serializing the packet if something needs it
+        let value = self
+            .pieces
+            .iter()
+            .any(|p| matches!(p, Piece::Field(Field::Variant | Field::Sequence)))
+            .then(|| packet_value(fields.packet));
+
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => out.push_str(text),
+                Piece::Field(field) => {
+                    let rendered = match field {
+                        Field::Time => fields.time.to_owned(),
+                        Field::Delta => format_delta(fields.delta),
+                        Field::FlowDelta => format_delta(fields.flow_delta),
+                        Field::Src => display_ip(fields.src.ip()),
+                        Field::SrcPort => fields.src.port().to_string(),
+                        Field::Dst => display_ip(fields.dst.ip()),
+                        Field::DstPort => fields.dst.port().to_string(),
+                        Field::Variant => variant_name(value.as_ref().unwrap()),
+                        Field::Sequence => sequence_number(value.as_ref().unwrap())
+                            .map(|seq| seq.to_string())
+                            .unwrap_or_else(|| "-".to_owned()),
+                        Field::Size => fields.size.to_string(),
+                        Field::Interface => fields.interface.unwrap_or("-").to_owned(),
+                        Field::Name => fields.name.unwrap_or("-").to_owned(),
+                        Field::Packet => readable(fields.packet),
+                    };
+                    out.push_str(&rendered);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn field_named(name: &str) -> Result<Field, String> {
+    Ok(match name.trim() {
+        "time" => Field::Time,
+        "delta" => Field::Delta,
+        "flow_delta" => Field::FlowDelta,
+        "src" => Field::Src,
+        "sport" => Field::SrcPort,
+        "dst" => Field::Dst,
+        "dport" => Field::DstPort,
+        "variant" => Field::Variant,
+        "seq" => Field::Sequence,
+        "size" => Field::Size,
+        "iface" => Field::Interface,
+        "name" => Field::Name,
+        "packet" => Field::Packet,
+        other => {
+            return Err(format!(
+                "unknown field '{{{}}}'; expected one of: {}",
+                other, FIELDS
+            ))
+        }
+    })
+}
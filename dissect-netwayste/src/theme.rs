@@ -0,0 +1,252 @@
+//! `--theme`: the colors packet lines are drawn in.
+//!
+//! A theme file has one setting per line, as `<setting> = <color>`:
+//!
+//! ```text
+//! # Blank lines and lines starting with # are ignored
+//! palette = cyan, #ff8700, 141, bright green
+//! flow 10.0.0.5:2016 = #5fd7ff
+//! flow 10.0.0.7 = yellow
+//! class duplicate = 244
+//! ```
+//!
+//! `palette` replaces the colors handed out in turn to each new source. `flow` pins the lines
+//! from one address, with or without its port, to a color; a full address wins over a bare IP.
+//! `class` colors lines marked `duplicate`, `out-of-order` or `retransmit`, whatever their source.
+//! With `--color-option packet-type` the classes `request`, `response`, `update`, `error` and
+//! `status` color lines by what kind of packet they show instead, and `flow` doesn't apply.
+//!
+//! Colors are one of the 16 terminal color names (`red`, `bright blue`, ...), a 256-color index
+//! from 0 to 255, or `#rrggbb`. Unless `COLORTERM` says the terminal handles truecolor, `#rrggbb`
+//! colors are drawn as the nearest of the 256.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+use colored::{Color, Colorize};
+use dissect_netwayste_core::output::{nested_variant, variant_name};
+use serde_json::Value;
+
+/// Response codes that mean the request failed.
+const ERROR_CODES: &[&str] = &[
+    "BadRequest",
+    "Unauthorized",
+    "TooManyRequests",
+    "ServerError",
+    "NotConnected",
+    "KickedFromRoom",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeColor {
+    Named(Color),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl ThemeColor {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if let Some(hex) = text.strip_prefix('#') {
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            };
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok(ThemeColor::Rgb(r, g, b)),
+                _ => Err(format!("bad color '{}', expected '#rrggbb'", text)),
+            };
+        }
+        if let Ok(index) = text.parse::<u8>() {
+            return Ok(ThemeColor::Indexed(index));
+        }
+        Color::from_str(&text.replace(['_', '-'], " "))
+            .map(ThemeColor::Named)
+            .map_err(|_| format!("unknown color '{}'", text))
+    }
+
+    /// Approximates truecolor with the 6x6x6 cube of the 256-color palette.
+    fn downgrade(self) -> Self {
+        match self {
+            ThemeColor::Rgb(r, g, b) => {
+                // The cube's levels are 0, 95, 135, 175, 215 and 255
+                let level = |c: u8| if c < 48 { 0 } else { (c.max(75) - 35) / 40 };
+                ThemeColor::Indexed(16 + 36 * level(r) + 6 * level(g) + level(b))
+            }
+            color => color,
+        }
+    }
+
+    pub fn paint(self, text: &str) -> String {
+        match self {
+            ThemeColor::Named(color) => text.color(color).to_string(),
+            ThemeColor::Rgb(r, g, b) => text.truecolor(r, g, b).to_string(),
+            // colored has no 256-color support, so only its on/off switch is borrowed
+            ThemeColor::Indexed(index) if colored::control::SHOULD_COLORIZE.should_colorize() => {
+                format!("\x1b[38;5;{}m{}\x1b[0m", index, text)
+            }
+            ThemeColor::Indexed(_) => text.to_owned(),
+        }
+    }
+}
+
+/// Lines marked in ways worth noticing whichever flow they're on, and the kinds of packet
+/// `--color-option packet-type` tells apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Duplicate,
+    OutOfOrder,
+    Retransmit,
+    Request,
+    Response,
+    Update,
+    /// Responses with an error code
+    Error,
+    /// `GetStatus`, `Status` and anything else
+    Status,
+}
+
+impl Class {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "duplicate" => Ok(Class::Duplicate),
+            "out-of-order" => Ok(Class::OutOfOrder),
+            "retransmit" => Ok(Class::Retransmit),
+            "request" => Ok(Class::Request),
+            "response" => Ok(Class::Response),
+            "update" => Ok(Class::Update),
+            "error" => Ok(Class::Error),
+            "status" => Ok(Class::Status),
+            _ => Err(format!("unknown class '{}'", name)),
+        }
+    }
+
+    /// `packet` is as returned by `output::packet_value()`.
+    pub fn of(packet: &Value) -> Self {
+        match variant_name(packet).as_str() {
+            "Request" => Class::Request,
+            "Response" => match nested_variant(packet, "code") {
+                Some(code) if ERROR_CODES.contains(&code.as_str()) => Class::Error,
+                _ => Class::Response,
+            },
+            "Update" | "UpdateReply" => Class::Update,
+            _ => Class::Status,
+        }
+    }
+}
+
+pub struct Theme {
+    /// Handed out in turn to each new source
+    pub palette: Vec<ThemeColor>,
+    flows: HashMap<SocketAddr, ThemeColor>,
+    hosts: HashMap<IpAddr, ThemeColor>,
+    classes: HashMap<Class, ThemeColor>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            // Ordered to reduce adjacent similarity.
+            // This may appear differently depending on one's terminal settings.
+            palette: [
+                Color::Cyan,
+                Color::Yellow,
+                Color::Red,
+                Color::Magenta,
+                Color::Green,
+                Color::Blue,
+            ]
+            .into_iter()
+            .map(ThemeColor::Named)
+            .collect(),
+            flows: HashMap::new(),
+            hosts: HashMap::new(),
+            classes: [
+                (Class::Request, Color::Cyan),
+                (Class::Response, Color::Green),
+                (Class::Update, Color::Blue),
+                (Class::Error, Color::Red),
+                (Class::Status, Color::Yellow),
+            ]
+            .into_iter()
+            .map(|(class, color)| (class, ThemeColor::Named(color)))
+            .collect(),
+        }
+    }
+}
+
+impl Theme {
+    /// Reads a theme file, failing with the line at fault.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Theme::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    /// Reads the contents of a theme file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let truecolor =
+            env::var("COLORTERM").map_or(false, |term| term == "truecolor" || term == "24bit");
+        let mut theme = Theme::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            theme
+                .set(line, truecolor)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(theme)
+    }
+
+    fn set(&mut self, line: &str, truecolor: bool) -> Result<(), String> {
+        let (setting, value) = line
+            .split_once('=')
+            .ok_or("expected '<setting> = <color>'")?;
+        let color = |text: &str| {
+            ThemeColor::parse(text).map(|color| if truecolor { color } else { color.downgrade() })
+        };
+        match setting.split_whitespace().collect::<Vec<_>>()[..] {
+            ["palette"] => {
+                self.palette = value.split(',').map(color).collect::<Result<_, _>>()?;
+                if self.palette.is_empty() {
+                    return Err("the palette needs at least one color".to_owned());
+                }
+            }
+            ["flow", addr] => {
+                if let Ok(addr) = addr.parse::<SocketAddr>() {
+                    self.flows.insert(addr, color(value)?);
+                } else if let Ok(ip) = addr.parse::<IpAddr>() {
+                    self.hosts.insert(ip, color(value)?);
+                } else {
+                    return Err(format!("bad address '{}'", addr));
+                }
+            }
+            ["class", name] => {
+                self.classes.insert(Class::parse(name)?, color(value)?);
+            }
+            _ => {
+                return Err(format!(
+                    "unknown setting '{}', expected 'palette', 'flow <addr>' or 'class <name>'",
+                    setting.trim()
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The color pinned to a source, if any.
+    pub fn flow(&self, src: SocketAddr) -> Option<ThemeColor> {
+        self.flows
+            .get(&src)
+            .or_else(|| self.hosts.get(&src.ip()))
+            .copied()
+    }
+
+    pub fn class(&self, class: Class) -> Option<ThemeColor> {
+        self.classes.get(&class).copied()
+    }
+}
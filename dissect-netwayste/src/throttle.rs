@@ -0,0 +1,104 @@
+//! `--sample` and `--max-lines-per-sec`: printing a subset of the packets on a busy server,
+//! where every line would be unreadable anyway and slow the capture down. Only the output is
+//! thinned; everything is still counted.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::*;
+
+/// `keep` of every `of` packets, e.g. `1/100`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    keep: u64,
+    of: u64,
+}
+
+impl Sample {
+    /// Meant to be used as a clap `value_parser`.
+    pub fn parse(s: &str) -> Result<Sample, String> {
+        let (keep, of) = s
+            .split_once('/')
+            .ok_or_else(|| format!("'{}' is not a fraction like '1/100'", s))?;
+        let number = |s: &str| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("'{}' is not a number: {}", s, e))
+        };
+        let (keep, of) = (number(keep)?, number(of)?);
+        if keep == 0 || keep > of {
+            return Err(format!("'{}' is not a fraction between 0 and 1", s));
+        }
+        Ok(Sample { keep, of })
+    }
+}
+
+/// Why a packet was left out of the output.
+pub enum Skip {
+    Sampled,
+    Limited,
+}
+
+pub struct Throttle {
+    sample: Option<Sample>,
+    max_per_sec: Option<u32>,
+    /// xorshift64 state. Packets are picked at random rather than every so many, which would
+    /// keep showing one side of a regular exchange, e.g. only the requests.
+    rng: u64,
+    /// Start of the second being counted towards `max_per_sec`
+    second: Instant,
+    printed: u32,
+    limited: u64,
+}
+
+impl Throttle {
+    pub fn new(sample: Option<Sample>, max_per_sec: Option<u32>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Throttle {
+            sample,
+            max_per_sec,
+            rng: seed | 1,
+            second: Instant::now(),
+            printed: 0,
+            limited: 0,
+        }
+    }
+
+    /// Call for each packet that would otherwise be printed.
+    pub fn admit(&mut self) -> Result<(), Skip> {
+        if let Some(sample) = self.sample {
+            if self.next_random() % sample.of >= sample.keep {
+                return Err(Skip::Sampled);
+            }
+        }
+        let Some(max_per_sec) = self.max_per_sec else {
+            return Ok(());
+        };
+        if self.second.elapsed() >= Duration::from_secs(1) {
+            if self.limited > 0 {
+                warn!(
+                    "Left {} packets out of the output to stay under {} lines per second",
+                    self.limited, max_per_sec
+                );
+            }
+            self.second = Instant::now();
+            self.printed = 0;
+            self.limited = 0;
+        }
+        if self.printed >= max_per_sec {
+            self.limited += 1;
+            return Err(Skip::Limited);
+        }
+        self.printed += 1;
+        Ok(())
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
@@ -0,0 +1,52 @@
+//! `--timeline-file`: the session events of every player as JSON lines, for postmortems.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use dissect_netwayste_core::output::format_time;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Event<'a> {
+    timestamp: Option<String>,
+    client: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    player: Option<&'a str>,
+    event: &'a str,
+}
+
+pub struct TimelineExport {
+    out: BufWriter<File>,
+}
+
+impl TimelineExport {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(TimelineExport {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        ts: Duration,
+        client: SocketAddr,
+        player: Option<&str>,
+        event: &str,
+    ) -> io::Result<()> {
+        let event = Event {
+            timestamp: format_time(ts),
+            client,
+            player,
+            event,
+        };
+        let line = serde_json::to_string(&event).expect("failed to serialize timeline event");
+        writeln!(self.out, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
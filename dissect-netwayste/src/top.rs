@@ -0,0 +1,135 @@
+//! The busiest flows, for `--top`.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use dissect_netwayste_core::output::variant_name;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopBy {
+    /// Packets per second
+    Packets,
+    /// Payload bytes per second
+    Bytes,
+}
+
+#[derive(Default)]
+struct FlowTraffic {
+    bytes: u64,
+    packets: u64,
+    variants: HashMap<String, u64>,
+}
+
+pub struct TopTalkers {
+    count: usize,
+    by: TopBy,
+    interval: Duration,
+    /// Start of the interval being counted, in capture time
+    started: Option<Duration>,
+    flows: HashMap<(SocketAddr, SocketAddr), FlowTraffic>,
+}
+
+impl TopTalkers {
+    /// Lists the `count` busiest flows every `interval` of capture time.
+    pub fn new(count: usize, by: TopBy, interval: Duration) -> Self {
+        TopTalkers {
+            count,
+            by,
+            interval,
+            started: None,
+            flows: HashMap::new(),
+        }
+    }
+
+    /// Call with every datagram on the netwayste port. `variant` is `None` for payloads that
+    /// didn't decode, which still count towards the rates.
+    pub fn observe(
+        &mut self,
+        ts: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        bytes: usize,
+        variant: Option<String>,
+    ) {
+        let started = *self.started.get_or_insert(ts);
+        let elapsed = ts.saturating_sub(started);
+        if elapsed >= self.interval {
+            self.report(elapsed);
+            self.started = Some(ts);
+        }
+
+        let flow = self.flows.entry((src, dst)).or_default();
+        flow.bytes += bytes as u64;
+        flow.packets += 1;
+        if let Some(variant) = variant {
+            *flow.variants.entry(variant).or_default() += 1;
+        }
+    }
+
+    fn report(&mut self, elapsed: Duration) {
+        if self.flows.is_empty() {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let mut flows: Vec<_> = self.flows.drain().collect();
+        flows.sort_by_key(|(_, flow)| {
+            std::cmp::Reverse(match self.by {
+                TopBy::Packets => flow.packets,
+                TopBy::Bytes => flow.bytes,
+            })
+        });
+        info!(
+            "Top {} of {} flows over the last {:.1?}:",
+            self.count.min(flows.len()),
+            flows.len(),
+            elapsed
+        );
+        for ((src, dst), flow) in flows.iter().take(self.count) {
+            let dominant = flow
+                .variants
+                .iter()
+                .max_by_key(|(_, count)| **count)
+                .map(|(variant, count)| {
+                    format!(
+                        "{} ({:.0}%)",
+                        variant,
+                        *count as f64 * 100.0 / flow.packets as f64
+                    )
+                })
+                .unwrap_or_else(|| "undecoded".to_owned());
+            info!(
+                "  {:>22} -> {:<22} {:>8.1} pkt/s {:>10.0} B/s  mostly {}",
+                src.to_string(),
+                dst.to_string(),
+                flow.packets as f64 / secs,
+                flow.bytes as f64 / secs,
+                dominant
+            );
+        }
+    }
+}
+
+impl Analyzer for TopTalkers {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        let (ts, src, dst) = (context.ts, context.src, context.dst);
+        self.observe(
+            ts,
+            src,
+            dst,
+            context.payload.len(),
+            Some(variant_name(value)),
+        );
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        let (ts, src, dst) = (context.ts, context.src, context.dst);
+        self.observe(ts, src, dst, context.payload.len(), None);
+    }
+}
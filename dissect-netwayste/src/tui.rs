@@ -0,0 +1,543 @@
+//! `--tui`: the log as a scrollable list next to the running totals, instead of scrolling past.
+//!
+//! Everything that would have been logged goes to the list, through `TuiWriter`. Keys:
+//! space pauses and resumes, the arrows, Page Up/Down and Home scroll back (pausing), End jumps
+//! back to the latest line and q or Ctrl-C quits.
+//!
+//! `/` searches back through the list for text, as in `less`. Matches are highlighted; n goes on
+//! to the next older one and N to the next newer one.
+//!
+//! Enter on the packet at the bottom of the list opens it as a tree of fields next to a hex
+//! dump of its payload, with the selected field's bytes highlighted. Up and down move through
+//! the fields, right expands one, left collapses it and Esc goes back to the list.
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Terminal;
+
+use crate::diagnose::Field;
+use crate::summary::Summary;
+
+/// Lines kept for scrolling back through; older ones are dropped.
+const SCROLLBACK: usize = 10_000;
+
+/// Matches `hexdump()`.
+const BYTES_PER_LINE: usize = 16;
+
+/// How often the screen is redrawn, and keys checked for.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// What Enter opens for a packet.
+struct Detail {
+    payload: Vec<u8>,
+    /// `None` if there's no layout to lay the payload out with
+    fields: Option<Field>,
+}
+
+struct Entry {
+    text: String,
+    /// Only packets have one
+    detail: Option<Arc<Detail>>,
+}
+
+#[derive(Default)]
+struct Screen {
+    lines: VecDeque<Entry>,
+    /// Lines ever added, so positions survive old lines being dropped
+    total: u64,
+    /// The start of a line still being written
+    partial: Vec<u8>,
+    /// Set once the terminal is given back, after which writes go to stderr
+    closed: bool,
+    /// Set once the capture has run out of packets
+    ended: bool,
+    /// Set to close the screen as if the user had quit
+    quit: bool,
+}
+
+impl Screen {
+    fn push(&mut self, text: String, detail: Option<Arc<Detail>>) {
+        if self.lines.len() == SCROLLBACK {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(Entry { text, detail });
+        self.total += 1;
+    }
+}
+
+/// Collects the log lines for the list.
+#[derive(Clone)]
+pub struct TuiWriter(Arc<Mutex<Screen>>);
+
+impl Write for TuiWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut screen = self.0.lock().unwrap();
+        if screen.closed {
+            return io::stderr().write(buf);
+        }
+        for &b in buf {
+            if b == b'\n' {
+                let line = String::from_utf8_lossy(&screen.partial).into_owned();
+                screen.partial.clear();
+                screen.push(line, None);
+            } else {
+                screen.partial.push(b);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where the list is looking.
+#[derive(Default)]
+struct View {
+    /// One past the last line shown when paused, so new lines don't move the list
+    paused_at: Option<u64>,
+    /// Lines scrolled back from there
+    scroll: u64,
+    /// What's being typed after `/`
+    prompt: Option<String>,
+    /// The last thing searched for, highlighted wherever it appears
+    search: Option<String>,
+    /// Shown in the status bar until the next key press
+    message: Option<String>,
+    /// The packet opened with Enter
+    detail: Option<DetailView>,
+}
+
+struct DetailView {
+    detail: Arc<Detail>,
+    /// Fields showing their children, by their position in the tree
+    expanded: HashSet<Vec<usize>>,
+    /// Index into the fields currently showing
+    selected: usize,
+}
+
+impl DetailView {
+    fn new(detail: Arc<Detail>) -> Self {
+        DetailView {
+            detail,
+            // The packet itself starts out open
+            expanded: HashSet::from([vec![]]),
+            selected: 0,
+        }
+    }
+
+    /// The fields showing, in order, each with its position in the tree.
+    fn visible(&self) -> Vec<(Vec<usize>, &Field)> {
+        let mut visible = vec![];
+        if let Some(root) = &self.detail.fields {
+            self.add_visible(root, vec![], &mut visible);
+        }
+        visible
+    }
+
+    fn add_visible<'a>(
+        &self,
+        field: &'a Field,
+        position: Vec<usize>,
+        visible: &mut Vec<(Vec<usize>, &'a Field)>,
+    ) {
+        let open = self.expanded.contains(&position);
+        visible.push((position.clone(), field));
+        if open {
+            for (i, child) in field.children.iter().enumerate() {
+                let mut child_position = position.clone();
+                child_position.push(i);
+                self.add_visible(child, child_position, visible);
+            }
+        }
+    }
+
+    fn key(&mut self, code: KeyCode) {
+        let visible = self.visible();
+        let count = visible.len();
+        let Some((position, field)) = visible.get(self.selected) else {
+            return;
+        };
+        let (position, has_children) = (position.clone(), !field.children.is_empty());
+        let parent = position
+            .split_last()
+            .and_then(|(_, parent)| visible.iter().position(|(p, _)| p == parent));
+        match code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(count - 1),
+            KeyCode::Right | KeyCode::Enter if has_children => {
+                self.expanded.insert(position);
+            }
+            KeyCode::Left if self.expanded.contains(&position) => {
+                self.expanded.remove(&position);
+            }
+            // Already closed, so go up to the field it's in
+            KeyCode::Left => {
+                if let Some(i) = parent {
+                    self.selected = i;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl View {
+    /// Moves to the nearest line containing the search text, before or after the one at the
+    /// bottom of the list, showing it at the bottom instead.
+    fn find(&mut self, screen: &Screen, older: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let first = screen.total - screen.lines.len() as u64;
+        let bottom = self.paused_at.unwrap_or(screen.total).max(first) - self.scroll;
+        let contains = |i: &u64| {
+            screen.lines[(*i - first) as usize]
+                .text
+                .contains(search.as_str())
+        };
+        // `bottom` is one past the last line shown, which is the previous match if paused
+        let found = if older {
+            let skip = self.paused_at.map_or(0, |_| 1);
+            (first..bottom.saturating_sub(skip).max(first))
+                .rev()
+                .find(contains)
+        } else {
+            (bottom..screen.total).find(contains)
+        };
+        match found {
+            Some(i) => {
+                self.paused_at = Some(i + 1);
+                self.scroll = 0;
+            }
+            None => self.message = Some(format!("No more matches for '{}'", search)),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Tui {
+    screen: Arc<Mutex<Screen>>,
+}
+
+impl Tui {
+    pub fn writer(&self) -> TuiWriter {
+        TuiWriter(Arc::clone(&self.screen))
+    }
+
+    /// Adds a decoded packet's line, which Enter opens to show `fields` and the payload.
+    pub fn packet(&self, text: &str, payload: &[u8], fields: Option<Field>) {
+        let detail = Arc::new(Detail {
+            payload: payload.to_vec(),
+            fields,
+        });
+        let mut screen = self.screen.lock().unwrap();
+        let mut lines = text.lines();
+        screen.push(lines.next().unwrap_or_default().to_owned(), Some(detail));
+        // e.g. `--hexdump` output below it
+        for line in lines {
+            screen.push(line.to_owned(), None);
+        }
+    }
+
+    /// Marks the capture as over; the screen stays up until the user quits.
+    pub fn ended(&self) {
+        self.screen.lock().unwrap().ended = true;
+    }
+
+    /// Closes the screen, e.g. when a signal ends the run.
+    pub fn close(&self) {
+        self.screen.lock().unwrap().quit = true;
+    }
+
+    /// Takes over the terminal until the user quits or it's closed, then gives it back and calls `quit`.
+    pub fn start(
+        &self,
+        source_name: String,
+        summary: Arc<Mutex<Summary>>,
+        quit: impl FnOnce() + Send + 'static,
+    ) -> JoinHandle<()> {
+        let screen = Arc::clone(&self.screen);
+        thread::spawn(move || {
+            if let Err(e) = run(&screen, &source_name, &summary) {
+                screen.lock().unwrap().closed = true;
+                panic!("Terminal UI failed: {}", e);
+            }
+            quit();
+        })
+    }
+}
+
+fn run(screen: &Mutex<Screen>, source_name: &str, summary: &Mutex<Summary>) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut view = View::default();
+
+    loop {
+        let totals = summary.lock().unwrap().overview();
+        let list_height = terminal.size()?.height.saturating_sub(3) as u64;
+        let opened = {
+            let screen = screen.lock().unwrap();
+            let first = screen.total - screen.lines.len() as u64;
+            let end = view.paused_at.unwrap_or(screen.total).max(first);
+            view.scroll = view.scroll.min(end - first);
+            let end = end - view.scroll;
+            let start = end.saturating_sub(list_height).max(first);
+            let mut lines: Vec<Line> = screen
+                .lines
+                .range((start - first) as usize..(end - first) as usize)
+                .map(|entry| highlighted(&entry.text, view.search.as_deref()))
+                .collect();
+            // What Enter would open
+            if let Some(bottom) = lines.last_mut().filter(|_| view.paused_at.is_some()) {
+                bottom.patch_style(Style::default().add_modifier(Modifier::BOLD));
+            }
+            let bottom = end.checked_sub(1).filter(|bottom| *bottom >= first);
+            let opened =
+                bottom.and_then(|bottom| screen.lines[(bottom - first) as usize].detail.clone());
+            let state = match (screen.ended, view.paused_at) {
+                (true, _) => "ENDED",
+                (false, Some(_)) => "PAUSED",
+                (false, None) => "LIVE",
+            };
+            let status = match (&view.prompt, &view.message, &view.detail) {
+                (Some(prompt), _, _) => format!("/{}", prompt),
+                (None, Some(message), _) => format!(" {}", message),
+                (None, None, Some(_)) => {
+                    " up/down select, right expand, left collapse, Esc back to list, q quit"
+                        .to_owned()
+                }
+                (None, None, None) => format!(
+                    " {} | {} | line {} of {} | space pause, arrows/PgUp/PgDn/Home scroll, End latest, / search, Enter open, q quit",
+                    source_name, state, end, screen.total
+                ),
+            };
+
+            terminal.draw(|f| {
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(3), Constraint::Length(1)])
+                    .split(f.size());
+                if let Some(detail) = &view.detail {
+                    let columns = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+                        .split(rows[0]);
+                    let (tree, bytes) = detail_lines(detail, columns[0].height.saturating_sub(2));
+                    f.render_widget(
+                        Paragraph::new(tree)
+                            .block(Block::default().borders(Borders::ALL).title("Fields")),
+                        columns[0],
+                    );
+                    f.render_widget(
+                        Paragraph::new(bytes)
+                            .block(Block::default().borders(Borders::ALL).title("Payload")),
+                        columns[1],
+                    );
+                } else {
+                    let columns = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+                        .split(rows[0]);
+                    f.render_widget(
+                        Paragraph::new(lines)
+                            .block(Block::default().borders(Borders::ALL).title("Packets")),
+                        columns[0],
+                    );
+                    let totals: Vec<Line> = totals.into_iter().map(Line::from).collect();
+                    f.render_widget(
+                        Paragraph::new(totals)
+                            .block(Block::default().borders(Borders::ALL).title("Totals")),
+                        columns[1],
+                    );
+                }
+                f.render_widget(
+                    Paragraph::new(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+                    rows[1],
+                );
+            })?;
+            opened
+        };
+
+        if screen.lock().unwrap().quit {
+            break;
+        }
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let latest = screen.lock().unwrap().total;
+        let page = list_height.max(1);
+        view.message = None;
+        if let Some(detail) = view.detail.as_mut() {
+            match key.code {
+                KeyCode::Char('q') => break,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Esc | KeyCode::Backspace => view.detail = None,
+                code => detail.key(code),
+            }
+            continue;
+        }
+        if let Some(prompt) = view.prompt.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => prompt.push(c),
+                KeyCode::Backspace => {
+                    prompt.pop();
+                }
+                KeyCode::Enter => {
+                    view.search = view.prompt.take().filter(|prompt| !prompt.is_empty());
+                    view.find(&screen.lock().unwrap(), true);
+                }
+                KeyCode::Esc => view.prompt = None,
+                _ => {}
+            }
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') => break,
+            // Raw mode turns Ctrl-C into a key press rather than a signal
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyCode::Char(' ') => {
+                view.paused_at = match view.paused_at {
+                    Some(_) => None,
+                    None => Some(latest),
+                };
+                view.scroll = 0;
+            }
+            KeyCode::Up | KeyCode::PageUp | KeyCode::Home => {
+                view.paused_at.get_or_insert(latest);
+                view.scroll += match key.code {
+                    KeyCode::Up => 1,
+                    KeyCode::PageUp => page,
+                    _ => u64::MAX / 2,
+                };
+            }
+            KeyCode::Down => view.scroll = view.scroll.saturating_sub(1),
+            KeyCode::PageDown => view.scroll = view.scroll.saturating_sub(page),
+            KeyCode::End => {
+                view.paused_at = None;
+                view.scroll = 0;
+            }
+            KeyCode::Char('/') => view.prompt = Some(String::new()),
+            KeyCode::Enter => match opened {
+                Some(detail) => {
+                    // Keep the list where it was for coming back to
+                    view.paused_at.get_or_insert(latest);
+                    view.detail = Some(DetailView::new(detail));
+                }
+                None => view.message = Some("That line isn't a packet".to_owned()),
+            },
+            KeyCode::Char('n') => view.find(&screen.lock().unwrap(), true),
+            KeyCode::Char('N') => view.find(&screen.lock().unwrap(), false),
+            _ => {}
+        }
+    }
+
+    screen.lock().unwrap().closed = true;
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
+
+/// The line with every occurrence of `search` picked out.
+fn highlighted(line: &str, search: Option<&str>) -> Line<'static> {
+    let Some(search) = search else {
+        return Line::from(line.to_owned());
+    };
+    let style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let mut spans = vec![];
+    let mut rest = line;
+    while let Some(at) = rest.find(search) {
+        spans.push(Span::raw(rest[..at].to_owned()));
+        spans.push(Span::styled(search.to_owned(), style));
+        rest = &rest[at + search.len()..];
+    }
+    spans.push(Span::raw(rest.to_owned()));
+    Line::from(spans)
+}
+
+/// The fields showing, scrolled to keep the selected one in view, and the hex dump of the
+/// payload with the selected field's bytes picked out.
+fn detail_lines(view: &DetailView, height: u16) -> (Vec<Line<'static>>, Vec<Line<'static>>) {
+    let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+    let visible = view.visible();
+    let skip = view
+        .selected
+        .saturating_sub(height.saturating_sub(1) as usize);
+    let mut tree = vec![];
+    let mut range = None;
+    for (i, (position, field)) in visible.iter().enumerate().skip(skip) {
+        let marker = match (field.children.is_empty(), view.expanded.contains(position)) {
+            (true, _) => " ",
+            (false, true) => "-",
+            (false, false) => "+",
+        };
+        let mut text = format!("{}{} {}", "  ".repeat(position.len()), marker, field.name);
+        if let Some(value) = &field.value {
+            text = format!("{}: {}", text, value);
+        }
+        if i == view.selected {
+            range = Some(field.start..field.end);
+            tree.push(Line::from(Span::styled(text, selected_style)));
+        } else {
+            tree.push(Line::from(text));
+        }
+    }
+    if view.detail.fields.is_none() {
+        tree.push(Line::from("No field layout for this packet"));
+    }
+
+    let highlight = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let mut bytes = vec![];
+    for (row, chunk) in view.detail.payload.chunks(BYTES_PER_LINE).enumerate() {
+        let mut spans = vec![Span::raw(format!("{:04x} ", row * BYTES_PER_LINE))];
+        let mut ascii = vec![Span::raw("   ")];
+        for (j, &b) in chunk.iter().enumerate() {
+            let offset = row * BYTES_PER_LINE + j;
+            let style = match &range {
+                Some(range) if range.contains(&offset) => highlight,
+                _ => Style::default(),
+            };
+            if j == BYTES_PER_LINE / 2 {
+                spans.push(Span::raw(" "));
+                ascii.push(Span::raw(" "));
+            }
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(format!("{:02x}", b), style));
+            let c = if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            };
+            ascii.push(Span::styled(c.to_string(), style));
+        }
+        // Line up the ASCII column on a short last row
+        let missing = BYTES_PER_LINE - chunk.len();
+        let padding = missing * 3 + usize::from(chunk.len() <= BYTES_PER_LINE / 2);
+        spans.push(Span::raw(" ".repeat(padding)));
+        spans.extend(ascii);
+        bytes.push(Line::from(spans));
+    }
+    (tree, bytes)
+}
@@ -0,0 +1,250 @@
+//! Each client's view of the game universe, rebuilt from the diffs the server sends it.
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+
+use dissect_netwayste_core::gen_diff::{find_diffs, Diff};
+use dissect_netwayste_core::ports::Ports;
+use serde_json::Value;
+use tracing::*;
+
+/// Diffs reaching a column or row past this are taken to be corrupt, since walking them would
+/// take more time and memory than any real universe.
+const MAX_SIDE: u64 = 1 << 16;
+
+/// Likewise for diffs changing more cells than this.
+const MAX_CHANGED: u64 = 1 << 22;
+
+/// Live cells as of one generation.
+#[derive(Default)]
+pub struct Universe {
+    pub generation: u64,
+    pub live: HashSet<(u64, u64)>,
+}
+
+impl Universe {
+    /// Left, top, right and bottom of the live cells, inclusive.
+    pub fn bounds(&self) -> Option<(u64, u64, u64, u64)> {
+        let mut cells = self.live.iter();
+        let &(x, y) = cells.next()?;
+        Some(
+            cells.fold((x, y, x, y), |(left, top, right, bottom), &(x, y)| {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }),
+        )
+    }
+
+    /// The live cells as RLE, the same format the diffs use.
+    pub fn to_rle(&self) -> String {
+        let Some((left, top, right, bottom)) = self.bounds() else {
+            return "!".to_owned();
+        };
+        let mut rle = String::new();
+        for y in top..=bottom {
+            if y > top {
+                push(&mut rle, 1, '$');
+            }
+            let mut x = left;
+            while x <= right {
+                let alive = self.live.contains(&(x, y));
+                let start = x;
+                while x <= right && self.live.contains(&(x, y)) == alive {
+                    x += 1;
+                }
+                // Trailing dead cells are implied by the end of the row
+                if alive || x <= right {
+                    push(&mut rle, x - start, if alive { 'o' } else { 'b' });
+                }
+            }
+        }
+        rle.push('!');
+        rle
+    }
+
+    /// Applies `diff`, unless it can't be parsed or is too big to be real. Returns whether it
+    /// was applied.
+    fn apply(&mut self, diff: &Diff<'_>) -> bool {
+        let Some(runs) = diff.runs() else {
+            return false;
+        };
+        let changed: u64 = runs.iter().map(|&(_, _, run)| run).sum();
+        if changed > MAX_CHANGED
+            || runs
+                .iter()
+                .any(|&(x, y, run)| x + run > MAX_SIDE || y >= MAX_SIDE)
+        {
+            return false;
+        }
+        if diff.gen0 == 0 {
+            self.live.clear();
+        }
+        for (x, y, run) in runs {
+            for x in x..x + run {
+                // A live cell in a diff is one that changed
+                if !self.live.remove(&(x, y)) {
+                    self.live.insert((x, y));
+                }
+            }
+        }
+        self.generation = diff.gen1;
+        true
+    }
+}
+
+fn push(rle: &mut String, run: u64, tag: char) {
+    match run {
+        0 => {}
+        1 => rle.push(tag),
+        run => rle.push_str(&format!("{}{}", run, tag)),
+    }
+}
+
+#[derive(Default)]
+struct ClientUniverse {
+    universe: Option<Universe>,
+    /// Diffs that didn't start from the generation the reconstruction was at, or were too big
+    /// to apply
+    mismatched: u64,
+}
+
+pub struct Universes {
+    server_ports: Ports,
+    clients: BTreeMap<SocketAddr, ClientUniverse>,
+    /// Generation at which to log each client's whole universe
+    dump_at: Option<u64>,
+}
+
+impl Universes {
+    pub fn new(server_ports: Ports, dump_at: Option<u64>) -> Self {
+        Universes {
+            server_ports,
+            clients: BTreeMap::new(),
+            dump_at,
+        }
+    }
+
+    /// Call with every decoded packet, in capture order.
+    pub fn observe(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) {
+        if !self.server_ports.contains(src.port()) {
+            return;
+        }
+        for diff in find_diffs(packet) {
+            let client = self.clients.entry(dst).or_default();
+            match &mut client.universe {
+                // Nothing to build on until the whole state comes along
+                None if diff.gen0 != 0 => {}
+                None => {
+                    let mut universe = Universe::default();
+                    if !universe.apply(&diff) {
+                        client.mismatched += 1;
+                        warn!("Diff for {} is too big to apply", dst);
+                        continue;
+                    }
+                    client.universe = Some(universe);
+                }
+                // Already applied; the server resends until the client acknowledges
+                Some(universe) if diff.gen1 <= universe.generation => {}
+                Some(universe) if diff.gen0 == 0 || diff.gen0 == universe.generation => {
+                    if !universe.apply(&diff) {
+                        client.mismatched += 1;
+                        warn!(
+                            "Diff for {} from generation {} to {} is too big to apply",
+                            dst, diff.gen0, diff.gen1
+                        );
+                        continue;
+                    }
+                }
+                Some(universe) => {
+                    client.mismatched += 1;
+                    warn!(
+                        "Diff for {} from generation {} to {} doesn't apply: its reconstruction is at generation {}",
+                        dst, diff.gen0, diff.gen1, universe.generation
+                    );
+                    continue;
+                }
+            }
+            if let (Some(universe), Some(dump_at)) = (&client.universe, self.dump_at) {
+                if universe.generation == dump_at {
+                    info!(
+                        "Universe of {} at generation {}: {}",
+                        dst,
+                        dump_at,
+                        universe.to_rle()
+                    );
+                }
+            }
+        }
+    }
+
+    /// The most advanced reconstruction of all, which is as close to the server's own as the
+    /// capture gets.
+    pub fn latest(&self) -> Option<&Universe> {
+        self.clients
+            .values()
+            .filter_map(|client| client.universe.as_ref())
+            .max_by_key(|universe| universe.generation)
+    }
+
+    pub fn log(&self) {
+        if let Some(latest) = self.latest() {
+            info!(
+                "Universe at generation {}: {} live cells",
+                latest.generation,
+                latest.live.len()
+            );
+        }
+        for (addr, client) in &self.clients {
+            match &client.universe {
+                Some(universe) => info!(
+                    "  {:<24} generation {}, {} live cells, {} diffs didn't apply",
+                    addr.to_string(),
+                    universe.generation,
+                    universe.live.len(),
+                    client.mismatched
+                ),
+                None => info!(
+                    "  {:<24} never received a whole universe to build on",
+                    addr.to_string()
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn diff(gen0: u64, gen1: u64, pattern: &str) -> Value {
+        json!({"Update": {"game_updates": [
+            {"GenStateDiff": {"gen0": gen0, "gen1": gen1, "pattern": pattern}}
+        ]}})
+    }
+
+    #[test]
+    fn oversized_diffs_are_not_applied() {
+        let server: SocketAddr = "10.0.0.1:2016".parse().unwrap();
+        let client: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let mut universes = Universes::new(Ports::from(2016), None);
+
+        // Nothing to build on yet, and nothing built from it
+        universes.observe(server, client, &diff(0, 1, "999999999999o!"));
+        assert!(universes.latest().is_none());
+        assert_eq!(universes.clients[&client].mismatched, 1);
+
+        universes.observe(server, client, &diff(0, 1, "2o$bo!"));
+        for pattern in ["999999999999o!", "99999999999999999999o!", "70000$o!"] {
+            universes.observe(server, client, &diff(1, 2, pattern));
+        }
+        assert_eq!(universes.clients[&client].mismatched, 4);
+        let universe = universes.latest().unwrap();
+        assert_eq!(universe.generation, 1);
+        assert_eq!(universe.to_rle(), "2o$bo!");
+
+        universes.observe(server, client, &diff(1, 2, "o!"));
+        // Rendered from the leftmost live column
+        assert_eq!(universes.latest().unwrap().to_rle(), "o$o!");
+    }
+}
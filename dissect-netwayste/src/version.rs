@@ -0,0 +1,115 @@
+//! Spots clients speaking a different protocol version, either because everything they send
+//! fails to decode or because the version they announce isn't the server's.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use dissect_netwayste_core::packet_filter::find_strings;
+use netwaystev2::protocol::Packet;
+use serde_json::Value;
+use tracing::*;
+
+use crate::analyzer::{Analyzer, Context};
+
+/// Failures in a row from one address before it's reported as speaking another version.
+const CONSISTENT_FAILURES: u32 = 5;
+
+#[derive(Default)]
+pub struct VersionWatch {
+    /// Undecodable packets in a row, by source
+    failures: HashMap<SocketAddr, u32>,
+    /// Sources already reported, whose individual failures aren't worth logging any more
+    reported: HashSet<SocketAddr>,
+    /// The `client_version` each client sent when connecting
+    announced: HashMap<SocketAddr, String>,
+    /// From the server's `LoggedIn` responses
+    server_version: Option<String>,
+    /// Clients already warned about announcing the wrong version
+    mismatched: HashSet<SocketAddr>,
+}
+
+impl VersionWatch {
+    /// Call with every decoded packet.
+    pub fn decoded(&mut self, src: SocketAddr, dst: SocketAddr, packet: &Value) {
+        self.failures.remove(&src);
+        self.reported.remove(&src);
+
+        let mut versions = vec![];
+        find_strings(packet, "client_version", &mut versions);
+        if let Some(version) = versions.first() {
+            self.announced.insert(src, version.to_string());
+            self.compare(src);
+        }
+
+        versions.clear();
+        find_strings(packet, "server_version", &mut versions);
+        if let Some(version) = versions.first() {
+            self.server_version = Some(version.to_string());
+            self.compare(dst);
+        }
+    }
+
+    /// Call with every payload that failed to decode.
+    pub fn failed(&mut self, src: SocketAddr) {
+        if self.reported.contains(&src) {
+            return;
+        }
+        let failures = self.failures.entry(src).or_default();
+        *failures += 1;
+        if *failures < CONSISTENT_FAILURES {
+            return;
+        }
+
+        let expected = match &self.server_version {
+            Some(version) => format!("the server speaks {}", version),
+            None => {
+                "this dissector only understands the netwayste version it was built with".to_owned()
+            }
+        };
+        match self.announced.get(&src) {
+            Some(version) => warn!(
+                "{} appears to speak protocol version {}: its last {} packets failed to decode, and {}",
+                src, version, failures, expected
+            ),
+            None => warn!(
+                "{} appears to speak another protocol version: its last {} packets failed to decode, and {}",
+                src, failures, expected
+            ),
+        }
+        self.reported.insert(src);
+    }
+
+    /// Whether `src` has been reported as speaking another version, in which case there's no
+    /// point logging its failures one by one.
+    pub fn reported(&self, src: SocketAddr) -> bool {
+        self.reported.contains(&src)
+    }
+
+    /// Warns if `client` announced a version other than the server's.
+    fn compare(&mut self, client: SocketAddr) {
+        if let (Some(client_version), Some(server_version)) =
+            (self.announced.get(&client), &self.server_version)
+        {
+            if client_version != server_version && self.mismatched.insert(client) {
+                warn!(
+                    "{} appears to speak protocol version {}, but the server speaks {}",
+                    client, client_version, server_version
+                );
+            }
+        }
+    }
+}
+
+/// Shared with the main loop, which asks `reported()` before logging a failure.
+impl Analyzer for Arc<Mutex<VersionWatch>> {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        self.lock()
+            .unwrap()
+            .decoded(context.src, context.dst, value);
+    }
+
+    fn on_error(&mut self, context: &Context, _error: &bincode::Error) {
+        self.lock().unwrap().failed(context.src);
+    }
+}
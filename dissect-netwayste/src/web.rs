@@ -0,0 +1,136 @@
+//! `--web-listen`: a small dashboard for watching a capture from a browser.
+//!
+//! `/` is a single page that opens a WebSocket on `/ws`. Each decoded packet is sent over it as
+//! `{"type":"packet","record":...}`, the record being the same object the `json` output prints,
+//! and the running totals as `{"type":"stats","lines":[...]}` once a second.
+
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dissect_netwayste_core::output::json_line;
+use netwaystev2::protocol::Packet;
+use serde_json::{json, Value};
+use tiny_http::{Header, Request, Response, Server, StatusCode};
+use tracing::*;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::analyzer::{Analyzer, Context};
+use crate::summary::Summary;
+
+const PAGE: &str = include_str!("web.html");
+
+/// Packets queued for each browser; a browser that falls further behind misses packets rather
+/// than holding up the capture.
+const QUEUE_LEN: usize = 1000;
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct WebDashboard {
+    browsers: Arc<Mutex<Vec<SyncSender<String>>>>,
+}
+
+impl WebDashboard {
+    pub fn serve(addr: SocketAddr, summary: Arc<Mutex<Summary>>) -> Self {
+        let server = Server::http(addr).expect("Failed to listen for the web dashboard");
+        let browsers: Arc<Mutex<Vec<SyncSender<String>>>> = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&browsers);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let result = match request.url() {
+                    "/ws" => {
+                        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+                        shared.lock().unwrap().push(sender);
+                        upgrade(request, receiver, Arc::clone(&summary))
+                    }
+                    "/" => {
+                        let content_type =
+                            Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap();
+                        request.respond(Response::from_string(PAGE).with_header(content_type))
+                    }
+                    _ => request.respond(Response::empty(StatusCode(404))),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to answer web dashboard request: '{}'", e);
+                }
+            }
+        });
+        WebDashboard { browsers }
+    }
+
+    /// Sends a packet, as a `json_line()`, to every browser watching.
+    pub fn packet(&self, record: &str) {
+        let message = format!(r#"{{"type":"packet","record":{}}}"#, record);
+        self.browsers
+            .lock()
+            .unwrap()
+            .retain(|browser| match browser.try_send(message.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+/// Completes the WebSocket handshake and streams to the browser from its own thread.
+fn upgrade(
+    request: Request,
+    receiver: Receiver<String>,
+    summary: Arc<Mutex<Summary>>,
+) -> std::io::Result<()> {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_bytes()));
+    let Some(accept) = key else {
+        return request.respond(Response::empty(StatusCode(400)));
+    };
+    let response = Response::empty(StatusCode(101))
+        .with_header(Header::from_bytes(&b"Upgrade"[..], &b"websocket"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Connection"[..], &b"Upgrade"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept.as_bytes()).unwrap());
+    let stream = request.upgrade("websocket", response);
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        let mut stats_sent: Option<Instant> = None;
+        loop {
+            let mut messages = vec![];
+            if stats_sent.map_or(true, |sent| sent.elapsed() >= STATS_INTERVAL) {
+                let lines = summary.lock().unwrap().overview();
+                messages.push(json!({ "type": "stats", "lines": lines }).to_string());
+                stats_sent = Some(Instant::now());
+            }
+            match receiver.recv_timeout(STATS_INTERVAL) {
+                Ok(text) => messages.push(text),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            // Most likely the browser went away; dropping the receiver tells `packet()` so
+            if messages
+                .into_iter()
+                .any(|text| socket.send(Message::Text(text)).is_err())
+            {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+impl Analyzer for WebDashboard {
+    fn on_packet(&mut self, context: &Context, _packet: &Packet, value: &Value) {
+        if context.shown {
+            self.packet(&json_line(
+                context.ts,
+                context.interface,
+                context.src,
+                context.dst,
+                value,
+            ));
+        }
+    }
+}
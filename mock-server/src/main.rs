@@ -0,0 +1,405 @@
+//! A stand-in netwayste server that does just enough to be tested against. It logs clients in,
+//! acknowledges their requests, answers `GetStatus`, echoes chat messages to everyone logged
+//! in, and sends each client the `GenStateDiff`s in a script at set times after it logs in.
+//! Nothing is random, so a run can be repeated exactly.
+//!
+//! Packets are built from the all-zero `templates` of dissect-netwayste-core, with only the
+//! fields the mock cares about filled in.
+
+mod script;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use dissect_netwayste_core::output::{nested_variant, packet_value, sequence_number, variant_name};
+use dissect_netwayste_core::packet_filter::find_strings;
+use dissect_netwayste_core::protocol::{Decoder, Protocol};
+use dissect_netwayste_core::templates::{
+    find_struct, set_field, template, template_with, trace_packet, zero_value,
+};
+use netwaystev2::protocol::Packet;
+use netwaystev2::DEFAULT_PORT;
+use serde_json::Value;
+use serde_reflection::{ContainerFormat, Format, Registry};
+use tracing::*;
+
+use script::Step;
+
+/// How long to wait for packets when no diff is due.
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(
+        long,
+        default_value_t = SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+        help = "Address to listen on"
+    )]
+    listen: SocketAddr,
+
+    #[arg(
+        long,
+        help = "File of GenStateDiffs to send each client after it logs in, one 'at gen0 gen1 pattern' per line"
+    )]
+    script: Option<PathBuf>,
+
+    #[arg(
+        long = "loop",
+        requires = "script",
+        help = "Start the script over once it's done, for as long as the client stays"
+    )]
+    repeat: bool,
+
+    #[arg(
+        long,
+        default_value = env!("CARGO_PKG_VERSION"),
+        help = "Server version to tell clients"
+    )]
+    server_version: String,
+
+    #[arg(short, long, help = "Log every packet received and sent")]
+    verbose: bool,
+}
+
+struct Client {
+    name: String,
+    /// Sequence number of the last packet sent to the client
+    sequence: u64,
+    /// When the script was last started for the client
+    round_start: Instant,
+    next_step: usize,
+}
+
+struct Server {
+    socket: UdpSocket,
+    decoder: Box<dyn Decoder>,
+    registry: Registry,
+    /// The type chat messages are broadcast in, if there is one
+    chat_type: Option<String>,
+    /// The type `GenStateDiff`s are sent in, if there is one
+    diff_type: Option<String>,
+    server_version: String,
+    verbose: bool,
+    /// Kept in order, so everyone is sent things in the same order every run
+    clients: BTreeMap<SocketAddr, Client>,
+    logins: u64,
+    chats: u64,
+}
+
+impl Server {
+    fn new(socket: UdpSocket, server_version: String, verbose: bool) -> Result<Self, String> {
+        let registry = trace_packet()?;
+        for code in ["LoggedIn", "OK"] {
+            let response = template(&registry, code)?;
+            serde_json::from_value::<Packet>(response)
+                .map_err(|e| format!("can't build a {} response: {}", code, e))?;
+        }
+        let chat_type = find_struct(&registry, |fields| {
+            fields
+                .iter()
+                .any(|field| field.name == "message" && matches!(field.value, Format::Str))
+        })
+        .map(str::to_owned);
+        if chat_type.is_none() {
+            warn!("No chat message type in the protocol; chats won't be echoed");
+        }
+        let diff_type = find_struct(&registry, |fields| {
+            let has = |name| fields.iter().any(|field| field.name == name);
+            has("gen0") && has("gen1")
+        })
+        .map(str::to_owned);
+        Ok(Server {
+            socket,
+            decoder: Protocol::V2.decoder(),
+            registry,
+            chat_type,
+            diff_type,
+            server_version,
+            verbose,
+            clients: BTreeMap::new(),
+            logins: 0,
+            chats: 0,
+        })
+    }
+
+    fn received(&mut self, from: SocketAddr, payload: &[u8]) {
+        let packet = match self.decoder.decode(payload) {
+            Ok(packet) => packet,
+            Err(e) => {
+                warn!(
+                    "Undecodable {} byte packet from {}: {}",
+                    payload.len(),
+                    from,
+                    e
+                );
+                return;
+            }
+        };
+        let value = packet_value(&packet);
+        if self.verbose {
+            info!("{} -> {}", from, value);
+        }
+        match variant_name(&value).as_str() {
+            "GetStatus" => self.status(from),
+            "Request" => self.request(from, &value),
+            variant => debug!("Ignoring a {} from {}", variant, from),
+        }
+    }
+
+    fn request(&mut self, from: SocketAddr, request: &Value) {
+        let Some(sequence) = sequence_number(request) else {
+            warn!("Request from {} has no sequence number", from);
+            return;
+        };
+        let action = nested_variant(request, "action").unwrap_or_default();
+        if action == "Connect" {
+            let mut names = vec![];
+            find_strings(request, "name", &mut names);
+            let name = names.first().copied().unwrap_or_default().to_owned();
+            info!("{} logged in as '{}'", from, name);
+            self.logins += 1;
+            let cookie = format!("mock-cookie-{}", self.logins);
+            self.clients.insert(
+                from,
+                Client {
+                    name,
+                    sequence: 0,
+                    round_start: Instant::now(),
+                    next_step: 0,
+                },
+            );
+            let server_version = self.server_version.clone();
+            self.respond(
+                from,
+                sequence,
+                "LoggedIn",
+                &[
+                    ("cookie", cookie.into()),
+                    ("server_version", server_version.into()),
+                ],
+            );
+            return;
+        }
+        if !self.clients.contains_key(&from) {
+            warn!("Ignoring {} from {}, which hasn't logged in", action, from);
+            return;
+        }
+        self.respond(from, sequence, "OK", &[]);
+        if action == "Disconnect" {
+            info!("{} disconnected", from);
+            self.clients.remove(&from);
+            return;
+        }
+        let mut messages = vec![];
+        find_strings(request, "message", &mut messages);
+        if let Some(message) = messages.first() {
+            self.chat(from, message);
+        }
+    }
+
+    fn respond(&mut self, to: SocketAddr, request_ack: u64, code: &str, fields: &[(&str, Value)]) {
+        let mut response = template(&self.registry, code).expect("checked at startup");
+        set_field(&mut response, "request_ack", &request_ack.into());
+        for (key, value) in fields {
+            set_field(&mut response, key, value);
+        }
+        self.send(to, response);
+    }
+
+    fn status(&mut self, to: SocketAddr) {
+        let Ok(mut status) = template(&self.registry, "Status") else {
+            return;
+        };
+        set_field(
+            &mut status,
+            "server_version",
+            &self.server_version.as_str().into(),
+        );
+        set_field(&mut status, "player_count", &self.clients.len().into());
+        self.send(to, status);
+    }
+
+    /// Sends `message` to everyone logged in, as from `from`. The message's other text fields
+    /// get the sender's name and any field ending in `seq` counts up.
+    fn chat(&mut self, from: SocketAddr, message: &str) {
+        let Some(chat_type) = self.chat_type.clone() else {
+            return;
+        };
+        let Some(ContainerFormat::Struct(fields)) = self.registry.get(&chat_type) else {
+            return;
+        };
+        self.chats += 1;
+        let mut chat = zero_value(&self.registry, &chat_type).expect("found in the registry");
+        for field in fields {
+            chat[&field.name] = match &field.value {
+                Format::Str if field.name == "message" => message.into(),
+                Format::Str => self.clients[&from].name.as_str().into(),
+                _ if field.name.ends_with("seq") => self.chats.into(),
+                _ => continue,
+            };
+        }
+        let update = match template_with(&self.registry, "Update", &chat_type, &chat) {
+            Ok(update) => update,
+            Err(e) => {
+                warn!("Can't echo chats: {}", e);
+                self.chat_type = None;
+                return;
+            }
+        };
+        let clients: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        for client in clients {
+            self.send(client, update.clone());
+        }
+    }
+
+    /// Sends every client the diffs in `steps` that are due. Returns when the next one is.
+    fn send_due(&mut self, steps: &[Step], repeat: bool) -> Option<Instant> {
+        let (Some(last), Some(diff_type)) = (steps.last(), self.diff_type.clone()) else {
+            return None;
+        };
+        let now = Instant::now();
+        let mut next_due: Option<Instant> = None;
+        let clients: Vec<SocketAddr> = self.clients.keys().copied().collect();
+        for addr in clients {
+            loop {
+                let client = self.clients.get_mut(&addr).unwrap();
+                if client.next_step == steps.len() {
+                    if !repeat {
+                        break;
+                    }
+                    client.next_step = 0;
+                    client.round_start += last.at;
+                }
+                let step = &steps[client.next_step];
+                let due = client.round_start + step.at;
+                if due > now {
+                    next_due = Some(next_due.map_or(due, |next| next.min(due)));
+                    break;
+                }
+                client.next_step += 1;
+                match self.diff_update(&diff_type, step) {
+                    Ok(update) => self.send(addr, update),
+                    Err(e) => error!("Can't send a diff: {}", e),
+                }
+            }
+        }
+        next_due
+    }
+
+    fn diff_update(&self, diff_type: &str, step: &Step) -> Result<Value, String> {
+        let Some(ContainerFormat::Struct(fields)) = self.registry.get(diff_type) else {
+            return Err(format!("{} isn't a struct", diff_type));
+        };
+        let mut diff = zero_value(&self.registry, diff_type)?;
+        diff["gen0"] = step.gen0.into();
+        diff["gen1"] = step.gen1.into();
+        if let Some(pattern) = fields
+            .iter()
+            .find(|field| field.name.starts_with("pattern"))
+        {
+            diff[&pattern.name] = step.pattern.as_str().into();
+        }
+        // Sent whole, if the protocol splits diffs into parts
+        set_field(&mut diff, "total_parts", &1.into());
+        template_with(&self.registry, "Update", diff_type, &diff)
+    }
+
+    fn send(&mut self, to: SocketAddr, mut value: Value) {
+        if let Some(client) = self.clients.get_mut(&to) {
+            client.sequence += 1;
+            set_field(&mut value, "sequence", &client.sequence.into());
+        }
+        let packet: Packet = match serde_json::from_value(value.clone()) {
+            Ok(packet) => packet,
+            Err(e) => {
+                error!("Can't build {} for {}: {}", value, to, e);
+                return;
+            }
+        };
+        if self.verbose {
+            info!("{} <- {}", to, value);
+        }
+        let payload = bincode::serialize(&packet).expect("netwayste packet not serializable");
+        if let Err(e) = self.socket.send_to(&payload, to) {
+            warn!("Failed to send to {}: {}", to, e);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    tracing_subscriber::fmt().init();
+
+    let steps = match &args.script {
+        Some(path) => script::load(path)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::InvalidValue, e).exit()),
+        None => vec![],
+    };
+    if args.repeat && steps.last().map_or(true, |last| last.at.is_zero()) {
+        Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "'loop' needs a script whose last diff comes after 0s",
+            )
+            .exit();
+    }
+
+    let socket = UdpSocket::bind(args.listen).unwrap_or_else(|e| {
+        Args::command()
+            .error(
+                ErrorKind::Io,
+                format!("failed to listen on {}: {}", args.listen, e),
+            )
+            .exit()
+    });
+    let mut server = match Server::new(socket, args.server_version.clone(), args.verbose) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+    if !steps.is_empty() && server.diff_type.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "the protocol has no GenStateDiff type to send the script in",
+            )
+            .exit();
+    }
+    info!(
+        "Mock netwayste server listening on {}, with {} scripted diffs",
+        args.listen,
+        steps.len()
+    );
+
+    let mut buf = [0; 65536];
+    loop {
+        let wait = server
+            .send_due(&steps, args.repeat)
+            .map_or(IDLE_WAIT, |due| {
+                due.saturating_duration_since(Instant::now())
+            })
+            .max(Duration::from_millis(1));
+        server
+            .socket
+            .set_read_timeout(Some(wait))
+            .expect("Failed to set the socket's timeout");
+        match server.socket.recv_from(&mut buf) {
+            Ok((len, from)) => server.received(from, &buf[..len]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            // e.g. ICMP port unreachable from a client that went away
+            Err(e) => debug!("Reading failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! `--script`: the `GenStateDiff`s sent to each client, and when.
+//!
+//! One diff per line, as the time after the client logs in, the generation the diff applies to,
+//! the one it brings the universe to, and the pattern in RLE. Blank lines and lines starting
+//! with `#` are skipped:
+//!
+//! ```text
+//! # at    gen0 gen1 pattern
+//! 0s      0    1    bo$2bo$3o!
+//! 500ms   1    2    obo$b2o$bo!
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Step {
+    /// How long after the client logs in this diff is due
+    pub at: Duration,
+    pub gen0: u64,
+    pub gen1: u64,
+    pub pattern: String,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Step>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("can't read '{}': {}", path.display(), e))?;
+    let mut steps: Vec<Step> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let step = parse(line).map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        if steps.last().is_some_and(|last| step.at < last.at) {
+            return Err(format!(
+                "{}:{}: steps must be in time order",
+                path.display(),
+                number + 1
+            ));
+        }
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn parse(line: &str) -> Result<Step, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [at, gen0, gen1, pattern] = fields[..] else {
+        return Err(format!(
+            "expected 'at gen0 gen1 pattern', got {} fields",
+            fields.len()
+        ));
+    };
+    let generation = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|e| format!("'{}' is not a generation: {}", s, e))
+    };
+    Ok(Step {
+        at: humantime::parse_duration(at).map_err(|e| format!("'{}' is not a time: {}", at, e))?,
+        gen0: generation(gen0)?,
+        gen1: generation(gen1)?,
+        pattern: pattern.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    /// Writes `text` to a file of its own, loads it, and removes the file again.
+    fn load_text(name: &str, text: &str) -> Result<Vec<Step>, String> {
+        let path = env::temp_dir().join(format!("mock-server-{}-{}.script", process::id(), name));
+        fs::write(&path, text).unwrap();
+        let steps = load(&path);
+        fs::remove_file(&path).unwrap();
+        steps.map_err(|e| e.replace(&path.display().to_string(), "script"))
+    }
+
+    #[test]
+    fn parses_a_step() {
+        let step = parse("500ms 1 2 obo$b2o$bo!").unwrap();
+        assert_eq!(step.at, Duration::from_millis(500));
+        assert_eq!(step.gen0, 1);
+        assert_eq!(step.gen1, 2);
+        assert_eq!(step.pattern, "obo$b2o$bo!");
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        assert_eq!(
+            parse("0s 0 1").unwrap_err(),
+            "expected 'at gen0 gen1 pattern', got 3 fields"
+        );
+        assert!(parse("0s -1 1 o!")
+            .unwrap_err()
+            .contains("'-1' is not a generation"));
+        assert!(parse("soon 0 1 o!")
+            .unwrap_err()
+            .contains("'soon' is not a time"));
+    }
+
+    #[test]
+    fn loads_steps_skipping_comments_and_blank_lines() {
+        let text = "# at gen0 gen1 pattern\n\n0s 0 1 bo$2bo$3o!\n  \n1s 1 2 obo$b2o$bo!\n";
+        let steps = load_text("good", text).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].at, Duration::ZERO);
+        assert_eq!(steps[1].at, Duration::from_secs(1));
+        assert_eq!(steps[1].gen1, 2);
+    }
+
+    #[test]
+    fn load_errors_give_the_line() {
+        let text = "# comment\n1s 0 1 o!\n500ms 1 2 o!\n";
+        assert_eq!(
+            load_text("unordered", text).unwrap_err(),
+            "script:3: steps must be in time order"
+        );
+        let text = "0s 0 1 o!\n1s 1 o!\n";
+        assert_eq!(
+            load_text("short", text).unwrap_err(),
+            "script:2: expected 'at gen0 gen1 pattern', got 3 fields"
+        );
+        assert!(load(Path::new("/nonexistent/mock.script"))
+            .unwrap_err()
+            .starts_with("can't read '/nonexistent/mock.script'"));
+    }
+}